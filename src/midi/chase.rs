@@ -0,0 +1,311 @@
+//! Per-channel controller state chasing
+//!
+//! Starting playback (or cutting a region) in the middle of a sequence
+//! needs each channel's effective program, controllers, pitch bend and
+//! parameter values at that point. `ChannelState` accumulates them from the
+//! events before a tick and renders the minimal messages that recreate
+//! them.
+
+use std::collections::BTreeMap;
+
+use super::controller::{self, ControllerEventKind, ParameterNumber, RPN_PITCH_BEND_RANGE};
+use super::event::MidiEvent;
+use super::message::MidiMessage;
+
+const BANK_SELECT_MSB: u8 = 0;
+const BANK_SELECT_LSB: u8 = 32;
+const SUSTAIN: u8 = 64;
+const RESET_ALL_CONTROLLERS: u8 = 121;
+
+/// Controllers reset by Reset All Controllers (CC 121), with their reset
+/// values (RP-015)
+const RESET_VALUES: [(u8, u8); 7] = [
+    (1, 0),
+    (11, 127),
+    (64, 0),
+    (65, 0),
+    (66, 0),
+    (67, 0),
+    (69, 0),
+];
+
+/// Effective state of one MIDI channel at a point in a sequence. Only values
+/// actually set by earlier events are `Some`; anything else is left at the
+/// receiver's own default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelState {
+    /// Current program
+    pub program: Option<u8>,
+    /// Last value of every controller that carries a value of its own
+    /// (including bank select 0/32 and sustain 64; RPN/NRPN selection and
+    /// data-entry controllers are folded into `parameters`, channel mode
+    /// messages 120-127 are not state)
+    pub controllers: [Option<u8>; 128],
+    /// Current 14-bit pitch bend
+    pub pitch_bend: Option<u16>,
+    /// Current channel pressure
+    pub channel_pressure: Option<u8>,
+    /// 14-bit values of RPNs and NRPNs set via data entry
+    pub parameters: BTreeMap<ParameterNumber, u16>,
+}
+
+impl Default for ChannelState {
+    fn default() -> Self {
+        Self {
+            program: None,
+            controllers: [None; 128],
+            pitch_bend: None,
+            channel_pressure: None,
+            parameters: BTreeMap::new(),
+        }
+    }
+}
+
+impl ChannelState {
+    /// Create an empty state
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check whether nothing has been set on this channel
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Get the last value of a controller
+    pub fn controller(&self, controller: u8) -> Option<u8> {
+        self.controllers.get(controller as usize).copied().flatten()
+    }
+
+    /// Bank select as a 14-bit value (CC 0 << 7 | CC 32), if either half
+    /// was set
+    pub fn bank(&self) -> Option<u16> {
+        let msb = self.controller(BANK_SELECT_MSB);
+        let lsb = self.controller(BANK_SELECT_LSB);
+        if msb.is_none() && lsb.is_none() {
+            return None;
+        }
+        Some(((msb.unwrap_or(0) as u16) << 7) | lsb.unwrap_or(0) as u16)
+    }
+
+    /// Pitch-bend range as (semitones, cents), from RPN 0
+    pub fn pitch_bend_range(&self) -> Option<(u8, u8)> {
+        self.parameters
+            .get(&ParameterNumber::Registered(RPN_PITCH_BEND_RANGE))
+            .map(|&value| ((value >> 7) as u8, (value & 0x7F) as u8))
+    }
+
+    /// Check whether the sustain pedal is held down
+    pub fn is_sustain_held(&self) -> bool {
+        self.controller(SUSTAIN).is_some_and(|value| value >= 64)
+    }
+
+    /// Update the state with a message on this channel. RPN/NRPN traffic
+    /// is handled separately by `apply_parameter`.
+    fn apply(&mut self, message: &MidiMessage) {
+        match *message {
+            MidiMessage::ProgramChange { program, .. } => self.program = Some(program),
+            MidiMessage::ChannelPressure { pressure, .. } => self.channel_pressure = Some(pressure),
+            MidiMessage::PitchBend { value, .. } => self.pitch_bend = Some(value),
+            MidiMessage::ControlChange { .. } => {
+                let (Some(controller), Some(value)) = (
+                    message.get_controller_number(),
+                    message.get_controller_value(),
+                ) else {
+                    return;
+                };
+                if controller == RESET_ALL_CONTROLLERS {
+                    self.reset_controllers();
+                } else if controller < 120 && !controller::is_parameter_controller(controller) {
+                    self.controllers[controller as usize] = Some(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Apply Reset All Controllers to the values it affects. Controllers
+    /// that were never set stay unset.
+    fn reset_controllers(&mut self) {
+        for (controller, value) in RESET_VALUES {
+            let slot = &mut self.controllers[controller as usize];
+            if slot.is_some() {
+                *slot = Some(value);
+            }
+        }
+        if self.pitch_bend.is_some() {
+            self.pitch_bend = Some(0x2000);
+        }
+        if self.channel_pressure.is_some() {
+            self.channel_pressure = Some(0);
+        }
+    }
+
+    /// Update the parameter values with a decoded RPN/NRPN event.
+    fn apply_parameter(&mut self, kind: &ControllerEventKind) {
+        match *kind {
+            ControllerEventKind::Rpn { param, value14 } => {
+                self.parameters
+                    .insert(ParameterNumber::Registered(param), value14);
+            }
+            ControllerEventKind::Nrpn { param, value14 } => {
+                self.parameters
+                    .insert(ParameterNumber::NonRegistered(param), value14);
+            }
+            ControllerEventKind::DataIncrement { parameter, .. } => {
+                if let Some(value) = self.parameters.get_mut(&parameter) {
+                    *value = (*value + 1).min(0x3FFF);
+                }
+            }
+            ControllerEventKind::DataDecrement { parameter, .. } => {
+                if let Some(value) = self.parameters.get_mut(&parameter) {
+                    *value = value.saturating_sub(1);
+                }
+            }
+            ControllerEventKind::Cc14 { .. } => {}
+        }
+    }
+
+    /// Messages that recreate this state on `channel`: bank select and
+    /// program first, then RPN/NRPN values (each closed with a null RPN),
+    /// the remaining controllers in ascending order, channel pressure and
+    /// pitch bend. Nothing is emitted for values that were never set.
+    pub fn chase_messages(&self, channel: u8) -> Vec<MidiMessage> {
+        let mut messages = Vec::new();
+        let cc =
+            |controller: u8, value: u8| MidiMessage::control_change(channel, controller, value);
+
+        for controller in [BANK_SELECT_MSB, BANK_SELECT_LSB] {
+            if let Some(value) = self.controller(controller) {
+                messages.push(cc(controller, value));
+            }
+        }
+        if let Some(program) = self.program {
+            messages.push(MidiMessage::program_change(channel, program));
+        }
+        for (&parameter, &value14) in &self.parameters {
+            let kind = match parameter {
+                ParameterNumber::Registered(param) => ControllerEventKind::Rpn { param, value14 },
+                ParameterNumber::NonRegistered(param) => {
+                    ControllerEventKind::Nrpn { param, value14 }
+                }
+            };
+            messages.extend(
+                controller::encode(&kind)
+                    .into_iter()
+                    .map(|(controller, value)| cc(controller, value)),
+            );
+        }
+        for (controller, value) in self.controllers.iter().enumerate() {
+            let controller = controller as u8;
+            if controller == BANK_SELECT_MSB || controller == BANK_SELECT_LSB {
+                continue;
+            }
+            if let Some(value) = *value {
+                messages.push(cc(controller, value));
+            }
+        }
+        if let Some(pressure) = self.channel_pressure {
+            messages.push(MidiMessage::ChannelPressure { channel, pressure });
+        }
+        if let Some(value) = self.pitch_bend {
+            messages.push(MidiMessage::pitch_bend(channel, value));
+        }
+        messages
+    }
+}
+
+/// Accumulate the state of all 16 channels over `events`, in order.
+pub(super) fn channel_states(events: &[MidiEvent]) -> [ChannelState; 16] {
+    let mut states: [ChannelState; 16] = Default::default();
+    for event in events {
+        if let Some(channel) = event.channel() {
+            states[(channel & 0x0F) as usize].apply(event.message());
+        }
+    }
+    for decoded in controller::decode(events) {
+        states[(decoded.channel & 0x0F) as usize].apply_parameter(&decoded.kind);
+    }
+    states
+}
+
+/// Events at `tick` that recreate `states`, channel by channel.
+pub(super) fn chase_events(states: &[ChannelState; 16], tick: u64) -> Vec<MidiEvent> {
+    states
+        .iter()
+        .enumerate()
+        .flat_map(|(channel, state)| state.chase_messages(channel as u8))
+        .map(|message| MidiEvent::new(tick, message))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::{MidiFile, MidiTrack};
+
+    #[test]
+    fn test_channel_state_at_tick() {
+        let mut track = MidiTrack::new();
+        track.add_controller(0, 0, 0, 1);
+        track.add_controller(0, 0, 32, 2);
+        track.add_patch_change(0, 0, 40);
+        track.add_pitch_bend_range(0, 0, 12, 0);
+        track.add_controller(10, 0, 7, 90);
+        track.add_sustain_on(20, 0);
+        track.add_pitch_bend(30, 0, 0x3000);
+        track.add_sustain_off(50, 0);
+        track.add_patch_change(50, 0, 41);
+        track.add_controller(60, 0, 121, 0);
+        track.sort();
+
+        let states = track.channel_states_at(40);
+        let state = &states[0];
+        assert_eq!(state.program, Some(40));
+        assert_eq!(state.bank(), Some((1 << 7) | 2));
+        assert_eq!(state.controller(7), Some(90));
+        assert_eq!(state.pitch_bend, Some(0x3000));
+        assert_eq!(state.pitch_bend_range(), Some((12, 0)));
+        assert!(state.is_sustain_held());
+        assert_eq!(state.controller(6), None);
+        assert!(states[1..].iter().all(ChannelState::is_empty));
+
+        // Events at the chase tick itself are not included.
+        assert!(!track.channel_states_at(50)[0].is_empty());
+        assert_eq!(track.channel_states_at(50)[0].program, Some(40));
+
+        let state = &track.channel_states_at(61)[0];
+        assert_eq!(state.program, Some(41));
+        assert!(!state.is_sustain_held());
+        assert_eq!(state.pitch_bend, Some(0x2000));
+        assert_eq!(state.controller(7), Some(90));
+    }
+
+    #[test]
+    fn test_chase_events_recreate_state_across_tracks() {
+        let mut file = MidiFile::new();
+        file.add_tracks(2);
+        file.add_patch_change(0, 0, 3, 19).unwrap();
+        file.add_sustain_on(1, 100, 3).unwrap();
+        file.add_controller(1, 0, 3, 10, 30).unwrap();
+        file.set_pitch_bend_range(0, 0, 3, 2, 0).unwrap();
+        file.add_note(1, 200, 100, 3, 60, 100).unwrap();
+
+        let chase = file.chase_events(200);
+        assert!(chase.iter().all(|e| e.tick() == 200));
+        let messages: Vec<&MidiMessage> = chase.iter().map(|e| e.message()).collect();
+        assert_eq!(messages[0], &MidiMessage::program_change(3, 19));
+        assert!(messages.iter().any(|m| m.is_sustain_on()));
+
+        // Replaying the chase list yields the same state.
+        let mut replay = MidiTrack::new();
+        for event in chase {
+            replay.add_event(event);
+        }
+        assert_eq!(replay.channel_states_at(201), file.channel_states_at(200));
+        assert_eq!(
+            file.channel_states_at(200)[3].pitch_bend_range(),
+            Some((2, 0))
+        );
+    }
+}
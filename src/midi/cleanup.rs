@@ -0,0 +1,294 @@
+//! Cleanup passes for recorded performances
+//!
+//! Live recordings hold notes with the sustain pedal rather than the keys,
+//! and often strike a key again before releasing it. `bake_sustain` moves
+//! the pedal into the note lengths, and `repair_overlaps` turns
+//! overlapping and duplicated notes on the same key into a clean sequence
+//! that `link_note_events` pairs correctly.
+
+use super::event::MidiEvent;
+use super::file::MidiFile;
+use super::track::MidiTrack;
+
+/// Notes changed by `MidiTrack::repair_overlaps`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OverlapReport {
+    /// Notes ended early because the same key was struck again
+    pub overlaps: usize,
+    /// Note-ons removed because the same key was already struck at the same
+    /// tick
+    pub duplicates: usize,
+}
+
+impl OverlapReport {
+    /// Total number of repairs
+    pub fn total(&self) -> usize {
+        self.overlaps + self.duplicates
+    }
+}
+
+fn note_slot(channel: u8, key: u8) -> usize {
+    (channel as usize & 0x0F) * 128 + (key as usize & 0x7F)
+}
+
+/// Replace the events of `track` with `events`, sorted, relinking if the
+/// track was linked before.
+fn replace_events(track: &mut MidiTrack, events: Vec<MidiEvent>, was_linked: bool) {
+    *track.events_mut() = events;
+    track.sort();
+    if was_linked {
+        track.link_note_events();
+    }
+}
+
+/// Extend note-offs to the sustain pedal release. Returns the number of
+/// note-offs moved.
+pub(super) fn bake_sustain(track: &mut MidiTrack, remove_pedal: bool) -> usize {
+    let was_linked = track.events().iter().any(MidiEvent::is_linked);
+    track.sort();
+    let end = track.last_tick();
+    let events = track.events();
+
+    let mut ticks: Vec<u64> = events.iter().map(MidiEvent::tick).collect();
+    let mut pedal_down = [false; 16];
+    // Note-offs waiting for the pedal, per channel.
+    let mut held: Vec<Vec<usize>> = vec![Vec::new(); 16];
+    // Notes still down on each key, and how many of them were struck
+    // again before their note-off (those note-offs are not held).
+    let mut open = vec![0usize; 16 * 128];
+    let mut restruck = vec![0usize; 16 * 128];
+    for (i, event) in events.iter().enumerate() {
+        let Some(channel) = event.channel() else {
+            continue;
+        };
+        let channel = channel as usize & 0x0F;
+        let message = event.message();
+        if message.is_sustain_on() {
+            pedal_down[channel] = true;
+        } else if message.is_sustain_off() {
+            pedal_down[channel] = false;
+            for off_idx in held[channel].drain(..) {
+                ticks[off_idx] = event.tick();
+            }
+        } else if let Some(key) = event.key() {
+            let slot = note_slot(channel as u8, key);
+            if event.is_note_on() {
+                // Striking a held key again ends the ringing note.
+                if let Some(pos) = held[channel]
+                    .iter()
+                    .position(|&off_idx| events[off_idx].key() == Some(key))
+                {
+                    ticks[held[channel].remove(pos)] = event.tick();
+                }
+                if open[slot] > 0 {
+                    restruck[slot] += 1;
+                }
+                open[slot] += 1;
+            } else if event.is_note_off() {
+                open[slot] = open[slot].saturating_sub(1);
+                if restruck[slot] > 0 {
+                    restruck[slot] -= 1;
+                } else if pedal_down[channel] {
+                    held[channel].push(i);
+                }
+            }
+        }
+    }
+    // A pedal that is never released holds its notes to the end.
+    for off_idx in held.into_iter().flatten() {
+        ticks[off_idx] = end;
+    }
+
+    let moved = events
+        .iter()
+        .zip(&ticks)
+        .filter(|(event, tick)| event.tick() != **tick)
+        .count();
+    let baked = events
+        .iter()
+        .zip(ticks)
+        .filter(|(event, _)| !(remove_pedal && event.message().is_sustain()))
+        .map(|(event, tick)| {
+            let mut event = event.clone();
+            event.set_tick(tick);
+            event.unlink_event();
+            event
+        })
+        .collect();
+    replace_events(track, baked, was_linked);
+    moved
+}
+
+/// End overlapping same-key notes at the next strike and drop duplicate
+/// note-ons, together with the note-offs that no longer have a note.
+pub(super) fn repair_overlaps(track: &mut MidiTrack) -> OverlapReport {
+    let was_linked = track.events().iter().any(MidiEvent::is_linked);
+    track.sort();
+
+    let mut report = OverlapReport::default();
+    let events = track.events();
+    // Start tick of the latest note and the number of notes still waiting
+    // for a note-off, on each channel/key.
+    let mut latest: Vec<Option<u64>> = vec![None; 16 * 128];
+    let mut open = vec![0usize; 16 * 128];
+    // Note-offs still to come that belong to notes already ended or removed.
+    let mut surplus_offs = vec![0usize; 16 * 128];
+    let mut repaired = Vec::with_capacity(events.len());
+    for (i, event) in events.iter().enumerate() {
+        if let (Some(channel), Some(key)) = (event.channel(), event.key()) {
+            let slot = note_slot(channel, key);
+            if event.is_note_on() {
+                if open[slot] > 0 {
+                    if latest[slot] == Some(event.tick()) {
+                        report.duplicates += 1;
+                        surplus_offs[slot] += 1;
+                        continue;
+                    }
+                    // A note ending on this very tick is not an overlap.
+                    let ends_here = events[i + 1..]
+                        .iter()
+                        .take_while(|e| e.tick() == event.tick())
+                        .any(|e| {
+                            e.is_note_off() && e.channel() == Some(channel) && e.key() == Some(key)
+                        });
+                    if ends_here {
+                        open[slot] += 1;
+                    } else {
+                        report.overlaps += 1;
+                        surplus_offs[slot] += 1;
+                        repaired.push(MidiEvent::note_off(event.tick(), channel, key, 0));
+                    }
+                } else {
+                    open[slot] = 1;
+                }
+                latest[slot] = Some(event.tick());
+            } else if event.is_note_off() && open[slot] > 0 {
+                if surplus_offs[slot] > 0 {
+                    surplus_offs[slot] -= 1;
+                    continue;
+                }
+                open[slot] -= 1;
+            }
+        }
+        let mut event = event.clone();
+        event.unlink_event();
+        repaired.push(event);
+    }
+    replace_events(track, repaired, was_linked);
+    report
+}
+
+impl MidiFile {
+    /// Bake the sustain pedal into note lengths on every track (see
+    /// `MidiTrack::bake_sustain`). Returns the number of note-offs moved.
+    pub fn bake_sustain(&mut self, remove_pedal: bool) -> usize {
+        self.tracks_mut()
+            .iter_mut()
+            .map(|track| track.bake_sustain(remove_pedal))
+            .sum()
+    }
+
+    /// Repair same-key overlaps and duplicate note-ons on every track (see
+    /// `MidiTrack::repair_overlaps`)
+    pub fn repair_overlaps(&mut self) -> OverlapReport {
+        let mut report = OverlapReport::default();
+        for track in self.tracks_mut() {
+            let track_report = track.repair_overlaps();
+            report.overlaps += track_report.overlaps;
+            report.duplicates += track_report.duplicates;
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(track: &MidiTrack) -> Vec<(u64, u64, u8)> {
+        let events = track.events();
+        events
+            .iter()
+            .filter(|e| e.is_note_on())
+            .map(|e| {
+                let off = &events[e.linked_event().unwrap()];
+                (e.tick(), off.tick(), e.key().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_bake_sustain_extends_to_pedal_release() {
+        let mut track = MidiTrack::new();
+        track.add_note(0, 100, 0, 60, 90);
+        track.add_sustain_on(50, 0);
+        track.add_note(120, 100, 0, 64, 90);
+        // Struck again on the tick it is released.
+        track.add_note(220, 80, 0, 64, 90);
+        track.add_sustain_off(480, 0);
+        track.add_note(500, 100, 0, 67, 90);
+        track.add_note(500, 100, 1, 48, 90);
+        track.add_sustain_on(550, 1);
+        // The track goes on past the last note.
+        track.add_controller(720, 2, 7, 100);
+        track.link_note_events();
+
+        let mut kept = track.clone();
+        assert_eq!(kept.bake_sustain(false), 3);
+        assert_eq!(
+            spans(&kept),
+            vec![
+                (0, 480, 60),
+                (120, 220, 64),
+                (220, 480, 64),
+                (500, 600, 67),
+                (500, 720, 48)
+            ]
+        );
+        assert_eq!(
+            kept.events()
+                .iter()
+                .filter(|e| e.message().is_sustain())
+                .count(),
+            3
+        );
+
+        // The channel 1 pedal is never released, so its note rings to the
+        // end of the track.
+        let mut removed = track.clone();
+        removed.bake_sustain(true);
+        assert!(!removed.events().iter().any(|e| e.message().is_sustain()));
+        assert_eq!(spans(&removed), spans(&kept));
+    }
+
+    #[test]
+    fn test_repair_overlaps_and_duplicates() {
+        let mut track = MidiTrack::new();
+        // Re-struck before release.
+        track.add_note(0, 200, 0, 60, 90);
+        track.add_note(100, 200, 0, 60, 90);
+        // Doubled note-on.
+        track.add_note(400, 100, 0, 62, 90);
+        track.add_note(400, 120, 0, 62, 90);
+        // Same key on another channel is independent.
+        track.add_note(50, 100, 1, 60, 90);
+
+        let report = track.repair_overlaps();
+        assert_eq!(
+            report,
+            OverlapReport {
+                overlaps: 1,
+                duplicates: 1
+            }
+        );
+        assert_eq!(report.total(), 2);
+        assert_eq!(
+            spans(&track),
+            vec![(0, 100, 60), (50, 150, 60), (100, 300, 60), (400, 520, 62)]
+        );
+        for event in track.events().iter().filter(|e| e.is_note_on()) {
+            assert!(event.tick_duration(track.events()).unwrap() > 0);
+        }
+        assert_eq!(track.repair_overlaps().total(), 0);
+    }
+}
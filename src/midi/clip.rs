@@ -0,0 +1,458 @@
+//! MIDI Clip Files (SMF2CLIP)
+//!
+//! A MIDI Clip File holds one stream of Universal MIDI Packets timed by
+//! delta clockstamps: the `SMF2CLIP` magic, a header of packets ending
+//! with Start of Clip, then the clip data ending with End of Clip. The
+//! resolution comes from the Delta Clockstamp Ticks Per Quarter Note
+//! message at the start of the header.
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use super::MidiError;
+use super::event::MidiEvent;
+use super::message::{MetaEvent, MidiMessage};
+use super::track::MidiTrack;
+use super::ump::{
+    FLEX_BANK_PERFORMANCE_TEXT, FlexData, MidiToUmp, PacketFormat, STREAM_END_OF_CLIP,
+    STREAM_START_OF_CLIP, Ump, UmpToMidi, Utility,
+};
+
+/// File magic of a MIDI Clip File
+const MAGIC: &[u8; 8] = b"SMF2CLIP";
+/// Largest delta clockstamp (20 bits)
+const MAX_DELTA: u64 = 0xF_FFFF;
+/// Flex Data status of Lyrics in the performance text bank
+const FLEX_LYRICS: u8 = 0x01;
+
+/// Push the delta clockstamp of a `delta` tick gap, split into several
+/// when it does not fit in 20 bits.
+fn push_delta(packets: &mut Vec<Ump>, mut delta: u64) {
+    while delta > MAX_DELTA {
+        packets.push(Ump::Utility(Utility::DeltaClockstamp(MAX_DELTA as u32)));
+        packets.push(Ump::Utility(Utility::Noop));
+        delta -= MAX_DELTA;
+    }
+    packets.push(Ump::Utility(Utility::DeltaClockstamp(delta as u32)));
+}
+
+/// A MIDI Clip File
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiClip {
+    ticks_per_quarter: u16,
+    header: Vec<Ump>,
+    events: Vec<(u64, Ump)>,
+    end_tick: u64,
+}
+
+impl Default for MidiClip {
+    fn default() -> Self {
+        Self::new(480)
+    }
+}
+
+impl MidiClip {
+    /// Create an empty clip
+    pub fn new(ticks_per_quarter: u16) -> Self {
+        Self {
+            ticks_per_quarter: ticks_per_quarter.max(1),
+            header: Vec::new(),
+            events: Vec::new(),
+            end_tick: 0,
+        }
+    }
+
+    /// Delta clockstamp ticks per quarter note
+    pub fn ticks_per_quarter(&self) -> u16 {
+        self.ticks_per_quarter
+    }
+
+    /// Packets of the clip header (between the resolution and Start of
+    /// Clip), such as initial tempo or project metadata
+    pub fn header(&self) -> &[Ump] {
+        &self.header
+    }
+
+    /// Mutable access to the clip header
+    pub fn header_mut(&mut self) -> &mut Vec<Ump> {
+        &mut self.header
+    }
+
+    /// Timed packets of the clip, by absolute tick
+    pub fn events(&self) -> &[(u64, Ump)] {
+        &self.events
+    }
+
+    /// Add a packet at `tick`, after any packets already at that tick
+    pub fn add_event(&mut self, tick: u64, ump: Ump) {
+        let index = self.events.partition_point(|(t, _)| *t <= tick);
+        self.events.insert(index, (tick, ump));
+        self.end_tick = self.end_tick.max(tick);
+    }
+
+    /// Tick of End of Clip
+    pub fn end_tick(&self) -> u64 {
+        self.end_tick
+    }
+
+    /// Move End of Clip; it never goes before the last packet
+    pub fn set_end_tick(&mut self, tick: u64) {
+        let last = self.events.last().map_or(0, |(t, _)| *t);
+        self.end_tick = tick.max(last);
+    }
+
+    /// Read a clip file from disk
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, MidiError> {
+        let file = File::open(path)?;
+        let mut reader = BufReader::new(file);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes(&data)
+    }
+
+    /// Write the clip file to disk
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<(), MidiError> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(&self.to_bytes())?;
+        Ok(())
+    }
+
+    /// Parse a clip file. Delta clockstamps before Start of Clip are
+    /// ignored; no-ops and jitter-reduction timestamps are dropped.
+    pub fn from_bytes(data: &[u8]) -> Result<Self, MidiError> {
+        if data.len() < MAGIC.len() || &data[..MAGIC.len()] != MAGIC {
+            return Err(MidiError::InvalidHeader);
+        }
+        let body = &data[MAGIC.len()..];
+        if !body.len().is_multiple_of(4) {
+            return Err(MidiError::UnexpectedEof);
+        }
+        let words: Vec<u32> = body
+            .chunks(4)
+            .map(|chunk| u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+
+        let mut clip = Self::new(1);
+        let mut resolution = None;
+        let mut started = false;
+        let mut tick = 0u64;
+        let mut rest = words.as_slice();
+        while !rest.is_empty() {
+            let (ump, size) = Ump::from_words(rest).ok_or(MidiError::UnexpectedEof)?;
+            rest = &rest[size..];
+            match ump {
+                Ump::Utility(Utility::DeltaClockstampTpq(tpq)) if !started => {
+                    resolution = Some(tpq);
+                }
+                Ump::Utility(Utility::DeltaClockstamp(delta)) => {
+                    if started {
+                        tick += delta as u64;
+                    }
+                }
+                Ump::Utility(_) => {}
+                Ump::Stream { status, .. } if status == STREAM_START_OF_CLIP && !started => {
+                    started = true;
+                }
+                Ump::Stream { status, .. } if status == STREAM_END_OF_CLIP && started => {
+                    clip.end_tick = tick;
+                    return Ok(clip);
+                }
+                ump if started => clip.events.push((tick, ump)),
+                ump => clip.header.push(ump),
+            }
+            if !started && resolution.is_none() {
+                return Err(MidiError::InvalidHeader);
+            }
+            clip.ticks_per_quarter = resolution.unwrap_or(1).max(1);
+        }
+        Err(if started {
+            MidiError::UnexpectedEof
+        } else {
+            MidiError::InvalidHeader
+        })
+    }
+
+    /// Serialize the clip. Every packet of the clip data is preceded by a
+    /// delta clockstamp; gaps too long for one are split.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut packets = vec![
+            Ump::Utility(Utility::DeltaClockstampTpq(self.ticks_per_quarter)),
+            Ump::Utility(Utility::DeltaClockstamp(0)),
+        ];
+        packets.extend(self.header.iter().cloned());
+        packets.push(Ump::stream(STREAM_START_OF_CLIP));
+        let mut last = 0;
+        for (tick, ump) in &self.events {
+            push_delta(&mut packets, tick.saturating_sub(last));
+            packets.push(ump.clone());
+            last = last.max(*tick);
+        }
+        push_delta(&mut packets, self.end_tick.saturating_sub(last));
+        packets.push(Ump::stream(STREAM_END_OF_CLIP));
+
+        let mut data = MAGIC.to_vec();
+        for ump in &packets {
+            data.extend(ump.to_words().iter().flat_map(|word| word.to_be_bytes()));
+        }
+        data
+    }
+
+    /// Convert a track to a clip of MIDI 2.0 protocol packets on `group`.
+    /// Channel messages are translated with `MidiToUmp` and SysEx becomes
+    /// 7-bit SysEx packets. Tempo, time signature and lyric meta events
+    /// become Flex Data; other meta events are dropped. End of Clip is
+    /// placed at the track's End of Track.
+    pub fn from_track(track: &MidiTrack, ticks_per_quarter: u16, group: u8) -> Self {
+        let mut track = track.clone();
+        if !track.is_absolute_time() {
+            track.make_absolute_times();
+        }
+        let mut clip = Self::new(ticks_per_quarter);
+        let mut translator = MidiToUmp::new(group);
+        for event in track.events() {
+            let tick = event.tick();
+            let packets = match event.message() {
+                MidiMessage::Meta(MetaEvent::Tempo(microseconds)) => vec![Ump::FlexData(
+                    FlexData::set_tempo(group, microseconds.saturating_mul(100)),
+                )],
+                MidiMessage::Meta(MetaEvent::TimeSignature {
+                    numerator,
+                    denominator_power,
+                    notated_32nd_per_quarter,
+                    ..
+                }) => vec![Ump::FlexData(FlexData::set_time_signature(
+                    group,
+                    *numerator,
+                    *denominator_power,
+                    *notated_32nd_per_quarter,
+                ))],
+                MidiMessage::Meta(MetaEvent::Lyric(text)) => {
+                    FlexData::text(group, FLEX_BANK_PERFORMANCE_TEXT, FLEX_LYRICS, text)
+                        .into_iter()
+                        .map(Ump::FlexData)
+                        .collect()
+                }
+                MidiMessage::Meta(_) => Vec::new(),
+                message => translator.translate(message),
+            };
+            for ump in packets {
+                clip.add_event(tick, ump);
+            }
+            clip.end_tick = clip.end_tick.max(tick);
+        }
+        clip
+    }
+
+    /// Convert the clip to a track of MIDI 1.0 messages, the inverse of
+    /// `from_track`. Header packets (such as the initial tempo and time
+    /// signature) are placed at tick 0, and packets with no MIDI 1.0 form
+    /// are dropped. The track ends with End of Track at the clip's End of
+    /// Clip.
+    pub fn to_track(&self) -> MidiTrack {
+        let mut track = MidiTrack::new();
+        let mut translator = UmpToMidi::new();
+        let mut lyric = Vec::new();
+        let header = self.header.iter().map(|ump| (0, ump));
+        let events = self.events.iter().map(|(tick, ump)| (*tick, ump));
+        for (tick, ump) in header.chain(events) {
+            let messages = match ump {
+                Ump::FlexData(flex) => {
+                    if let Some(tempo) = flex.tempo() {
+                        vec![MidiMessage::Meta(MetaEvent::Tempo(tempo / 100))]
+                    } else if let Some((numerator, denominator_power, thirty_seconds)) =
+                        flex.time_signature()
+                    {
+                        vec![MidiMessage::Meta(MetaEvent::TimeSignature {
+                            numerator,
+                            denominator_power,
+                            clocks_per_click: 24,
+                            notated_32nd_per_quarter: thirty_seconds,
+                        })]
+                    } else if flex.bank == FLEX_BANK_PERFORMANCE_TEXT && flex.status == FLEX_LYRICS
+                    {
+                        if matches!(flex.format, PacketFormat::Complete | PacketFormat::Start) {
+                            lyric.clear();
+                        }
+                        lyric.extend(flex.text_bytes());
+                        if matches!(flex.format, PacketFormat::Complete | PacketFormat::End) {
+                            let text = String::from_utf8_lossy(&lyric).into_owned();
+                            vec![MidiMessage::Meta(MetaEvent::Lyric(text))]
+                        } else {
+                            Vec::new()
+                        }
+                    } else {
+                        Vec::new()
+                    }
+                }
+                ump => translator.translate(ump),
+            };
+            for message in messages {
+                track.add_event(MidiEvent::new(tick, message));
+            }
+        }
+        track.add_event(MidiEvent::new(
+            self.end_tick,
+            MidiMessage::Meta(MetaEvent::EndOfTrack),
+        ));
+        track
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::ump::Midi2Message;
+
+    #[test]
+    fn test_clip_round_trip() {
+        let mut clip = MidiClip::new(960);
+        clip.header_mut()
+            .push(Ump::FlexData(FlexData::set_tempo(0, 50_000_000)));
+        clip.add_event(
+            0,
+            Ump::Midi2ChannelVoice {
+                group: 0,
+                channel: 0,
+                message: Midi2Message::NoteOn {
+                    note: 60,
+                    velocity: 0xFFFF,
+                    attribute_type: 0,
+                    attribute: 0,
+                },
+            },
+        );
+        clip.add_event(
+            0,
+            Ump::Midi2ChannelVoice {
+                group: 0,
+                channel: 0,
+                message: Midi2Message::PerNotePitchBend {
+                    note: 60,
+                    value: 0x9000_0000,
+                },
+            },
+        );
+        // Longer than one delta clockstamp can hold.
+        clip.add_event(
+            0x12_3456,
+            Ump::SysEx8 {
+                group: 0,
+                format: PacketFormat::Complete,
+                stream_id: 0,
+                data: vec![1, 2, 3],
+            },
+        );
+        clip.set_end_tick(0x20_0000);
+
+        let bytes = clip.to_bytes();
+        assert_eq!(&bytes[..8], b"SMF2CLIP");
+        let parsed = MidiClip::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, clip);
+
+        assert!(matches!(
+            MidiClip::from_bytes(b"SMF2CLIQ"),
+            Err(MidiError::InvalidHeader)
+        ));
+        assert!(matches!(
+            MidiClip::from_bytes(&bytes[..bytes.len() - 16]),
+            Err(MidiError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_clip_from_and_to_track() {
+        let mut track = MidiTrack::new();
+        track.add_tempo(0, 100.0);
+        track.add_time_signature(0, 3, 4);
+        track.add_meta_event(240, MetaEvent::Lyric("Hello, clip files!".into()));
+        track.add_note(240, 480, 1, 64, 100);
+        track.add_pitch_bend(300, 1, 0x3000);
+        track.add_sysex(600, vec![0x7E, 0x7F, 0x09, 0x01, 0x00, 0x10, 0x20]);
+        track.add_event(MidiEvent::new(
+            960,
+            MidiMessage::Meta(MetaEvent::EndOfTrack),
+        ));
+        track.sort();
+
+        let clip = MidiClip::from_track(&track, 480, 0);
+        assert_eq!(clip.end_tick(), 960);
+        assert!(clip.events().iter().any(|(tick, ump)| *tick == 240
+            && matches!(
+                ump,
+                Ump::Midi2ChannelVoice {
+                    channel: 1,
+                    message: Midi2Message::NoteOn { note: 64, .. },
+                    ..
+                }
+            )));
+
+        let parsed = MidiClip::from_bytes(&clip.to_bytes()).unwrap();
+        let back = parsed.to_track();
+        let original: Vec<_> = track
+            .events()
+            .iter()
+            .map(|e| (e.tick(), e.message().clone()))
+            .collect();
+        let restored: Vec<_> = back
+            .events()
+            .iter()
+            .map(|e| (e.tick(), e.message().clone()))
+            .collect();
+        assert_eq!(restored, original);
+    }
+
+    #[test]
+    fn test_clip_from_unsorted_track() {
+        let mut track = MidiTrack::new();
+        track.add_event(MidiEvent::note_on(480, 0, 62, 100));
+        track.add_event(MidiEvent::note_on(0, 0, 60, 100));
+        track.add_event(MidiEvent::note_off(960, 0, 62, 0));
+        track.add_event(MidiEvent::note_off(240, 0, 60, 0));
+
+        let clip = MidiClip::from_track(&track, 480, 0);
+        let ticks: Vec<_> = clip.events().iter().map(|(tick, _)| *tick).collect();
+        assert_eq!(ticks, vec![0, 240, 480, 960]);
+        assert_eq!(clip.end_tick(), 960);
+
+        let parsed = MidiClip::from_bytes(&clip.to_bytes()).unwrap();
+        assert_eq!(parsed.events(), clip.events());
+    }
+
+    #[test]
+    fn test_clip_header_to_track() {
+        let mut clip = MidiClip::new(480);
+        clip.header_mut()
+            .push(Ump::FlexData(FlexData::set_tempo(0, 50_000_000)));
+        clip.header_mut()
+            .push(Ump::FlexData(FlexData::set_time_signature(0, 6, 3, 8)));
+        clip.add_event(
+            240,
+            Ump::from_midi1(0, &MidiMessage::note_on(0, 60, 100))[0].clone(),
+        );
+
+        let track = clip.to_track();
+        let events: Vec<_> = track
+            .events()
+            .iter()
+            .map(|e| (e.tick(), e.message().clone()))
+            .collect();
+        assert_eq!(
+            events[..2],
+            [
+                (0, MidiMessage::Meta(MetaEvent::Tempo(500_000))),
+                (
+                    0,
+                    MidiMessage::Meta(MetaEvent::TimeSignature {
+                        numerator: 6,
+                        denominator_power: 3,
+                        clocks_per_click: 24,
+                        notated_32nd_per_quarter: 8,
+                    })
+                ),
+            ]
+        );
+        assert_eq!(events[2], (240, MidiMessage::note_on(0, 60, 100)));
+    }
+}
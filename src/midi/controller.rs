@@ -0,0 +1,529 @@
+//! RPN/NRPN and 14-bit controller decoding
+//!
+//! Registered and non-registered parameter numbers are sent as a stream of
+//! plain control changes: CC 101/100 (RPN) or 99/98 (NRPN) select a
+//! parameter, CC 6/38 set its value and CC 96/97 step it. Controllers 0-31
+//! can likewise be paired with 32-63 to carry a 14-bit value. `decode`
+//! folds such raw streams back into logical `ControllerEvent`s.
+
+use std::collections::{HashMap, HashSet};
+
+use super::event::MidiEvent;
+use super::message::MidiMessage;
+
+/// RPN 0: pitch-bend sensitivity (MSB semitones, LSB cents)
+pub const RPN_PITCH_BEND_RANGE: u16 = 0x0000;
+/// RPN 1: channel fine tuning
+pub const RPN_FINE_TUNING: u16 = 0x0001;
+/// RPN 2: channel coarse tuning
+pub const RPN_COARSE_TUNING: u16 = 0x0002;
+/// RPN 127/127: the null parameter, which deselects any RPN/NRPN so that
+/// later data-entry messages are ignored
+pub const RPN_NULL: u16 = 0x3FFF;
+
+const NRPN_LSB: u8 = 98;
+const NRPN_MSB: u8 = 99;
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+const DATA_INCREMENT: u8 = 96;
+const DATA_DECREMENT: u8 = 97;
+
+/// Whether `controller` belongs to the RPN/NRPN mechanism (selection,
+/// data entry or increment/decrement) rather than carrying a value of its
+/// own.
+pub(super) fn is_parameter_controller(controller: u8) -> bool {
+    matches!(
+        controller,
+        DATA_ENTRY_MSB | DATA_ENTRY_LSB | DATA_INCREMENT..=RPN_MSB
+    )
+}
+
+/// A registered or non-registered parameter number
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ParameterNumber {
+    /// Registered parameter (CC 101/100)
+    Registered(u16),
+    /// Non-registered parameter (CC 99/98)
+    NonRegistered(u16),
+}
+
+impl ParameterNumber {
+    /// The 14-bit parameter number
+    pub fn number(&self) -> u16 {
+        match *self {
+            ParameterNumber::Registered(n) | ParameterNumber::NonRegistered(n) => n,
+        }
+    }
+
+    /// Controller numbers of the (MSB, LSB) selection pair
+    fn select_controllers(&self) -> (u8, u8) {
+        match self {
+            ParameterNumber::Registered(_) => (RPN_MSB, RPN_LSB),
+            ParameterNumber::NonRegistered(_) => (NRPN_MSB, NRPN_LSB),
+        }
+    }
+}
+
+/// A logical controller change decoded from one or more control changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControllerEventKind {
+    /// A registered parameter was set via data entry
+    Rpn {
+        /// Parameter number
+        param: u16,
+        /// 14-bit value (data entry MSB << 7 | LSB)
+        value14: u16,
+    },
+    /// A non-registered parameter was set via data entry
+    Nrpn {
+        /// Parameter number
+        param: u16,
+        /// 14-bit value (data entry MSB << 7 | LSB)
+        value14: u16,
+    },
+    /// Data increment (CC 96) applied to the selected parameter
+    DataIncrement {
+        /// Selected parameter
+        parameter: ParameterNumber,
+        /// Raw data byte of the increment message
+        amount: u8,
+    },
+    /// Data decrement (CC 97) applied to the selected parameter
+    DataDecrement {
+        /// Selected parameter
+        parameter: ParameterNumber,
+        /// Raw data byte of the decrement message
+        amount: u8,
+    },
+    /// A 14-bit value on a controller pair (0-31 with 32-63)
+    Cc14 {
+        /// MSB controller number (0-31)
+        controller: u8,
+        /// 14-bit value (MSB << 7 | LSB)
+        value14: u16,
+    },
+}
+
+/// A decoded controller change on one channel
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControllerEvent {
+    /// Tick of the first control change contributing to this event
+    pub tick: u64,
+    /// MIDI channel (0-15)
+    pub channel: u8,
+    /// What was changed
+    pub kind: ControllerEventKind,
+    /// Indices of the contributing control changes in the source event list
+    pub events: Vec<usize>,
+}
+
+/// A data-entry or 14-bit MSB waiting for a possible LSB.
+struct Pending {
+    tick: u64,
+    index: usize,
+    target: Target,
+    msb: u8,
+}
+
+/// What a data-entry or 14-bit MSB/LSB applies to.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Target {
+    Parameter(ParameterNumber),
+    Controller(u8),
+}
+
+impl Target {
+    fn event(self, value14: u16) -> ControllerEventKind {
+        match self {
+            Target::Parameter(ParameterNumber::Registered(param)) => {
+                ControllerEventKind::Rpn { param, value14 }
+            }
+            Target::Parameter(ParameterNumber::NonRegistered(param)) => {
+                ControllerEventKind::Nrpn { param, value14 }
+            }
+            Target::Controller(controller) => ControllerEventKind::Cc14 {
+                controller,
+                value14,
+            },
+        }
+    }
+}
+
+/// Per-channel decoding state.
+struct DecoderState {
+    rpn: (u8, u8),
+    nrpn: (u8, u8),
+    /// Parameter selected by the most recent RPN/NRPN selection, if any.
+    selected: Option<ParameterNumber>,
+    pending: Option<Pending>,
+}
+
+impl Default for DecoderState {
+    fn default() -> Self {
+        // Receivers power up with the null parameter selected.
+        Self {
+            rpn: (127, 127),
+            nrpn: (127, 127),
+            selected: None,
+            pending: None,
+        }
+    }
+}
+
+fn select(pair: (u8, u8), nrpn: bool) -> Option<ParameterNumber> {
+    let number = ((pair.0 as u16) << 7) | pair.1 as u16;
+    match (number, nrpn) {
+        (RPN_NULL, _) => None,
+        (n, false) => Some(ParameterNumber::Registered(n)),
+        (n, true) => Some(ParameterNumber::NonRegistered(n)),
+    }
+}
+
+/// Decode RPN/NRPN and 14-bit controller streams in `events`.
+///
+/// State is kept per channel in event order. Data entry and increment /
+/// decrement are only reported while a parameter is selected; a 127/127
+/// selection (null RPN or NRPN) deselects it. An MSB immediately followed
+/// (on the same channel) by its LSB yields a single event; a lone MSB
+/// implies an LSB of 0, and a lone LSB reuses the last MSB. Controllers
+/// 0-31 are only treated as 14-bit if their LSB controller occurs on that
+/// channel somewhere in `events`, so plain 7-bit volume or pan changes are
+/// not reported.
+pub(super) fn decode(events: &[MidiEvent]) -> Vec<ControllerEvent> {
+    let paired: HashSet<(u8, u8)> = events
+        .iter()
+        .filter_map(|e| match *e.message() {
+            MidiMessage::ControlChange {
+                channel,
+                controller: lsb @ 32..=63,
+                ..
+            } if lsb != DATA_ENTRY_LSB => Some((channel, lsb - 32)),
+            _ => None,
+        })
+        .collect();
+
+    let mut channels: [DecoderState; 16] = Default::default();
+    let mut last_values: HashMap<(u8, Target), u16> = HashMap::new();
+    let mut out = Vec::new();
+
+    let flush = |state: &mut DecoderState,
+                 channel: u8,
+                 last_values: &mut HashMap<(u8, Target), u16>,
+                 out: &mut Vec<ControllerEvent>| {
+        if let Some(pending) = state.pending.take() {
+            let value14 = (pending.msb as u16) << 7;
+            last_values.insert((channel, pending.target), value14);
+            out.push(ControllerEvent {
+                tick: pending.tick,
+                channel,
+                kind: pending.target.event(value14),
+                events: vec![pending.index],
+            });
+        }
+    };
+
+    for (index, event) in events.iter().enumerate() {
+        let Some(channel) = event.channel() else {
+            continue;
+        };
+        let state = &mut channels[(channel & 0x0F) as usize];
+        let (controller, value) = match *event.message() {
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => (controller, value),
+            _ => {
+                flush(state, channel, &mut last_values, &mut out);
+                continue;
+            }
+        };
+
+        // An LSB completing the pending MSB.
+        let lsb_target = match controller {
+            DATA_ENTRY_LSB => state.selected.map(Target::Parameter),
+            32..=63 if paired.contains(&(channel, controller - 32)) => {
+                Some(Target::Controller(controller - 32))
+            }
+            _ => None,
+        };
+        if let (Some(target), Some(pending)) = (lsb_target, state.pending.as_ref())
+            && pending.target == target
+        {
+            let pending = state.pending.take().unwrap();
+            let value14 = ((pending.msb as u16) << 7) | value as u16;
+            last_values.insert((channel, target), value14);
+            out.push(ControllerEvent {
+                tick: pending.tick,
+                channel,
+                kind: target.event(value14),
+                events: vec![pending.index, index],
+            });
+            continue;
+        }
+        flush(state, channel, &mut last_values, &mut out);
+
+        match controller {
+            RPN_MSB | RPN_LSB => {
+                if controller == RPN_MSB {
+                    state.rpn.0 = value;
+                } else {
+                    state.rpn.1 = value;
+                }
+                state.selected = select(state.rpn, false);
+            }
+            NRPN_MSB | NRPN_LSB => {
+                if controller == NRPN_MSB {
+                    state.nrpn.0 = value;
+                } else {
+                    state.nrpn.1 = value;
+                }
+                state.selected = select(state.nrpn, true);
+            }
+            DATA_ENTRY_MSB => {
+                if let Some(parameter) = state.selected {
+                    state.pending = Some(Pending {
+                        tick: event.tick(),
+                        index,
+                        target: Target::Parameter(parameter),
+                        msb: value,
+                    });
+                }
+            }
+            DATA_INCREMENT | DATA_DECREMENT => {
+                if let Some(parameter) = state.selected {
+                    out.push(ControllerEvent {
+                        tick: event.tick(),
+                        channel,
+                        kind: if controller == DATA_INCREMENT {
+                            ControllerEventKind::DataIncrement {
+                                parameter,
+                                amount: value,
+                            }
+                        } else {
+                            ControllerEventKind::DataDecrement {
+                                parameter,
+                                amount: value,
+                            }
+                        },
+                        events: vec![index],
+                    });
+                }
+            }
+            0..=31 if paired.contains(&(channel, controller)) => {
+                state.pending = Some(Pending {
+                    tick: event.tick(),
+                    index,
+                    target: Target::Controller(controller),
+                    msb: value,
+                });
+            }
+            _ => {
+                // A lone LSB keeps the last MSB of its parameter/controller.
+                if let Some(target) = lsb_target {
+                    let msb = last_values.get(&(channel, target)).copied().unwrap_or(0) >> 7;
+                    let value14 = (msb << 7) | value as u16;
+                    last_values.insert((channel, target), value14);
+                    out.push(ControllerEvent {
+                        tick: event.tick(),
+                        channel,
+                        kind: target.event(value14),
+                        events: vec![index],
+                    });
+                }
+            }
+        }
+    }
+
+    for (channel, state) in channels.iter_mut().enumerate() {
+        flush(state, channel as u8, &mut last_values, &mut out);
+    }
+    out.sort_by_key(|e| (e.tick, e.events[0]));
+    out
+}
+
+/// Control changes (controller, value) that encode `kind`. Parameter
+/// changes select the parameter, apply the change and finish with a null
+/// RPN so stray data-entry messages cannot alter it afterwards.
+pub(super) fn encode(kind: &ControllerEventKind) -> Vec<(u8, u8)> {
+    let split = |value14: u16| (((value14 >> 7) & 0x7F) as u8, (value14 & 0x7F) as u8);
+    let selected = |parameter: ParameterNumber, body: &[(u8, u8)]| {
+        let (msb_cc, lsb_cc) = parameter.select_controllers();
+        let (msb, lsb) = split(parameter.number());
+        let mut ccs = vec![(msb_cc, msb), (lsb_cc, lsb)];
+        ccs.extend_from_slice(body);
+        ccs.extend([(RPN_MSB, 127), (RPN_LSB, 127)]);
+        ccs
+    };
+
+    match *kind {
+        ControllerEventKind::Rpn { param, value14 } => {
+            let (msb, lsb) = split(value14);
+            selected(
+                ParameterNumber::Registered(param),
+                &[(DATA_ENTRY_MSB, msb), (DATA_ENTRY_LSB, lsb)],
+            )
+        }
+        ControllerEventKind::Nrpn { param, value14 } => {
+            let (msb, lsb) = split(value14);
+            selected(
+                ParameterNumber::NonRegistered(param),
+                &[(DATA_ENTRY_MSB, msb), (DATA_ENTRY_LSB, lsb)],
+            )
+        }
+        ControllerEventKind::DataIncrement { parameter, amount } => {
+            selected(parameter, &[(DATA_INCREMENT, amount & 0x7F)])
+        }
+        ControllerEventKind::DataDecrement { parameter, amount } => {
+            selected(parameter, &[(DATA_DECREMENT, amount & 0x7F)])
+        }
+        ControllerEventKind::Cc14 {
+            controller,
+            value14,
+        } => {
+            let (msb, lsb) = split(value14);
+            let controller = controller & 0x1F;
+            vec![(controller, msb), (controller + 32, lsb)]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::{MidiFile, MidiTrack};
+
+    #[test]
+    fn test_decode_rpn_nrpn_and_null_reset() {
+        let mut track = MidiTrack::new();
+        track.add_pitch_bend_range(0, 1, 12, 50);
+        // Data entry after the null RPN must be ignored.
+        track.add_controller(10, 1, 6, 99);
+        track.add_nrpn(20, 1, 0x0123, 0x2001);
+        track.add_data_increment(30, 1, ParameterNumber::NonRegistered(5), 1);
+        // Selection left open: MSB-only entry, then a lone LSB.
+        track.add_controller(40, 2, 99, 0);
+        track.add_controller(40, 2, 98, 7);
+        track.add_controller(40, 2, 6, 3);
+        track.add_note(45, 10, 2, 60, 100);
+        track.add_controller(50, 2, 38, 9);
+        track.sort();
+
+        let kinds: Vec<(u64, u8, ControllerEventKind)> = track
+            .decode_controllers()
+            .into_iter()
+            .map(|e| (e.tick, e.channel, e.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    0,
+                    1,
+                    ControllerEventKind::Rpn {
+                        param: RPN_PITCH_BEND_RANGE,
+                        value14: (12 << 7) | 50
+                    }
+                ),
+                (
+                    20,
+                    1,
+                    ControllerEventKind::Nrpn {
+                        param: 0x0123,
+                        value14: 0x2001
+                    }
+                ),
+                (
+                    30,
+                    1,
+                    ControllerEventKind::DataIncrement {
+                        parameter: ParameterNumber::NonRegistered(5),
+                        amount: 1
+                    }
+                ),
+                (
+                    40,
+                    2,
+                    ControllerEventKind::Nrpn {
+                        param: 7,
+                        value14: 3 << 7
+                    }
+                ),
+                (
+                    50,
+                    2,
+                    ControllerEventKind::Nrpn {
+                        param: 7,
+                        value14: (3 << 7) | 9
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_decode_cc14_only_for_paired_controllers() {
+        let mut track = MidiTrack::new();
+        track.add_controller(0, 0, 7, 100); // 7-bit volume, no LSB anywhere
+        track.add_cc14(0, 0, 1, 0x1234);
+        track.add_controller(10, 0, 1, 5); // lone MSB: LSB implied 0
+        track.add_pitch_bend(15, 0, 8192);
+        track.add_controller(20, 0, 33, 6); // lone LSB keeps MSB 5
+
+        let events = track.decode_controllers();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].events, vec![1, 2]);
+        assert_eq!(
+            events.iter().map(|e| e.kind.clone()).collect::<Vec<_>>(),
+            vec![
+                ControllerEventKind::Cc14 {
+                    controller: 1,
+                    value14: 0x1234
+                },
+                ControllerEventKind::Cc14 {
+                    controller: 1,
+                    value14: 5 << 7
+                },
+                ControllerEventKind::Cc14 {
+                    controller: 1,
+                    value14: (5 << 7) | 6
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_writers_roundtrip_through_file() {
+        let kinds = vec![
+            ControllerEventKind::Rpn {
+                param: RPN_COARSE_TUNING,
+                value14: 0x2000,
+            },
+            ControllerEventKind::Nrpn {
+                param: 0x3F00,
+                value14: 0x3FFF,
+            },
+            ControllerEventKind::DataDecrement {
+                parameter: ParameterNumber::Registered(RPN_FINE_TUNING),
+                amount: 0,
+            },
+            ControllerEventKind::Cc14 {
+                controller: 11,
+                value14: 0x0ABC,
+            },
+        ];
+
+        let mut file = MidiFile::new();
+        file.add_track();
+        for (i, kind) in kinds.iter().enumerate() {
+            file.add_controller_event(0, i as u64 * 100, 3, kind)
+                .unwrap();
+        }
+        let parsed = MidiFile::from_bytes(&file.to_bytes()).unwrap();
+        let decoded: Vec<ControllerEventKind> = parsed.decode_controllers()[0]
+            .iter()
+            .map(|e| e.kind.clone())
+            .collect();
+        assert_eq!(decoded, kinds);
+    }
+}
@@ -0,0 +1,703 @@
+//! midicsv text format
+//!
+//! Converts a `MidiFile` to and from the text format of the `midicsv` and
+//! `csvmidi` tools: one `track, tick, Type, arguments...` record per line,
+//! with a `Header` record first, `Start_track`/`End_track` around each
+//! track and `End_of_file` last. Ticks are absolute and tracks are numbered
+//! from 1.
+
+use std::fs;
+use std::path::Path;
+
+use super::event::MidiEvent;
+use super::file::{MidiFile, decode_sysex, write_varlen};
+use super::message::{MetaEvent, MidiMessage};
+use super::sysex::SysExFraming;
+use super::{MidiError, MidiFormat, TimeDivision};
+
+/// Append a quoted string field, escaping quotes, backslashes and any byte
+/// outside printable ASCII the way midicsv does.
+fn push_quoted(line: &mut String, text: &str) {
+    line.push('"');
+    for &byte in text.as_bytes() {
+        match byte {
+            b'"' => line.push_str("\"\""),
+            b'\\' => line.push_str("\\\\"),
+            0x20..=0x7E => line.push(byte as char),
+            _ => line.push_str(&format!("\\{byte:03o}")),
+        }
+    }
+    line.push('"');
+}
+
+fn push_bytes(line: &mut String, bytes: &[u8]) {
+    line.push_str(&format!(", {}", bytes.len()));
+    for byte in bytes {
+        line.push_str(&format!(", {byte}"));
+    }
+}
+
+/// midicsv record for one event, without the track and tick fields, or
+/// `None` for system common and real-time messages, which midicsv has no
+/// record for.
+fn record(event: &MidiEvent) -> Option<String> {
+    let mut line = String::new();
+    match event.message() {
+        MidiMessage::NoteOff {
+            channel,
+            key,
+            velocity,
+        } => line = format!("Note_off_c, {channel}, {key}, {velocity}"),
+        MidiMessage::NoteOn {
+            channel,
+            key,
+            velocity,
+        } => line = format!("Note_on_c, {channel}, {key}, {velocity}"),
+        MidiMessage::PolyPressure {
+            channel,
+            key,
+            pressure,
+        } => line = format!("Poly_aftertouch_c, {channel}, {key}, {pressure}"),
+        MidiMessage::ControlChange {
+            channel,
+            controller,
+            value,
+        } => line = format!("Control_c, {channel}, {controller}, {value}"),
+        MidiMessage::ProgramChange { channel, program } => {
+            line = format!("Program_c, {channel}, {program}")
+        }
+        MidiMessage::ChannelPressure { channel, pressure } => {
+            line = format!("Channel_aftertouch_c, {channel}, {pressure}")
+        }
+        MidiMessage::PitchBend { channel, value } => {
+            line = format!("Pitch_bend_c, {channel}, {value}")
+        }
+        MidiMessage::SysEx(payload) => {
+            // As stored in the file: the payload and, unless the packet is
+            // unterminated, the closing F7.
+            let framing = event.sysex_framing().unwrap_or(SysExFraming::COMPLETE);
+            let mut bytes = payload.clone();
+            if framing.terminated {
+                bytes.push(0xF7);
+            }
+            line.push_str(if framing.escape {
+                "System_exclusive_packet"
+            } else {
+                "System_exclusive"
+            });
+            push_bytes(&mut line, &bytes);
+        }
+        MidiMessage::Meta(meta) => {
+            let text = |line: &mut String, name: &str, text: &str| {
+                line.push_str(name);
+                line.push_str(", ");
+                push_quoted(line, text);
+            };
+            match meta {
+                MetaEvent::SequenceNumber(number) => line = format!("Sequence_number, {number}"),
+                MetaEvent::Text(s) => text(&mut line, "Text_t", s),
+                MetaEvent::Copyright(s) => text(&mut line, "Copyright_t", s),
+                MetaEvent::TrackName(s) => text(&mut line, "Title_t", s),
+                MetaEvent::InstrumentName(s) => text(&mut line, "Instrument_name_t", s),
+                MetaEvent::Lyric(s) => text(&mut line, "Lyric_t", s),
+                MetaEvent::Marker(s) => text(&mut line, "Marker_t", s),
+                MetaEvent::CuePoint(s) => text(&mut line, "Cue_point_t", s),
+                MetaEvent::ChannelPrefix(channel) => line = format!("Channel_prefix, {channel}"),
+                MetaEvent::MidiPort(port) => line = format!("MIDI_port, {port}"),
+                MetaEvent::EndOfTrack => line.push_str("End_track"),
+                MetaEvent::Tempo(us) => line = format!("Tempo, {us}"),
+                MetaEvent::SmpteOffset {
+                    hours,
+                    minutes,
+                    seconds,
+                    frames,
+                    subframes,
+                } => {
+                    line = format!(
+                        "SMPTE_offset, {hours}, {minutes}, {seconds}, {frames}, {subframes}"
+                    )
+                }
+                MetaEvent::TimeSignature {
+                    numerator,
+                    denominator_power,
+                    clocks_per_click,
+                    notated_32nd_per_quarter,
+                } => {
+                    line = format!(
+                        "Time_signature, {numerator}, {denominator_power}, {clocks_per_click}, {notated_32nd_per_quarter}"
+                    )
+                }
+                MetaEvent::KeySignature {
+                    sharps_flats,
+                    minor,
+                } => {
+                    let mode = if *minor { "minor" } else { "major" };
+                    line = format!("Key_signature, {sharps_flats}, \"{mode}\"");
+                }
+                MetaEvent::SequencerSpecific(data) => {
+                    line.push_str("Sequencer_specific");
+                    push_bytes(&mut line, data);
+                }
+                // midicsv has no records of its own for these.
+                MetaEvent::ProgramName(s) | MetaEvent::DeviceName(s) => {
+                    line = format!("Unknown_meta_event, {}", meta.type_byte());
+                    push_bytes(&mut line, s.as_bytes());
+                }
+                MetaEvent::Unknown { type_, data } => {
+                    line = format!("Unknown_meta_event, {type_}");
+                    push_bytes(&mut line, data);
+                }
+            }
+        }
+        _ => return None,
+    }
+    Some(line)
+}
+
+/// One comma-separated field: plain text, or the decoded bytes of a
+/// quoted string.
+enum Field {
+    Plain(String),
+    Quoted(Vec<u8>),
+}
+
+fn split_fields(line: &str) -> Result<Vec<Field>, String> {
+    let bytes = line.as_bytes();
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    loop {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos < bytes.len() && bytes[pos] == b'"' {
+            pos += 1;
+            let mut text = Vec::new();
+            loop {
+                match bytes.get(pos) {
+                    None => return Err("unterminated string".into()),
+                    Some(b'"') if bytes.get(pos + 1) == Some(&b'"') => {
+                        text.push(b'"');
+                        pos += 2;
+                    }
+                    Some(b'"') => {
+                        pos += 1;
+                        break;
+                    }
+                    Some(b'\\') => {
+                        let digits = bytes[pos + 1..]
+                            .iter()
+                            .take(3)
+                            .take_while(|b| (b'0'..=b'7').contains(b))
+                            .count();
+                        if digits > 0 {
+                            let octal = &line[pos + 1..pos + 1 + digits];
+                            let value = u32::from_str_radix(octal, 8).unwrap_or(0);
+                            text.push(value as u8);
+                            pos += 1 + digits;
+                        } else {
+                            text.push(bytes.get(pos + 1).copied().unwrap_or(b'\\'));
+                            pos += 2;
+                        }
+                    }
+                    Some(&byte) => {
+                        text.push(byte);
+                        pos += 1;
+                    }
+                }
+            }
+            while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            fields.push(Field::Quoted(text));
+        } else {
+            let end = line[pos..].find(',').map_or(line.len(), |i| pos + i);
+            fields.push(Field::Plain(line[pos..end].trim().to_string()));
+            pos = end;
+        }
+        match bytes.get(pos) {
+            None => return Ok(fields),
+            Some(b',') => pos += 1,
+            Some(_) => return Err("expected ',' after string".into()),
+        }
+    }
+}
+
+/// Typed access to the fields of one record.
+struct Record {
+    fields: Vec<Field>,
+}
+
+impl Record {
+    fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    fn name(&self) -> Result<&str, String> {
+        match self.fields.get(2) {
+            Some(Field::Plain(name)) => Ok(name),
+            _ => Err("missing record type".into()),
+        }
+    }
+
+    fn number<T: std::str::FromStr>(&self, index: usize) -> Result<T, String> {
+        match self.fields.get(index) {
+            Some(Field::Plain(text)) => text
+                .parse()
+                .map_err(|_| format!("field {} is not a valid number: {text:?}", index + 1)),
+            _ => Err(format!("missing numeric field {}", index + 1)),
+        }
+    }
+
+    fn string(&self, index: usize) -> Result<String, String> {
+        match self.fields.get(index) {
+            Some(Field::Quoted(bytes)) => Ok(String::from_utf8_lossy(bytes).into_owned()),
+            Some(Field::Plain(text)) => Ok(text.clone()),
+            None => Err(format!("missing string field {}", index + 1)),
+        }
+    }
+
+    /// A `length, byte, byte, ...` list starting at `index`.
+    fn bytes(&self, index: usize) -> Result<Vec<u8>, String> {
+        let length: usize = self.number(index)?;
+        if self.len() != index + 1 + length {
+            return Err(format!(
+                "expected {length} data bytes, found {}",
+                self.len().saturating_sub(index + 1)
+            ));
+        }
+        (index + 1..index + 1 + length)
+            .map(|i| self.number(i))
+            .collect()
+    }
+
+    fn expect_fields(&self, count: usize) -> Result<(), String> {
+        if self.len() == count {
+            Ok(())
+        } else {
+            Err(format!("expected {count} fields, found {}", self.len()))
+        }
+    }
+}
+
+/// Parse the message of one track record and, for a SysEx record, the
+/// framing of its packet.
+fn parse_event(record: &Record) -> Result<(MidiMessage, Option<SysExFraming>), String> {
+    // The bytes are the packet as stored after its length, so the framing
+    // is decoded as the file reader would.
+    match record.name()? {
+        "System_exclusive" => Ok(decode_sysex(0xF0, &record.bytes(3)?)),
+        "System_exclusive_packet" => Ok(decode_sysex(0xF7, &record.bytes(3)?)),
+        _ => Ok((parse_message(record)?, None)),
+    }
+}
+
+/// Parse the message of one track record other than SysEx.
+fn parse_message(record: &Record) -> Result<MidiMessage, String> {
+    let name = record.name()?;
+    let n = |i| record.number::<u8>(i);
+    let channel = || -> Result<u8, String> {
+        let channel = n(3)?;
+        if channel > 15 {
+            return Err(format!("channel {channel} out of range"));
+        }
+        Ok(channel)
+    };
+    let data = |i| -> Result<u8, String> {
+        let value = n(i)?;
+        if value > 0x7F {
+            return Err(format!("data byte {value} out of range"));
+        }
+        Ok(value)
+    };
+    let meta = |meta: MetaEvent| Ok(MidiMessage::Meta(meta));
+    let fields = |count| record.expect_fields(count);
+
+    match name {
+        "Note_off_c" => {
+            fields(6)?;
+            Ok(MidiMessage::NoteOff {
+                channel: channel()?,
+                key: data(4)?,
+                velocity: data(5)?,
+            })
+        }
+        "Note_on_c" => {
+            fields(6)?;
+            Ok(MidiMessage::NoteOn {
+                channel: channel()?,
+                key: data(4)?,
+                velocity: data(5)?,
+            })
+        }
+        "Poly_aftertouch_c" => {
+            fields(6)?;
+            Ok(MidiMessage::PolyPressure {
+                channel: channel()?,
+                key: data(4)?,
+                pressure: data(5)?,
+            })
+        }
+        "Control_c" => {
+            fields(6)?;
+            Ok(MidiMessage::ControlChange {
+                channel: channel()?,
+                controller: data(4)?,
+                value: data(5)?,
+            })
+        }
+        "Program_c" => {
+            fields(5)?;
+            Ok(MidiMessage::ProgramChange {
+                channel: channel()?,
+                program: data(4)?,
+            })
+        }
+        "Channel_aftertouch_c" => {
+            fields(5)?;
+            Ok(MidiMessage::ChannelPressure {
+                channel: channel()?,
+                pressure: data(4)?,
+            })
+        }
+        "Pitch_bend_c" => {
+            fields(5)?;
+            let value: u16 = record.number(4)?;
+            if value > 0x3FFF {
+                return Err(format!("pitch bend {value} out of range"));
+            }
+            Ok(MidiMessage::PitchBend {
+                channel: channel()?,
+                value,
+            })
+        }
+        "Sequence_number" => {
+            fields(4)?;
+            meta(MetaEvent::SequenceNumber(record.number(3)?))
+        }
+        "Text_t" => meta(MetaEvent::Text(record.string(3)?)),
+        "Copyright_t" => meta(MetaEvent::Copyright(record.string(3)?)),
+        "Title_t" => meta(MetaEvent::TrackName(record.string(3)?)),
+        "Instrument_name_t" => meta(MetaEvent::InstrumentName(record.string(3)?)),
+        "Lyric_t" => meta(MetaEvent::Lyric(record.string(3)?)),
+        "Marker_t" => meta(MetaEvent::Marker(record.string(3)?)),
+        "Cue_point_t" => meta(MetaEvent::CuePoint(record.string(3)?)),
+        "Channel_prefix" => {
+            fields(4)?;
+            meta(MetaEvent::ChannelPrefix(n(3)?))
+        }
+        "MIDI_port" => {
+            fields(4)?;
+            meta(MetaEvent::MidiPort(n(3)?))
+        }
+        "End_track" => meta(MetaEvent::EndOfTrack),
+        "Tempo" => {
+            fields(4)?;
+            meta(MetaEvent::Tempo(record.number(3)?))
+        }
+        "SMPTE_offset" => {
+            fields(8)?;
+            meta(MetaEvent::SmpteOffset {
+                hours: n(3)?,
+                minutes: n(4)?,
+                seconds: n(5)?,
+                frames: n(6)?,
+                subframes: n(7)?,
+            })
+        }
+        "Time_signature" => {
+            fields(7)?;
+            meta(MetaEvent::TimeSignature {
+                numerator: n(3)?,
+                denominator_power: n(4)?,
+                clocks_per_click: n(5)?,
+                notated_32nd_per_quarter: n(6)?,
+            })
+        }
+        "Key_signature" => {
+            fields(5)?;
+            let minor = match record.string(4)?.to_ascii_lowercase().as_str() {
+                "major" => false,
+                "minor" => true,
+                other => return Err(format!("unknown key mode {other:?}")),
+            };
+            meta(MetaEvent::KeySignature {
+                sharps_flats: record.number(3)?,
+                minor,
+            })
+        }
+        "Sequencer_specific" => meta(MetaEvent::SequencerSpecific(record.bytes(3)?)),
+        "Unknown_meta_event" => {
+            let type_: u8 = record.number(3)?;
+            let data = record.bytes(4)?;
+            // Decode as the file reader would, so types without a midicsv
+            // record of their own (e.g. program names) come back typed.
+            let mut encoded = vec![type_];
+            encoded.extend(write_varlen(data.len() as u32));
+            encoded.extend(&data);
+            let (event, _) =
+                MetaEvent::from_bytes(&encoded).ok_or_else(|| "invalid meta event".to_string())?;
+            meta(event)
+        }
+        other => Err(format!("unknown record type {other:?}")),
+    }
+}
+
+fn csv_error(line: usize, reason: impl Into<String>) -> MidiError {
+    MidiError::InvalidCsv {
+        line,
+        reason: reason.into(),
+    }
+}
+
+impl MidiFile {
+    /// Convert the file to midicsv text. Events are written in the order
+    /// `to_bytes` writes them, so `from_csv` followed by `to_bytes` gives
+    /// the same bytes as `to_bytes` on this file. System common and
+    /// real-time messages have no midicsv record and give
+    /// `MidiError::UnsupportedCsvEvent`.
+    pub fn to_csv(&self) -> Result<String, MidiError> {
+        let mut out = format!(
+            "0, 0, Header, {}, {}, {}\n",
+            self.format() as u16,
+            self.num_tracks(),
+            self.time_division().to_u16()
+        );
+        for (index, track) in self.tracks().iter().enumerate() {
+            let number = index + 1;
+            out.push_str(&format!("{number}, 0, Start_track\n"));
+            let mut events: Vec<MidiEvent> = track.events().to_vec();
+            events.sort();
+            for event in &events {
+                let record = record(event).ok_or_else(|| MidiError::UnsupportedCsvEvent {
+                    track: index,
+                    tick: event.tick(),
+                    status: event.message().status_byte().unwrap_or(0),
+                })?;
+                out.push_str(&format!("{number}, {}, {record}\n", event.tick()));
+            }
+        }
+        out.push_str("0, 0, End_of_file\n");
+        Ok(out)
+    }
+
+    /// Parse midicsv text. Blank lines and lines starting with `#` or `;`
+    /// are ignored. Events keep the order they appear in (as with
+    /// `from_bytes`, via `mark_sequence`).
+    pub fn from_csv(text: &str) -> Result<Self, MidiError> {
+        let mut file: Option<MidiFile> = None;
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with(';') {
+                continue;
+            }
+            let record = Record {
+                fields: split_fields(trimmed).map_err(|e| csv_error(line_number, e))?,
+            };
+            let track: usize = record.number(0).map_err(|e| csv_error(line_number, e))?;
+            let tick: u64 = record.number(1).map_err(|e| csv_error(line_number, e))?;
+            let name = record.name().map_err(|e| csv_error(line_number, e))?;
+
+            match name {
+                "Header" => {
+                    if file.is_some() {
+                        return Err(csv_error(line_number, "duplicate header"));
+                    }
+                    record
+                        .expect_fields(6)
+                        .map_err(|e| csv_error(line_number, e))?;
+                    let parse = |i| {
+                        record
+                            .number::<u16>(i)
+                            .map_err(|e| csv_error(line_number, e))
+                    };
+                    let mut header = MidiFile::with_format(MidiFormat::try_from(parse(3)?)?, 480);
+                    header.set_time_division(TimeDivision::try_from(parse(5)?)?);
+                    header.add_tracks(parse(4)? as usize);
+                    file = Some(header);
+                }
+                "End_of_file" => break,
+                _ => {
+                    let Some(file) = file.as_mut() else {
+                        return Err(csv_error(line_number, "record before header"));
+                    };
+                    if track == 0 {
+                        return Err(csv_error(line_number, "track numbers start at 1"));
+                    }
+                    if name == "Start_track" {
+                        if track > file.num_tracks() {
+                            file.add_tracks(track - file.num_tracks());
+                        }
+                        continue;
+                    }
+                    let (message, framing) =
+                        parse_event(&record).map_err(|e| csv_error(line_number, e))?;
+                    let target = file
+                        .tracks_mut()
+                        .get_mut(track - 1)
+                        .ok_or_else(|| csv_error(line_number, "track missing from header"))?;
+                    let mut event = MidiEvent::new(tick, message);
+                    event.set_sysex_framing(framing);
+                    target.add_event(event);
+                }
+            }
+        }
+
+        let mut file = file.ok_or_else(|| csv_error(0, "missing header"))?;
+        for track in file.tracks_mut() {
+            track.mark_sequence();
+        }
+        Ok(file)
+    }
+
+    /// Write the file to disk as midicsv text
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> Result<(), MidiError> {
+        fs::write(path, self.to_csv()?)?;
+        Ok(())
+    }
+
+    /// Read a midicsv text file from disk
+    pub fn read_csv(path: impl AsRef<Path>) -> Result<Self, MidiError> {
+        Self::from_csv(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> MidiFile {
+        let mut file = MidiFile::new();
+        file.add_tracks(2);
+        file.add_tempo(0, 0, 100.0).unwrap();
+        file.add_time_signature(0, 0, 6, 8).unwrap();
+        file.add_key_signature(0, 0, -3, true).unwrap();
+        file.add_meta_event(
+            0,
+            0,
+            MetaEvent::SmpteOffset {
+                hours: 1,
+                minutes: 2,
+                seconds: 3,
+                frames: 4,
+                subframes: 5,
+            },
+        )
+        .unwrap();
+        file.add_track_name(1, 0, "Lead \"synth\", v2\\café")
+            .unwrap();
+        file.add_meta_event(1, 0, MetaEvent::ProgramName("Pad\n".into()))
+            .unwrap();
+        file.add_meta_event(
+            1,
+            0,
+            MetaEvent::Unknown {
+                type_: 0x60,
+                data: vec![1, 2, 3],
+            },
+        )
+        .unwrap();
+        file.add_note(1, 0, 480, 3, 60, 100).unwrap();
+        file.add_pitch_bend(1, 240, 3, 0x3FFF).unwrap();
+        file.add_controller(1, 120, 3, 7, 90).unwrap();
+        let track = &mut file.tracks_mut()[1];
+        track.add_sysex(0, vec![0x7E, 0x7F, 0x09, 0x01]);
+        for (data, escape, terminated) in
+            [(vec![0x43, 0x12], false, false), (vec![0x01], true, true)]
+        {
+            let mut packet = MidiEvent::new(60, MidiMessage::SysEx(data));
+            packet.set_sysex_framing(Some(SysExFraming { escape, terminated }));
+            track.add_event(packet);
+        }
+        track.add_event(MidiEvent::new(480, MidiMessage::note_on(3, 64, 0)));
+        track.add_event(MidiEvent::new(
+            300,
+            MidiMessage::PolyPressure {
+                channel: 3,
+                key: 60,
+                pressure: 20,
+            },
+        ));
+        file.finalize();
+        file
+    }
+
+    #[test]
+    fn test_csv_records() {
+        let csv = sample().to_csv().unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], "0, 0, Header, 1, 2, 480");
+        assert_eq!(lines[1], "1, 0, Start_track");
+        assert!(lines.contains(&"1, 0, Tempo, 600000"));
+        assert!(lines.contains(&"1, 0, Time_signature, 6, 3, 24, 8"));
+        assert!(lines.contains(&"1, 0, Key_signature, -3, \"minor\""));
+        assert!(lines.contains(&"2, 0, Title_t, \"Lead \"\"synth\"\", v2\\\\caf\\303\\251\""));
+        assert!(lines.contains(&"2, 0, Unknown_meta_event, 8, 4, 80, 97, 100, 10"));
+        assert!(lines.contains(&"2, 0, System_exclusive, 5, 126, 127, 9, 1, 247"));
+        assert!(lines.contains(&"2, 60, System_exclusive, 2, 67, 18"));
+        assert!(lines.contains(&"2, 60, System_exclusive_packet, 2, 1, 247"));
+        assert!(lines.contains(&"2, 0, Note_on_c, 3, 60, 100"));
+        assert!(lines.contains(&"2, 240, Pitch_bend_c, 3, 16383"));
+        assert!(lines.contains(&"2, 480, Note_on_c, 3, 64, 0"));
+        assert_eq!(lines.last(), Some(&"0, 0, End_of_file"));
+    }
+
+    #[test]
+    fn test_csv_round_trip_is_exact() {
+        let file = sample();
+        let parsed = MidiFile::from_csv(&file.to_csv().unwrap()).unwrap();
+        assert_eq!(parsed.to_bytes(), file.to_bytes());
+        assert_eq!(parsed.to_csv().unwrap(), file.to_csv().unwrap());
+        assert!(parsed.tracks()[1].events().iter().any(|e| e.message()
+            == &MidiMessage::Meta(MetaEvent::TrackName("Lead \"synth\", v2\\café".into()))));
+
+        let reread = MidiFile::from_bytes(&file.to_bytes()).unwrap();
+        assert_eq!(
+            MidiFile::from_csv(&reread.to_csv().unwrap())
+                .unwrap()
+                .to_bytes(),
+            file.to_bytes()
+        );
+    }
+
+    #[test]
+    fn test_csv_errors() {
+        let error = |text: &str| match MidiFile::from_csv(text) {
+            Err(MidiError::InvalidCsv { line, .. }) => line,
+            other => panic!("expected a CSV error, got {other:?}"),
+        };
+        assert_eq!(error("1, 0, Start_track\n"), 1);
+        assert_eq!(
+            error("0, 0, Header, 1, 1, 96\n1, 0, Note_on_c, 16, 60, 1\n"),
+            2
+        );
+        assert_eq!(error("0, 0, Header, 1, 1, 96\n\n1, 0, Bogus\n"), 3);
+        assert_eq!(error("0, 0, Header, 1, 1, 96\n1, 0, Text_t, \"open\n"), 2);
+        assert_eq!(
+            error("0, 0, Header, 1, 1, 96\n1, 0, Note_on_c, 0, 200, 1\n"),
+            2
+        );
+        assert_eq!(
+            error("0, 0, Header, 1, 1, 96\n1, 0, Pitch_bend_c, 0, 16384\n"),
+            2
+        );
+
+        let mut file = sample();
+        file.tracks_mut()[1].add_event(MidiEvent::new(100, MidiMessage::TimingClock));
+        assert!(matches!(
+            file.to_csv(),
+            Err(MidiError::UnsupportedCsvEvent {
+                track: 1,
+                tick: 100,
+                status: 0xF8
+            })
+        ));
+
+        let file = MidiFile::from_csv(
+            "# comment\n0, 0, Header, 0, 1, 96\n1, 0, Start_track\n1, 10, Control_c, 0, 64, 127\n",
+        )
+        .unwrap();
+        assert_eq!(file.ticks_per_quarter(), 96);
+        assert_eq!(file.tracks()[0].events()[0].tick(), 10);
+    }
+}
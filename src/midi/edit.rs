@@ -0,0 +1,577 @@
+//! Tick-range editing of MIDI files
+//!
+//! Extracting, deleting, opening up and splicing ranges of a `MidiFile`.
+//! Notes are handled as linked on/off pairs (see
+//! `MidiTrack::link_note_events`) so a note crossing a range boundary can be
+//! clipped or kept whole, and the tempo, meter, key and controller state in
+//! effect at a cut is carried over so the result plays back the same.
+
+use super::chase::ChannelState;
+use super::event::MidiEvent;
+use super::file::MidiFile;
+use super::message::{MetaEvent, MidiMessage};
+use super::track::MidiTrack;
+
+/// How notes crossing a range boundary are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BoundaryNotes {
+    /// Clip notes to the part that survives the edit. A note already
+    /// sounding at the start of an extracted range is re-struck at tick 0.
+    #[default]
+    Truncate,
+    /// Keep a note at its full length if its note-on survives the edit, and
+    /// drop it entirely otherwise
+    KeepWhole,
+}
+
+/// The meta events carried across a cut: tempo, time signature and key
+/// signature, in that order.
+const TIMING_KINDS: usize = 3;
+
+fn timing_kind(message: &MidiMessage) -> Option<usize> {
+    match message {
+        MidiMessage::Meta(MetaEvent::Tempo(_)) => Some(0),
+        MidiMessage::Meta(MetaEvent::TimeSignature { .. }) => Some(1),
+        MidiMessage::Meta(MetaEvent::KeySignature { .. }) => Some(2),
+        _ => None,
+    }
+}
+
+/// Track-level identity metas carried into an extracted range.
+fn is_track_setup(message: &MidiMessage) -> bool {
+    matches!(
+        message,
+        MidiMessage::Meta(
+            MetaEvent::TrackName(_) | MetaEvent::InstrumentName(_) | MetaEvent::MidiPort(_)
+        )
+    )
+}
+
+fn is_end_of_track(event: &MidiEvent) -> bool {
+    matches!(event.message(), MidiMessage::Meta(MetaEvent::EndOfTrack))
+}
+
+/// The last tempo, time signature and key signature strictly before `tick`
+/// across all tracks.
+fn timing_before(file: &MidiFile, tick: u64) -> [Option<(u64, MetaEvent)>; TIMING_KINDS] {
+    let mut latest: [Option<(u64, MetaEvent)>; TIMING_KINDS] = Default::default();
+    for event in file.tracks().iter().flat_map(|t| t.events()) {
+        if event.tick() >= tick {
+            continue;
+        }
+        if let (Some(kind), MidiMessage::Meta(meta)) =
+            (timing_kind(event.message()), event.message())
+            && latest[kind]
+                .as_ref()
+                .is_none_or(|(latest_tick, _)| event.tick() >= *latest_tick)
+        {
+            latest[kind] = Some((event.tick(), meta.clone()));
+        }
+    }
+    latest
+}
+
+fn has_timing_at(file: &MidiFile, tick: u64, kind: usize) -> bool {
+    file.tracks()
+        .iter()
+        .flat_map(|t| t.events())
+        .any(|e| e.tick() == tick && timing_kind(e.message()) == Some(kind))
+}
+
+/// A copy of `event` moved to `tick`, with its link and sequence number
+/// cleared (both are rebuilt by `finish`).
+fn moved(event: &MidiEvent, tick: u64) -> MidiEvent {
+    let mut event = event.clone();
+    event.set_tick(tick);
+    event.unlink_event();
+    event.set_seq(0);
+    event
+}
+
+/// A sorted, note-linked working copy of `track`.
+fn linked_copy(track: &MidiTrack) -> MidiTrack {
+    let mut copy = track.clone();
+    copy.link_note_events();
+    copy
+}
+
+/// An empty track with the same name as `track`.
+fn empty_like(track: &MidiTrack) -> MidiTrack {
+    let mut out = MidiTrack::new();
+    if let Some(name) = track.name() {
+        out.set_name(name);
+    }
+    out
+}
+
+/// Sort `out`, end it at `length` (or its last event, if later) and restore
+/// the note links and sequence numbers `source` had.
+fn finish(mut out: MidiTrack, source: &MidiTrack, length: u64) -> MidiTrack {
+    let end = out.last_tick().max(length);
+    out.add_event(MidiEvent::new(
+        end,
+        MidiMessage::Meta(MetaEvent::EndOfTrack),
+    ));
+    out.sort();
+    if source.events().iter().any(MidiEvent::is_linked) {
+        out.link_note_events();
+    }
+    if source.events().iter().any(|e| e.seq() != 0) {
+        out.mark_sequence();
+    }
+    out
+}
+
+/// Chase messages at `tick` for every channel whose state differs between
+/// `before` and `after`, recreating `after`.
+fn chase_changes(
+    before: &[ChannelState; 16],
+    after: &[ChannelState; 16],
+    tick: u64,
+) -> Vec<MidiEvent> {
+    before
+        .iter()
+        .zip(after)
+        .enumerate()
+        .filter(|(_, (before, after))| before != after)
+        .flat_map(|(channel, (_, after))| after.chase_messages(channel as u8))
+        .map(|message| MidiEvent::new(tick, message))
+        .collect()
+}
+
+fn extract_track(track: &MidiTrack, start: u64, end: u64, notes: BoundaryNotes) -> MidiTrack {
+    let source = linked_copy(track);
+    let events = source.events();
+    let mut out = empty_like(track);
+
+    // Track identity and channel state in effect at the cut.
+    let mut setup: Vec<&MidiEvent> = Vec::new();
+    for event in events.iter().filter(|e| e.tick() < start) {
+        if is_track_setup(event.message()) {
+            setup.retain(|e| {
+                std::mem::discriminant(e.message()) != std::mem::discriminant(event.message())
+            });
+            setup.push(event);
+        }
+    }
+    for event in setup {
+        out.add_event(moved(event, 0));
+    }
+    for event in source.chase_events(start) {
+        out.add_event(moved(&event, 0));
+    }
+
+    for event in events {
+        if is_end_of_track(event) {
+            continue;
+        }
+        if event.is_note_on()
+            && let Some(off_idx) = event.linked_event()
+        {
+            let (on, off) = (event.tick(), events[off_idx].tick());
+            let span = match notes {
+                BoundaryNotes::Truncate if on < end && (off > start || on >= start) => {
+                    Some((on.max(start), off.clamp(start, end)))
+                }
+                BoundaryNotes::KeepWhole if (start..end).contains(&on) => Some((on, off)),
+                _ => None,
+            };
+            if let Some((on, off)) = span {
+                out.add_event(moved(event, on - start));
+                out.add_event(moved(&events[off_idx], off - start));
+            }
+            continue;
+        }
+        if event.is_note_off() && event.linked_event().is_some() {
+            continue; // Handled with its note-on.
+        }
+        if (start..end).contains(&event.tick()) {
+            out.add_event(moved(event, event.tick() - start));
+        }
+    }
+
+    finish(out, track, end - start)
+}
+
+fn delete_track_range(track: &MidiTrack, start: u64, end: u64, notes: BoundaryNotes) -> MidiTrack {
+    let source = linked_copy(track);
+    let events = source.events();
+    let length = end - start;
+    let mut out = empty_like(track);
+    let close = |tick: u64| {
+        if tick < start {
+            tick
+        } else if tick >= end {
+            tick - length
+        } else {
+            start
+        }
+    };
+
+    // Whatever changed inside the removed range still applies after it.
+    for event in chase_changes(
+        &source.channel_states_at(start),
+        &source.channel_states_at(end),
+        start,
+    ) {
+        out.add_event(event);
+    }
+
+    for event in events {
+        if is_end_of_track(event) {
+            continue;
+        }
+        if event.is_note_on()
+            && let Some(off_idx) = event.linked_event()
+        {
+            let (on, off) = (event.tick(), events[off_idx].tick());
+            let span = if on < start {
+                match notes {
+                    BoundaryNotes::Truncate => Some((on, close(off))),
+                    BoundaryNotes::KeepWhole => Some((on, off)),
+                }
+            } else if on >= end {
+                Some((on - length, off - length))
+            } else if notes == BoundaryNotes::Truncate && off > end {
+                Some((start, off - length))
+            } else {
+                None
+            };
+            if let Some((on, off)) = span {
+                out.add_event(moved(event, on));
+                out.add_event(moved(&events[off_idx], off));
+            }
+            continue;
+        }
+        if event.is_note_off() && event.linked_event().is_some() {
+            continue;
+        }
+        if !(start..end).contains(&event.tick()) {
+            out.add_event(moved(event, close(event.tick())));
+        }
+    }
+
+    let track_end = events.iter().map(MidiEvent::tick).max().unwrap_or(0);
+    finish(out, track, close(track_end))
+}
+
+fn insert_track_silence(
+    track: &MidiTrack,
+    at: u64,
+    length: u64,
+    notes: BoundaryNotes,
+) -> MidiTrack {
+    let source = linked_copy(track);
+    let events = source.events();
+    let mut out = empty_like(track);
+    let open = |tick: u64| if tick >= at { tick + length } else { tick };
+
+    for event in events {
+        if is_end_of_track(event) {
+            continue;
+        }
+        if event.is_note_on()
+            && let Some(off_idx) = event.linked_event()
+        {
+            let (on, off) = (event.tick(), events[off_idx].tick());
+            let off = if on < at && off > at {
+                match notes {
+                    BoundaryNotes::Truncate => at,
+                    BoundaryNotes::KeepWhole => off,
+                }
+            } else {
+                open(off)
+            };
+            out.add_event(moved(event, open(on)));
+            out.add_event(moved(&events[off_idx], off));
+            continue;
+        }
+        if event.is_note_off() && event.linked_event().is_some() {
+            continue;
+        }
+        out.add_event(moved(event, open(event.tick())));
+    }
+
+    let track_end = events.iter().map(MidiEvent::tick).max().unwrap_or(0);
+    finish(out, track, open(track_end))
+}
+
+impl MidiFile {
+    /// Copy the tick range `[start, end)` into a new file starting at tick
+    /// 0. The tempo, time signature and key signature in effect at `start`
+    /// are re-emitted at tick 0 of the first track, and each track starts
+    /// with its name and the chase events that recreate its channel state
+    /// (see `MidiTrack::chase_events`). Every track ends at `end - start`.
+    pub fn extract_range(&self, start: u64, end: u64, notes: BoundaryNotes) -> MidiFile {
+        let end = end.max(start);
+        let mut result = MidiFile::new();
+        result.set_format(self.format());
+        result.set_time_division(self.time_division());
+        for track in self.tracks() {
+            result.add_track_from(extract_track(track, start, end, notes));
+        }
+
+        if let Some(first) = result.tracks_mut().first_mut() {
+            for (kind, latest) in timing_before(self, start).into_iter().enumerate() {
+                if let Some((_, meta)) = latest
+                    && !has_timing_at(self, start, kind)
+                {
+                    first.add_event(MidiEvent::new(0, MidiMessage::Meta(meta)));
+                }
+            }
+            first.sort();
+        }
+        result
+    }
+
+    /// Copy the range `[start, end)` in seconds into a new file (see
+    /// `extract_range`)
+    pub fn extract_seconds_range(&self, start: f64, end: f64, notes: BoundaryNotes) -> MidiFile {
+        self.extract_range(
+            self.seconds_to_ticks(start),
+            self.seconds_to_ticks(end),
+            notes,
+        )
+    }
+
+    /// Remove the tick range `[start, end)` and move everything after it
+    /// earlier to close the gap. Tempo, meter, key and channel state changed
+    /// inside the removed range are re-emitted at `start`.
+    pub fn delete_range(&mut self, start: u64, end: u64, notes: BoundaryNotes) {
+        if end <= start {
+            return;
+        }
+        let before = timing_before(self, start);
+        let after = timing_before(self, end);
+        let carried: Vec<MetaEvent> = (0..TIMING_KINDS)
+            .filter(|&kind| before[kind] != after[kind] && !has_timing_at(self, end, kind))
+            .filter_map(|kind| after[kind].clone().map(|(_, meta)| meta))
+            .collect();
+
+        let tracks = self.tracks_mut();
+        for track in tracks.iter_mut() {
+            *track = delete_track_range(track, start, end, notes);
+        }
+        if let Some(first) = tracks.first_mut() {
+            for meta in carried {
+                first.add_event(MidiEvent::new(start, MidiMessage::Meta(meta)));
+            }
+            first.sort();
+        }
+    }
+
+    /// Shift everything at or after `at` later by `length` ticks. Notes
+    /// sounding across `at` end there (`Truncate`) or keep their original
+    /// end tick (`KeepWhole`) rather than being stretched over the gap.
+    pub fn insert_silence(&mut self, at: u64, length: u64, notes: BoundaryNotes) {
+        if length == 0 {
+            return;
+        }
+        for track in self.tracks_mut().iter_mut() {
+            *track = insert_track_silence(track, at, length, notes);
+        }
+    }
+
+    /// Insert `other` at tick `at`: open a gap as long as `other` (see
+    /// `insert_silence`) and merge its tracks into the corresponding tracks
+    /// of this file, adding tracks as needed. `other` is rescaled to this
+    /// file's resolution. After the inserted material, this file's tempo,
+    /// meter, key and the channel state of each track are restored where
+    /// `other` changed them.
+    pub fn splice(&mut self, at: u64, other: &MidiFile, notes: BoundaryNotes) {
+        let from = other.ticks_per_quarter().max(1) as u128;
+        let to = self.ticks_per_quarter() as u128;
+        let rescale = |tick: u64| ((tick as u128 * to + from / 2) / from) as u64;
+        let length = rescale(other.total_ticks());
+        let resume = at + length;
+
+        let timing = timing_before(self, at);
+        let restore_timing: Vec<MetaEvent> = (0..TIMING_KINDS)
+            .filter(|&kind| {
+                other
+                    .tracks()
+                    .iter()
+                    .flat_map(|t| t.events())
+                    .any(|e| timing_kind(e.message()) == Some(kind))
+                    && !has_timing_at(self, at, kind)
+            })
+            .filter_map(|kind| timing[kind].clone().map(|(_, meta)| meta))
+            .collect();
+        let states: Vec<[ChannelState; 16]> = self
+            .tracks()
+            .iter()
+            .map(|t| t.channel_states_at(at))
+            .collect();
+
+        self.insert_silence(at, length, notes);
+        while self.num_tracks() < other.num_tracks() {
+            self.add_track();
+        }
+
+        let tracks = self.tracks_mut();
+        for (index, inserted) in other.tracks().iter().enumerate() {
+            let inserted = linked_copy(inserted);
+            let target = &mut tracks[index];
+            let linked = target.events().iter().any(MidiEvent::is_linked);
+            let sequenced = target.events().iter().any(|e| e.seq() != 0);
+
+            let eot = target.events().iter().position(is_end_of_track);
+            if let Some(eot) = eot {
+                target.remove_event(eot);
+            }
+            for event in inserted.events().iter().filter(|e| !is_end_of_track(e)) {
+                target.add_event(moved(event, at + rescale(event.tick())));
+            }
+
+            // Put back what this track had in effect before the splice.
+            let empty: [ChannelState; 16] = Default::default();
+            let restore = states.get(index).unwrap_or(&empty);
+            let changed = inserted.channel_states_at(u64::MAX);
+            for (channel, state) in restore.iter().enumerate() {
+                if !changed[channel].is_empty() && changed[channel] != *state {
+                    for message in state.chase_messages(channel as u8) {
+                        target.add_event(MidiEvent::new(resume, message));
+                    }
+                }
+            }
+            if index == 0 {
+                for meta in &restore_timing {
+                    target.add_event(MidiEvent::new(resume, MidiMessage::Meta(meta.clone())));
+                }
+            }
+
+            let end = target.last_tick().max(resume);
+            target.add_event(MidiEvent::new(
+                end,
+                MidiMessage::Meta(MetaEvent::EndOfTrack),
+            ));
+            target.sort();
+            if linked {
+                target.link_note_events();
+            }
+            if sequenced {
+                target.mark_sequence();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(file: &MidiFile, track: usize) -> Vec<(u64, u64, u8)> {
+        let mut track = file.track(track).unwrap().clone();
+        track.link_note_events();
+        track
+            .events()
+            .iter()
+            .filter(|e| e.is_note_on())
+            .map(|e| {
+                let off = e.linked_event().map(|i| track.events()[i].tick());
+                (e.tick(), off.unwrap(), e.key().unwrap())
+            })
+            .collect()
+    }
+
+    fn sample() -> MidiFile {
+        let mut file = MidiFile::new();
+        file.add_tracks(2);
+        file.add_tempo(0, 0, 100.0).unwrap();
+        file.add_tempo(0, 240, 140.0).unwrap();
+        file.add_time_signature(0, 0, 3, 4).unwrap();
+        file.add_track_name(1, 0, "Piano").unwrap();
+        file.add_patch_change(1, 0, 0, 5).unwrap();
+        file.add_note(1, 0, 480, 0, 60, 100).unwrap();
+        file.add_note(1, 400, 400, 0, 62, 100).unwrap();
+        file.add_note(1, 960, 240, 0, 64, 100).unwrap();
+        file.finalize();
+        file
+    }
+
+    #[test]
+    fn test_extract_range_truncate_and_keep_whole() {
+        let file = sample();
+        let cut = file.extract_range(300, 700, BoundaryNotes::Truncate);
+        assert_eq!(notes(&cut, 1), vec![(0, 180, 60), (100, 400, 62)]);
+        assert_eq!(cut.total_ticks(), 400);
+
+        let first: Vec<&MidiMessage> = cut.tracks()[0]
+            .events()
+            .iter()
+            .map(|e| e.message())
+            .collect();
+        assert!(first.contains(&&MidiMessage::Meta(MetaEvent::tempo_from_bpm(140.0))));
+        assert!(first.contains(&&MidiMessage::Meta(MetaEvent::time_signature(3, 4))));
+        let piano = cut.track(1).unwrap();
+        assert!(
+            piano
+                .events()
+                .iter()
+                .any(|e| e.tick() == 0 && e.message() == &MidiMessage::program_change(0, 5))
+        );
+        assert!(piano.events().iter().any(
+            |e| matches!(e.message(), MidiMessage::Meta(MetaEvent::TrackName(n)) if n == "Piano")
+        ));
+
+        let whole = file.extract_range(300, 700, BoundaryNotes::KeepWhole);
+        assert_eq!(notes(&whole, 1), vec![(100, 500, 62)]);
+        assert_eq!(whole.track(1).unwrap().last_tick(), 500);
+    }
+
+    #[test]
+    fn test_delete_range_and_insert_silence() {
+        let mut file = sample();
+        file.delete_range(200, 600, BoundaryNotes::Truncate);
+        assert_eq!(
+            notes(&file, 1),
+            vec![(0, 200, 60), (200, 400, 62), (560, 800, 64)]
+        );
+        // The tempo change inside the deleted range still applies.
+        assert!(file.tracks()[0].events().iter().any(|e| e.tick() == 200
+            && e.message() == &MidiMessage::Meta(MetaEvent::tempo_from_bpm(140.0))));
+
+        let mut file = sample();
+        file.delete_range(200, 600, BoundaryNotes::KeepWhole);
+        assert_eq!(notes(&file, 1), vec![(0, 480, 60), (560, 800, 64)]);
+
+        let mut file = sample();
+        file.insert_silence(440, 100, BoundaryNotes::Truncate);
+        assert_eq!(
+            notes(&file, 1),
+            vec![(0, 440, 60), (400, 440, 62), (1060, 1300, 64)]
+        );
+    }
+
+    #[test]
+    fn test_splice_rescales_and_restores_state() {
+        let mut file = sample();
+        let mut other = MidiFile::with_format(crate::midi::MidiFormat::MultiTrack, 240);
+        other.add_tracks(2);
+        other.add_tempo(0, 0, 60.0).unwrap();
+        other.add_patch_change(1, 0, 0, 30).unwrap();
+        other.add_note(1, 0, 120, 0, 72, 90).unwrap();
+        other.finalize();
+
+        file.splice(960, &other, BoundaryNotes::Truncate);
+        assert_eq!(
+            notes(&file, 1),
+            vec![
+                (0, 480, 60),
+                (400, 800, 62),
+                (960, 1200, 72),
+                (1200, 1440, 64)
+            ]
+        );
+        let piano = file.track(1).unwrap();
+        assert!(
+            piano
+                .events()
+                .iter()
+                .any(|e| e.tick() == 1200 && e.message() == &MidiMessage::program_change(0, 5))
+        );
+        assert!(file.tracks()[0].events().iter().any(|e| e.tick() == 1200
+            && e.message() == &MidiMessage::Meta(MetaEvent::tempo_from_bpm(140.0))));
+        assert_eq!(file.total_ticks(), 1440);
+    }
+}
@@ -6,6 +6,7 @@ use std::cmp::Ordering;
 use std::fmt;
 
 use super::message::{MetaEvent, MidiMessage};
+use super::sysex::SysExFraming;
 
 /// A MIDI event with timing information
 #[derive(Debug, Clone, PartialEq)]
@@ -22,6 +23,8 @@ pub struct MidiEvent {
     seq: u32,
     /// Linked event index (for note on/off pairing)
     linked_event: Option<usize>,
+    /// File framing of a SysEx packet that is not a complete message
+    sysex_framing: Option<SysExFraming>,
 }
 
 impl MidiEvent {
@@ -34,6 +37,7 @@ impl MidiEvent {
             seconds: None,
             seq: 0,
             linked_event: None,
+            sysex_framing: None,
         }
     }
 
@@ -46,6 +50,7 @@ impl MidiEvent {
             seconds: None,
             seq: 0,
             linked_event: None,
+            sysex_framing: None,
         }
     }
 
@@ -114,6 +119,17 @@ impl MidiEvent {
         self.linked_event = index;
     }
 
+    /// Get the file framing of a SysEx packet (`None` for a complete
+    /// F0…F7 message and for other messages)
+    pub fn sysex_framing(&self) -> Option<SysExFraming> {
+        self.sysex_framing
+    }
+
+    /// Set the file framing of a SysEx packet
+    pub fn set_sysex_framing(&mut self, framing: Option<SysExFraming>) {
+        self.sysex_framing = framing;
+    }
+
     /// Check if this event is linked
     pub fn is_linked(&self) -> bool {
         self.linked_event.is_some()
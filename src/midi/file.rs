@@ -7,10 +7,17 @@ use std::fs::File;
 use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
 
+use super::chase::{self, ChannelState};
+use super::controller::{ControllerEvent, ControllerEventKind};
 use super::event::{MidiEvent, NoteSortOrder};
+use super::lenient::{self, ParseDiagnostic};
 use super::message::{MetaEvent, MidiMessage};
+use super::rmid::{self, RmidInfo};
+use super::sysex::SysExFraming;
+use super::tempo_map::{MeterMap, TempoMap};
 use super::track::MidiTrack;
-use super::{MidiError, MidiFormat};
+use super::{MidiError, MidiFormat, SmpteFps, TimeDivision};
+use crate::notation::TimeSignature;
 
 /// Whether a `MidiFile`'s tracks are stored separately or have been merged
 /// into a single track by `join_tracks`.
@@ -41,8 +48,8 @@ pub enum TickState {
 pub struct MidiFile {
     /// MIDI file format (0, 1, or 2)
     format: MidiFormat,
-    /// Ticks per quarter note (timing resolution)
-    ticks_per_quarter: u16,
+    /// Timing resolution: ticks per quarter note, or SMPTE frames
+    division: TimeDivision,
     /// Tracks in this file
     tracks: Vec<MidiTrack>,
     /// Whether `tracks` currently holds separate tracks or a single
@@ -51,7 +58,10 @@ pub struct MidiFile {
     /// Time map for tick-to-seconds conversion. Lazily rebuilt by `build_time_map`,
     /// which needs to run from `&self` accessors (e.g. `ticks_to_seconds`), hence
     /// the interior mutability.
-    time_map: RefCell<Option<TimeMap>>,
+    time_map: RefCell<Option<TempoMap>>,
+    /// RIFF RMID metadata, present when the file was read from (or is meant
+    /// to be written as) an `.rmi` container
+    rmid: Option<RmidInfo>,
 }
 
 impl MidiFile {
@@ -59,21 +69,27 @@ impl MidiFile {
     pub fn new() -> Self {
         Self {
             format: MidiFormat::MultiTrack,
-            ticks_per_quarter: 480,
+            division: TimeDivision::Ppqn(480),
             tracks: Vec::new(),
             track_state: TrackState::Split,
             time_map: RefCell::new(None),
+            rmid: None,
         }
     }
 
-    /// Create a MIDI file with specified format and resolution
+    /// Create a MIDI file with specified format and resolution. A
+    /// resolution above `TimeDivision::MAX_TICKS_PER_QUARTER`, which the
+    /// header cannot store, is clamped to it.
     pub fn with_format(format: MidiFormat, ticks_per_quarter: u16) -> Self {
         Self {
             format,
-            ticks_per_quarter,
+            division: TimeDivision::Ppqn(
+                ticks_per_quarter.min(TimeDivision::MAX_TICKS_PER_QUARTER),
+            ),
             tracks: Vec::new(),
             track_state: TrackState::Split,
             time_map: RefCell::new(None),
+            rmid: None,
         }
     }
 
@@ -86,8 +102,16 @@ impl MidiFile {
         Self::from_bytes(&data)
     }
 
-    /// Parse MIDI file from bytes
+    /// Parse MIDI file from bytes. A RIFF RMID container is unwrapped
+    /// automatically and its INFO tags kept as `rmid_info`.
     pub fn from_bytes(data: &[u8]) -> Result<Self, MidiError> {
+        if rmid::is_rmid(data) {
+            let (smf, info) = rmid::unwrap(data)?;
+            let mut midi_file = Self::from_bytes(&data[smf])?;
+            midi_file.rmid = Some(info);
+            return Ok(midi_file);
+        }
+
         if data.len() < 14 {
             return Err(MidiError::InvalidHeader);
         }
@@ -104,29 +128,15 @@ impl MidiFile {
 
         let format = MidiFormat::try_from(read_u16_be(&data[8..10]))?;
         let num_tracks = read_u16_be(&data[10..12]) as usize;
-        let raw_division = read_u16_be(&data[12..14]);
-
-        // SMPTE-style timing: top byte is frames-per-second stored as a negative
-        // two's-complement i8 (e.g. -24/-25/-29/-30), bottom byte is the subframe
-        // (ticks-per-frame) resolution. Convert to an effective ticks-per-quarter
-        // value (frames_per_second * subframes), matching upstream midifile's
-        // handling. This is a one-way conversion: writing the file back out emits
-        // a plain (non-SMPTE) PPQN header at this resolution rather than
-        // reproducing the original SMPTE division bytes.
-        let ticks_per_quarter = if raw_division & 0x8000 != 0 {
-            let frames_per_second = ((raw_division >> 8) as u8 as i8).unsigned_abs() as u16;
-            let subframes = raw_division & 0xFF;
-            frames_per_second.saturating_mul(subframes)
-        } else {
-            raw_division
-        };
+        let division = TimeDivision::try_from(read_u16_be(&data[12..14]))?;
 
         let mut midi_file = Self {
             format,
-            ticks_per_quarter,
+            division,
             tracks: Vec::with_capacity(num_tracks),
             track_state: TrackState::Split,
             time_map: RefCell::new(None),
+            rmid: None,
         };
 
         // Parse track chunks
@@ -158,6 +168,35 @@ impl MidiFile {
         Ok(midi_file)
     }
 
+    /// Read a MIDI file from disk, recovering from damage where possible
+    /// (see `from_bytes_lenient`)
+    pub fn read_lenient(path: impl AsRef<Path>) -> Result<(Self, Vec<ParseDiagnostic>), MidiError> {
+        let file = File::open(path)?;
+        let mut reader = BufReader::new(file);
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        Self::from_bytes_lenient(&data)
+    }
+
+    /// Parse MIDI file from bytes, keeping going past truncated tracks,
+    /// wrong chunk lengths, missing End-of-Track events, stray data bytes
+    /// and unknown chunks. Every problem worked around is returned as a
+    /// diagnostic; only a missing or malformed `MThd` header is fatal.
+    pub fn from_bytes_lenient(data: &[u8]) -> Result<(Self, Vec<ParseDiagnostic>), MidiError> {
+        if rmid::is_rmid(data) {
+            // Report offsets relative to the whole container.
+            let (smf, info) = rmid::unwrap(data)?;
+            let base = smf.start;
+            let (mut midi_file, mut diagnostics) = lenient::parse(&data[smf])?;
+            for diagnostic in &mut diagnostics {
+                diagnostic.offset += base;
+            }
+            midi_file.rmid = Some(info);
+            return Ok((midi_file, diagnostics));
+        }
+        lenient::parse(data)
+    }
+
     /// Write MIDI file to disk
     pub fn write(&self, path: impl AsRef<Path>) -> Result<(), MidiError> {
         let file = File::create(path)?;
@@ -167,7 +206,39 @@ impl MidiFile {
         Ok(())
     }
 
-    /// Convert to bytes
+    /// Write the file to disk as a RIFF RMID container carrying
+    /// `rmid_info` (or no tags if none is set)
+    pub fn write_rmid(&self, path: impl AsRef<Path>) -> Result<(), MidiError> {
+        let file = File::create(path)?;
+        let mut writer = BufWriter::new(file);
+        writer.write_all(&self.to_rmid_bytes())?;
+        Ok(())
+    }
+
+    /// Convert to RIFF RMID bytes (see `write_rmid`)
+    pub fn to_rmid_bytes(&self) -> Vec<u8> {
+        let info = self.rmid.clone().unwrap_or_default();
+        rmid::wrap(&self.to_bytes(), &info)
+    }
+
+    /// Get the RMID metadata, if the file came from an RMID container or
+    /// some was set
+    pub fn rmid_info(&self) -> Option<&RmidInfo> {
+        self.rmid.as_ref()
+    }
+
+    /// Get mutable RMID metadata
+    pub fn rmid_info_mut(&mut self) -> Option<&mut RmidInfo> {
+        self.rmid.as_mut()
+    }
+
+    /// Set or clear the RMID metadata
+    pub fn set_rmid_info(&mut self, info: Option<RmidInfo>) {
+        self.rmid = info;
+    }
+
+    /// Convert to bytes (a plain Standard MIDI File; see `to_rmid_bytes`
+    /// for the RMID container)
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut data = Vec::new();
 
@@ -176,7 +247,7 @@ impl MidiFile {
         data.extend(&6u32.to_be_bytes());
         data.extend(&(self.format as u16).to_be_bytes());
         data.extend(&(self.tracks.len() as u16).to_be_bytes());
-        data.extend(&self.ticks_per_quarter.to_be_bytes());
+        data.extend(&self.division.to_u16().to_be_bytes());
 
         // Track chunks
         for track in &self.tracks {
@@ -201,15 +272,57 @@ impl MidiFile {
 
     /// Get ticks per quarter note
     pub fn ticks_per_quarter(&self) -> u16 {
-        self.ticks_per_quarter
+        self.division.ticks_per_quarter()
     }
 
-    /// Set ticks per quarter note
+    /// Set ticks per quarter note. Event ticks are left untouched; use
+    /// `resample` to rescale them as well. An SMPTE file switches to PPQN
+    /// timing, and a resolution above `TimeDivision::MAX_TICKS_PER_QUARTER`
+    /// is clamped to it; `try_set_ticks_per_quarter` refuses both instead.
     pub fn set_ticks_per_quarter(&mut self, tpq: u16) {
-        self.ticks_per_quarter = tpq;
+        self.set_time_division(TimeDivision::Ppqn(tpq));
+    }
+
+    /// Set ticks per quarter note, failing for SMPTE files (use
+    /// `try_set_time_division` to change the kind of timing) and for
+    /// resolutions the header cannot store
+    pub fn try_set_ticks_per_quarter(&mut self, tpq: u16) -> Result<(), MidiError> {
+        if self.division.is_smpte() {
+            return Err(MidiError::InvalidTimeDivision(self.division.to_u16()));
+        }
+        self.try_set_time_division(TimeDivision::ppqn(tpq)?)
+    }
+
+    /// Get the time division (PPQN or SMPTE)
+    pub fn time_division(&self) -> TimeDivision {
+        self.division
+    }
+
+    /// Set the time division. Event ticks are left untouched. A PPQN
+    /// resolution above `TimeDivision::MAX_TICKS_PER_QUARTER` is clamped to
+    /// it; `try_set_time_division` refuses it instead.
+    pub fn set_time_division(&mut self, division: TimeDivision) {
+        self.division = match division {
+            TimeDivision::Ppqn(tpq) => {
+                TimeDivision::Ppqn(tpq.min(TimeDivision::MAX_TICKS_PER_QUARTER))
+            }
+            smpte => smpte,
+        };
         *self.time_map.borrow_mut() = None; // Invalidate time map
     }
 
+    /// Set the time division, failing for divisions the header cannot store
+    pub fn try_set_time_division(&mut self, division: TimeDivision) -> Result<(), MidiError> {
+        division.validate()?;
+        self.set_time_division(division);
+        Ok(())
+    }
+
+    /// Check whether the file uses SMPTE (timecode) timing
+    pub fn is_smpte(&self) -> bool {
+        self.division.is_smpte()
+    }
+
     /// Get all tracks
     pub fn tracks(&self) -> &[MidiTrack] {
         &self.tracks
@@ -406,9 +519,13 @@ impl MidiFile {
         self.tracks.iter().map(|t| t.last_tick()).max().unwrap_or(0)
     }
 
-    /// Get the total duration expressed in quarter notes.
+    /// Get the total duration expressed in quarter notes (0 for a file
+    /// with a zero resolution).
     pub fn get_file_duration_in_quarters(&self) -> f64 {
-        self.total_ticks() as f64 / self.ticks_per_quarter as f64
+        match self.ticks_per_quarter() {
+            0 => 0.0,
+            tpq => self.total_ticks() as f64 / tpq as f64,
+        }
     }
 
     /// Whether every track currently uses absolute tick timing.
@@ -508,9 +625,20 @@ impl MidiFile {
     /// Rescale the file so that each tick represents exactly one
     /// millisecond, based on the first tempo event found (or 120 BPM if
     /// none). Existing event ticks are rescaled proportionally so absolute
-    /// timing is preserved.
+    /// timing is preserved. An SMPTE file is rescaled to 25 fps with 40
+    /// ticks per frame, which is exactly 1000 ticks per second.
     pub fn set_millisecond_ticks(&mut self) {
-        let old_tpq = self.ticks_per_quarter as f64;
+        if let Some(ticks_per_second) = self.division.ticks_per_second() {
+            self.rescale_ticks(1000.0 / ticks_per_second);
+            self.division = TimeDivision::Smpte {
+                fps: SmpteFps::Fps25,
+                ticks_per_frame: 40,
+            };
+            *self.time_map.borrow_mut() = None;
+            return;
+        }
+
+        let old_tpq = self.ticks_per_quarter() as f64;
         let us_per_quarter = self
             .tracks
             .iter()
@@ -522,17 +650,18 @@ impl MidiFile {
             .unwrap_or(500_000) as f64;
 
         let new_tpq = (us_per_quarter / 1000.0).round().max(1.0);
-        let scale = new_tpq / old_tpq;
+        self.rescale_ticks(new_tpq / old_tpq);
+        self.division = TimeDivision::Ppqn(new_tpq as u16);
+        *self.time_map.borrow_mut() = None;
+    }
 
+    fn rescale_ticks(&mut self, scale: f64) {
         for track in &mut self.tracks {
             for event in track.events_mut() {
                 let new_tick = (event.tick() as f64 * scale).round() as u64;
                 event.set_tick(new_tick);
             }
         }
-
-        self.ticks_per_quarter = new_tpq as u16;
-        *self.time_map.borrow_mut() = None;
     }
 
     /// Get the total duration in seconds
@@ -561,6 +690,59 @@ impl MidiFile {
             .seconds_to_ticks(seconds)
     }
 
+    /// The file's tempo map, built from the tempo events on all tracks
+    pub fn tempo_map(&self) -> TempoMap {
+        self.build_time_map();
+        self.time_map.borrow().clone().unwrap()
+    }
+
+    /// Replace every tempo event in the file with `map`, written to
+    /// `track`. Ramps are rendered as a tempo event every `ramp_step`
+    /// ticks (see `TempoMap::to_tempo_events`).
+    pub fn set_tempo_map(
+        &mut self,
+        track: usize,
+        map: &TempoMap,
+        ramp_step: u64,
+    ) -> Result<(), MidiError> {
+        if track >= self.tracks.len() {
+            return Err(MidiError::TrackOutOfBounds(track));
+        }
+        for t in &mut self.tracks {
+            t.events_mut()
+                .retain(|e| !matches!(e.message(), MidiMessage::Meta(MetaEvent::Tempo(_))));
+        }
+        for (tick, microseconds) in map.to_tempo_events(ramp_step) {
+            self.tracks[track].add_meta_event(tick, MetaEvent::Tempo(microseconds));
+        }
+        self.tracks[track].sort();
+        *self.time_map.borrow_mut() = None;
+        Ok(())
+    }
+
+    /// The file's meter map, built from the time signature events on all
+    /// tracks (4/4 until the first one)
+    pub fn meter_map(&self) -> MeterMap {
+        let mut signatures: Vec<(u64, TimeSignature)> = Vec::new();
+        for track in &self.tracks {
+            for event in track.events() {
+                if let MidiMessage::Meta(meta @ MetaEvent::TimeSignature { numerator, .. }) =
+                    event.message()
+                    && let Some(denominator) = meta.time_signature_denominator()
+                {
+                    signatures.push((event.tick(), TimeSignature::new(*numerator, denominator)));
+                }
+            }
+        }
+        signatures.sort_by_key(|(tick, _)| *tick);
+
+        let mut map = MeterMap::new(self.ticks_per_quarter());
+        for (tick, time_signature) in signatures {
+            map.set_time_signature(tick, time_signature);
+        }
+        map
+    }
+
     /// Build the time map for tempo conversion
     fn build_time_map(&self) {
         if self.time_map.borrow().is_some() {
@@ -584,7 +766,8 @@ impl MidiFile {
             tempo_events.push((0, 500_000)); // 120 BPM
         }
 
-        *self.time_map.borrow_mut() = Some(TimeMap::new(tempo_events, self.ticks_per_quarter));
+        *self.time_map.borrow_mut() =
+            Some(TempoMap::from_tempo_events(self.division, &tempo_events));
     }
 
     /// Add a note to a track
@@ -854,6 +1037,52 @@ impl MidiFile {
         Ok(())
     }
 
+    /// Write the control changes for a decoded controller event (see
+    /// `MidiTrack::add_controller_event`).
+    pub fn add_controller_event(
+        &mut self,
+        track: usize,
+        tick: u64,
+        channel: u8,
+        kind: &ControllerEventKind,
+    ) -> Result<(), MidiError> {
+        let track = self
+            .tracks
+            .get_mut(track)
+            .ok_or(MidiError::TrackOutOfBounds(track))?;
+        track.add_controller_event(tick, channel, kind);
+        Ok(())
+    }
+
+    /// Decode RPN/NRPN and 14-bit controller streams on every track (see
+    /// `MidiTrack::decode_controllers`). Parameter selection state is kept
+    /// per track; event indices refer to that track's events.
+    pub fn decode_controllers(&self) -> Vec<Vec<ControllerEvent>> {
+        self.tracks
+            .iter()
+            .map(|track| track.decode_controllers())
+            .collect()
+    }
+
+    /// Effective state of each channel just before `tick`, accumulated over
+    /// all tracks in tick order (ties keep track order)
+    pub fn channel_states_at(&self, tick: u64) -> [ChannelState; 16] {
+        let mut before: Vec<MidiEvent> = self
+            .tracks
+            .iter()
+            .flat_map(|track| track.events().iter().filter(|e| e.tick() < tick))
+            .cloned()
+            .collect();
+        before.sort_by_key(|e| e.tick());
+        chase::channel_states(&before)
+    }
+
+    /// Events at `tick` that recreate each channel's state at that point,
+    /// for prepending to playback or an exported region starting at `tick`
+    pub fn chase_events(&self, tick: u64) -> Vec<MidiEvent> {
+        chase::chase_events(&self.channel_states_at(tick), tick)
+    }
+
     /// Sort a single track with an explicit note-on/off tie-break order.
     pub fn sort_track(&mut self, index: usize, order: NoteSortOrder) -> Result<(), MidiError> {
         let track = self
@@ -926,102 +1155,17 @@ impl Default for MidiFile {
     }
 }
 
-/// Time map for tick-to-seconds conversion
-#[derive(Debug, Clone)]
-struct TimeMap {
-    /// Tempo change points: (tick, seconds at that tick, microseconds per quarter)
-    points: Vec<(u64, f64, u32)>,
-    ticks_per_quarter: u16,
-}
-
-impl TimeMap {
-    fn new(tempo_events: Vec<(u64, u32)>, ticks_per_quarter: u16) -> Self {
-        let mut points = Vec::new();
-        let mut current_seconds = 0.0;
-        let mut prev_tick: u64 = 0;
-        let mut prev_tempo: u32 = 500_000; // Default 120 BPM
-
-        for (tick, tempo) in tempo_events {
-            // Calculate time elapsed since last tempo change
-            let tick_delta = tick - prev_tick;
-            let seconds_per_tick = prev_tempo as f64 / 1_000_000.0 / ticks_per_quarter as f64;
-            current_seconds += tick_delta as f64 * seconds_per_tick;
-
-            points.push((tick, current_seconds, tempo));
-            prev_tick = tick;
-            prev_tempo = tempo;
-        }
-
-        Self {
-            points,
-            ticks_per_quarter,
-        }
-    }
-
-    fn ticks_to_seconds(&self, ticks: u64) -> f64 {
-        if self.points.is_empty() {
-            // Default 120 BPM
-            let seconds_per_tick = 0.5 / self.ticks_per_quarter as f64;
-            return ticks as f64 * seconds_per_tick;
-        }
-
-        // Find the tempo region
-        let mut base_tick: u64 = 0;
-        let mut base_seconds = 0.0;
-        let mut tempo: u32 = 500_000;
-
-        for &(point_tick, point_seconds, point_tempo) in &self.points {
-            if point_tick > ticks {
-                break;
-            }
-            base_tick = point_tick;
-            base_seconds = point_seconds;
-            tempo = point_tempo;
-        }
-
-        let tick_delta = ticks - base_tick;
-        let seconds_per_tick = tempo as f64 / 1_000_000.0 / self.ticks_per_quarter as f64;
-        base_seconds + tick_delta as f64 * seconds_per_tick
-    }
-
-    fn seconds_to_ticks(&self, seconds: f64) -> u64 {
-        if self.points.is_empty() {
-            // Default 120 BPM
-            let ticks_per_second = self.ticks_per_quarter as f64 * 2.0;
-            return (seconds * ticks_per_second) as u64;
-        }
-
-        // Find the tempo region
-        let mut base_tick: u64 = 0;
-        let mut base_seconds = 0.0;
-        let mut tempo: u32 = 500_000;
-
-        for &(point_tick, point_seconds, point_tempo) in &self.points {
-            if point_seconds > seconds {
-                break;
-            }
-            base_tick = point_tick;
-            base_seconds = point_seconds;
-            tempo = point_tempo;
-        }
-
-        let seconds_delta = seconds - base_seconds;
-        let ticks_per_second = self.ticks_per_quarter as f64 * 1_000_000.0 / tempo as f64;
-        base_tick + (seconds_delta * ticks_per_second) as u64
-    }
-}
-
 // Helper functions
 
-fn read_u16_be(data: &[u8]) -> u16 {
+pub(super) fn read_u16_be(data: &[u8]) -> u16 {
     ((data[0] as u16) << 8) | data[1] as u16
 }
 
-fn read_u32_be(data: &[u8]) -> u32 {
+pub(super) fn read_u32_be(data: &[u8]) -> u32 {
     ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | data[3] as u32
 }
 
-fn read_varlen(data: &[u8]) -> Option<(u32, usize)> {
+pub(super) fn read_varlen(data: &[u8]) -> Option<(u32, usize)> {
     let mut value: u32 = 0;
     let mut bytes_read = 0;
 
@@ -1037,7 +1181,7 @@ fn read_varlen(data: &[u8]) -> Option<(u32, usize)> {
     None
 }
 
-fn write_varlen(value: u32) -> Vec<u8> {
+pub(super) fn write_varlen(value: u32) -> Vec<u8> {
     if value == 0 {
         return vec![0];
     }
@@ -1064,144 +1208,196 @@ fn parse_track(data: &[u8]) -> Result<MidiTrack, MidiError> {
     let mut current_tick: u64 = 0;
 
     while pos < data.len() {
-        // Read delta time
-        let (delta, delta_len) = read_varlen(&data[pos..]).ok_or(MidiError::InvalidVarLen)?;
-        pos += delta_len;
+        let Some((delta, message, framing, len)) = decode_event(&data[pos..], &mut running_status)?
+        else {
+            break;
+        };
+        pos += len;
         current_tick += delta as u64;
+        let mut event = MidiEvent::new(current_tick, message);
+        event.set_sysex_framing(framing);
+        track.add_event(event);
+    }
 
-        if pos >= data.len() {
-            break;
-        }
+    Ok(track)
+}
 
-        let status = data[pos];
+/// Delta time, message, SysEx packet framing and length of an encoded
+/// track event
+pub(super) type DecodedEvent = (u32, MidiMessage, Option<SysExFraming>, usize);
 
-        // Check for meta event
-        if status == 0xFF {
-            pos += 1;
-            if pos >= data.len() {
-                return Err(MidiError::UnexpectedEof);
-            }
+/// Decode one track event (delta time and message) from the start of
+/// `data`, updating `running_status`. Returns the delta, the message, the
+/// framing of a SysEx packet and the number of bytes consumed, or `None` if
+/// `data` ends right after a delta time.
+pub(super) fn decode_event(
+    data: &[u8],
+    running_status: &mut Option<u8>,
+) -> Result<Option<DecodedEvent>, MidiError> {
+    // Read delta time
+    let (delta, delta_len) = read_varlen(data).ok_or(MidiError::InvalidVarLen)?;
+    let mut pos = delta_len;
+
+    if pos >= data.len() {
+        return Ok(None);
+    }
 
-            let (meta, meta_len) =
-                MetaEvent::from_bytes(&data[pos..]).ok_or(MidiError::UnexpectedEof)?;
-            pos += meta_len;
+    let status = data[pos];
 
-            let event = MidiEvent::new(current_tick, MidiMessage::Meta(meta));
-            track.add_event(event);
-            running_status = None;
-            continue;
+    // Check for meta event
+    if status == 0xFF {
+        pos += 1;
+        if pos >= data.len() {
+            return Err(MidiError::UnexpectedEof);
         }
 
-        // Check for SysEx
-        if status == 0xF0 || status == 0xF7 {
-            pos += 1;
-            let (length, len_bytes) = read_varlen(&data[pos..]).ok_or(MidiError::InvalidVarLen)?;
-            pos += len_bytes;
+        let (meta, meta_len) =
+            MetaEvent::from_bytes(&data[pos..]).ok_or(MidiError::UnexpectedEof)?;
+        pos += meta_len;
+        *running_status = None;
+        return Ok(Some((delta, MidiMessage::Meta(meta), None, pos)));
+    }
 
-            let sysex_data = data[pos..pos + length as usize].to_vec();
-            pos += length as usize;
+    // Check for SysEx
+    if status == 0xF0 || status == 0xF7 {
+        pos += 1;
+        let (length, len_bytes) = read_varlen(&data[pos..]).ok_or(MidiError::InvalidVarLen)?;
+        pos += len_bytes;
 
-            let event = MidiEvent::new(current_tick, MidiMessage::SysEx(sysex_data));
-            track.add_event(event);
-            running_status = None;
-            continue;
+        let end = pos + length as usize;
+        if end > data.len() {
+            return Err(MidiError::UnexpectedEof);
         }
-
-        // Channel message
-        let (actual_status, data_start) = if status & 0x80 != 0 {
-            running_status = Some(status);
-            pos += 1;
-            (status, pos)
-        } else {
-            // Use running status
-            let rs = running_status.ok_or(MidiError::InvalidRunningStatus)?;
-            (rs, pos)
-        };
-
-        let channel = actual_status & 0x0F;
-        let message = match actual_status & 0xF0 {
-            0x80 => {
-                if data_start + 2 > data.len() {
-                    return Err(MidiError::UnexpectedEof);
-                }
-                pos = data_start + 2;
-                MidiMessage::NoteOff {
-                    channel,
-                    key: data[data_start],
-                    velocity: data[data_start + 1],
-                }
+        *running_status = None;
+        let (message, framing) = decode_sysex(status, &data[pos..end]);
+        return Ok(Some((delta, message, framing, end)));
+    }
+
+    // Channel message
+    let (actual_status, data_start) = if status & 0x80 != 0 {
+        *running_status = Some(status);
+        pos += 1;
+        (status, pos)
+    } else {
+        // Use running status
+        let rs = running_status.ok_or(MidiError::InvalidRunningStatus)?;
+        (rs, pos)
+    };
+
+    let channel = actual_status & 0x0F;
+    let message = match actual_status & 0xF0 {
+        0x80 => {
+            if data_start + 2 > data.len() {
+                return Err(MidiError::UnexpectedEof);
             }
-            0x90 => {
-                if data_start + 2 > data.len() {
-                    return Err(MidiError::UnexpectedEof);
-                }
-                pos = data_start + 2;
-                MidiMessage::NoteOn {
-                    channel,
-                    key: data[data_start],
-                    velocity: data[data_start + 1],
-                }
+            pos = data_start + 2;
+            MidiMessage::NoteOff {
+                channel,
+                key: data[data_start],
+                velocity: data[data_start + 1],
             }
-            0xA0 => {
-                if data_start + 2 > data.len() {
-                    return Err(MidiError::UnexpectedEof);
-                }
-                pos = data_start + 2;
-                MidiMessage::PolyPressure {
-                    channel,
-                    key: data[data_start],
-                    pressure: data[data_start + 1],
-                }
+        }
+        0x90 => {
+            if data_start + 2 > data.len() {
+                return Err(MidiError::UnexpectedEof);
             }
-            0xB0 => {
-                if data_start + 2 > data.len() {
-                    return Err(MidiError::UnexpectedEof);
-                }
-                pos = data_start + 2;
-                MidiMessage::ControlChange {
-                    channel,
-                    controller: data[data_start],
-                    value: data[data_start + 1],
-                }
+            pos = data_start + 2;
+            MidiMessage::NoteOn {
+                channel,
+                key: data[data_start],
+                velocity: data[data_start + 1],
             }
-            0xC0 => {
-                if data_start + 1 > data.len() {
-                    return Err(MidiError::UnexpectedEof);
-                }
-                pos = data_start + 1;
-                MidiMessage::ProgramChange {
-                    channel,
-                    program: data[data_start],
-                }
+        }
+        0xA0 => {
+            if data_start + 2 > data.len() {
+                return Err(MidiError::UnexpectedEof);
             }
-            0xD0 => {
-                if data_start + 1 > data.len() {
-                    return Err(MidiError::UnexpectedEof);
-                }
-                pos = data_start + 1;
-                MidiMessage::ChannelPressure {
-                    channel,
-                    pressure: data[data_start],
-                }
+            pos = data_start + 2;
+            MidiMessage::PolyPressure {
+                channel,
+                key: data[data_start],
+                pressure: data[data_start + 1],
             }
-            0xE0 => {
-                if data_start + 2 > data.len() {
-                    return Err(MidiError::UnexpectedEof);
-                }
-                pos = data_start + 2;
-                MidiMessage::PitchBend {
-                    channel,
-                    value: (data[data_start] as u16) | ((data[data_start + 1] as u16) << 7),
-                }
+        }
+        0xB0 => {
+            if data_start + 2 > data.len() {
+                return Err(MidiError::UnexpectedEof);
             }
-            _ => return Err(MidiError::InvalidStatus(actual_status)),
-        };
+            pos = data_start + 2;
+            MidiMessage::ControlChange {
+                channel,
+                controller: data[data_start],
+                value: data[data_start + 1],
+            }
+        }
+        0xC0 => {
+            if data_start + 1 > data.len() {
+                return Err(MidiError::UnexpectedEof);
+            }
+            pos = data_start + 1;
+            MidiMessage::ProgramChange {
+                channel,
+                program: data[data_start],
+            }
+        }
+        0xD0 => {
+            if data_start + 1 > data.len() {
+                return Err(MidiError::UnexpectedEof);
+            }
+            pos = data_start + 1;
+            MidiMessage::ChannelPressure {
+                channel,
+                pressure: data[data_start],
+            }
+        }
+        0xE0 => {
+            if data_start + 2 > data.len() {
+                return Err(MidiError::UnexpectedEof);
+            }
+            pos = data_start + 2;
+            MidiMessage::PitchBend {
+                channel,
+                value: (data[data_start] as u16) | ((data[data_start + 1] as u16) << 7),
+            }
+        }
+        _ => return Err(MidiError::InvalidStatus(actual_status)),
+    };
 
-        let event = MidiEvent::new(current_tick, message);
-        track.add_event(event);
-    }
+    Ok(Some((delta, message, None, pos)))
+}
 
-    Ok(track)
+/// Message and framing for a SysEx packet with status `status` (F0 or F7)
+/// and the `body` following its length. A complete F0…F7 packet has no
+/// framing; for anything else the payload excludes only a final F7.
+pub(super) fn decode_sysex(status: u8, body: &[u8]) -> (MidiMessage, Option<SysExFraming>) {
+    let (data, terminated) = match body.split_last() {
+        Some((0xF7, data)) => (data, true),
+        _ => (body, false),
+    };
+    let framing = match (status, terminated) {
+        (0xF0, true) => None,
+        _ => Some(SysExFraming {
+            escape: status == 0xF7,
+            terminated,
+        }),
+    };
+    (MidiMessage::SysEx(data.to_vec()), framing)
+}
+
+/// File form of a SysEx event: status, length and body (the inverse of
+/// `decode_sysex`), or `None` for other messages
+pub(super) fn encode_sysex(event: &MidiEvent) -> Option<Vec<u8>> {
+    let MidiMessage::SysEx(data) = event.message() else {
+        return None;
+    };
+    let framing = event.sysex_framing().unwrap_or(SysExFraming::COMPLETE);
+    let mut bytes = vec![framing.status()];
+    bytes.extend(write_varlen(data.len() as u32 + framing.terminated as u32));
+    bytes.extend(data);
+    if framing.terminated {
+        bytes.push(0xF7);
+    }
+    Some(bytes)
 }
 
 fn encode_track(track: &MidiTrack) -> Vec<u8> {
@@ -1218,8 +1414,12 @@ fn encode_track(track: &MidiTrack) -> Vec<u8> {
         data.extend(write_varlen(delta as u32));
         prev_tick = event.tick();
 
-        // Write message
-        data.extend(event.message().to_bytes());
+        // Write message. In a file, SysEx carries a length after its
+        // status byte.
+        match encode_sysex(event) {
+            Some(bytes) => data.extend(bytes),
+            None => data.extend(event.message().to_bytes()),
+        }
     }
 
     data
@@ -1228,6 +1428,7 @@ fn encode_track(track: &MidiTrack) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::midi::UniversalSysEx;
 
     #[test]
     fn test_join_split_tracks_roundtrip() {
@@ -1399,6 +1600,9 @@ mod tests {
         track.add_note(480, 960, 0, 64, 100); // ends at tick 1440
 
         assert!((file.get_file_duration_in_quarters() - 3.0).abs() < 0.0001);
+
+        file.set_ticks_per_quarter(0);
+        assert_eq!(file.get_file_duration_in_quarters(), 0.0);
     }
 
     #[test]
@@ -1457,6 +1661,190 @@ mod tests {
         assert_eq!(parsed.ticks_per_quarter(), 1000);
     }
 
+    #[test]
+    fn test_smpte_division_roundtrip() {
+        for (bytes, fps, ticks_per_frame) in [
+            ([0xE8, 0x04], SmpteFps::Fps24, 4),
+            ([0xE7, 0x28], SmpteFps::Fps25, 40),
+            ([0xE3, 0x50], SmpteFps::Fps29_97Drop, 80),
+            ([0xE2, 0x64], SmpteFps::Fps30, 100),
+        ] {
+            let mut data = Vec::new();
+            data.extend(b"MThd");
+            data.extend(&6u32.to_be_bytes());
+            data.extend(&0u16.to_be_bytes());
+            data.extend(&0u16.to_be_bytes());
+            data.extend(&bytes);
+
+            let parsed = MidiFile::from_bytes(&data).unwrap();
+            assert_eq!(
+                parsed.time_division(),
+                TimeDivision::Smpte {
+                    fps,
+                    ticks_per_frame
+                }
+            );
+            assert_eq!(parsed.to_bytes(), data);
+        }
+
+        // Only 24/25/29/30 are valid SMPTE frame rates.
+        let invalid = [b'M', b'T', b'h', b'd', 0, 0, 0, 6, 0, 0, 0, 0, 0xE6, 0x28];
+        assert!(matches!(
+            MidiFile::from_bytes(&invalid),
+            Err(MidiError::InvalidTimeDivision(0xE628))
+        ));
+    }
+
+    #[test]
+    fn test_smpte_timing_ignores_tempo() {
+        let mut file = MidiFile::new();
+        file.set_time_division(TimeDivision::Smpte {
+            fps: SmpteFps::Fps25,
+            ticks_per_frame: 40,
+        });
+        file.add_track();
+        file.add_tempo(0, 0, 60.0).unwrap();
+        file.add_note(0, 500, 1000, 0, 60, 100).unwrap();
+
+        assert!(file.is_smpte());
+        assert!((file.ticks_to_seconds(1500) - 1.5).abs() < 1e-9);
+        assert_eq!(file.seconds_to_ticks(0.5), 500);
+
+        file.update_seconds();
+        let seconds: Vec<f64> = file.tracks()[0]
+            .events()
+            .iter()
+            .filter_map(|e| e.seconds())
+            .collect();
+        assert!(seconds.contains(&0.5));
+        assert!(seconds.contains(&1.5));
+
+        // 29.97 drop-frame runs slightly slower than 30 frames per second.
+        file.set_time_division(TimeDivision::Smpte {
+            fps: SmpteFps::Fps29_97Drop,
+            ticks_per_frame: 100,
+        });
+        assert!((file.ticks_to_seconds(3000) - 1.001).abs() < 1e-9);
+        assert_eq!(file.seconds_to_ticks(1.001), 3000);
+
+        file.set_millisecond_ticks();
+        assert_eq!(file.time_division().to_u16(), 0xE728);
+        assert_eq!(file.seconds_to_ticks(1.0), 1000);
+    }
+
+    #[test]
+    fn test_division_out_of_range() {
+        assert!(matches!(
+            TimeDivision::ppqn(40000),
+            Err(MidiError::InvalidTimeDivision(40000))
+        ));
+        assert_eq!(TimeDivision::ppqn(0x7FFF).unwrap().to_u16(), 0x7FFF);
+
+        let mut file = MidiFile::new();
+        assert!(file.try_set_ticks_per_quarter(40000).is_err());
+        assert!(
+            file.try_set_time_division(TimeDivision::Ppqn(0x8000))
+                .is_err()
+        );
+        assert_eq!(file.ticks_per_quarter(), 480);
+
+        // The infallible setters and constructor clamp instead.
+        file.set_ticks_per_quarter(40000);
+        assert_eq!(file.ticks_per_quarter(), 0x7FFF);
+        let file = MidiFile::with_format(MidiFormat::MultiTrack, 40000);
+        assert_eq!(file.time_division().to_u16(), 0x7FFF);
+
+        // The fallible setter never silently turns SMPTE timing into PPQN.
+        let mut file = MidiFile::new();
+        let smpte = TimeDivision::Smpte {
+            fps: SmpteFps::Fps25,
+            ticks_per_frame: 40,
+        };
+        file.try_set_time_division(smpte).unwrap();
+        assert!(matches!(
+            file.try_set_ticks_per_quarter(960),
+            Err(MidiError::InvalidTimeDivision(0xE728))
+        ));
+        assert_eq!(file.time_division(), smpte);
+    }
+
+    #[test]
+    fn test_sysex_roundtrip() {
+        let mut file = MidiFile::new();
+        let track = file.add_track();
+        track.add_gm_system_on(0);
+        track.add_master_volume(0, 0.5);
+        track.add_sysex(
+            10,
+            vec![0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7F, 0x00, 0x41],
+        );
+        track.add_end_of_track();
+
+        let bytes = file.to_bytes();
+        // F0, length (payload + F7), payload, F7
+        assert_eq!(
+            &bytes[22..30],
+            &[0x00, 0xF0, 0x05, 0x7E, 0x7F, 0x09, 0x01, 0xF7]
+        );
+
+        let parsed = MidiFile::from_bytes(&bytes).unwrap();
+        let events = parsed.tracks()[0].events();
+        assert_eq!(
+            UniversalSysEx::from_message(events[0].message()),
+            Some(UniversalSysEx::GmSystemOn {
+                device_id: crate::midi::ALL_CALL
+            })
+        );
+        assert_eq!(events[1].message(), file.tracks()[0].events()[1].message());
+        assert_eq!(events[2].message(), file.tracks()[0].events()[2].message());
+        assert_eq!(parsed.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_sysex_packet_roundtrip() {
+        // A SysEx split into an unterminated F0 packet and an F7
+        // continuation, then an F7 escape packet with a Tune Request
+        let events = [
+            0x00, 0xF0, 0x03, 0x43, 0x12, 0x00, // first packet
+            0x00, 0xF7, 0x02, 0x01, 0xF7, // continuation
+            0x00, 0xF7, 0x01, 0xF6, // escape
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let mut bytes = Vec::new();
+        bytes.extend(b"MThd");
+        bytes.extend(6u32.to_be_bytes());
+        bytes.extend([0x00, 0x00, 0x00, 0x01, 0x01, 0xE0]);
+        bytes.extend(b"MTrk");
+        bytes.extend((events.len() as u32).to_be_bytes());
+        bytes.extend(events);
+
+        let file = MidiFile::from_bytes(&bytes).unwrap();
+        let packets: Vec<_> = file.tracks()[0]
+            .events()
+            .iter()
+            .map(|e| (e.message().clone(), e.sysex_framing()))
+            .collect();
+        let packet = |data: &[u8], escape, terminated| {
+            (
+                MidiMessage::SysEx(data.to_vec()),
+                Some(SysExFraming { escape, terminated }),
+            )
+        };
+        assert_eq!(
+            packets[..3],
+            [
+                packet(&[0x43, 0x12, 0x00], false, false),
+                packet(&[0x01], true, true),
+                packet(&[0xF6], true, false),
+            ]
+        );
+        assert_eq!(file.to_bytes(), bytes);
+
+        let (lenient, diagnostics) = MidiFile::from_bytes_lenient(&bytes).unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!(lenient.to_bytes(), bytes);
+    }
+
     #[test]
     fn test_varlen() {
         // Test encoding
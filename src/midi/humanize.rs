@@ -0,0 +1,270 @@
+//! Humanization of note timing, velocity and length
+//!
+//! `HumanizeOptions` adds bounded random deviations to notes. The random
+//! sequence comes from a seed, so the same options applied to the same
+//! track always give the same result.
+
+use super::file::MidiFile;
+use super::quantize;
+use super::tempo_map::MeterMap;
+use super::track::MidiTrack;
+
+/// Shape of the random deviations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HumanizeDistribution {
+    /// Every value up to the limit is equally likely
+    Uniform,
+    /// Normally distributed with the limit at three standard deviations,
+    /// clamped to the limit
+    #[default]
+    Gaussian,
+}
+
+/// Settings for `MidiTrack::humanize`
+#[derive(Debug, Clone, PartialEq)]
+pub struct HumanizeOptions {
+    seed: u64,
+    distribution: HumanizeDistribution,
+    timing: u64,
+    velocity: u8,
+    length: f64,
+    tight_chords: bool,
+    meter: Option<MeterMap>,
+    accent: u8,
+}
+
+impl HumanizeOptions {
+    /// Create options that change nothing, drawing random numbers from
+    /// `seed`
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            distribution: HumanizeDistribution::default(),
+            timing: 0,
+            velocity: 0,
+            length: 0.0,
+            tight_chords: false,
+            meter: None,
+            accent: 0,
+        }
+    }
+
+    /// Set the shape of the random deviations
+    pub fn with_distribution(mut self, distribution: HumanizeDistribution) -> Self {
+        self.distribution = distribution;
+        self
+    }
+
+    /// Move note starts by up to `ticks` either way
+    pub fn with_timing(mut self, ticks: u64) -> Self {
+        self.timing = ticks;
+        self
+    }
+
+    /// Change note velocities by up to `amount` either way
+    pub fn with_velocity(mut self, amount: u8) -> Self {
+        self.velocity = amount;
+        self
+    }
+
+    /// Change note lengths by up to `percent` either way
+    pub fn with_length(mut self, percent: f64) -> Self {
+        self.length = percent.clamp(0.0, 100.0);
+        self
+    }
+
+    /// Move notes that start together by the same amount, so chords stay
+    /// tight while still drifting as a whole
+    pub fn with_tight_chords(mut self, enabled: bool) -> Self {
+        self.tight_chords = enabled;
+        self
+    }
+
+    /// Follow the beat strength of `meter` (see `MeterMap::beat_strength`):
+    /// downbeats drift half as much as weak subdivisions, and velocities
+    /// gain up to `accent` on downbeats and lose up to `accent` on the
+    /// weakest subdivisions
+    pub fn with_meter(mut self, meter: MeterMap, accent: u8) -> Self {
+        self.meter = Some(meter);
+        self.accent = accent;
+        self
+    }
+
+    /// Seed the random deviations are drawn from
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+/// SplitMix64 pseudo-random generator: small, fast and stable across
+/// platforms and releases, which is all reproducible humanizing needs.
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Deviation in [-1, 1] with the given distribution.
+    fn deviation(&mut self, distribution: HumanizeDistribution) -> f64 {
+        match distribution {
+            HumanizeDistribution::Uniform => self.next_f64() * 2.0 - 1.0,
+            HumanizeDistribution::Gaussian => {
+                // Box-Muller; 1 - u keeps the logarithm finite.
+                let u1 = 1.0 - self.next_f64();
+                let u2 = self.next_f64();
+                let normal = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+                (normal / 3.0).clamp(-1.0, 1.0)
+            }
+        }
+    }
+}
+
+/// Humanize the notes of `track`.
+pub(super) fn humanize(track: &mut MidiTrack, options: &HumanizeOptions) {
+    let mut random = Random::new(options.seed);
+    let mut chord: Option<(u64, f64)> = None;
+    quantize::move_notes(track, |start, end, velocity| {
+        let strength = options
+            .meter
+            .as_ref()
+            .map(|meter| meter.beat_strength(start));
+
+        let shared = chord.filter(|&(tick, _)| options.tight_chords && tick == start);
+        let drift = match shared {
+            Some((_, drift)) => drift,
+            None => random.deviation(options.distribution),
+        };
+        chord = Some((start, drift));
+        let scale = strength.map_or(1.0, |strength| 1.0 - strength / 2.0);
+        let offset = drift * options.timing as f64 * scale;
+        let new_start = (start as f64 + offset).round().max(0.0) as u64;
+
+        let accent = strength.map_or(0.0, |strength| {
+            options.accent as f64 * (2.0 * strength - 1.0)
+        });
+        let change = random.deviation(options.distribution) * options.velocity as f64;
+        let new_velocity = (velocity as f64 + change + accent)
+            .round()
+            .clamp(1.0, 127.0) as u8;
+
+        let stretch = 1.0 + random.deviation(options.distribution) * options.length / 100.0;
+        let new_end = end.map(|end| {
+            let length = end.saturating_sub(start) as f64 * stretch;
+            new_start + length.round() as u64
+        });
+        (new_start, new_end, new_velocity)
+    });
+}
+
+impl MidiFile {
+    /// Humanize the notes of every track (see `MidiTrack::humanize`). Each
+    /// track draws from its own sequence derived from the seed.
+    pub fn humanize(&mut self, options: &HumanizeOptions) {
+        for (index, track) in self.tracks_mut().iter_mut().enumerate() {
+            let mut options = options.clone();
+            options.seed = Random::new(options.seed ^ index as u64).next_u64();
+            track.humanize(&options);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::MidiEvent;
+    use crate::notation::TimeSignature;
+
+    fn notes(track: &MidiTrack) -> Vec<(u64, u64, u8)> {
+        let events = track.events();
+        events
+            .iter()
+            .filter(|e| e.is_note_on())
+            .map(|e| {
+                let duration = e.tick_duration(events).unwrap();
+                (e.tick(), duration, e.velocity().unwrap())
+            })
+            .collect()
+    }
+
+    fn pattern() -> MidiTrack {
+        let mut track = MidiTrack::new();
+        for beat in 0..16 {
+            let tick = 1920 + beat * 240;
+            track.add_note(tick, 200, 0, 60, 80);
+            track.add_note(tick, 200, 0, 64, 80);
+        }
+        track.link_note_events();
+        track
+    }
+
+    #[test]
+    fn test_humanize_is_seeded_and_bounded() {
+        let options = HumanizeOptions::new(7)
+            .with_distribution(HumanizeDistribution::Uniform)
+            .with_timing(10)
+            .with_velocity(10)
+            .with_length(5.0);
+        let original = notes(&pattern());
+
+        let mut first = pattern();
+        first.humanize(&options);
+        let mut second = pattern();
+        second.humanize(&options);
+        assert_eq!(notes(&first), notes(&second));
+        assert!(first.events().iter().all(MidiEvent::is_linked));
+
+        let humanized = notes(&first);
+        assert_ne!(humanized, original);
+        for &(tick, duration, velocity) in &humanized {
+            let base = original
+                .iter()
+                .map(|&(t, _, _)| t)
+                .min_by_key(|&t| t.abs_diff(tick))
+                .unwrap();
+            assert!(tick.abs_diff(base) <= 10);
+            assert!((190..=210).contains(&duration));
+            assert!((70..=90).contains(&velocity));
+        }
+
+        let mut other = pattern();
+        other.humanize(&HumanizeOptions { seed: 8, ..options });
+        assert_ne!(notes(&other), humanized);
+    }
+
+    #[test]
+    fn test_humanize_tight_chords_and_meter_accents() {
+        let mut meter = MeterMap::new(480);
+        meter.set_time_signature(0, TimeSignature::new(4, 4));
+        let mut track = pattern();
+        track.humanize(
+            &HumanizeOptions::new(3)
+                .with_timing(30)
+                .with_tight_chords(true)
+                .with_meter(meter, 20),
+        );
+        let humanized = notes(&track);
+        let mut by_onset: Vec<u64> = humanized.iter().map(|&(tick, _, _)| tick).collect();
+        by_onset.dedup();
+        assert_eq!(by_onset.len(), 16);
+
+        // The downbeat of bar 2 is accented; the eighth after it is not.
+        assert_eq!(humanized[0].2, 100);
+        assert_eq!(humanized[2].2, 65);
+    }
+}
@@ -0,0 +1,618 @@
+//! Lenient Standard MIDI File parsing
+//!
+//! `MidiFile::from_bytes_lenient` keeps going past the problems that make
+//! `MidiFile::from_bytes` fail (truncated tracks, wrong chunk lengths,
+//! missing End-of-Track, stray data bytes, unknown chunks), salvaging every
+//! event it can and recording each problem it worked around as a
+//! `ParseDiagnostic`.
+
+use std::fmt;
+
+use super::event::MidiEvent;
+use super::file::{MidiFile, decode_sysex, read_u16_be, read_u32_be, read_varlen};
+use super::message::{MetaEvent, MidiMessage};
+use super::track::MidiTrack;
+use super::{MidiError, MidiFormat, TimeDivision};
+
+/// How serious a parse problem was
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The file deviates from the spec, but no data was lost.
+    Warning,
+    /// Some bytes could not be interpreted and were dropped.
+    Error,
+}
+
+/// A problem found (and worked around) by the lenient parser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// Unknown header format; the file was read as format 1.
+    InvalidFormat(u16),
+    /// Unknown SMPTE frame rate; the file was read at 480 PPQN.
+    InvalidTimeDivision(u16),
+    /// The header's track count disagrees with the tracks found.
+    TrackCountMismatch { expected: usize, found: usize },
+    /// A chunk other than `MTrk` was skipped.
+    UnknownChunk([u8; 4]),
+    /// Bytes that are not part of any chunk were skipped.
+    Garbage { bytes: usize },
+    /// A track chunk's declared length did not match where the next chunk
+    /// actually starts.
+    ChunkLengthMismatch { declared: usize, actual: usize },
+    /// A track chunk runs past the end of the file.
+    TruncatedChunk { declared: usize, available: usize },
+    /// An event was cut off by the end of its track or by a new status byte.
+    TruncatedEvent,
+    /// A delta time was not a valid variable-length quantity.
+    InvalidVarLen,
+    /// Data bytes with no status byte (or running status) to apply to.
+    StrayDataBytes { count: usize },
+    /// A status byte that cannot appear in a file (0xF1-0xFE).
+    InvalidStatus(u8),
+    /// The track had no End-of-Track meta event; one was added.
+    MissingEndOfTrack,
+    /// Bytes after the End-of-Track meta event were ignored.
+    DataAfterEndOfTrack { bytes: usize },
+}
+
+impl DiagnosticKind {
+    /// Severity of this kind of problem
+    pub fn severity(&self) -> Severity {
+        match self {
+            DiagnosticKind::InvalidFormat(_)
+            | DiagnosticKind::InvalidTimeDivision(_)
+            | DiagnosticKind::TrackCountMismatch { .. }
+            | DiagnosticKind::UnknownChunk(_)
+            | DiagnosticKind::ChunkLengthMismatch { .. }
+            | DiagnosticKind::MissingEndOfTrack
+            | DiagnosticKind::DataAfterEndOfTrack { .. } => Severity::Warning,
+            DiagnosticKind::Garbage { .. }
+            | DiagnosticKind::TruncatedChunk { .. }
+            | DiagnosticKind::TruncatedEvent
+            | DiagnosticKind::InvalidVarLen
+            | DiagnosticKind::StrayDataBytes { .. }
+            | DiagnosticKind::InvalidStatus(_) => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiagnosticKind::InvalidFormat(format) => {
+                write!(f, "invalid MIDI format {}, assuming 1", format)
+            }
+            DiagnosticKind::InvalidTimeDivision(division) => {
+                write!(
+                    f,
+                    "invalid time division {:#06x}, assuming 480 PPQN",
+                    division
+                )
+            }
+            DiagnosticKind::TrackCountMismatch { expected, found } => {
+                write!(f, "header declares {} tracks, found {}", expected, found)
+            }
+            DiagnosticKind::UnknownChunk(id) => {
+                write!(f, "skipped unknown chunk {:?}", String::from_utf8_lossy(id))
+            }
+            DiagnosticKind::Garbage { bytes } => {
+                write!(f, "skipped {} bytes outside any chunk", bytes)
+            }
+            DiagnosticKind::ChunkLengthMismatch { declared, actual } => write!(
+                f,
+                "track chunk declares {} bytes but is {} bytes long",
+                declared, actual
+            ),
+            DiagnosticKind::TruncatedChunk {
+                declared,
+                available,
+            } => write!(
+                f,
+                "track chunk declares {} bytes but only {} remain",
+                declared, available
+            ),
+            DiagnosticKind::TruncatedEvent => write!(f, "truncated event"),
+            DiagnosticKind::InvalidVarLen => write!(f, "invalid variable-length quantity"),
+            DiagnosticKind::StrayDataBytes { count } => {
+                write!(f, "skipped {} data bytes without a status byte", count)
+            }
+            DiagnosticKind::InvalidStatus(status) => {
+                write!(f, "skipped invalid status byte {:#04x}", status)
+            }
+            DiagnosticKind::MissingEndOfTrack => write!(f, "missing End-of-Track, added one"),
+            DiagnosticKind::DataAfterEndOfTrack { bytes } => {
+                write!(f, "ignored {} bytes after End-of-Track", bytes)
+            }
+        }
+    }
+}
+
+/// A problem reported by `MidiFile::from_bytes_lenient`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDiagnostic {
+    /// Byte offset into the file where the problem was found
+    pub offset: usize,
+    /// Index of the track being parsed, if any
+    pub track: Option<usize>,
+    /// How serious the problem was
+    pub severity: Severity,
+    /// What went wrong
+    pub kind: DiagnosticKind,
+}
+
+impl fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.track {
+            Some(track) => write!(
+                f,
+                "{} at byte {} (track {}): {}",
+                severity, self.offset, track, self.kind
+            ),
+            None => write!(f, "{} at byte {}: {}", severity, self.offset, self.kind),
+        }
+    }
+}
+
+fn push(
+    diagnostics: &mut Vec<ParseDiagnostic>,
+    offset: usize,
+    track: Option<usize>,
+    kind: DiagnosticKind,
+) {
+    diagnostics.push(ParseDiagnostic {
+        offset,
+        track,
+        severity: kind.severity(),
+        kind,
+    });
+}
+
+/// Printable ASCII, as used by every registered chunk type.
+fn is_chunk_id(bytes: &[u8]) -> bool {
+    bytes.len() >= 4 && bytes[..4].iter().all(|b| (0x20..0x7F).contains(b))
+}
+
+fn find_track_chunk(data: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(4)
+        .position(|w| w == b"MTrk")
+        .map(|i| from + i)
+}
+
+/// Parse a file, recovering from everything except a missing header.
+pub(super) fn parse(data: &[u8]) -> Result<(MidiFile, Vec<ParseDiagnostic>), MidiError> {
+    let mut diagnostics = Vec::new();
+
+    if data.len() < 14 || &data[0..4] != b"MThd" {
+        return Err(MidiError::InvalidHeader);
+    }
+    let header_len = read_u32_be(&data[4..8]) as usize;
+    if header_len < 6 {
+        return Err(MidiError::InvalidHeader);
+    }
+
+    let raw_format = read_u16_be(&data[8..10]);
+    let format = MidiFormat::try_from(raw_format).unwrap_or_else(|_| {
+        push(
+            &mut diagnostics,
+            8,
+            None,
+            DiagnosticKind::InvalidFormat(raw_format),
+        );
+        MidiFormat::MultiTrack
+    });
+    let num_tracks = read_u16_be(&data[10..12]) as usize;
+    let raw_division = read_u16_be(&data[12..14]);
+    let division = TimeDivision::try_from(raw_division).unwrap_or_else(|_| {
+        push(
+            &mut diagnostics,
+            12,
+            None,
+            DiagnosticKind::InvalidTimeDivision(raw_division),
+        );
+        TimeDivision::default()
+    });
+
+    let mut midi_file = MidiFile::with_format(format, 480);
+    midi_file.set_time_division(division);
+
+    let mut pos = 8usize.saturating_add(header_len).min(data.len());
+    while pos + 8 <= data.len() {
+        let id = &data[pos..pos + 4];
+        let declared = read_u32_be(&data[pos + 4..pos + 8]) as usize;
+
+        if id != b"MTrk" {
+            if is_chunk_id(id) {
+                let mut chunk_id = [0u8; 4];
+                chunk_id.copy_from_slice(id);
+                push(
+                    &mut diagnostics,
+                    pos,
+                    None,
+                    DiagnosticKind::UnknownChunk(chunk_id),
+                );
+                pos = (pos + 8).saturating_add(declared).min(data.len());
+            } else {
+                let next = find_track_chunk(data, pos + 1).unwrap_or(data.len());
+                push(
+                    &mut diagnostics,
+                    pos,
+                    None,
+                    DiagnosticKind::Garbage { bytes: next - pos },
+                );
+                pos = next;
+            }
+            continue;
+        }
+
+        let track_index = midi_file.num_tracks();
+        let start = pos + 8;
+        let declared_end = start.saturating_add(declared);
+        let end = if declared_end > data.len() {
+            match find_track_chunk(data, start) {
+                Some(next) => {
+                    push(
+                        &mut diagnostics,
+                        pos + 4,
+                        Some(track_index),
+                        DiagnosticKind::ChunkLengthMismatch {
+                            declared,
+                            actual: next - start,
+                        },
+                    );
+                    next
+                }
+                None => {
+                    push(
+                        &mut diagnostics,
+                        pos + 4,
+                        Some(track_index),
+                        DiagnosticKind::TruncatedChunk {
+                            declared,
+                            available: data.len() - start,
+                        },
+                    );
+                    data.len()
+                }
+            }
+        } else if declared_end < data.len() && !is_chunk_id(&data[declared_end..]) {
+            // The declared length doesn't land on another chunk: trust the
+            // position of the next track header instead.
+            let next = find_track_chunk(data, start).unwrap_or(data.len());
+            push(
+                &mut diagnostics,
+                pos + 4,
+                Some(track_index),
+                DiagnosticKind::ChunkLengthMismatch {
+                    declared,
+                    actual: next - start,
+                },
+            );
+            next
+        } else {
+            declared_end
+        };
+
+        let track = parse_track(&data[start..end], start, track_index, &mut diagnostics);
+        midi_file.add_track_from(track);
+        pos = end;
+    }
+
+    if pos < data.len() {
+        push(
+            &mut diagnostics,
+            pos,
+            None,
+            DiagnosticKind::Garbage {
+                bytes: data.len() - pos,
+            },
+        );
+    }
+    if midi_file.num_tracks() != num_tracks {
+        push(
+            &mut diagnostics,
+            10,
+            None,
+            DiagnosticKind::TrackCountMismatch {
+                expected: num_tracks,
+                found: midi_file.num_tracks(),
+            },
+        );
+    }
+
+    Ok((midi_file, diagnostics))
+}
+
+/// Parse one track chunk's body. `base` is the body's offset in the file.
+fn parse_track(
+    data: &[u8],
+    base: usize,
+    track_index: usize,
+    diagnostics: &mut Vec<ParseDiagnostic>,
+) -> MidiTrack {
+    let track_id = Some(track_index);
+    let mut track = MidiTrack::new();
+    let mut pos = 0;
+    let mut running_status: Option<u8> = None;
+    let mut current_tick: u64 = 0;
+    let mut has_end_of_track = false;
+    // False when resuming at a status byte that interrupted the previous
+    // event, which has no delta time of its own.
+    let mut read_delta = true;
+
+    while pos < data.len() {
+        if read_delta {
+            let Some((delta, delta_len)) = read_varlen(&data[pos..]) else {
+                push(
+                    diagnostics,
+                    base + pos,
+                    track_id,
+                    DiagnosticKind::InvalidVarLen,
+                );
+                break;
+            };
+            pos += delta_len;
+            current_tick += delta as u64;
+            if pos >= data.len() {
+                push(
+                    diagnostics,
+                    base + pos,
+                    track_id,
+                    DiagnosticKind::TruncatedEvent,
+                );
+                break;
+            }
+        }
+        read_delta = true;
+
+        let event_start = pos;
+        let status = data[pos];
+        match status {
+            0xFF => {
+                let Some((meta, meta_len)) = data.get(pos + 1..).and_then(MetaEvent::from_bytes)
+                else {
+                    push(
+                        diagnostics,
+                        base + pos,
+                        track_id,
+                        DiagnosticKind::TruncatedEvent,
+                    );
+                    break;
+                };
+                pos += 1 + meta_len;
+                running_status = None;
+                let is_end = matches!(meta, MetaEvent::EndOfTrack);
+                track.add_event(MidiEvent::new(current_tick, MidiMessage::Meta(meta)));
+                if is_end {
+                    has_end_of_track = true;
+                    break;
+                }
+            }
+            0xF0 | 0xF7 => {
+                let body = read_varlen(&data[pos + 1..])
+                    .map(|(length, len_bytes)| (pos + 1 + len_bytes, length as usize))
+                    .filter(|&(start, length)| start + length <= data.len());
+                let Some((start, length)) = body else {
+                    push(
+                        diagnostics,
+                        base + pos,
+                        track_id,
+                        DiagnosticKind::TruncatedEvent,
+                    );
+                    break;
+                };
+                let (message, framing) = decode_sysex(status, &data[start..start + length]);
+                let mut event = MidiEvent::new(current_tick, message);
+                event.set_sysex_framing(framing);
+                track.add_event(event);
+                pos = start + length;
+                running_status = None;
+            }
+            0xF1..=0xFE => {
+                push(
+                    diagnostics,
+                    base + pos,
+                    track_id,
+                    DiagnosticKind::InvalidStatus(status),
+                );
+                pos += 1;
+            }
+            _ => {
+                let (status, data_start) = if status & 0x80 != 0 {
+                    running_status = Some(status);
+                    (status, pos + 1)
+                } else if let Some(status) = running_status {
+                    (status, pos)
+                } else {
+                    let count = data[pos..]
+                        .iter()
+                        .position(|b| b & 0x80 != 0)
+                        .unwrap_or(data.len() - pos);
+                    push(
+                        diagnostics,
+                        base + pos,
+                        track_id,
+                        DiagnosticKind::StrayDataBytes { count },
+                    );
+                    pos += count;
+                    read_delta = false;
+                    continue;
+                };
+
+                let needed = if matches!(status & 0xF0, 0xC0 | 0xD0) {
+                    1
+                } else {
+                    2
+                };
+                let bytes = &data[data_start..(data_start + needed).min(data.len())];
+                if let Some(i) = bytes.iter().position(|b| b & 0x80 != 0) {
+                    push(
+                        diagnostics,
+                        base + event_start,
+                        track_id,
+                        DiagnosticKind::TruncatedEvent,
+                    );
+                    pos = data_start + i;
+                    read_delta = false;
+                    continue;
+                }
+                if bytes.len() < needed {
+                    push(
+                        diagnostics,
+                        base + event_start,
+                        track_id,
+                        DiagnosticKind::TruncatedEvent,
+                    );
+                    break;
+                }
+
+                let mut message = [status, 0, 0];
+                message[1..=needed].copy_from_slice(bytes);
+                if let Some((message, _)) = MidiMessage::from_bytes(&message[..=needed]) {
+                    track.add_event(MidiEvent::new(current_tick, message));
+                }
+                pos = data_start + needed;
+            }
+        }
+    }
+
+    if has_end_of_track && pos < data.len() {
+        push(
+            diagnostics,
+            base + pos,
+            track_id,
+            DiagnosticKind::DataAfterEndOfTrack {
+                bytes: data.len() - pos,
+            },
+        );
+    }
+    if !has_end_of_track {
+        push(
+            diagnostics,
+            base + data.len(),
+            track_id,
+            DiagnosticKind::MissingEndOfTrack,
+        );
+        track.add_event(MidiEvent::new(
+            current_tick,
+            MidiMessage::Meta(MetaEvent::EndOfTrack),
+        ));
+    }
+
+    // Same as the strict reader: keep the file's event order as a tie-break.
+    track.mark_sequence();
+    track
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn smf(tracks: &[&[u8]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(b"MThd");
+        data.extend(&6u32.to_be_bytes());
+        data.extend(&1u16.to_be_bytes());
+        data.extend(&(tracks.len() as u16).to_be_bytes());
+        data.extend(&480u16.to_be_bytes());
+        for track in tracks {
+            data.extend(b"MTrk");
+            data.extend(&(track.len() as u32).to_be_bytes());
+            data.extend(*track);
+        }
+        data
+    }
+
+    fn kinds(diagnostics: &[ParseDiagnostic]) -> Vec<&DiagnosticKind> {
+        diagnostics.iter().map(|d| &d.kind).collect()
+    }
+
+    #[test]
+    fn test_valid_file_matches_strict_reader() {
+        let bytes = smf(&[&[0x00, 0x90, 60, 100, 0x60, 60, 0, 0x00, 0xFF, 0x2F, 0x00]]);
+        let (file, diagnostics) = MidiFile::from_bytes_lenient(&bytes).unwrap();
+        assert!(diagnostics.is_empty());
+        let strict = MidiFile::from_bytes(&bytes).unwrap();
+        assert_eq!(file.tracks()[0].events(), strict.tracks()[0].events());
+    }
+
+    #[test]
+    fn test_truncated_track_and_missing_end_of_track() {
+        // Second note-on is cut off, and the chunk claims more bytes than exist.
+        let mut bytes = smf(&[&[0x00, 0x90, 60, 100, 0x60, 0x80, 60]]);
+        bytes[18..22].copy_from_slice(&100u32.to_be_bytes());
+
+        assert!(MidiFile::from_bytes(&bytes).is_err());
+        let (file, diagnostics) = MidiFile::from_bytes_lenient(&bytes).unwrap();
+        assert_eq!(
+            kinds(&diagnostics),
+            vec![
+                &DiagnosticKind::TruncatedChunk {
+                    declared: 100,
+                    available: 7
+                },
+                &DiagnosticKind::TruncatedEvent,
+                &DiagnosticKind::MissingEndOfTrack,
+            ]
+        );
+        assert_eq!(diagnostics[1].offset, 22 + 5);
+        assert_eq!(diagnostics[1].track, Some(0));
+        assert_eq!(diagnostics[1].severity, Severity::Error);
+
+        let events = file.tracks()[0].events();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].is_note_on());
+        assert_eq!(events[1].tick(), 0x60);
+        assert!(matches!(
+            events[1].message(),
+            MidiMessage::Meta(MetaEvent::EndOfTrack)
+        ));
+    }
+
+    #[test]
+    fn test_stray_bytes_unknown_chunk_and_wrong_length() {
+        let track0: &[u8] = &[
+            0x00, 0x3C, 0x40, // stray data bytes, no running status yet
+            0x90, 60, 100, // note-on (no delta: resumes at the status byte)
+            0x10, 0xF4, // undefined system common status
+            0x00, 0x80, 60, 0, 0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let track1: &[u8] = &[0x00, 0xC0, 5, 0x00, 0xFF, 0x2F, 0x00, 0xAA];
+        let mut bytes = smf(&[track0, track1]);
+        // Claim a short length for track 0 so its tail is not a chunk header.
+        bytes[18..22].copy_from_slice(&10u32.to_be_bytes());
+        // Insert an unknown chunk before track 0.
+        bytes.splice(14..14, [b'X', b'F', b'I', b'H', 0, 0, 0, 2, 1, 2]);
+
+        let (file, diagnostics) = MidiFile::from_bytes_lenient(&bytes).unwrap();
+        assert_eq!(
+            kinds(&diagnostics),
+            vec![
+                &DiagnosticKind::UnknownChunk(*b"XFIH"),
+                &DiagnosticKind::ChunkLengthMismatch {
+                    declared: 10,
+                    actual: 16
+                },
+                &DiagnosticKind::StrayDataBytes { count: 2 },
+                &DiagnosticKind::InvalidStatus(0xF4),
+                &DiagnosticKind::DataAfterEndOfTrack { bytes: 1 },
+            ]
+        );
+        assert_eq!(diagnostics[0].offset, 14);
+        assert_eq!(diagnostics[2].offset, 32 + 1);
+        assert_eq!(diagnostics[4].track, Some(1));
+        assert_eq!(file.num_tracks(), 2);
+        let events = file.tracks()[0].events();
+        assert!(events[0].is_note_on());
+        assert!(events[1].is_note_off());
+        assert_eq!(events[1].tick(), 0x10);
+        assert!(matches!(
+            file.tracks()[1].events()[0].message(),
+            MidiMessage::ProgramChange { program: 5, .. }
+        ));
+    }
+}
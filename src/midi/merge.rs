@@ -0,0 +1,363 @@
+//! Combining whole MIDI files
+//!
+//! `MidiFile::append` and `MidiFile::concatenate` play files one after
+//! another; `MidiFile::layer` plays them at the same time. Unlike
+//! `merge_tracks`, which works within one file, these reconcile the
+//! resolutions, tempo maps and channels of separate files.
+
+use super::event::MidiEvent;
+use super::file::MidiFile;
+use super::message::{MetaEvent, MidiMessage};
+use super::resample::TickRounding;
+use super::track::MidiTrack;
+use super::{MidiError, MidiFormat, TimeDivision};
+use crate::notation::TimeSignature;
+
+/// How `MidiFile::layer` keeps files from sounding on each other's channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChannelCollision {
+    /// Leave channels alone; files using the same channel share it
+    Share,
+    /// Move each file's channels to channels no earlier file uses. Drums on
+    /// channel 10 stay there, so drum parts share it.
+    #[default]
+    Remap,
+    /// Leave channels alone and put each file on its own port: every track
+    /// of the n-th file starts with a MIDI port meta event for port n
+    SeparatePorts,
+}
+
+fn is_end_of_track(event: &MidiEvent) -> bool {
+    matches!(event.message(), MidiMessage::Meta(MetaEvent::EndOfTrack))
+}
+
+/// Events that only the first of several layered files keeps.
+fn is_conductor_timing(message: &MidiMessage) -> bool {
+    matches!(
+        message,
+        MidiMessage::Meta(
+            MetaEvent::Tempo(_) | MetaEvent::TimeSignature { .. } | MetaEvent::SmpteOffset { .. }
+        )
+    )
+}
+
+/// `message` on channel `channel`, if it is a channel message.
+fn rechannel(message: &MidiMessage, channel: u8) -> MidiMessage {
+    let mut message = message.clone();
+    match &mut message {
+        MidiMessage::NoteOff { channel: c, .. }
+        | MidiMessage::NoteOn { channel: c, .. }
+        | MidiMessage::PolyPressure { channel: c, .. }
+        | MidiMessage::ControlChange { channel: c, .. }
+        | MidiMessage::ProgramChange { channel: c, .. }
+        | MidiMessage::ChannelPressure { channel: c, .. }
+        | MidiMessage::PitchBend { channel: c, .. } => *c = channel,
+        _ => {}
+    }
+    message
+}
+
+/// A copy of `file` at `division`, rescaled with `MidiFile::resample` if
+/// needed.
+fn rescaled(file: &MidiFile, division: TimeDivision) -> Result<MidiFile, MidiError> {
+    let mut file = file.clone();
+    if file.time_division() != division {
+        let TimeDivision::Ppqn(ticks_per_quarter) = division else {
+            return Err(MidiError::InvalidTimeDivision(division.to_u16()));
+        };
+        file.resample(ticks_per_quarter, TickRounding::Nearest)?;
+    }
+    Ok(file)
+}
+
+/// Merge the events of `source`, `offset` ticks later, into `target`, and
+/// end `target` at its last event or at `end`, whichever is later. Note
+/// links and sequence numbers are rebuilt if `target` had them.
+fn merge_into(target: &mut MidiTrack, source: &MidiTrack, offset: u64, end: u64) {
+    let linked = target.events().iter().any(MidiEvent::is_linked);
+    let sequenced = target.events().iter().any(|e| e.seq() != 0);
+    target.events_mut().retain(|event| !is_end_of_track(event));
+    for event in source.events().iter().filter(|e| !is_end_of_track(e)) {
+        let mut event = event.clone();
+        event.set_tick(event.tick() + offset);
+        event.unlink_event();
+        event.set_seq(0);
+        target.add_event(event);
+    }
+    let last = target.events().iter().map(MidiEvent::tick).max();
+    target.add_event(MidiEvent::new(
+        last.unwrap_or(0).max(end),
+        MidiMessage::Meta(MetaEvent::EndOfTrack),
+    ));
+    target.sort();
+    if linked {
+        target.link_note_events();
+    }
+    if sequenced {
+        target.mark_sequence();
+    }
+}
+
+/// Channel map for the next layered file: each channel `used` is kept if
+/// free and moved to the lowest free channel otherwise. Channel 10 is
+/// never moved or handed out.
+fn remap_channels(used: &[bool; 16], taken: &mut [bool; 16]) -> Option<[u8; 16]> {
+    const DRUMS: usize = 9;
+    let mut map: [u8; 16] = std::array::from_fn(|channel| channel as u8);
+    let mut claimed = *taken;
+    for (channel, _) in used.iter().enumerate().filter(|&(_, &used)| used) {
+        if channel == DRUMS || !taken[channel] {
+            claimed[channel] = true;
+            continue;
+        }
+        let free = (0..16).find(|&c| c != DRUMS && !claimed[c] && !used[c])?;
+        map[channel] = free as u8;
+        claimed[free] = true;
+    }
+    *taken = claimed;
+    Some(map)
+}
+
+impl MidiFile {
+    /// Append `other` after the end of this file, `gap` ticks later.
+    /// `other` is rescaled to this file's resolution, and its starting
+    /// tempo and time signature are set explicitly where it relies on the
+    /// defaults, so it plays as it would on its own. Tracks are appended to
+    /// the track with the same index (all to the first track in a format 0
+    /// file), adding tracks as needed.
+    ///
+    /// Fails if the resolutions differ and either file uses SMPTE timing.
+    pub fn append(&mut self, other: &MidiFile, gap: u64) -> Result<(), MidiError> {
+        let mut other = rescaled(other, self.time_division())?;
+        let at = self.total_ticks() + gap;
+        let end = at + other.total_ticks();
+
+        let starts_with = |file: &MidiFile, is_kind: fn(&MetaEvent) -> bool| {
+            file.tracks().iter().flat_map(|t| t.events()).any(|e| {
+                e.tick() == 0 && matches!(e.message(), MidiMessage::Meta(meta) if is_kind(meta))
+            })
+        };
+        if other.num_tracks() == 0 {
+            other.add_track();
+        }
+        if !starts_with(&other, |m| matches!(m, MetaEvent::Tempo(_)))
+            && self.tempo_map().bpm_at(at) != 120.0
+        {
+            other.tracks_mut()[0].add_tempo(0, 120.0);
+        }
+        if !starts_with(&other, |m| matches!(m, MetaEvent::TimeSignature { .. }))
+            && self.meter_map().time_signature_at(at) != TimeSignature::common_time()
+        {
+            other.tracks_mut()[0].add_time_signature(0, 4, 4);
+        }
+
+        let single = self.format() == MidiFormat::SingleTrack;
+        for (index, track) in other.tracks().iter().enumerate() {
+            let index = if single { 0 } else { index };
+            while self.num_tracks() <= index {
+                self.add_track();
+            }
+            merge_into(&mut self.tracks_mut()[index], track, at, end);
+        }
+        Ok(())
+    }
+
+    /// Play `files` one after another, with `gap` ticks between them (see
+    /// `append`). The result has the format and resolution of the first
+    /// file.
+    pub fn concatenate(files: &[MidiFile], gap: u64) -> Result<MidiFile, MidiError> {
+        let Some((first, rest)) = files.split_first() else {
+            return Ok(MidiFile::new());
+        };
+        let mut result = first.clone();
+        for file in rest {
+            result.append(file, gap)?;
+        }
+        Ok(result)
+    }
+
+    /// Play `files` at the same time, as a format 1 file holding the tracks
+    /// of each file in turn.
+    ///
+    /// The result's resolution is the least common multiple of the files'
+    /// resolutions (or the highest one, if that does not fit in the header),
+    /// so every file is rescaled exactly where possible. The first file is
+    /// the conductor: tempo, time signature and SMPTE offset events of the
+    /// other files are dropped. `channels` decides how files sharing a
+    /// channel are kept apart; `Remap` fails if the files need more
+    /// channels than there are.
+    pub fn layer(files: &[MidiFile], channels: ChannelCollision) -> Result<MidiFile, MidiError> {
+        let mut ticks_per_quarter = 0u16;
+        for file in files {
+            let TimeDivision::Ppqn(tpq) = file.time_division() else {
+                return Err(MidiError::InvalidTimeDivision(
+                    file.time_division().to_u16(),
+                ));
+            };
+            ticks_per_quarter = match ticks_per_quarter {
+                0 => tpq,
+                current => {
+                    let lcm = num::integer::lcm(current as u32, tpq as u32);
+                    if lcm <= 0x7FFF {
+                        lcm as u16
+                    } else {
+                        current.max(tpq)
+                    }
+                }
+            };
+        }
+        if ticks_per_quarter == 0 {
+            return Ok(MidiFile::new());
+        }
+
+        let division = TimeDivision::Ppqn(ticks_per_quarter);
+        let mut result = MidiFile::with_format(MidiFormat::MultiTrack, ticks_per_quarter);
+        let mut taken = [false; 16];
+        for (index, file) in files.iter().enumerate() {
+            let file = rescaled(file, division)?;
+            let map = match channels {
+                ChannelCollision::Remap => {
+                    let mut used = [false; 16];
+                    for event in file.tracks().iter().flat_map(|t| t.events()) {
+                        if let Some(channel) = event.channel() {
+                            used[channel as usize & 0x0F] = true;
+                        }
+                    }
+                    remap_channels(&used, &mut taken).ok_or(MidiError::ChannelsExhausted(index))?
+                }
+                _ => std::array::from_fn(|channel| channel as u8),
+            };
+
+            for track in file.tracks() {
+                let mut track = track.clone();
+                track.events_mut().retain(|event| match event.message() {
+                    MidiMessage::Meta(MetaEvent::MidiPort(_)) => {
+                        channels != ChannelCollision::SeparatePorts
+                    }
+                    message => index == 0 || !is_conductor_timing(message),
+                });
+                for event in track.events_mut() {
+                    if let Some(channel) = event.channel() {
+                        let message = rechannel(event.message(), map[channel as usize & 0x0F]);
+                        event.set_message(message);
+                    }
+                }
+                if channels == ChannelCollision::SeparatePorts {
+                    let mut port =
+                        MidiEvent::new(0, MidiMessage::Meta(MetaEvent::MidiPort(index as u8)));
+                    port.set_seq(0);
+                    track.add_event(port);
+                    track.sort();
+                    if track.events().iter().any(|e| e.seq() != 0) {
+                        track.mark_sequence();
+                    }
+                }
+                result.add_track_from(track);
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notes(file: &MidiFile, track: usize) -> Vec<(u64, u64, u8, u8)> {
+        let mut track = file.tracks()[track].clone();
+        track.link_note_events();
+        let events = track.events();
+        events
+            .iter()
+            .filter(|e| e.is_note_on())
+            .map(|e| {
+                let duration = e.tick_duration(events).unwrap();
+                (e.tick(), duration, e.channel().unwrap(), e.key().unwrap())
+            })
+            .collect()
+    }
+
+    fn part(ticks_per_quarter: u16, channel: u8, key: u8) -> MidiFile {
+        let mut file = MidiFile::with_format(MidiFormat::MultiTrack, ticks_per_quarter);
+        file.add_tracks(2);
+        let quarter = ticks_per_quarter as u64;
+        file.add_note(1, 0, quarter, channel, key, 100).unwrap();
+        file.add_note(1, quarter, quarter, 9, 36, 100).unwrap();
+        file.finalize();
+        file
+    }
+
+    #[test]
+    fn test_append_rescales_and_keeps_tempo() {
+        let mut first = part(480, 0, 60);
+        first.add_tempo(0, 0, 100.0).unwrap();
+        first.add_time_signature(0, 0, 3, 4).unwrap();
+        let second = part(960, 1, 64);
+        first.append(&second, 480).unwrap();
+
+        assert_eq!(
+            notes(&first, 1),
+            vec![
+                (0, 480, 0, 60),
+                (480, 480, 9, 36),
+                (1440, 480, 1, 64),
+                (1920, 480, 9, 36)
+            ]
+        );
+        let tempo = first.tempo_map();
+        assert_eq!(tempo.bpm_at(1439), 100.0);
+        assert_eq!(tempo.bpm_at(1440), 120.0);
+        assert_eq!(
+            first.meter_map().time_signature_at(1440),
+            TimeSignature::common_time()
+        );
+        assert_eq!(first.total_ticks(), 2400);
+        assert!(
+            first.tracks()[1]
+                .events()
+                .last()
+                .is_some_and(is_end_of_track)
+        );
+
+        let joined = MidiFile::concatenate(&[part(480, 0, 60), part(480, 0, 62)], 0).unwrap();
+        assert_eq!(notes(&joined, 1)[2], (960, 480, 0, 62));
+        assert_eq!(joined.tempo_map().points().len(), 1);
+    }
+
+    #[test]
+    fn test_layer_remaps_channels() {
+        let mut first = part(480, 0, 60);
+        first.add_tempo(0, 0, 80.0).unwrap();
+        let mut second = part(384, 0, 64);
+        second.add_tempo(0, 0, 140.0).unwrap();
+
+        let layered =
+            MidiFile::layer(&[first.clone(), second.clone()], ChannelCollision::Remap).unwrap();
+        assert_eq!(layered.ticks_per_quarter(), 1920);
+        assert_eq!(layered.num_tracks(), 4);
+        assert_eq!(
+            notes(&layered, 1),
+            vec![(0, 1920, 0, 60), (1920, 1920, 9, 36)]
+        );
+        assert_eq!(
+            notes(&layered, 3),
+            vec![(0, 1920, 1, 64), (1920, 1920, 9, 36)]
+        );
+        assert_eq!(layered.tempo_map().bpm_at(0), 80.0);
+        assert_eq!(layered.tempo_map().points().len(), 1);
+
+        let ports = MidiFile::layer(&[first, second], ChannelCollision::SeparatePorts).unwrap();
+        assert_eq!(notes(&ports, 3)[0].2, 0);
+        assert_eq!(
+            ports.tracks()[3].events()[0].message(),
+            &MidiMessage::Meta(MetaEvent::MidiPort(1))
+        );
+
+        let crowd: Vec<MidiFile> = (0..16).map(|_| part(480, 0, 60)).collect();
+        assert!(matches!(
+            MidiFile::layer(&crowd, ChannelCollision::Remap),
+            Err(MidiError::ChannelsExhausted(15))
+        ));
+        assert!(MidiFile::layer(&crowd[..15], ChannelCollision::Remap).is_ok());
+    }
+}
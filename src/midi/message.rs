@@ -30,7 +30,8 @@ pub enum MidiMessage {
         /// 14-bit value: 0x2000 = center, range 0x0000-0x3FFF
         value: u16,
     },
-    /// System exclusive message
+    /// System exclusive message (payload without the F0/F7 framing; see
+    /// `UniversalSysEx` for typed Universal SysEx payloads)
     SysEx(Vec<u8>),
     /// Meta event (in MIDI files only)
     Meta(MetaEvent),
@@ -3,17 +3,62 @@
 //! This module provides support for reading and writing Standard MIDI Files (SMF),
 //! as well as types for representing MIDI messages and events.
 
+mod chase;
+mod cleanup;
+mod clip;
+mod controller;
+mod csv;
+mod edit;
 mod event;
 mod file;
+mod humanize;
+mod lenient;
+mod merge;
 mod message;
+mod mpe;
+mod piano_roll;
+mod quantize;
+mod reader;
+mod resample;
+mod rmid;
+mod stats;
+mod sysex;
+mod tempo_map;
 mod track;
 mod translate;
+pub mod ump;
+mod validate;
 
+pub use chase::ChannelState;
+pub use cleanup::OverlapReport;
+pub use clip::MidiClip;
+pub use controller::{
+    ControllerEvent, ControllerEventKind, ParameterNumber, RPN_COARSE_TUNING, RPN_FINE_TUNING,
+    RPN_NULL, RPN_PITCH_BEND_RANGE,
+};
+pub use edit::BoundaryNotes;
 pub use event::{MidiEvent, NoteSortOrder, compare_events};
 pub use file::{MidiFile, TickState, TrackState};
+pub use humanize::{HumanizeDistribution, HumanizeOptions};
+pub use lenient::{DiagnosticKind, ParseDiagnostic, Severity};
+pub use merge::ChannelCollision;
 pub use message::{MetaEvent, MidiMessage, frequency_to_semitones};
+pub use mpe::{
+    CC_TIMBRE, DEFAULT_MANAGER_BEND_RANGE, DEFAULT_MEMBER_BEND_RANGE, MpeAllocator,
+    MpeConfiguration, MpeDecoder, MpeExpression, MpeNote, MpeNoteCurve, MpeNoteId, MpeNoteOn,
+    MpeZone, MpeZoneSide, RPN_MPE_CONFIGURATION,
+};
+pub use piano_roll::{PianoRoll, ROLL_PITCHES, RollCell, RollMatrix, RollOptions, RollResolution};
+pub use quantize::{GrooveSlot, GrooveTemplate, QuantizeOptions};
+pub use reader::{MergedEvents, SmfEvent, SmfReader, TrackEvents};
+pub use resample::{ResampleReport, TickRounding};
+pub use rmid::RmidInfo;
+pub use stats::{ChannelStats, MidiStatistics, NoteStats, ProgramUse, TrackStats};
+pub use sysex::{ALL_CALL, ManufacturerId, NoteTuning, SysExFraming, UniversalSysEx};
+pub use tempo_map::{BarBeatTick, MeterMap, MeterPoint, TempoMap, TempoPoint, TempoRamp};
 pub use track::MidiTrack;
 pub use translate::{MidiToScore, ScoreToMidi};
+pub use validate::{ValidationIssue, ValidationKind};
 
 use thiserror::Error;
 
@@ -42,6 +87,156 @@ impl TryFrom<u16> for MidiFormat {
     }
 }
 
+/// SMPTE frame rate of a `TimeDivision::Smpte` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmpteFps {
+    /// 24 frames per second (film)
+    Fps24,
+    /// 25 frames per second (PAL)
+    Fps25,
+    /// 29.97 frames per second, drop-frame (NTSC)
+    Fps29_97Drop,
+    /// 30 frames per second
+    Fps30,
+}
+
+impl SmpteFps {
+    /// Actual frame rate in frames per second (29.97 for drop-frame)
+    pub fn frames_per_second(&self) -> f64 {
+        match self {
+            SmpteFps::Fps24 => 24.0,
+            SmpteFps::Fps25 => 25.0,
+            SmpteFps::Fps29_97Drop => 30_000.0 / 1001.0,
+            SmpteFps::Fps30 => 30.0,
+        }
+    }
+
+    /// Nominal frame count as stored in the SMF header (24, 25, 29 or 30)
+    pub fn nominal(&self) -> u8 {
+        match self {
+            SmpteFps::Fps24 => 24,
+            SmpteFps::Fps25 => 25,
+            SmpteFps::Fps29_97Drop => 29,
+            SmpteFps::Fps30 => 30,
+        }
+    }
+
+    /// Look up a frame rate from its nominal header value
+    pub fn from_nominal(value: u8) -> Option<Self> {
+        match value {
+            24 => Some(SmpteFps::Fps24),
+            25 => Some(SmpteFps::Fps25),
+            29 => Some(SmpteFps::Fps29_97Drop),
+            30 => Some(SmpteFps::Fps30),
+            _ => None,
+        }
+    }
+}
+
+/// Time division of a MIDI file (the header's `division` word)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeDivision {
+    /// Metrical timing: ticks per quarter note
+    Ppqn(u16),
+    /// Timecode-based timing: ticks are fixed subdivisions of an SMPTE frame
+    /// and do not depend on tempo.
+    Smpte {
+        /// Frame rate
+        fps: SmpteFps,
+        /// Ticks (subframes) per frame
+        ticks_per_frame: u8,
+    },
+}
+
+impl TimeDivision {
+    /// Largest ticks per quarter note the header can store (the top bit
+    /// marks SMPTE timing)
+    pub const MAX_TICKS_PER_QUARTER: u16 = 0x7FFF;
+
+    /// A PPQN division, rejecting resolutions the header cannot store
+    pub fn ppqn(ticks_per_quarter: u16) -> Result<Self, MidiError> {
+        let division = TimeDivision::Ppqn(ticks_per_quarter);
+        division.validate()?;
+        Ok(division)
+    }
+
+    /// Check that the division can be stored in the header
+    pub fn validate(&self) -> Result<(), MidiError> {
+        match *self {
+            TimeDivision::Ppqn(tpq) if tpq > Self::MAX_TICKS_PER_QUARTER => {
+                Err(MidiError::InvalidTimeDivision(tpq))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether this is an SMPTE (timecode) division
+    pub fn is_smpte(&self) -> bool {
+        matches!(self, TimeDivision::Smpte { .. })
+    }
+
+    /// Ticks per quarter note. For SMPTE divisions this is the
+    /// `frames * ticks_per_frame` convention used by upstream midifile, which
+    /// treats the file as if it were at 60 BPM.
+    pub fn ticks_per_quarter(&self) -> u16 {
+        match *self {
+            TimeDivision::Ppqn(tpq) => tpq,
+            TimeDivision::Smpte {
+                fps,
+                ticks_per_frame,
+            } => fps.nominal() as u16 * ticks_per_frame as u16,
+        }
+    }
+
+    /// Ticks per second for SMPTE divisions (`None` for PPQN, where it
+    /// depends on tempo)
+    pub fn ticks_per_second(&self) -> Option<f64> {
+        match *self {
+            TimeDivision::Ppqn(_) => None,
+            TimeDivision::Smpte {
+                fps,
+                ticks_per_frame,
+            } => Some(fps.frames_per_second() * ticks_per_frame as f64),
+        }
+    }
+
+    /// Encode as the 16-bit header `division` word. `MidiFile` only holds
+    /// divisions that pass `validate`, which encode without loss.
+    pub fn to_u16(&self) -> u16 {
+        match *self {
+            TimeDivision::Ppqn(tpq) => tpq & Self::MAX_TICKS_PER_QUARTER,
+            TimeDivision::Smpte {
+                fps,
+                ticks_per_frame,
+            } => ((-(fps.nominal() as i8) as u8 as u16) << 8) | ticks_per_frame as u16,
+        }
+    }
+}
+
+impl Default for TimeDivision {
+    fn default() -> Self {
+        TimeDivision::Ppqn(480)
+    }
+}
+
+impl TryFrom<u16> for TimeDivision {
+    type Error = MidiError;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        if value & 0x8000 == 0 {
+            return Ok(TimeDivision::Ppqn(value));
+        }
+        // Top byte is the frame rate as a negative two's-complement i8,
+        // bottom byte is the number of ticks per frame.
+        let frames = ((value >> 8) as u8 as i8).unsigned_abs();
+        let fps = SmpteFps::from_nominal(frames).ok_or(MidiError::InvalidTimeDivision(value))?;
+        Ok(TimeDivision::Smpte {
+            fps,
+            ticks_per_frame: (value & 0xFF) as u8,
+        })
+    }
+}
+
 /// Errors that can occur during MIDI operations
 #[derive(Debug, Error)]
 pub enum MidiError {
@@ -57,6 +252,9 @@ pub enum MidiError {
     #[error("invalid MIDI format: {0}")]
     InvalidFormat(u16),
 
+    #[error("invalid time division: {0:#06x}")]
+    InvalidTimeDivision(u16),
+
     #[error("unexpected end of data")]
     UnexpectedEof,
 
@@ -74,6 +272,15 @@ pub enum MidiError {
 
     #[error("track index out of bounds: {0}")]
     TrackOutOfBounds(usize),
+
+    #[error("invalid CSV at line {line}: {reason}")]
+    InvalidCsv { line: usize, reason: String },
+
+    #[error("no midicsv record for status {status:#04x} in track {track} at tick {tick}")]
+    UnsupportedCsvEvent { track: usize, tick: u64, status: u8 },
+
+    #[error("no free channels left for file {0}")]
+    ChannelsExhausted(usize),
 }
 
 /// Standard MIDI controller numbers
@@ -0,0 +1,845 @@
+//! MPE (MIDI Polyphonic Expression)
+//!
+//! MPE gives every sounding note a channel of its own inside a zone, so
+//! pitch bend, channel pressure and CC 74 (timbre) act on single notes. A
+//! lower zone is managed from channel 1 with member channels counting up
+//! from channel 2; an upper zone is managed from channel 16 with members
+//! counting down from channel 15. `MpeZone` describes a zone and produces
+//! its configuration messages, `MpeAllocator` spreads outgoing notes over
+//! the member channels, and `MpeDecoder` turns an MPE stream back into
+//! per-note expression curves. Everything is expressed as plain
+//! `MidiMessage`s, which can be written to a track or sent live with
+//! `MidiOutput::send_midi_message`.
+
+use super::controller::{self, ControllerEventKind, RPN_PITCH_BEND_RANGE};
+use super::message::MidiMessage;
+use super::track::MidiTrack;
+
+/// RPN 6: MPE configuration message (MCM). The data entry MSB sent on a
+/// zone's manager channel sets the number of member channels.
+pub const RPN_MPE_CONFIGURATION: u16 = 0x0006;
+/// Controller carrying the per-note timbre dimension (CC 74)
+pub const CC_TIMBRE: u8 = 74;
+
+/// Pitch-bend range of member channels set by an MCM, in semitones
+pub const DEFAULT_MEMBER_BEND_RANGE: u8 = 48;
+/// Pitch-bend range of a manager channel set by an MCM, in semitones
+pub const DEFAULT_MANAGER_BEND_RANGE: u8 = 2;
+
+const BEND_CENTER: u16 = 0x2000;
+const TIMBRE_CENTER: u8 = 64;
+const CC_RPN_LSB: u8 = 100;
+const CC_RPN_MSB: u8 = 101;
+const CC_DATA_ENTRY_MSB: u8 = 6;
+
+/// Which end of the channel range a zone occupies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MpeZoneSide {
+    /// Manager channel 1, members from channel 2 upwards
+    Lower,
+    /// Manager channel 16, members from channel 15 downwards
+    Upper,
+}
+
+/// One MPE zone: a manager channel and its member channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MpeZone {
+    side: MpeZoneSide,
+    members: u8,
+    pitch_bend_range: u8,
+    manager_pitch_bend_range: u8,
+}
+
+impl MpeZone {
+    /// Create a zone with `member_channels` member channels (at most 15)
+    /// and the default pitch-bend ranges
+    pub fn new(side: MpeZoneSide, member_channels: u8) -> Self {
+        Self {
+            side,
+            members: member_channels.min(15),
+            pitch_bend_range: DEFAULT_MEMBER_BEND_RANGE,
+            manager_pitch_bend_range: DEFAULT_MANAGER_BEND_RANGE,
+        }
+    }
+
+    /// Create a lower zone
+    pub fn lower(member_channels: u8) -> Self {
+        Self::new(MpeZoneSide::Lower, member_channels)
+    }
+
+    /// Create an upper zone
+    pub fn upper(member_channels: u8) -> Self {
+        Self::new(MpeZoneSide::Upper, member_channels)
+    }
+
+    /// Set the pitch-bend range of the member channels, in semitones
+    pub fn with_pitch_bend_range(mut self, semitones: u8) -> Self {
+        self.pitch_bend_range = semitones.min(96);
+        self
+    }
+
+    /// Set the pitch-bend range of the manager channel, in semitones
+    pub fn with_manager_pitch_bend_range(mut self, semitones: u8) -> Self {
+        self.manager_pitch_bend_range = semitones.min(96);
+        self
+    }
+
+    /// Which end of the channel range the zone occupies
+    pub fn side(&self) -> MpeZoneSide {
+        self.side
+    }
+
+    /// Number of member channels
+    pub fn member_count(&self) -> u8 {
+        self.members
+    }
+
+    /// Pitch-bend range of the member channels, in semitones
+    pub fn pitch_bend_range(&self) -> u8 {
+        self.pitch_bend_range
+    }
+
+    /// Pitch-bend range of the manager channel, in semitones
+    pub fn manager_pitch_bend_range(&self) -> u8 {
+        self.manager_pitch_bend_range
+    }
+
+    /// The manager channel (0-based: 0 or 15)
+    pub fn manager_channel(&self) -> u8 {
+        match self.side {
+            MpeZoneSide::Lower => 0,
+            MpeZoneSide::Upper => 15,
+        }
+    }
+
+    /// The member channels (0-based), nearest the manager channel first
+    pub fn member_channels(&self) -> Vec<u8> {
+        match self.side {
+            MpeZoneSide::Lower => (1..=self.members).collect(),
+            MpeZoneSide::Upper => (0..self.members).map(|i| 14 - i).collect(),
+        }
+    }
+
+    /// Whether `channel` is one of the member channels
+    pub fn is_member(&self, channel: u8) -> bool {
+        match self.side {
+            MpeZoneSide::Lower => (1..=self.members).contains(&channel),
+            MpeZoneSide::Upper => channel < 15 && 14 - channel < self.members,
+        }
+    }
+
+    /// Whether `channel` is the manager channel or a member channel
+    pub fn contains(&self, channel: u8) -> bool {
+        channel == self.manager_channel() || self.is_member(channel)
+    }
+
+    /// Messages that set the zone up on a receiver: the MCM on the manager
+    /// channel, followed by pitch-bend range RPNs where the ranges differ
+    /// from the defaults the MCM sets
+    pub fn configuration_messages(&self) -> Vec<MidiMessage> {
+        let rpn = |channel: u8, param: u16, value14: u16| {
+            controller::encode(&ControllerEventKind::Rpn { param, value14 })
+                .into_iter()
+                .map(move |(cc, value)| MidiMessage::control_change(channel, cc, value))
+        };
+        let manager = self.manager_channel();
+        let mut messages: Vec<MidiMessage> =
+            rpn(manager, RPN_MPE_CONFIGURATION, (self.members as u16) << 7).collect();
+        if self.members > 0 && self.pitch_bend_range != DEFAULT_MEMBER_BEND_RANGE {
+            for channel in self.member_channels() {
+                let range = (self.pitch_bend_range as u16) << 7;
+                messages.extend(rpn(channel, RPN_PITCH_BEND_RANGE, range));
+            }
+        }
+        if self.manager_pitch_bend_range != DEFAULT_MANAGER_BEND_RANGE {
+            let range = (self.manager_pitch_bend_range as u16) << 7;
+            messages.extend(rpn(manager, RPN_PITCH_BEND_RANGE, range));
+        }
+        messages
+    }
+}
+
+/// The zone layout of one port: up to one lower and one upper zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MpeConfiguration {
+    lower: Option<MpeZone>,
+    upper: Option<MpeZone>,
+}
+
+impl MpeConfiguration {
+    /// Create a configuration with no zones (plain MIDI)
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The lower zone, if configured
+    pub fn lower(&self) -> Option<&MpeZone> {
+        self.lower.as_ref()
+    }
+
+    /// The upper zone, if configured
+    pub fn upper(&self) -> Option<&MpeZone> {
+        self.upper.as_ref()
+    }
+
+    /// Configure a zone the way a receiver handles an MCM: a zone without
+    /// member channels is removed, and the other zone shrinks (or is
+    /// removed) where the two would overlap
+    pub fn set_zone(&mut self, zone: MpeZone) {
+        let (this, other) = match zone.side {
+            MpeZoneSide::Lower => (&mut self.lower, &mut self.upper),
+            MpeZoneSide::Upper => (&mut self.upper, &mut self.lower),
+        };
+        *this = (zone.members > 0).then_some(zone);
+        if zone.members > 0
+            && let Some(existing) = other
+        {
+            // The two managers take channels 1 and 16, leaving 14 members.
+            existing.members = existing.members.min(14u8.saturating_sub(zone.members));
+            if existing.members == 0 {
+                *other = None;
+            }
+        }
+    }
+
+    /// The zone `channel` belongs to, as manager or member
+    pub fn zone_of(&self, channel: u8) -> Option<&MpeZone> {
+        [self.lower.as_ref(), self.upper.as_ref()]
+            .into_iter()
+            .flatten()
+            .find(|zone| zone.contains(channel))
+    }
+
+    fn zone_mut(&mut self, side: MpeZoneSide) -> Option<&mut MpeZone> {
+        match side {
+            MpeZoneSide::Lower => self.lower.as_mut(),
+            MpeZoneSide::Upper => self.upper.as_mut(),
+        }
+    }
+}
+
+/// 14-bit pitch-bend value for `semitones` at a bend range of `range`.
+fn bend_value(semitones: f64, range: u8) -> u16 {
+    if range == 0 {
+        return BEND_CENTER;
+    }
+    let offset = (semitones / range as f64 * BEND_CENTER as f64).round();
+    (BEND_CENTER as f64 + offset).clamp(0.0, 0x3FFF as f64) as u16
+}
+
+/// Semitones for a 14-bit pitch-bend value at a bend range of `range`.
+fn bend_semitones(value: u16, range: u8) -> f64 {
+    (value as f64 - BEND_CENTER as f64) / BEND_CENTER as f64 * range as f64
+}
+
+/// Per-note expression: pitch bend, pressure and timbre
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MpeExpression {
+    /// Pitch bend in semitones
+    pub pitch_bend: f64,
+    /// Channel pressure (0-127)
+    pub pressure: u8,
+    /// Timbre, sent as CC 74 (0-127)
+    pub timbre: u8,
+}
+
+impl Default for MpeExpression {
+    fn default() -> Self {
+        Self {
+            pitch_bend: 0.0,
+            pressure: 0,
+            timbre: TIMBRE_CENTER,
+        }
+    }
+}
+
+/// Identifies a note started by an `MpeAllocator`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MpeNoteId(pub u64);
+
+/// A note sounding through an `MpeAllocator`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MpeNote {
+    /// Note identifier
+    pub id: MpeNoteId,
+    /// Member channel the note plays on
+    pub channel: u8,
+    /// MIDI key
+    pub key: u8,
+    /// Note-on velocity
+    pub velocity: u8,
+    /// Current expression
+    pub expression: MpeExpression,
+}
+
+/// The result of `MpeAllocator::note_on`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MpeNoteOn {
+    /// The new note
+    pub id: MpeNoteId,
+    /// Member channel the note was given
+    pub channel: u8,
+    /// A note ended to free the channel, when every channel was busy
+    pub stolen: Option<MpeNoteId>,
+    /// Messages to send, in order
+    pub messages: Vec<MidiMessage>,
+}
+
+/// Assigns notes to the member channels of a zone
+///
+/// A new note goes to the free member channel that has been idle longest,
+/// so release tails of recent notes are left alone. When every channel is
+/// busy, the oldest note is ended and its channel reused. A zone without
+/// member channels plays everything on its manager channel.
+#[derive(Debug, Clone)]
+pub struct MpeAllocator {
+    zone: MpeZone,
+    channels: Vec<u8>,
+    /// When each channel was last given a note or freed.
+    last_used: Vec<u64>,
+    /// Sounding notes, oldest first.
+    notes: Vec<MpeNote>,
+    clock: u64,
+    next_id: u64,
+}
+
+impl MpeAllocator {
+    /// Create an allocator for `zone`
+    pub fn new(zone: MpeZone) -> Self {
+        let mut channels = zone.member_channels();
+        if channels.is_empty() {
+            channels.push(zone.manager_channel());
+        }
+        Self {
+            zone,
+            last_used: vec![0; channels.len()],
+            channels,
+            notes: Vec::new(),
+            clock: 0,
+            next_id: 0,
+        }
+    }
+
+    /// The zone notes are allocated in
+    pub fn zone(&self) -> &MpeZone {
+        &self.zone
+    }
+
+    /// Sounding notes, oldest first
+    pub fn notes(&self) -> &[MpeNote] {
+        &self.notes
+    }
+
+    /// A sounding note
+    pub fn note(&self, id: MpeNoteId) -> Option<&MpeNote> {
+        self.notes.iter().find(|note| note.id == id)
+    }
+
+    fn touch(&mut self, channel: u8) {
+        self.clock += 1;
+        if let Some(slot) = self.channels.iter().position(|&c| c == channel) {
+            self.last_used[slot] = self.clock;
+        }
+    }
+
+    fn bend_message(&self, channel: u8, semitones: f64) -> MidiMessage {
+        let range = if self.zone.is_member(channel) {
+            self.zone.pitch_bend_range
+        } else {
+            self.zone.manager_pitch_bend_range
+        };
+        MidiMessage::pitch_bend(channel, bend_value(semitones, range))
+    }
+
+    /// Start a note. Its channel is set to `expression` before the note-on,
+    /// so the note starts with it.
+    pub fn note_on(&mut self, key: u8, velocity: u8, expression: MpeExpression) -> MpeNoteOn {
+        let mut messages = Vec::new();
+        let free = (0..self.channels.len())
+            .filter(|&slot| !self.notes.iter().any(|n| n.channel == self.channels[slot]))
+            .min_by_key(|&slot| self.last_used[slot]);
+        let (channel, stolen) = match free {
+            Some(slot) => (self.channels[slot], None),
+            None => {
+                let oldest = self.notes.remove(0);
+                messages.push(MidiMessage::note_off(oldest.channel, oldest.key, 0));
+                (oldest.channel, Some(oldest.id))
+            }
+        };
+
+        let id = MpeNoteId(self.next_id);
+        self.next_id += 1;
+        self.touch(channel);
+        messages.extend([
+            self.bend_message(channel, expression.pitch_bend),
+            MidiMessage::control_change(channel, CC_TIMBRE, expression.timbre),
+            MidiMessage::ChannelPressure {
+                channel,
+                pressure: expression.pressure & 0x7F,
+            },
+            MidiMessage::note_on(channel, key, velocity),
+        ]);
+        self.notes.push(MpeNote {
+            id,
+            channel,
+            key: key & 0x7F,
+            velocity: velocity & 0x7F,
+            expression,
+        });
+        MpeNoteOn {
+            id,
+            channel,
+            stolen,
+            messages,
+        }
+    }
+
+    /// Bend a note by `semitones` from its key. `None` if the note is not
+    /// sounding.
+    pub fn pitch_bend(&mut self, id: MpeNoteId, semitones: f64) -> Option<MidiMessage> {
+        let note = self.notes.iter_mut().find(|note| note.id == id)?;
+        note.expression.pitch_bend = semitones;
+        let channel = note.channel;
+        Some(self.bend_message(channel, semitones))
+    }
+
+    /// Set the pressure of a note. `None` if the note is not sounding.
+    pub fn pressure(&mut self, id: MpeNoteId, pressure: u8) -> Option<MidiMessage> {
+        let note = self.notes.iter_mut().find(|note| note.id == id)?;
+        note.expression.pressure = pressure & 0x7F;
+        Some(MidiMessage::ChannelPressure {
+            channel: note.channel,
+            pressure: pressure & 0x7F,
+        })
+    }
+
+    /// Set the timbre (CC 74) of a note. `None` if the note is not
+    /// sounding.
+    pub fn timbre(&mut self, id: MpeNoteId, timbre: u8) -> Option<MidiMessage> {
+        let note = self.notes.iter_mut().find(|note| note.id == id)?;
+        note.expression.timbre = timbre & 0x7F;
+        Some(MidiMessage::control_change(
+            note.channel,
+            CC_TIMBRE,
+            timbre & 0x7F,
+        ))
+    }
+
+    /// End a note. `None` if the note is not sounding.
+    pub fn note_off(&mut self, id: MpeNoteId, velocity: u8) -> Option<MidiMessage> {
+        let index = self.notes.iter().position(|note| note.id == id)?;
+        let note = self.notes.remove(index);
+        self.touch(note.channel);
+        Some(MidiMessage::note_off(note.channel, note.key, velocity))
+    }
+
+    /// End every sounding note
+    pub fn all_notes_off(&mut self) -> Vec<MidiMessage> {
+        let ids: Vec<MpeNoteId> = self.notes.iter().map(|note| note.id).collect();
+        ids.into_iter()
+            .filter_map(|id| self.note_off(id, 0))
+            .collect()
+    }
+}
+
+/// The expression of one decoded MPE note over time
+#[derive(Debug, Clone, PartialEq)]
+pub struct MpeNoteCurve {
+    /// Channel the note played on
+    pub channel: u8,
+    /// MIDI key
+    pub key: u8,
+    /// Note-on velocity
+    pub velocity: u8,
+    /// Time of the note-on
+    pub start: u64,
+    /// Time of the note-off, if the note has ended
+    pub end: Option<u64>,
+    /// Note-off velocity
+    pub release_velocity: u8,
+    /// Pitch bend in semitones, including the zone-wide bend of the
+    /// manager channel, starting with the value at the note-on
+    pub pitch_bend: Vec<(u64, f64)>,
+    /// Pressure, starting with the value at the note-on
+    pub pressure: Vec<(u64, u8)>,
+    /// Timbre (CC 74), starting with the value at the note-on
+    pub timbre: Vec<(u64, u8)>,
+}
+
+/// Expression state of one channel, and its RPN selection.
+#[derive(Debug, Clone, Copy)]
+struct ChannelExpression {
+    bend: u16,
+    pressure: u8,
+    timbre: u8,
+    rpn: (u8, u8),
+}
+
+impl Default for ChannelExpression {
+    fn default() -> Self {
+        Self {
+            bend: BEND_CENTER,
+            pressure: 0,
+            timbre: TIMBRE_CENTER,
+            rpn: (127, 127),
+        }
+    }
+}
+
+/// Decodes an MPE stream into per-note expression curves
+///
+/// Feed messages in order with `process`. Times are in whatever unit the
+/// caller uses (ticks for a track, microseconds for live input). MCMs and
+/// pitch-bend range RPNs in the stream update the zone layout as they
+/// arrive. Expression sent on a channel before a note-on becomes the
+/// note's starting value.
+#[derive(Debug, Clone, Default)]
+pub struct MpeDecoder {
+    configuration: MpeConfiguration,
+    channels: [ChannelExpression; 16],
+    active: Vec<MpeNoteCurve>,
+    finished: Vec<MpeNoteCurve>,
+}
+
+impl MpeDecoder {
+    /// Create a decoder starting from `configuration`
+    pub fn new(configuration: MpeConfiguration) -> Self {
+        Self {
+            configuration,
+            ..Self::default()
+        }
+    }
+
+    /// The current zone layout
+    pub fn configuration(&self) -> &MpeConfiguration {
+        &self.configuration
+    }
+
+    /// Notes still sounding
+    pub fn active_notes(&self) -> &[MpeNoteCurve] {
+        &self.active
+    }
+
+    /// Remove and return the notes that have ended so far, in the order
+    /// they ended
+    pub fn take_finished(&mut self) -> Vec<MpeNoteCurve> {
+        std::mem::take(&mut self.finished)
+    }
+
+    /// All notes, finished and still sounding, by start time
+    pub fn finish(mut self) -> Vec<MpeNoteCurve> {
+        let mut notes = std::mem::take(&mut self.finished);
+        notes.append(&mut self.active);
+        notes.sort_by_key(|note| note.start);
+        notes
+    }
+
+    /// Pitch of a note on `channel` in semitones, including the zone-wide
+    /// bend when `channel` is a member channel.
+    fn pitch(&self, channel: u8) -> f64 {
+        let own = self.channels[channel as usize].bend;
+        match self.configuration.zone_of(channel) {
+            Some(zone) if zone.is_member(channel) => {
+                let manager = self.channels[zone.manager_channel() as usize].bend;
+                bend_semitones(own, zone.pitch_bend_range)
+                    + bend_semitones(manager, zone.manager_pitch_bend_range)
+            }
+            Some(zone) => bend_semitones(own, zone.manager_pitch_bend_range),
+            None => bend_semitones(own, DEFAULT_MANAGER_BEND_RANGE),
+        }
+    }
+
+    /// Add a pitch point to every active note whose pitch depends on
+    /// `channel`.
+    fn update_pitch(&mut self, time: u64, channel: u8) {
+        let zone = self
+            .configuration
+            .zone_of(channel)
+            .filter(|zone| zone.manager_channel() == channel)
+            .copied();
+        for index in 0..self.active.len() {
+            let note_channel = self.active[index].channel;
+            if note_channel == channel || zone.is_some_and(|zone| zone.is_member(note_channel)) {
+                let pitch = self.pitch(note_channel);
+                self.active[index].pitch_bend.push((time, pitch));
+            }
+        }
+    }
+
+    fn data_entry(&mut self, channel: u8, value: u8) {
+        let (msb, lsb) = self.channels[channel as usize].rpn;
+        let param = ((msb as u16) << 7) | lsb as u16;
+        if param == RPN_MPE_CONFIGURATION {
+            let side = match channel {
+                0 => MpeZoneSide::Lower,
+                15 => MpeZoneSide::Upper,
+                _ => return,
+            };
+            self.configuration.set_zone(MpeZone::new(side, value));
+        } else if param == RPN_PITCH_BEND_RANGE
+            && let Some(side) = self.configuration.zone_of(channel).map(|zone| zone.side)
+            && let Some(zone) = self.configuration.zone_mut(side)
+        {
+            if zone.manager_channel() == channel {
+                zone.manager_pitch_bend_range = value;
+            } else {
+                zone.pitch_bend_range = value;
+            }
+        }
+    }
+
+    /// Feed the next message, received at `time`. Messages on channels
+    /// above 15 are ignored.
+    pub fn process(&mut self, time: u64, message: &MidiMessage) {
+        if message.channel().is_some_and(|channel| channel > 15) {
+            return;
+        }
+        match *message {
+            MidiMessage::NoteOn {
+                channel,
+                key,
+                velocity,
+            } if velocity > 0 => {
+                let state = self.channels[channel as usize];
+                self.active.push(MpeNoteCurve {
+                    channel,
+                    key,
+                    velocity,
+                    start: time,
+                    end: None,
+                    release_velocity: 0,
+                    pitch_bend: vec![(time, self.pitch(channel))],
+                    pressure: vec![(time, state.pressure)],
+                    timbre: vec![(time, state.timbre)],
+                });
+            }
+            MidiMessage::NoteOn { channel, key, .. }
+            | MidiMessage::NoteOff { channel, key, .. } => {
+                let velocity = match *message {
+                    MidiMessage::NoteOff { velocity, .. } => velocity,
+                    _ => 0,
+                };
+                if let Some(index) = self
+                    .active
+                    .iter()
+                    .position(|note| note.channel == channel && note.key == key)
+                {
+                    let mut note = self.active.remove(index);
+                    note.end = Some(time);
+                    note.release_velocity = velocity;
+                    self.finished.push(note);
+                }
+            }
+            MidiMessage::PitchBend { channel, value } => {
+                self.channels[channel as usize].bend = value;
+                self.update_pitch(time, channel);
+            }
+            MidiMessage::ChannelPressure { channel, pressure } => {
+                self.channels[channel as usize].pressure = pressure;
+                for note in self.active.iter_mut().filter(|n| n.channel == channel) {
+                    note.pressure.push((time, pressure));
+                }
+            }
+            MidiMessage::PolyPressure {
+                channel,
+                key,
+                pressure,
+            } => {
+                for note in self
+                    .active
+                    .iter_mut()
+                    .filter(|n| n.channel == channel && n.key == key)
+                {
+                    note.pressure.push((time, pressure));
+                }
+            }
+            MidiMessage::ControlChange {
+                channel,
+                controller,
+                value,
+            } => match controller {
+                CC_TIMBRE => {
+                    self.channels[channel as usize].timbre = value;
+                    for note in self.active.iter_mut().filter(|n| n.channel == channel) {
+                        note.timbre.push((time, value));
+                    }
+                }
+                CC_RPN_MSB => self.channels[channel as usize].rpn.0 = value,
+                CC_RPN_LSB => self.channels[channel as usize].rpn.1 = value,
+                CC_DATA_ENTRY_MSB => self.data_entry(channel, value),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+/// Decode the MPE notes of `track`, with times in ticks.
+pub(super) fn decode_track(
+    track: &MidiTrack,
+    configuration: MpeConfiguration,
+) -> Vec<MpeNoteCurve> {
+    let mut decoder = MpeDecoder::new(configuration);
+    for event in track.events() {
+        decoder.process(event.tick(), event.message());
+    }
+    decoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn controls(messages: &[MidiMessage]) -> Vec<(u8, u8, u8)> {
+        messages
+            .iter()
+            .map(|message| match *message {
+                MidiMessage::ControlChange {
+                    channel,
+                    controller,
+                    value,
+                } => (channel, controller, value),
+                _ => panic!("not a control change: {message:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_zone_configuration() {
+        let zone = MpeZone::upper(3).with_pitch_bend_range(24);
+        assert_eq!(zone.manager_channel(), 15);
+        assert_eq!(zone.member_channels(), vec![14, 13, 12]);
+        assert!(zone.is_member(12) && !zone.is_member(11) && !zone.is_member(15));
+
+        let messages = zone.configuration_messages();
+        assert_eq!(
+            controls(&messages[..6]),
+            vec![
+                (15, 101, 0),
+                (15, 100, 6),
+                (15, 6, 3),
+                (15, 38, 0),
+                (15, 101, 127),
+                (15, 100, 127)
+            ]
+        );
+        assert_eq!(messages.len(), 6 * 4);
+        assert_eq!(controls(&messages[6..])[2], (14, 6, 24));
+
+        let mut layout = MpeConfiguration::new();
+        layout.set_zone(MpeZone::lower(15));
+        layout.set_zone(zone);
+        assert_eq!(layout.lower().unwrap().member_count(), 11);
+        assert_eq!(layout.zone_of(13).unwrap().side(), MpeZoneSide::Upper);
+        assert!(layout.zone_of(12).is_some() && layout.zone_of(11).is_some());
+        layout.set_zone(MpeZone::upper(14));
+        assert!(layout.lower().is_none());
+        layout.set_zone(MpeZone::upper(0));
+        assert_eq!(layout, MpeConfiguration::new());
+    }
+
+    #[test]
+    fn test_allocator_rotation_and_stealing() {
+        let mut allocator = MpeAllocator::new(MpeZone::lower(3));
+        let expression = MpeExpression::default();
+        let first = allocator.note_on(60, 100, expression);
+        let second = allocator.note_on(62, 100, expression);
+        assert_eq!((first.channel, second.channel), (1, 2));
+        assert_eq!(
+            first.messages,
+            vec![
+                MidiMessage::pitch_bend(1, 0x2000),
+                MidiMessage::control_change(1, CC_TIMBRE, 64),
+                MidiMessage::ChannelPressure {
+                    channel: 1,
+                    pressure: 0
+                },
+                MidiMessage::note_on(1, 60, 100),
+            ]
+        );
+
+        allocator.note_off(first.id, 0).unwrap();
+        // Channel 3 has been idle longer than the just-released channel 1.
+        assert_eq!(allocator.note_on(64, 100, expression).channel, 3);
+        assert_eq!(allocator.note_on(65, 100, expression).channel, 1);
+
+        let stealing = allocator.note_on(67, 100, expression);
+        assert_eq!(stealing.stolen, Some(second.id));
+        assert_eq!(stealing.channel, 2);
+        assert_eq!(stealing.messages[0], MidiMessage::note_off(2, 62, 0));
+        assert_eq!(allocator.notes().len(), 3);
+
+        assert_eq!(
+            allocator.pitch_bend(stealing.id, 12.0),
+            Some(MidiMessage::pitch_bend(2, 0x2800))
+        );
+        assert_eq!(
+            allocator.note(stealing.id).unwrap().expression.pitch_bend,
+            12.0
+        );
+        assert_eq!(allocator.pitch_bend(second.id, 1.0), None);
+        assert_eq!(
+            allocator.timbre(stealing.id, 0xC8),
+            Some(MidiMessage::control_change(2, CC_TIMBRE, 0x48))
+        );
+        assert_eq!(allocator.note(stealing.id).unwrap().expression.timbre, 0x48);
+        assert_eq!(allocator.all_notes_off().len(), 3);
+        assert!(allocator.notes().is_empty());
+    }
+
+    #[test]
+    fn test_decode_track() {
+        let zone = MpeZone::lower(4).with_pitch_bend_range(32);
+        let mut allocator = MpeAllocator::new(zone);
+        let mut track = MidiTrack::new();
+        let mut add = |tick: u64, messages: Vec<MidiMessage>| {
+            for message in messages {
+                track.add_event(crate::midi::MidiEvent::new(tick, message));
+            }
+        };
+        add(0, zone.configuration_messages());
+        let low = allocator.note_on(
+            48,
+            90,
+            MpeExpression {
+                pitch_bend: -2.0,
+                pressure: 20,
+                timbre: 64,
+            },
+        );
+        add(10, low.messages);
+        let high = allocator.note_on(55, 80, MpeExpression::default());
+        add(10, high.messages);
+        add(20, allocator.pitch_bend(high.id, 2.0).into_iter().collect());
+        add(30, allocator.timbre(low.id, 100).into_iter().collect());
+        // Zone-wide bend of one semitone on the manager channel.
+        add(40, vec![MidiMessage::pitch_bend(0, 0x3000)]);
+        // Out-of-range channels are ignored.
+        add(
+            45,
+            vec![
+                MidiMessage::PitchBend {
+                    channel: 16,
+                    value: 0,
+                },
+                MidiMessage::NoteOn {
+                    channel: 17,
+                    key: 60,
+                    velocity: 100,
+                },
+            ],
+        );
+        add(50, allocator.note_off(low.id, 40).into_iter().collect());
+
+        let notes = track.decode_mpe(MpeConfiguration::new());
+        assert_eq!(notes.len(), 2);
+        let (first, second) = (&notes[0], &notes[1]);
+        assert_eq!((first.channel, first.key, first.velocity), (1, 48, 90));
+        assert_eq!((first.end, first.release_velocity), (Some(50), 40));
+        assert_eq!(first.pitch_bend, vec![(10, -2.0), (40, -1.0)]);
+        assert_eq!(first.pressure, vec![(10, 20)]);
+        assert_eq!(first.timbre, vec![(10, 64), (30, 100)]);
+        assert_eq!(second.end, None);
+        assert_eq!(second.pitch_bend, vec![(10, 0.0), (20, 2.0), (40, 3.0)]);
+    }
+}
@@ -0,0 +1,350 @@
+//! Piano-roll matrices
+//!
+//! `MidiFile::piano_roll` samples notes onto a 128-pitch × N-frame grid at a
+//! fixed frame rate in seconds or ticks, producing velocity, onset and
+//! offset matrices. `PianoRoll::to_track` turns a roll back into notes.
+
+use super::file::MidiFile;
+use super::tempo_map::TempoMap;
+use super::track::MidiTrack;
+
+/// Number of rows in a piano roll, one per MIDI key
+pub const ROLL_PITCHES: usize = 128;
+
+/// Length of one piano-roll frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RollResolution {
+    /// Fixed number of frames per second (follows tempo changes)
+    FramesPerSecond(f64),
+    /// Fixed number of ticks per frame (follows the beat)
+    TicksPerFrame(u64),
+}
+
+/// One non-zero cell of a roll matrix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RollCell {
+    /// MIDI key (row)
+    pub pitch: u8,
+    /// Frame index (column)
+    pub frame: usize,
+    /// Cell value
+    pub value: u8,
+}
+
+/// A dense 128 × N matrix of 7-bit values, stored pitch-major
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RollMatrix {
+    frames: usize,
+    data: Vec<u8>,
+}
+
+impl RollMatrix {
+    /// Create an all-zero matrix of `frames` frames
+    pub fn new(frames: usize) -> Self {
+        Self {
+            frames,
+            data: vec![0; ROLL_PITCHES * frames],
+        }
+    }
+
+    /// Build a matrix from its non-zero cells. Cells past `frames` are
+    /// ignored.
+    pub fn from_sparse(frames: usize, cells: &[RollCell]) -> Self {
+        let mut matrix = Self::new(frames);
+        for cell in cells {
+            matrix.set(cell.pitch, cell.frame, cell.value);
+        }
+        matrix
+    }
+
+    /// Number of frames (columns)
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Value at `pitch`, `frame` (0 outside the matrix)
+    pub fn get(&self, pitch: u8, frame: usize) -> u8 {
+        if frame < self.frames && (pitch as usize) < ROLL_PITCHES {
+            self.data[pitch as usize * self.frames + frame]
+        } else {
+            0
+        }
+    }
+
+    /// Set the value at `pitch`, `frame`. Writes outside the matrix are
+    /// ignored.
+    pub fn set(&mut self, pitch: u8, frame: usize, value: u8) {
+        if frame < self.frames && (pitch as usize) < ROLL_PITCHES {
+            self.data[pitch as usize * self.frames + frame] = value & 0x7F;
+        }
+    }
+
+    /// All frames of one pitch
+    pub fn row(&self, pitch: u8) -> &[u8] {
+        let start = (pitch as usize).min(ROLL_PITCHES - 1) * self.frames;
+        &self.data[start..start + self.frames]
+    }
+
+    /// The whole matrix, pitch-major (`pitch * frames + frame`)
+    pub fn as_slice(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The non-zero cells, by pitch and then frame
+    pub fn to_sparse(&self) -> Vec<RollCell> {
+        self.data
+            .iter()
+            .enumerate()
+            .filter(|&(_, &value)| value > 0)
+            .map(|(index, &value)| RollCell {
+                pitch: (index / self.frames) as u8,
+                frame: index % self.frames,
+                value,
+            })
+            .collect()
+    }
+
+    fn raise(&mut self, pitch: u8, frame: usize, value: u8) {
+        let current = self.get(pitch, frame);
+        self.set(pitch, frame, current.max(value));
+    }
+}
+
+/// Which notes `MidiFile::piano_roll` includes, and how they are sampled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RollOptions {
+    resolution: RollResolution,
+    track: Option<usize>,
+    channel: Option<u8>,
+    pedal: bool,
+}
+
+impl RollOptions {
+    /// Sample every note of the file at `resolution`
+    pub fn new(resolution: RollResolution) -> Self {
+        Self {
+            resolution,
+            track: None,
+            channel: None,
+            pedal: false,
+        }
+    }
+
+    /// Only include notes from one track
+    pub fn with_track(mut self, track: usize) -> Self {
+        self.track = Some(track);
+        self
+    }
+
+    /// Only include notes on one channel
+    pub fn with_channel(mut self, channel: u8) -> Self {
+        self.channel = Some(channel & 0x0F);
+        self
+    }
+
+    /// Hold notes while the sustain pedal is down (see
+    /// `MidiTrack::bake_sustain`)
+    pub fn with_pedal(mut self, enabled: bool) -> Self {
+        self.pedal = enabled;
+        self
+    }
+}
+
+/// Velocity, onset and offset matrices of a sampled performance
+#[derive(Debug, Clone, PartialEq)]
+pub struct PianoRoll {
+    /// Length of one frame
+    pub resolution: RollResolution,
+    /// Velocity of the sounding note (the loudest, if several overlap) in
+    /// every frame it covers
+    pub velocities: RollMatrix,
+    /// Note velocity in the frame each note starts
+    pub onsets: RollMatrix,
+    /// 1 in the frame after each note's last frame
+    pub offsets: RollMatrix,
+}
+
+impl PianoRoll {
+    /// Number of frames
+    pub fn frames(&self) -> usize {
+        self.velocities.frames()
+    }
+
+    /// Convert the roll back into notes on `channel`. A note starts where
+    /// a pitch becomes active or where an onset is marked, and ends where
+    /// the pitch falls silent. Frame times in seconds are converted with
+    /// `tempo_map`; tick resolutions use frame times directly.
+    pub fn to_track(&self, channel: u8, tempo_map: &TempoMap) -> MidiTrack {
+        let frame_tick = |frame: usize| match self.resolution {
+            RollResolution::TicksPerFrame(ticks) => frame as u64 * ticks,
+            RollResolution::FramesPerSecond(fps) => {
+                // Nudge past float error so exact frame times do not truncate
+                // to the previous tick.
+                tempo_map.seconds_to_ticks(frame as f64 / fps + 1e-9)
+            }
+        };
+
+        let mut track = MidiTrack::new();
+        for pitch in 0..ROLL_PITCHES as u8 {
+            let row = self.velocities.row(pitch);
+            let mut start: Option<(usize, u8)> = None;
+            for frame in 0..=row.len() {
+                let value = row.get(frame).copied().unwrap_or(0);
+                let onset = value > 0 && self.onsets.get(pitch, frame) > 0;
+                if let Some((first, velocity)) = start
+                    && (value == 0 || onset)
+                {
+                    let tick = frame_tick(first);
+                    track.add_note(tick, frame_tick(frame) - tick, channel, pitch, velocity);
+                    start = None;
+                }
+                if value > 0 && start.is_none() {
+                    start = Some((frame, value));
+                }
+            }
+        }
+        track.link_note_events();
+        track
+    }
+}
+
+impl MidiFile {
+    /// Sample the notes of the file onto a piano roll
+    pub fn piano_roll(&self, options: &RollOptions) -> PianoRoll {
+        let frame_of = |tick: u64| -> f64 {
+            match options.resolution {
+                RollResolution::TicksPerFrame(ticks) => tick as f64 / ticks.max(1) as f64,
+                RollResolution::FramesPerSecond(fps) => self.ticks_to_seconds(tick) * fps,
+            }
+        };
+
+        // (pitch, first frame, end frame, velocity)
+        let mut notes: Vec<(u8, usize, usize, u8)> = Vec::new();
+        for (index, track) in self.tracks().iter().enumerate() {
+            if options.track.is_some_and(|only| only != index) {
+                continue;
+            }
+            let mut track = track.clone();
+            if options.pedal {
+                track.bake_sustain(false);
+            }
+            track.link_note_events();
+            let events = track.events();
+            for event in events.iter().filter(|e| e.is_note_on()) {
+                if options
+                    .channel
+                    .is_some_and(|channel| event.channel() != Some(channel))
+                {
+                    continue;
+                }
+                let (Some(pitch), Some(velocity), Some(off)) =
+                    (event.key(), event.velocity(), event.linked_event())
+                else {
+                    continue;
+                };
+                let first = frame_of(event.tick()).floor() as usize;
+                let end = (frame_of(events[off].tick()).floor() as usize).max(first + 1);
+                notes.push((pitch, first, end, velocity));
+            }
+        }
+
+        let frames = notes
+            .iter()
+            .map(|&(_, _, end, _)| end + 1)
+            .max()
+            .unwrap_or(0);
+        let mut roll = PianoRoll {
+            resolution: options.resolution,
+            velocities: RollMatrix::new(frames),
+            onsets: RollMatrix::new(frames),
+            offsets: RollMatrix::new(frames),
+        };
+        for (pitch, first, end, velocity) in notes {
+            for frame in first..end {
+                roll.velocities.raise(pitch, frame, velocity);
+            }
+            roll.onsets.raise(pitch, first, velocity);
+            roll.offsets.raise(pitch, end, 1);
+        }
+        roll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_file() -> MidiFile {
+        let mut file = MidiFile::new();
+        file.add_tracks(2);
+        file.add_tempo(0, 0, 120.0).unwrap();
+        file.add_note(1, 0, 480, 0, 60, 100).unwrap();
+        file.add_note(1, 480, 240, 0, 60, 80).unwrap();
+        file.add_note(1, 240, 960, 1, 67, 90).unwrap();
+        file.add_sustain_on(1, 0, 0).unwrap();
+        file.add_sustain_off(1, 1440, 0).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_piano_roll_ticks_and_seconds() {
+        let file = test_file();
+        let roll = file.piano_roll(&RollOptions::new(RollResolution::TicksPerFrame(240)));
+        assert_eq!(roll.frames(), 6);
+        assert_eq!(roll.velocities.row(60), &[100, 100, 80, 0, 0, 0]);
+        assert_eq!(roll.onsets.row(60), &[100, 0, 80, 0, 0, 0]);
+        assert_eq!(roll.offsets.row(60), &[0, 0, 1, 1, 0, 0]);
+        assert_eq!(roll.velocities.row(67), &[0, 90, 90, 90, 90, 0]);
+
+        // 120 BPM: 480 ticks = 0.5 s = 5 frames at 10 fps.
+        let seconds = file
+            .piano_roll(&RollOptions::new(RollResolution::FramesPerSecond(10.0)).with_channel(1));
+        assert_eq!(seconds.frames(), 13);
+        assert_eq!(seconds.velocities.get(60, 0), 0);
+        assert_eq!(seconds.velocities.to_sparse().len(), 10);
+        assert_eq!(
+            seconds.onsets.to_sparse(),
+            vec![RollCell {
+                pitch: 67,
+                frame: 2,
+                value: 90
+            }]
+        );
+
+        let pedal = file.piano_roll(
+            &RollOptions::new(RollResolution::TicksPerFrame(240))
+                .with_channel(0)
+                .with_pedal(true),
+        );
+        assert_eq!(pedal.velocities.row(60), &[100, 100, 80, 80, 80, 80, 0]);
+        assert!(pedal.velocities.row(67).iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn test_piano_roll_round_trip() {
+        let file = test_file();
+        let roll = file.piano_roll(&RollOptions::new(RollResolution::TicksPerFrame(120)));
+        let track = roll.to_track(0, &file.tempo_map());
+        let notes: Vec<(u64, u64, u8, u8)> = track
+            .events()
+            .iter()
+            .filter(|e| e.is_note_on())
+            .map(|e| {
+                let duration = e.tick_duration(track.events()).unwrap();
+                (e.tick(), duration, e.key().unwrap(), e.velocity().unwrap())
+            })
+            .collect();
+        assert_eq!(
+            notes,
+            vec![(0, 480, 60, 100), (240, 960, 67, 90), (480, 240, 60, 80)]
+        );
+
+        let seconds = file.piano_roll(&RollOptions::new(RollResolution::FramesPerSecond(8.0)));
+        let track = seconds.to_track(0, &file.tempo_map());
+        assert_eq!(track.events()[0].tick(), 0);
+        assert_eq!(
+            RollMatrix::from_sparse(seconds.frames(), &seconds.onsets.to_sparse()),
+            seconds.onsets
+        );
+    }
+}
@@ -0,0 +1,423 @@
+//! Note quantization, swing and groove templates
+//!
+//! `QuantizeOptions` describes a grid (optionally swung or divided into
+//! tuplets) and how hard notes are pulled towards it. `GrooveTemplate`
+//! captures the timing and velocity feel of a performance per grid slot so
+//! it can be imposed on other tracks.
+
+use super::event::MidiEvent;
+use super::file::MidiFile;
+use super::message::MidiMessage;
+use super::track::MidiTrack;
+
+/// Settings for `MidiTrack::quantize`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantizeOptions {
+    grid: u64,
+    strength: f64,
+    window: f64,
+    swing: f64,
+    tuplet: (u32, u32),
+    ends: bool,
+}
+
+impl QuantizeOptions {
+    /// Quantize note starts fully to a grid of `grid` ticks (e.g. 120 for
+    /// sixteenth notes at 480 PPQN)
+    pub fn new(grid: u64) -> Self {
+        Self {
+            grid,
+            strength: 100.0,
+            window: 100.0,
+            swing: 0.0,
+            tuplet: (1, 1),
+            ends: false,
+        }
+    }
+
+    /// Move notes this percentage of the way towards the grid (100 snaps
+    /// them onto it)
+    pub fn with_strength(mut self, percent: f64) -> Self {
+        self.strength = percent.clamp(0.0, 100.0);
+        self
+    }
+
+    /// Only move notes within this percentage of half a grid step from a
+    /// grid line (100 moves every note)
+    pub fn with_window(mut self, percent: f64) -> Self {
+        self.window = percent.clamp(0.0, 100.0);
+        self
+    }
+
+    /// Delay every second grid line by this percentage of half a grid step
+    /// (0 is straight; about 67 gives a triplet feel)
+    pub fn with_swing(mut self, percent: f64) -> Self {
+        self.swing = percent.clamp(0.0, 100.0);
+        self
+    }
+
+    /// Divide the grid into tuplets: `notes` grid steps in the time of
+    /// `span` (e.g. `(3, 2)` turns an eighth grid into eighth triplets)
+    pub fn with_tuplet(mut self, notes: u32, span: u32) -> Self {
+        self.tuplet = (notes.max(1), span.max(1));
+        self
+    }
+
+    /// Also quantize note ends (by default notes keep their length)
+    pub fn with_ends(mut self, enabled: bool) -> Self {
+        self.ends = enabled;
+        self
+    }
+
+    /// Grid size in ticks, before tuplets
+    pub fn grid(&self) -> u64 {
+        self.grid
+    }
+
+    /// Length of one grid step in ticks, with tuplets applied.
+    fn step(&self) -> f64 {
+        self.grid as f64 * self.tuplet.1 as f64 / self.tuplet.0 as f64
+    }
+
+    /// Position of grid line `index`, with swing applied.
+    fn line(&self, index: i64) -> f64 {
+        let position = index as f64 * self.step();
+        if index.rem_euclid(2) == 1 {
+            position + self.swing / 100.0 * self.step() / 2.0
+        } else {
+            position
+        }
+    }
+
+    /// Quantized position of `tick`.
+    fn quantize_tick(&self, tick: u64) -> u64 {
+        let step = self.step();
+        if step <= 0.0 {
+            return tick;
+        }
+        let position = tick as f64;
+        let below = (position / step).floor() as i64;
+        let target = (below - 1..=below + 2)
+            .map(|index| self.line(index))
+            .min_by(|a, b| (a - position).abs().total_cmp(&(b - position).abs()))
+            .unwrap_or(position);
+        let distance = target - position;
+        if self.window < 100.0 && distance.abs() > self.window / 100.0 * step / 2.0 {
+            return tick;
+        }
+        (position + distance * self.strength / 100.0)
+            .round()
+            .max(0.0) as u64
+    }
+}
+
+/// Average timing and velocity deviation of the notes on one grid slot
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GrooveSlot {
+    /// Ticks from the grid line to the note start (negative is early)
+    pub timing: f64,
+    /// Velocity relative to the performance's mean velocity
+    pub velocity: f64,
+}
+
+/// Per-slot timing and velocity offsets over a repeating run of grid slots
+/// (e.g. 16 slots of 120 ticks for one bar of sixteenths at 480 PPQN)
+#[derive(Debug, Clone, PartialEq)]
+pub struct GrooveTemplate {
+    /// Grid size in ticks
+    pub grid: u64,
+    /// Offsets for each slot; grid line `n` uses slot `n % slots.len()`
+    pub slots: Vec<GrooveSlot>,
+}
+
+impl GrooveTemplate {
+    /// Create a template of `slots` neutral slots
+    pub fn new(grid: u64, slots: usize) -> Self {
+        Self {
+            grid,
+            slots: vec![GrooveSlot::default(); slots],
+        }
+    }
+
+    /// Number of slots before the template repeats
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Check whether the template has no slots
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Nearest grid line to `tick` and the slot it falls on.
+    fn slot(&self, tick: u64) -> (u64, usize) {
+        let line = (tick + self.grid / 2) / self.grid;
+        (line * self.grid, (line % self.slots.len() as u64) as usize)
+    }
+}
+
+/// Move every note of `track` to the start, end and velocity returned by
+/// `place(start, end, velocity)`.
+///
+/// Notes that get no end keep their length, notes that had a length keep at
+/// least one tick, and a note that ended before the next note on the same
+/// key started is cut short if it would now overlap it, so that
+/// `link_note_events` pairs the moved events the same way. Links are
+/// rebuilt if the track was linked.
+pub(super) fn move_notes<F>(track: &mut MidiTrack, mut place: F)
+where
+    F: FnMut(u64, Option<u64>, u8) -> (u64, Option<u64>, u8),
+{
+    let was_linked = track.events().iter().any(MidiEvent::is_linked);
+    track.link_note_events();
+    let events = track.events();
+
+    let mut ticks: Vec<u64> = events.iter().map(MidiEvent::tick).collect();
+    let mut velocities: Vec<Option<u8>> = vec![None; events.len()];
+    let mut previous: Vec<Option<(usize, usize)>> = vec![None; 16 * 128];
+    for (on_idx, event) in events.iter().enumerate() {
+        if !event.is_note_on() {
+            continue;
+        }
+        let (Some(channel), Some(key), Some(velocity)) =
+            (event.channel(), event.key(), event.velocity())
+        else {
+            continue;
+        };
+        let off_idx = event.linked_event();
+        let end = off_idx.map(|i| events[i].tick());
+        let (start, new_end, new_velocity) = place(event.tick(), end, velocity);
+        ticks[on_idx] = start;
+        if new_velocity != velocity {
+            velocities[on_idx] = Some(new_velocity.clamp(1, 127));
+        }
+
+        let slot = &mut previous[(channel as usize & 0x0F) * 128 + key as usize];
+        if let Some((prev_on_idx, prev_off_idx)) = *slot
+            && events[prev_off_idx].tick() <= event.tick()
+            && ticks[prev_off_idx] > start
+        {
+            ticks[prev_off_idx] = start.max(ticks[prev_on_idx]);
+        }
+        if let (Some(off_idx), Some(end)) = (off_idx, end) {
+            let length = end.saturating_sub(event.tick());
+            let min_end = start + u64::from(length > 0);
+            ticks[off_idx] = new_end.unwrap_or(start + length).max(min_end);
+            *slot = Some((on_idx, off_idx));
+        }
+    }
+
+    for (i, event) in track.events_mut().iter_mut().enumerate() {
+        event.set_tick(ticks[i]);
+        event.unlink_event();
+        if let (Some(velocity), MidiMessage::NoteOn { velocity: v, .. }) =
+            (velocities[i], event.message_mut())
+        {
+            *v = velocity;
+        }
+    }
+    track.sort();
+    if was_linked {
+        track.link_note_events();
+    }
+}
+
+/// Quantize the notes of `track`.
+pub(super) fn quantize(track: &mut MidiTrack, options: &QuantizeOptions) {
+    if options.grid == 0 {
+        return;
+    }
+    move_notes(track, |start, end, velocity| {
+        let new_start = options.quantize_tick(start);
+        let new_end = end.filter(|_| options.ends).map(|end| {
+            let quantized = options.quantize_tick(end);
+            if quantized > new_start || end <= start {
+                quantized
+            } else {
+                // The end collapsed onto the start: keep up to a grid step.
+                new_start + (end - start).min(options.step().round().max(1.0) as u64)
+            }
+        });
+        (new_start, new_end, velocity)
+    });
+}
+
+/// Measure the groove of the notes in `events` over `slots` slots of `grid`
+/// ticks.
+pub(super) fn extract_groove(events: &[MidiEvent], grid: u64, slots: usize) -> GrooveTemplate {
+    let mut template = GrooveTemplate::new(grid, slots);
+    if grid == 0 || slots == 0 {
+        return template;
+    }
+    let notes: Vec<(u64, u8)> = events
+        .iter()
+        .filter(|e| e.is_note_on())
+        .filter_map(|e| Some((e.tick(), e.velocity()?)))
+        .collect();
+    if notes.is_empty() {
+        return template;
+    }
+    let mean_velocity = notes.iter().map(|&(_, v)| v as f64).sum::<f64>() / notes.len() as f64;
+
+    let mut counts = vec![0usize; slots];
+    for &(tick, velocity) in &notes {
+        let (line, slot) = template.slot(tick);
+        counts[slot] += 1;
+        template.slots[slot].timing += tick as f64 - line as f64;
+        template.slots[slot].velocity += velocity as f64 - mean_velocity;
+    }
+    for (slot, count) in template.slots.iter_mut().zip(counts) {
+        if count > 0 {
+            slot.timing /= count as f64;
+            slot.velocity /= count as f64;
+        }
+    }
+    template
+}
+
+/// Pull the notes of `track` towards `template` by `strength` percent.
+pub(super) fn apply_groove(track: &mut MidiTrack, template: &GrooveTemplate, strength: f64) {
+    if template.grid == 0 || template.is_empty() {
+        return;
+    }
+    let amount = strength.clamp(0.0, 100.0) / 100.0;
+    move_notes(track, |start, _, velocity| {
+        let (line, slot) = template.slot(start);
+        let groove = template.slots[slot];
+        let target = line as f64 + groove.timing;
+        let new_start = (start as f64 + (target - start as f64) * amount)
+            .round()
+            .max(0.0) as u64;
+        let new_velocity = (velocity as f64 + groove.velocity * amount)
+            .round()
+            .clamp(1.0, 127.0) as u8;
+        (new_start, None, new_velocity)
+    });
+}
+
+impl MidiFile {
+    /// Quantize the notes of every track (see `MidiTrack::quantize`)
+    pub fn quantize(&mut self, options: &QuantizeOptions) {
+        for track in self.tracks_mut() {
+            track.quantize(options);
+        }
+    }
+
+    /// Apply a groove template to the notes of every track (see
+    /// `MidiTrack::apply_groove`)
+    pub fn apply_groove(&mut self, template: &GrooveTemplate, strength: f64) {
+        for track in self.tracks_mut() {
+            track.apply_groove(template, strength);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_spans(track: &MidiTrack) -> Vec<(u64, u64, u8)> {
+        let events = track.events();
+        events
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.is_note_on())
+            .map(|(i, e)| {
+                let off = e.linked_event().unwrap();
+                assert_eq!(events[off].linked_event(), Some(i));
+                (e.tick(), events[off].tick(), e.velocity().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_quantize_strength_window_and_ends() {
+        let mut track = MidiTrack::new();
+        track.add_note(10, 100, 0, 60, 100);
+        track.add_note(130, 100, 0, 62, 100);
+        track.add_note(290, 100, 0, 64, 100);
+        track.sort();
+
+        let mut full = track.clone();
+        full.quantize(&QuantizeOptions::new(120));
+        assert_eq!(
+            note_spans(&full),
+            vec![(0, 100, 100), (120, 220, 100), (240, 340, 100)]
+        );
+
+        let mut half = track.clone();
+        half.quantize(&QuantizeOptions::new(120).with_strength(50.0));
+        assert_eq!(half.events()[0].tick(), 5);
+
+        // 290 is 50 ticks from 240, outside a 50% window of 30 ticks.
+        let mut windowed = track.clone();
+        windowed.quantize(&QuantizeOptions::new(120).with_window(50.0).with_ends(true));
+        assert_eq!(
+            note_spans(&windowed),
+            vec![(0, 120, 100), (120, 240, 100), (290, 360, 100)]
+        );
+    }
+
+    #[test]
+    fn test_quantize_swing_tuplets_and_same_key_notes() {
+        let mut track = MidiTrack::new();
+        track.add_note(0, 230, 0, 60, 100);
+        track.add_note(230, 200, 0, 60, 100);
+        track.sort();
+
+        // Swung eighths: the offbeat line moves from 240 to 320.
+        let mut swung = track.clone();
+        swung.quantize(&QuantizeOptions::new(240).with_swing(66.67));
+        assert_eq!(note_spans(&swung), vec![(0, 230, 100), (320, 520, 100)]);
+
+        // Eighth triplets at 480 PPQN fall every 160 ticks; the first note
+        // is cut so the repeated key still pairs up.
+        let mut triplets = track.clone();
+        triplets.quantize(&QuantizeOptions::new(240).with_tuplet(3, 2));
+        assert_eq!(note_spans(&triplets), vec![(0, 160, 100), (160, 360, 100)]);
+        triplets.link_note_events();
+        assert_eq!(note_spans(&triplets), vec![(0, 160, 100), (160, 360, 100)]);
+    }
+
+    #[test]
+    fn test_groove_extract_and_apply() {
+        let mut reference = MidiTrack::new();
+        for bar in 0..2 {
+            let base = bar * 480;
+            reference.add_note(base, 100, 9, 36, 110);
+            reference.add_note(base + 130, 100, 9, 42, 70);
+            reference.add_note(base + 240, 100, 9, 38, 110);
+            reference.add_note(base + 370, 100, 9, 42, 70);
+        }
+        reference.sort();
+        let groove = reference.extract_groove(120, 4);
+        assert_eq!(groove.len(), 4);
+        assert_eq!(
+            groove.slots[1],
+            GrooveSlot {
+                timing: 10.0,
+                velocity: -20.0
+            }
+        );
+        assert_eq!(groove.slots[3].timing, 10.0);
+        assert_eq!(groove.slots[0].velocity, 20.0);
+
+        let mut file = MidiFile::new();
+        file.add_track();
+        for i in 0..4 {
+            file.add_note(0, i * 120, 60, 0, 40 + i as u8, 90).unwrap();
+        }
+        file.apply_groove(&groove, 100.0);
+        let mut track = file.tracks()[0].clone();
+        track.link_note_events();
+        assert_eq!(
+            note_spans(&track),
+            vec![
+                (0, 60, 110),
+                (130, 190, 70),
+                (240, 300, 110),
+                (370, 430, 70)
+            ]
+        );
+    }
+}
@@ -0,0 +1,394 @@
+//! Streaming Standard MIDI File reader
+//!
+//! `SmfReader` pulls events one at a time from any `Read + Seek` source
+//! instead of building a `MidiFile`. Only the header and a table of track
+//! chunk offsets are kept in memory; each track is decoded through a small
+//! refillable buffer, so memory stays bounded by the number of tracks and
+//! the largest single event.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{Read, Seek, SeekFrom};
+
+use super::file::{decode_event, read_u16_be, read_u32_be};
+use super::message::MidiMessage;
+use super::{MidiError, MidiFormat, TimeDivision};
+
+/// Bytes read from a track chunk at a time
+const READ_CHUNK_SIZE: usize = 4096;
+
+/// An event yielded by `SmfReader`: track index, absolute tick and message.
+/// SysEx packets that are not complete messages lose their framing (see
+/// `MidiEvent::sysex_framing`).
+pub type SmfEvent = (usize, u64, MidiMessage);
+
+/// Location of a track chunk's body in the underlying stream
+#[derive(Debug, Clone, Copy)]
+struct TrackChunk {
+    offset: u64,
+    len: u64,
+}
+
+/// Pull-based reader over a Standard MIDI File
+#[derive(Debug)]
+pub struct SmfReader<R> {
+    reader: R,
+    format: MidiFormat,
+    division: TimeDivision,
+    chunks: Vec<TrackChunk>,
+}
+
+impl<R: Read + Seek> SmfReader<R> {
+    /// Read the header and locate every track chunk. Track contents are not
+    /// read until they are iterated.
+    pub fn new(mut reader: R) -> Result<Self, MidiError> {
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut header = [0u8; 14];
+        reader
+            .read_exact(&mut header)
+            .map_err(|_| MidiError::InvalidHeader)?;
+        if &header[0..4] != b"MThd" {
+            return Err(MidiError::InvalidHeader);
+        }
+        let header_len = read_u32_be(&header[4..8]) as u64;
+        if header_len < 6 {
+            return Err(MidiError::InvalidHeader);
+        }
+        let format = MidiFormat::try_from(read_u16_be(&header[8..10]))?;
+        let num_tracks = read_u16_be(&header[10..12]) as usize;
+        let division = TimeDivision::try_from(read_u16_be(&header[12..14]))?;
+
+        // Same chunk walk as `MidiFile::from_bytes`, without reading bodies.
+        let mut chunks = Vec::with_capacity(num_tracks);
+        let mut pos = 8 + header_len;
+        while pos + 8 <= file_len && chunks.len() < num_tracks {
+            reader.seek(SeekFrom::Start(pos))?;
+            let mut chunk_header = [0u8; 8];
+            reader.read_exact(&mut chunk_header)?;
+            if &chunk_header[0..4] != b"MTrk" {
+                return Err(MidiError::InvalidTrackHeader);
+            }
+
+            let len = read_u32_be(&chunk_header[4..8]) as u64;
+            pos += 8;
+            if pos + len > file_len {
+                return Err(MidiError::UnexpectedEof);
+            }
+            chunks.push(TrackChunk { offset: pos, len });
+            pos += len;
+        }
+
+        Ok(Self {
+            reader,
+            format,
+            division,
+            chunks,
+        })
+    }
+
+    /// Get the file format
+    pub fn format(&self) -> MidiFormat {
+        self.format
+    }
+
+    /// Get the time division
+    pub fn time_division(&self) -> TimeDivision {
+        self.division
+    }
+
+    /// Get the number of track chunks found
+    pub fn num_tracks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Iterate over the events of a single track, in file order
+    pub fn track_events(&mut self, track: usize) -> Result<TrackEvents<'_, R>, MidiError> {
+        let chunk = *self
+            .chunks
+            .get(track)
+            .ok_or(MidiError::TrackOutOfBounds(track))?;
+        Ok(TrackEvents {
+            reader: &mut self.reader,
+            cursor: TrackCursor::new(track, chunk),
+        })
+    }
+
+    /// Iterate over the events of every track merged into time order. Ties
+    /// are broken by track index, then by file order within the track.
+    pub fn merged_events(&mut self) -> MergedEvents<'_, R> {
+        let cursors = self
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(track, &chunk)| TrackCursor::new(track, chunk))
+            .collect();
+        MergedEvents {
+            reader: &mut self.reader,
+            cursors,
+            pending: Vec::new(),
+            heap: BinaryHeap::new(),
+            started: false,
+            failed: false,
+        }
+    }
+
+    /// Consume the reader and return the underlying stream
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// Decoding state for one track chunk
+#[derive(Debug)]
+struct TrackCursor {
+    track: usize,
+    /// Stream offset of the next chunk byte not yet in `buf`
+    next_offset: u64,
+    /// Chunk bytes not yet read into `buf`
+    remaining: u64,
+    buf: Vec<u8>,
+    pos: usize,
+    running_status: Option<u8>,
+    tick: u64,
+    done: bool,
+}
+
+impl TrackCursor {
+    fn new(track: usize, chunk: TrackChunk) -> Self {
+        Self {
+            track,
+            next_offset: chunk.offset,
+            remaining: chunk.len,
+            buf: Vec::new(),
+            pos: 0,
+            running_status: None,
+            tick: 0,
+            done: false,
+        }
+    }
+
+    /// Read more of the chunk, keeping any partially decoded event. The
+    /// buffer at least doubles so an event larger than `READ_CHUNK_SIZE`
+    /// (e.g. a long SysEx) is reached in a few reads.
+    fn refill<R: Read + Seek>(&mut self, reader: &mut R) -> Result<(), MidiError> {
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+
+        let want = READ_CHUNK_SIZE.max(self.buf.len()) as u64;
+        let n = want.min(self.remaining) as usize;
+        let old_len = self.buf.len();
+        self.buf.resize(old_len + n, 0);
+        reader.seek(SeekFrom::Start(self.next_offset))?;
+        reader.read_exact(&mut self.buf[old_len..])?;
+        self.next_offset += n as u64;
+        self.remaining -= n as u64;
+        Ok(())
+    }
+
+    fn next_event<R: Read + Seek>(
+        &mut self,
+        reader: &mut R,
+    ) -> Option<Result<SmfEvent, MidiError>> {
+        while !self.done {
+            let available = &self.buf[self.pos..];
+            if available.is_empty() && self.remaining == 0 {
+                break;
+            }
+
+            let mut running_status = self.running_status;
+            match decode_event(available, &mut running_status) {
+                Ok(Some((delta, message, _, len))) => {
+                    self.running_status = running_status;
+                    self.pos += len;
+                    self.tick += delta as u64;
+                    return Some(Ok((self.track, self.tick, message)));
+                }
+                // A trailing delta time or a malformed event is only final
+                // once the whole chunk is in the buffer.
+                Ok(None) if self.remaining == 0 => break,
+                Err(e) if self.remaining == 0 => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                _ => {
+                    if let Err(e) = self.refill(reader) {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                }
+            }
+        }
+
+        self.done = true;
+        self.buf = Vec::new();
+        None
+    }
+}
+
+/// Iterator over one track's events (see `SmfReader::track_events`)
+pub struct TrackEvents<'a, R> {
+    reader: &'a mut R,
+    cursor: TrackCursor,
+}
+
+impl<R: Read + Seek> Iterator for TrackEvents<'_, R> {
+    type Item = Result<SmfEvent, MidiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.cursor.next_event(self.reader)
+    }
+}
+
+/// Iterator over all tracks in time order (see `SmfReader::merged_events`)
+pub struct MergedEvents<'a, R> {
+    reader: &'a mut R,
+    cursors: Vec<TrackCursor>,
+    /// The next undelivered event of each track
+    pending: Vec<Option<SmfEvent>>,
+    /// (tick, track) of every pending event
+    heap: BinaryHeap<Reverse<(u64, usize)>>,
+    started: bool,
+    failed: bool,
+}
+
+impl<R: Read + Seek> MergedEvents<'_, R> {
+    /// Pull the next event of `track` into `pending`/`heap`.
+    fn advance(&mut self, track: usize) -> Result<(), MidiError> {
+        match self.cursors[track].next_event(self.reader) {
+            Some(Ok(event)) => {
+                self.heap.push(Reverse((event.1, track)));
+                self.pending[track] = Some(event);
+                Ok(())
+            }
+            Some(Err(e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<R: Read + Seek> Iterator for MergedEvents<'_, R> {
+    type Item = Result<SmfEvent, MidiError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if !self.started {
+            self.started = true;
+            self.pending = vec![None; self.cursors.len()];
+            for track in 0..self.cursors.len() {
+                if let Err(e) = self.advance(track) {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        let Reverse((_, track)) = self.heap.pop()?;
+        let event = self.pending[track].take();
+        if let Err(e) = self.advance(track) {
+            self.failed = true;
+            return Some(Err(e));
+        }
+        event.map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::{MidiFile, MidiTrack};
+    use std::io::Cursor;
+
+    fn two_track_file() -> Vec<u8> {
+        let mut file = MidiFile::new();
+        let mut conductor = MidiTrack::new();
+        conductor.add_tempo(0, 120.0);
+        conductor.add_tempo(960, 90.0);
+        conductor.add_end_of_track();
+        file.add_track_from(conductor);
+
+        let mut notes = MidiTrack::new();
+        notes.add_note(0, 480, 0, 60, 100);
+        notes.add_note(480, 480, 0, 64, 100);
+        notes.add_end_of_track();
+        file.add_track_from(notes);
+        file.to_bytes()
+    }
+
+    #[test]
+    fn test_track_events_match_midi_file() {
+        let bytes = two_track_file();
+        let parsed = MidiFile::from_bytes(&bytes).unwrap();
+        let mut reader = SmfReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.num_tracks(), 2);
+        assert_eq!(reader.time_division(), TimeDivision::Ppqn(480));
+
+        for track in 0..2 {
+            let streamed: Vec<SmfEvent> = reader
+                .track_events(track)
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            let expected: Vec<SmfEvent> = parsed.tracks()[track]
+                .events()
+                .iter()
+                .map(|e| (track, e.tick(), e.message().clone()))
+                .collect();
+            assert_eq!(streamed, expected);
+        }
+        assert!(matches!(
+            reader.track_events(2),
+            Err(MidiError::TrackOutOfBounds(2))
+        ));
+    }
+
+    #[test]
+    fn test_merged_events_time_ordered() {
+        let mut reader = SmfReader::new(Cursor::new(two_track_file())).unwrap();
+        let merged: Vec<SmfEvent> = reader.merged_events().collect::<Result<_, _>>().unwrap();
+        assert_eq!(merged.len(), 3 + 5);
+        assert!(merged.windows(2).all(|w| w[0].1 <= w[1].1));
+        // Ties go to the lower track index.
+        assert_eq!((merged[0].0, merged[0].1), (0, 0));
+        assert_eq!((merged[1].0, merged[1].1), (1, 0));
+        let tempo_at_960 = merged.iter().position(|e| e.0 == 0 && e.1 == 960).unwrap();
+        let note_off_at_960 = merged.iter().position(|e| e.0 == 1 && e.1 == 960).unwrap();
+        assert!(tempo_at_960 < note_off_at_960);
+    }
+
+    #[test]
+    fn test_events_larger_than_read_buffer() {
+        let mut track = MidiTrack::new();
+        track.add_text(0, "x".repeat(READ_CHUNK_SIZE * 3));
+        track.add_note(10, 10, 1, 62, 90);
+        track.add_end_of_track();
+        let mut file = MidiFile::new();
+        file.add_track_from(track);
+
+        let mut reader = SmfReader::new(Cursor::new(file.to_bytes())).unwrap();
+        let events: Vec<SmfEvent> = reader
+            .track_events(0)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[1].2, MidiMessage::NoteOn { key: 62, .. }));
+        assert_eq!(events[2].1, 20);
+    }
+
+    #[test]
+    fn test_truncated_track_reports_error() {
+        let mut bytes = two_track_file();
+        // Turn the final End-of-Track into a tempo event whose three data
+        // bytes are missing.
+        let len = bytes.len();
+        bytes[len - 2] = 0x51;
+        bytes[len - 1] = 0x03;
+        let mut reader = SmfReader::new(Cursor::new(bytes)).unwrap();
+        let result: Result<Vec<SmfEvent>, MidiError> = reader.track_events(1).unwrap().collect();
+        assert!(matches!(result, Err(MidiError::UnexpectedEof)));
+    }
+}
@@ -0,0 +1,251 @@
+//! PPQN resolution changes
+//!
+//! `MidiFile::resample` converts a file to a new ticks-per-quarter value and
+//! rescales every event so the music keeps its tempo, keeping note order
+//! and note lengths intact where rounding would otherwise collapse them.
+
+use super::event::MidiEvent;
+use super::file::MidiFile;
+use super::message::{MetaEvent, MidiMessage};
+use super::track::MidiTrack;
+use super::{MidiError, TimeDivision};
+
+/// How rescaled ticks are rounded to whole ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TickRounding {
+    /// Round to the nearest tick (halves round up)
+    #[default]
+    Nearest,
+    /// Round towards the start of the file
+    Down,
+    /// Round towards the end of the file
+    Up,
+}
+
+impl TickRounding {
+    fn apply(self, tick: u64, from: u64, to: u64) -> u64 {
+        let scaled = tick as u128 * to as u128;
+        let from = from as u128;
+        let rounded = match self {
+            TickRounding::Nearest => (scaled + from / 2) / from,
+            TickRounding::Down => scaled / from,
+            TickRounding::Up => scaled.div_ceil(from),
+        };
+        rounded as u64
+    }
+}
+
+/// Quantization error introduced by `MidiFile::resample`, in ticks of the
+/// new resolution
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ResampleReport {
+    /// Number of events rescaled (end-of-track markers excluded)
+    pub events: usize,
+    /// Largest distance between an event's new tick and its exact position
+    pub max_error: f64,
+    /// Mean distance between an event's new tick and its exact position
+    pub mean_error: f64,
+    /// Notes moved or lengthened beyond plain rounding, to keep them at
+    /// least one tick long and to keep consecutive same-key notes from
+    /// overlapping
+    pub adjusted_notes: usize,
+}
+
+/// Running totals while resampling.
+#[derive(Default)]
+struct ErrorTotals {
+    events: usize,
+    max: f64,
+    sum: f64,
+    adjusted_notes: usize,
+}
+
+fn is_end_of_track(event: &MidiEvent) -> bool {
+    matches!(event.message(), MidiMessage::Meta(MetaEvent::EndOfTrack))
+}
+
+fn resample_track(
+    track: &mut MidiTrack,
+    from: u64,
+    to: u64,
+    rounding: TickRounding,
+    totals: &mut ErrorTotals,
+) {
+    let was_linked = track.events().iter().any(MidiEvent::is_linked);
+    track.link_note_events();
+    let events = track.events();
+
+    let mut ticks: Vec<u64> = events
+        .iter()
+        .map(|e| rounding.apply(e.tick(), from, to))
+        .collect();
+
+    // Keep each note at least one tick long (if it was) and keep a note from
+    // starting before the previous note on the same key ends (if it didn't).
+    // Notes are visited in note-on order, so a push carries forward.
+    let mut previous: Vec<Option<usize>> = vec![None; 16 * 128];
+    for (on_idx, event) in events.iter().enumerate() {
+        let (Some(off_idx), Some(channel), Some(key)) =
+            (event.linked_event(), event.channel(), event.key())
+        else {
+            continue;
+        };
+        if !event.is_note_on() {
+            continue;
+        }
+        let slot = &mut previous[(channel as usize & 0x0F) * 128 + key as usize];
+        let mut adjusted = false;
+        if let Some(prev_off_idx) = *slot
+            && event.tick() >= events[prev_off_idx].tick()
+            && ticks[on_idx] < ticks[prev_off_idx]
+        {
+            ticks[on_idx] = ticks[prev_off_idx];
+            adjusted = true;
+        }
+        let min_length = u64::from(events[off_idx].tick() > event.tick());
+        if ticks[off_idx] < ticks[on_idx] + min_length {
+            ticks[off_idx] = ticks[on_idx] + min_length;
+            adjusted = true;
+        }
+        if adjusted {
+            totals.adjusted_notes += 1;
+        }
+        *slot = Some(off_idx);
+    }
+
+    let end = ticks.iter().copied().max().unwrap_or(0);
+    for (i, event) in events.iter().enumerate() {
+        if is_end_of_track(event) {
+            ticks[i] = end;
+            continue;
+        }
+        let exact = event.tick() as f64 * to as f64 / from as f64;
+        let error = (ticks[i] as f64 - exact).abs();
+        totals.events += 1;
+        totals.max = totals.max.max(error);
+        totals.sum += error;
+    }
+
+    // Rebuild in the new tick order, falling back to the original order for
+    // events that now share a tick, and freeze that order with sequence
+    // numbers so a later sort cannot reorder them by event type.
+    let mut order: Vec<usize> = (0..events.len()).collect();
+    order.sort_by_key(|&i| (ticks[i], i));
+    let rebuilt: Vec<MidiEvent> = order
+        .into_iter()
+        .map(|i| {
+            let mut event = events[i].clone();
+            event.set_tick(ticks[i]);
+            event.unlink_event();
+            event
+        })
+        .collect();
+    *track.events_mut() = rebuilt;
+    track.mark_sequence();
+    track.sort();
+    if was_linked {
+        track.link_note_events();
+    }
+}
+
+impl MidiFile {
+    /// Convert the file to `ticks_per_quarter` and rescale every event tick
+    /// so it plays back at the same speed (unlike `set_ticks_per_quarter`,
+    /// which only changes the header).
+    ///
+    /// Note-on/note-off order is preserved, notes that had a length keep at
+    /// least one tick, and a note never starts before the previous note on
+    /// the same key has ended. Fails for SMPTE files and for resolutions that
+    /// cannot be stored in the header.
+    pub fn resample(
+        &mut self,
+        ticks_per_quarter: u16,
+        rounding: TickRounding,
+    ) -> Result<ResampleReport, MidiError> {
+        let TimeDivision::Ppqn(from) = self.time_division() else {
+            return Err(MidiError::InvalidTimeDivision(
+                self.time_division().to_u16(),
+            ));
+        };
+        if ticks_per_quarter == 0 || ticks_per_quarter > 0x7FFF {
+            return Err(MidiError::InvalidTimeDivision(ticks_per_quarter));
+        }
+
+        let mut totals = ErrorTotals::default();
+        if from != ticks_per_quarter {
+            let from = from.max(1) as u64;
+            for track in self.tracks_mut() {
+                resample_track(track, from, ticks_per_quarter as u64, rounding, &mut totals);
+            }
+        }
+        self.set_ticks_per_quarter(ticks_per_quarter);
+
+        Ok(ResampleReport {
+            events: totals.events,
+            max_error: totals.max,
+            mean_error: if totals.events == 0 {
+                0.0
+            } else {
+                totals.sum / totals.events as f64
+            },
+            adjusted_notes: totals.adjusted_notes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note_spans(file: &MidiFile) -> Vec<(u64, u64)> {
+        let mut track = file.tracks()[0].clone();
+        track.link_note_events();
+        track
+            .events()
+            .iter()
+            .filter(|e| e.is_note_on())
+            .map(|e| (e.tick(), track.events()[e.linked_event().unwrap()].tick()))
+            .collect()
+    }
+
+    #[test]
+    fn test_resample_exact_and_seconds_preserved() {
+        let mut file = MidiFile::with_format(crate::midi::MidiFormat::MultiTrack, 96);
+        file.add_track();
+        file.add_tempo(0, 0, 90.0).unwrap();
+        file.add_note(0, 96, 48, 0, 60, 100).unwrap();
+        file.finalize();
+        let seconds = file.total_seconds();
+
+        let report = file.resample(960, TickRounding::Nearest).unwrap();
+        assert_eq!(file.ticks_per_quarter(), 960);
+        assert_eq!(note_spans(&file), vec![(960, 1440)]);
+        assert_eq!(report.max_error, 0.0);
+        assert_eq!(report.adjusted_notes, 0);
+        assert!((file.total_seconds() - seconds).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_resample_down_keeps_lengths_and_order() {
+        let mut file = MidiFile::with_format(crate::midi::MidiFormat::MultiTrack, 960);
+        file.add_track();
+        // Two short repeated notes on the same key collapse at 96 PPQN.
+        file.add_note(0, 0, 5, 0, 60, 100).unwrap();
+        file.add_note(0, 5, 5, 0, 60, 100).unwrap();
+        file.add_note(0, 961, 959, 0, 62, 100).unwrap();
+        file.finalize();
+
+        let report = file.resample(96, TickRounding::Nearest).unwrap();
+        assert_eq!(note_spans(&file), vec![(0, 1), (1, 2), (96, 192)]);
+        assert_eq!(report.adjusted_notes, 1);
+        assert!(report.max_error > 0.4 && report.max_error <= 1.0);
+        assert!(report.mean_error > 0.0 && report.mean_error < report.max_error);
+
+        let mut smpte = MidiFile::new();
+        smpte.set_time_division(TimeDivision::Smpte {
+            fps: crate::midi::SmpteFps::Fps25,
+            ticks_per_frame: 40,
+        });
+        assert!(smpte.resample(480, TickRounding::Down).is_err());
+    }
+}
@@ -0,0 +1,264 @@
+//! RIFF RMID container support
+//!
+//! An `.rmi` file is a RIFF form of type `RMID` whose `data` chunk holds a
+//! complete Standard MIDI File, optionally alongside a `LIST`/`INFO` chunk
+//! of text tags and embedded DLS instrument data.
+
+use super::MidiError;
+
+/// Metadata carried by an RMID container
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RmidInfo {
+    /// Title (`INAM`)
+    pub title: Option<String>,
+    /// Copyright notice (`ICOP`)
+    pub copyright: Option<String>,
+    /// Comments (`ICMT`)
+    pub comments: Option<String>,
+    /// Any other INFO tags (e.g. `ISFT`, `ICRD`), in file order
+    pub other_tags: Vec<([u8; 4], String)>,
+    /// Chunks other than `data` and `LIST`/`INFO` (e.g. embedded DLS),
+    /// kept verbatim so they survive a rewrite
+    pub extra_chunks: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl RmidInfo {
+    /// Create empty metadata
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the title
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    /// Set the copyright notice
+    pub fn with_copyright(mut self, copyright: impl Into<String>) -> Self {
+        self.copyright = Some(copyright.into());
+        self
+    }
+
+    /// Set the comments
+    pub fn with_comments(mut self, comments: impl Into<String>) -> Self {
+        self.comments = Some(comments.into());
+        self
+    }
+
+    /// Look up an INFO tag by its four-character id
+    pub fn tag(&self, id: &[u8; 4]) -> Option<&str> {
+        match id {
+            b"INAM" => self.title.as_deref(),
+            b"ICOP" => self.copyright.as_deref(),
+            b"ICMT" => self.comments.as_deref(),
+            _ => self
+                .other_tags
+                .iter()
+                .find(|(tag, _)| tag == id)
+                .map(|(_, value)| value.as_str()),
+        }
+    }
+
+    fn set_tag(&mut self, id: [u8; 4], value: String) {
+        match &id {
+            b"INAM" => self.title = Some(value),
+            b"ICOP" => self.copyright = Some(value),
+            b"ICMT" => self.comments = Some(value),
+            _ => self.other_tags.push((id, value)),
+        }
+    }
+}
+
+/// Check whether `data` starts with a RIFF `RMID` header
+pub(super) fn is_rmid(data: &[u8]) -> bool {
+    data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"RMID"
+}
+
+/// Split an RMID container into the byte range of its embedded SMF and its
+/// metadata.
+pub(super) fn unwrap(data: &[u8]) -> Result<(std::ops::Range<usize>, RmidInfo), MidiError> {
+    let riff_end = (8 + read_u32_le(&data[4..8]) as usize).min(data.len());
+    let mut info = RmidInfo::new();
+    let mut smf = None;
+
+    for (id, range) in chunks(data, 12, riff_end) {
+        let body = &data[range.clone()];
+        match &id {
+            b"data" if smf.is_none() => smf = Some(range),
+            b"LIST" if body.len() >= 4 && &body[0..4] == b"INFO" => {
+                let base = range.start + 4;
+                for (tag, tag_range) in chunks(data, base, range.end) {
+                    info.set_tag(tag, decode_zstr(&data[tag_range]));
+                }
+            }
+            _ => info.extra_chunks.push((id, body.to_vec())),
+        }
+    }
+
+    smf.map(|range| (range, info))
+        .ok_or(MidiError::InvalidHeader)
+}
+
+/// Wrap SMF bytes in an RMID container with the given metadata.
+pub(super) fn wrap(smf: &[u8], info: &RmidInfo) -> Vec<u8> {
+    let mut form = Vec::new();
+    form.extend(b"RMID");
+    write_chunk(&mut form, b"data", smf);
+
+    let mut tags = Vec::new();
+    let known = [
+        (b"INAM", &info.title),
+        (b"ICOP", &info.copyright),
+        (b"ICMT", &info.comments),
+    ];
+    for (id, value) in known {
+        if let Some(value) = value {
+            write_chunk(&mut tags, id, &encode_zstr(value));
+        }
+    }
+    for (id, value) in &info.other_tags {
+        write_chunk(&mut tags, id, &encode_zstr(value));
+    }
+    if !tags.is_empty() {
+        let mut list = b"INFO".to_vec();
+        list.extend(tags);
+        write_chunk(&mut form, b"LIST", &list);
+    }
+
+    for (id, body) in &info.extra_chunks {
+        write_chunk(&mut form, id, body);
+    }
+
+    let mut data = Vec::with_capacity(form.len() + 8);
+    write_chunk(&mut data, b"RIFF", &form);
+    data
+}
+
+fn read_u32_le(data: &[u8]) -> u32 {
+    u32::from_le_bytes([data[0], data[1], data[2], data[3]])
+}
+
+/// Iterate over the RIFF chunks in `data[start..end]`, yielding each id and
+/// the byte range of its body. A truncated final chunk is clipped.
+fn chunks(
+    data: &[u8],
+    start: usize,
+    end: usize,
+) -> impl Iterator<Item = ([u8; 4], std::ops::Range<usize>)> + '_ {
+    let mut pos = start;
+    std::iter::from_fn(move || {
+        if pos + 8 > end {
+            return None;
+        }
+        let mut id = [0u8; 4];
+        id.copy_from_slice(&data[pos..pos + 4]);
+        let body_start = pos + 8;
+        let body_end = body_start
+            .saturating_add(read_u32_le(&data[pos + 4..pos + 8]) as usize)
+            .min(end);
+        // Chunk bodies are padded to an even length.
+        pos = body_end + (body_end - body_start) % 2;
+        Some((id, body_start..body_end))
+    })
+}
+
+fn write_chunk(out: &mut Vec<u8>, id: &[u8; 4], body: &[u8]) {
+    out.extend(id);
+    out.extend(&(body.len() as u32).to_le_bytes());
+    out.extend(body);
+    if body.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// INFO strings are NUL-terminated and usually in a Windows ANSI code page;
+/// accept UTF-8 and fall back to Latin-1.
+fn decode_zstr(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    let bytes = &bytes[..end];
+    match std::str::from_utf8(bytes) {
+        Ok(s) => s.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Text that fits Latin-1 is written as such, so tags read from a file are
+/// written back unchanged and ANSI readers show them correctly; anything
+/// else is written as UTF-8.
+fn encode_zstr(value: &str) -> Vec<u8> {
+    let mut bytes: Vec<u8> = match value.chars().map(|c| u8::try_from(c).ok()).collect() {
+        Some(latin1) => latin1,
+        None => value.as_bytes().to_vec(),
+    };
+    bytes.push(0);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::MidiFile;
+
+    #[test]
+    fn test_rmid_roundtrip_with_tags_and_dls() {
+        let mut file = MidiFile::new();
+        file.add_track();
+        file.add_note(0, 0, 480, 0, 60, 100).unwrap();
+        file.set_rmid_info(Some(
+            RmidInfo::new()
+                .with_title("Canyon")
+                .with_copyright("(c) 1991")
+                .with_comments("odd"),
+        ));
+        file.rmid_info_mut()
+            .unwrap()
+            .extra_chunks
+            .push((*b"DLS ", vec![1, 2, 3]));
+
+        let bytes = file.to_rmid_bytes();
+        assert!(is_rmid(&bytes));
+        assert_eq!(read_u32_le(&bytes[4..8]) as usize, bytes.len() - 8);
+
+        let parsed = MidiFile::from_bytes(&bytes).unwrap();
+        let info = parsed.rmid_info().unwrap();
+        assert_eq!(info.title.as_deref(), Some("Canyon"));
+        assert_eq!(info.tag(b"ICOP"), Some("(c) 1991"));
+        assert_eq!(info.comments.as_deref(), Some("odd"));
+        assert_eq!(info.extra_chunks, vec![(*b"DLS ", vec![1, 2, 3])]);
+        assert_eq!(parsed.to_bytes(), file.to_bytes());
+        assert_eq!(parsed.to_rmid_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_rmid_latin1_tags_and_missing_data_chunk() {
+        let mut list = b"INFO".to_vec();
+        write_chunk(&mut list, b"INAM", b"Caf\xe9\0");
+        write_chunk(&mut list, b"ISFT", b"Sequencer\0");
+        let mut form = b"RMID".to_vec();
+        write_chunk(&mut form, b"LIST", &list);
+        let mut bytes = Vec::new();
+        write_chunk(&mut bytes, b"RIFF", &form);
+
+        assert!(matches!(
+            MidiFile::from_bytes(&bytes),
+            Err(MidiError::InvalidHeader)
+        ));
+
+        let smf = MidiFile::new().to_bytes();
+        write_chunk(&mut form, b"data", &smf);
+        bytes.clear();
+        write_chunk(&mut bytes, b"RIFF", &form);
+        let parsed = MidiFile::from_bytes(&bytes).unwrap();
+        let info = parsed.rmid_info().unwrap();
+        assert_eq!(info.title.as_deref(), Some("Café"));
+        assert_eq!(info.tag(b"ISFT"), Some("Sequencer"));
+
+        // Writing the tags back keeps their Latin-1 bytes.
+        let written = parsed.to_rmid_bytes();
+        assert!(written.windows(5).any(|w| w == b"Caf\xe9\0"));
+        let reparsed = MidiFile::from_bytes(&written).unwrap();
+        assert_eq!(reparsed.rmid_info(), Some(info));
+        assert_eq!(encode_zstr("日本"), "日本\0".as_bytes());
+    }
+}
@@ -0,0 +1,372 @@
+//! File statistics
+//!
+//! `MidiFile::statistics` gathers the numbers tools usually want about a
+//! file in one pass: note counts, ranges and velocities per track and per
+//! channel, polyphony, programs and controllers, tempo, meter and
+//! duration.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use super::file::MidiFile;
+use super::gm_instrument_name;
+use super::message::{MetaEvent, MidiMessage};
+use super::tempo_map::MeterPoint;
+
+/// Note counts, range and velocities of a set of notes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoteStats {
+    /// Number of note-ons
+    pub count: usize,
+    /// Lowest and highest key, if there are notes
+    pub pitch_range: Option<(u8, u8)>,
+    /// Number of note-ons with each velocity (index 0 is always 0)
+    pub velocities: [usize; 128],
+}
+
+impl Default for NoteStats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            pitch_range: None,
+            velocities: [0; 128],
+        }
+    }
+}
+
+impl NoteStats {
+    /// Mean note-on velocity, if there are notes
+    pub fn average_velocity(&self) -> Option<f64> {
+        let total: usize = self
+            .velocities
+            .iter()
+            .enumerate()
+            .map(|(velocity, count)| velocity * count)
+            .sum();
+        (self.count > 0).then(|| total as f64 / self.count as f64)
+    }
+
+    fn add(&mut self, key: u8, velocity: u8) {
+        self.count += 1;
+        self.pitch_range = Some(match self.pitch_range {
+            Some((low, high)) => (low.min(key), high.max(key)),
+            None => (key, key),
+        });
+        self.velocities[velocity as usize & 0x7F] += 1;
+    }
+}
+
+/// Statistics for one track
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TrackStats {
+    /// Track name, from the first track name event or `MidiTrack::name`
+    pub name: Option<String>,
+    /// Notes on the track
+    pub notes: NoteStats,
+    /// Channels the track sends channel messages on, in ascending order
+    pub channels: Vec<u8>,
+}
+
+/// Statistics for one channel, across all tracks
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ChannelStats {
+    /// Notes on the channel
+    pub notes: NoteStats,
+    /// Programs selected on the channel, in order of first use
+    pub programs: Vec<u8>,
+    /// Number of control changes for each controller number used
+    pub controllers: BTreeMap<u8, usize>,
+}
+
+impl ChannelStats {
+    /// Whether the channel carries any channel message
+    pub fn is_used(&self) -> bool {
+        self.notes.count > 0 || !self.programs.is_empty() || !self.controllers.is_empty()
+    }
+}
+
+/// A program change found in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramUse {
+    /// Track index
+    pub track: usize,
+    /// Tick of the program change
+    pub tick: u64,
+    /// Channel
+    pub channel: u8,
+    /// Program number
+    pub program: u8,
+    /// General MIDI name of the program
+    pub name: &'static str,
+}
+
+/// Summary of a whole file, returned by `MidiFile::statistics`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MidiStatistics {
+    /// Per-track statistics, by track index
+    pub tracks: Vec<TrackStats>,
+    /// Per-channel statistics, by channel number
+    pub channels: [ChannelStats; 16],
+    /// All notes in the file
+    pub notes: NoteStats,
+    /// Largest number of notes sounding at once
+    pub max_polyphony: usize,
+    /// Number of notes sounding on average over the file's duration in
+    /// seconds
+    pub average_polyphony: f64,
+    /// Every program change, in tick order
+    pub programs: Vec<ProgramUse>,
+    /// Slowest and fastest tempo in BPM
+    pub tempo_range: (f64, f64),
+    /// Time signature changes (4/4 at tick 0 if the file has none)
+    pub meter_changes: Vec<MeterPoint>,
+    /// Length of the file in ticks
+    pub duration_ticks: u64,
+    /// Length of the file in seconds
+    pub duration_seconds: f64,
+    /// Channels that are set to more than one program
+    pub conflicting_programs: Vec<u8>,
+}
+
+impl MidiStatistics {
+    /// Channels carrying any channel message, in ascending order
+    pub fn used_channels(&self) -> Vec<u8> {
+        (0..16)
+            .filter(|&channel| self.channels[channel as usize].is_used())
+            .collect()
+    }
+}
+
+impl fmt::Display for MidiStatistics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "duration: {:.3} s ({} ticks)",
+            self.duration_seconds, self.duration_ticks
+        )?;
+        let (slowest, fastest) = self.tempo_range;
+        writeln!(f, "tempo: {:.2}-{:.2} BPM", slowest, fastest)?;
+        let meters: Vec<String> = self
+            .meter_changes
+            .iter()
+            .map(|point| format!("{} at bar {}", point.time_signature, point.bar))
+            .collect();
+        writeln!(f, "meter: {}", meters.join(", "))?;
+        writeln!(
+            f,
+            "notes: {} (polyphony max {}, average {:.2})",
+            self.notes.count, self.max_polyphony, self.average_polyphony
+        )?;
+        for (index, track) in self.tracks.iter().enumerate() {
+            writeln!(
+                f,
+                "track {} {:?}: {} notes",
+                index,
+                track.name.as_deref().unwrap_or(""),
+                track.notes.count
+            )?;
+        }
+        for channel in self.used_channels() {
+            let stats = &self.channels[channel as usize];
+            write!(f, "channel {}: {} notes", channel + 1, stats.notes.count)?;
+            if let Some((low, high)) = stats.notes.pitch_range {
+                write!(f, ", keys {}-{}", low, high)?;
+            }
+            let programs: Vec<&str> = stats
+                .programs
+                .iter()
+                .map(|&program| gm_instrument_name(program))
+                .collect();
+            if !programs.is_empty() {
+                write!(f, ", programs: {}", programs.join(", "))?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl MidiFile {
+    /// Gather statistics about the whole file
+    pub fn statistics(&self) -> MidiStatistics {
+        let mut tracks = Vec::with_capacity(self.num_tracks());
+        let mut channels: [ChannelStats; 16] = Default::default();
+        let mut notes = NoteStats::default();
+        let mut programs = Vec::new();
+        // (tick, +1 for a note start, -1 for a note end)
+        let mut changes: Vec<(u64, i64)> = Vec::new();
+
+        for (index, track) in self.tracks().iter().enumerate() {
+            let mut track = track.clone();
+            track.link_note_events();
+            let events = track.events();
+
+            let mut stats = TrackStats {
+                name: events
+                    .iter()
+                    .find_map(|event| match event.message() {
+                        MidiMessage::Meta(MetaEvent::TrackName(name)) => Some(name.clone()),
+                        _ => None,
+                    })
+                    .or_else(|| track.name().map(str::to_string)),
+                ..TrackStats::default()
+            };
+            for event in events {
+                let Some(channel) = event.channel() else {
+                    continue;
+                };
+                if !stats.channels.contains(&channel) {
+                    stats.channels.push(channel);
+                }
+                let channel_stats = &mut channels[channel as usize & 0x0F];
+                match *event.message() {
+                    MidiMessage::NoteOn { key, velocity, .. } if velocity > 0 => {
+                        stats.notes.add(key, velocity);
+                        channel_stats.notes.add(key, velocity);
+                        notes.add(key, velocity);
+                        if let Some(off) = event.linked_event() {
+                            changes.push((event.tick(), 1));
+                            changes.push((events[off].tick(), -1));
+                        }
+                    }
+                    MidiMessage::ProgramChange { program, .. } => {
+                        if !channel_stats.programs.contains(&program) {
+                            channel_stats.programs.push(program);
+                        }
+                        programs.push(ProgramUse {
+                            track: index,
+                            tick: event.tick(),
+                            channel,
+                            program,
+                            name: gm_instrument_name(program),
+                        });
+                    }
+                    MidiMessage::ControlChange { controller, .. } => {
+                        *channel_stats.controllers.entry(controller).or_default() += 1;
+                    }
+                    _ => {}
+                }
+            }
+            stats.channels.sort_unstable();
+            tracks.push(stats);
+        }
+        programs.sort_by_key(|program| program.tick);
+
+        // Ends sort before starts on the same tick, so back-to-back notes
+        // do not count as overlapping.
+        changes.sort_unstable();
+        let mut sounding = 0i64;
+        let mut max_polyphony = 0;
+        let mut weighted = 0.0;
+        let mut previous = 0.0;
+        for (tick, change) in changes {
+            let seconds = self.ticks_to_seconds(tick);
+            weighted += sounding as f64 * (seconds - previous);
+            previous = seconds;
+            sounding += change;
+            max_polyphony = max_polyphony.max(sounding as usize);
+        }
+
+        let tempo_map = self.tempo_map();
+        let tempo_range = tempo_map
+            .points()
+            .iter()
+            .map(|point| point.bpm())
+            .fold((f64::INFINITY, 0.0f64), |(slowest, fastest), bpm| {
+                (slowest.min(bpm), fastest.max(bpm))
+            });
+        let duration_seconds = self.total_seconds();
+        let conflicting_programs = (0..16)
+            .filter(|&channel| channels[channel as usize].programs.len() > 1)
+            .collect();
+
+        MidiStatistics {
+            tracks,
+            channels,
+            notes,
+            max_polyphony,
+            average_polyphony: if duration_seconds > 0.0 {
+                weighted / duration_seconds
+            } else {
+                0.0
+            },
+            programs,
+            tempo_range,
+            meter_changes: self.meter_map().points().to_vec(),
+            duration_ticks: self.total_ticks(),
+            duration_seconds,
+            conflicting_programs,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_file() -> MidiFile {
+        let mut file = MidiFile::new();
+        file.add_tracks(3);
+        file.add_tempo(0, 0, 120.0).unwrap();
+        file.add_tempo(0, 960, 60.0).unwrap();
+        file.add_time_signature(0, 960, 3, 4).unwrap();
+        file.add_track_name(1, 0, "Piano").unwrap();
+        file.add_patch_change(1, 0, 0, 0).unwrap();
+        file.add_note(1, 0, 960, 0, 48, 100).unwrap();
+        file.add_note(1, 480, 480, 0, 72, 60).unwrap();
+        file.add_note(1, 960, 960, 0, 60, 80).unwrap();
+        file.add_controller(1, 0, 0, 7, 100).unwrap();
+        file.add_controller(1, 100, 0, 7, 90).unwrap();
+        file.add_patch_change(2, 0, 1, 40).unwrap();
+        file.add_patch_change(2, 960, 1, 41).unwrap();
+        file.add_note(2, 0, 1920, 1, 67, 80).unwrap();
+        file.finalize();
+        file
+    }
+
+    #[test]
+    fn test_statistics_counts() {
+        let stats = test_file().statistics();
+        assert_eq!(stats.tracks.len(), 3);
+        assert_eq!(stats.tracks[1].name.as_deref(), Some("Piano"));
+        assert_eq!(stats.tracks[1].notes.count, 3);
+        assert_eq!(stats.tracks[1].notes.pitch_range, Some((48, 72)));
+        assert_eq!(stats.tracks[1].channels, vec![0]);
+        assert_eq!(stats.tracks[0].notes.count, 0);
+
+        assert_eq!(stats.notes.count, 4);
+        assert_eq!(stats.notes.velocities[80], 2);
+        assert_eq!(stats.channels[0].notes.average_velocity(), Some(80.0));
+        assert_eq!(stats.channels[0].controllers.get(&7), Some(&2));
+        assert_eq!(stats.used_channels(), vec![0, 1]);
+
+        assert_eq!(stats.programs.len(), 3);
+        assert_eq!(stats.programs[0].name, "Acoustic Grand Piano");
+        assert_eq!(stats.programs[2].program, 41);
+        assert_eq!(stats.conflicting_programs, vec![1]);
+    }
+
+    #[test]
+    fn test_statistics_time() {
+        let stats = test_file().statistics();
+        // Two quarters at 120 BPM, then two at 60 BPM.
+        assert_eq!(stats.duration_ticks, 1920);
+        assert!((stats.duration_seconds - 3.0).abs() < 1e-9);
+        assert_eq!(stats.tempo_range, (60.0, 120.0));
+        assert_eq!(stats.meter_changes.len(), 2);
+        assert_eq!(stats.meter_changes[1].tick, 960);
+
+        // 0-0.5 s: 2 notes, 0.5-1 s: 3, 1-3 s: 2 (48 ends as 60 starts).
+        assert_eq!(stats.max_polyphony, 3);
+        assert!((stats.average_polyphony - 6.5 / 3.0).abs() < 1e-9);
+
+        let text = stats.to_string();
+        assert!(text.contains("tempo: 60.00-120.00 BPM"));
+        assert!(text.contains("channel 2: 1 notes, keys 67-67, programs: Violin, Viola"));
+
+        let empty = MidiFile::new().statistics();
+        assert_eq!(empty.notes.count, 0);
+        assert_eq!(empty.max_polyphony, 0);
+        assert_eq!(empty.average_polyphony, 0.0);
+    }
+}
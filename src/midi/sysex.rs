@@ -0,0 +1,601 @@
+//! Typed Universal System Exclusive messages
+//!
+//! Universal SysEx payloads start with `0x7E` (non-real-time) or `0x7F`
+//! (real-time), a device ID and two sub-IDs. `UniversalSysEx` covers the
+//! messages most files and devices use and converts to and from the
+//! payload stored in `MidiMessage::SysEx` (without the F0/F7 framing).
+
+use super::message::MidiMessage;
+
+/// Device ID addressing every device ("all call")
+pub const ALL_CALL: u8 = 0x7F;
+
+const NON_REAL_TIME: u8 = 0x7E;
+const REAL_TIME: u8 = 0x7F;
+
+/// A manufacturer's SysEx ID, either one byte or three bytes starting with
+/// `0x00`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ManufacturerId {
+    /// One-byte ID (e.g. `0x41` Roland, `0x43` Yamaha)
+    Short(u8),
+    /// Extended ID, encoded as `00 hi lo`
+    Extended(u8, u8),
+}
+
+impl ManufacturerId {
+    /// Encode as SysEx bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            ManufacturerId::Short(id) => vec![id & 0x7F],
+            ManufacturerId::Extended(hi, lo) => vec![0x00, hi & 0x7F, lo & 0x7F],
+        }
+    }
+
+    /// Decode from the start of `data`, returning the ID and bytes consumed
+    pub fn from_bytes(data: &[u8]) -> Option<(Self, usize)> {
+        match data {
+            [0x00, hi, lo, ..] => Some((ManufacturerId::Extended(*hi, *lo), 3)),
+            [0x00, ..] | [] => None,
+            [id, ..] => Some((ManufacturerId::Short(*id), 1)),
+        }
+    }
+}
+
+/// MIDI Tuning Standard frequency for one note: a base semitone plus a
+/// 14-bit fraction of a semitone (units of 100/16384 cents)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NoteTuning {
+    /// Base MIDI note number
+    pub semitone: u8,
+    /// Fraction of a semitone above `semitone`, 0-16383
+    pub fraction: u16,
+}
+
+impl NoteTuning {
+    /// The reserved `7F 7F 7F` value meaning "leave this note unchanged"
+    pub const NO_CHANGE: NoteTuning = NoteTuning {
+        semitone: 0x7F,
+        fraction: 0x3FFF,
+    };
+
+    /// Build from a fractional MIDI note number (69.0 = A440)
+    pub fn from_semitones(semitones: f64) -> Self {
+        let clamped = semitones.clamp(0.0, 127.0 + 16383.0 / 16384.0);
+        let semitone = clamped.floor();
+        let fraction = ((clamped - semitone) * 16384.0).round().min(16383.0);
+        Self {
+            semitone: semitone as u8,
+            fraction: fraction as u16,
+        }
+    }
+
+    /// Fractional MIDI note number, or `None` for `NO_CHANGE`
+    pub fn to_semitones(&self) -> Option<f64> {
+        if *self == Self::NO_CHANGE {
+            return None;
+        }
+        Some(self.semitone as f64 + self.fraction as f64 / 16384.0)
+    }
+
+    fn to_bytes(self) -> [u8; 3] {
+        [
+            self.semitone & 0x7F,
+            ((self.fraction >> 7) & 0x7F) as u8,
+            (self.fraction & 0x7F) as u8,
+        ]
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            semitone: bytes[0],
+            fraction: ((bytes[1] as u16) << 7) | bytes[2] as u16,
+        }
+    }
+}
+
+/// A Universal Real-Time or Non-Real-Time System Exclusive message
+#[derive(Debug, Clone, PartialEq)]
+pub enum UniversalSysEx {
+    /// General MIDI System On (`7E id 09 01`)
+    GmSystemOn { device_id: u8 },
+    /// General MIDI System Off (`7E id 09 02`)
+    GmSystemOff { device_id: u8 },
+    /// General MIDI 2 System On (`7E id 09 03`)
+    Gm2SystemOn { device_id: u8 },
+    /// Device Control: Master Volume, 14-bit (`7F id 04 01`)
+    MasterVolume { device_id: u8, volume: u16 },
+    /// Device Control: Master Balance, 14-bit, 0x2000 = center (`7F id 04 02`)
+    MasterBalance { device_id: u8, balance: u16 },
+    /// Device Control: Master Fine Tuning, 14-bit, 0x2000 = A440, range
+    /// -100 to +100 cents (`7F id 04 03`)
+    MasterFineTuning { device_id: u8, value: u16 },
+    /// Device Control: Master Coarse Tuning in semitones, -64 to +63
+    /// (`7F id 04 04`)
+    MasterCoarseTuning { device_id: u8, semitones: i8 },
+    /// Identity Request (`7E id 06 01`)
+    IdentityRequest { device_id: u8 },
+    /// Identity Reply (`7E id 06 02`)
+    IdentityReply {
+        device_id: u8,
+        manufacturer: ManufacturerId,
+        /// Device family code (14-bit, LSB first on the wire)
+        family: u16,
+        /// Device family member code (14-bit, LSB first on the wire)
+        model: u16,
+        /// Software revision level
+        version: [u8; 4],
+    },
+    /// MTS Bulk Tuning Dump Request (`7E id 08 00`)
+    TuningDumpRequest { device_id: u8, program: u8 },
+    /// MTS Bulk Tuning Dump (`7E id 08 01`), with a checksum over the
+    /// whole message
+    TuningDump {
+        device_id: u8,
+        program: u8,
+        /// Tuning name, 16 ASCII characters on the wire
+        name: String,
+        /// Frequencies for all 128 notes
+        tunings: Box<[NoteTuning; 128]>,
+    },
+    /// MTS Single Note Tuning Change (`7F id 08 02`, or `08 07` for the
+    /// bank-select form, which also exists as a non-real-time message)
+    SingleNoteTuning {
+        device_id: u8,
+        /// Whether this is the real-time (`7F`) form
+        real_time: bool,
+        /// Tuning bank, for the bank-select (`08 07`) form
+        bank: Option<u8>,
+        program: u8,
+        /// (key, tuning) pairs
+        changes: Vec<(u8, NoteTuning)>,
+    },
+    /// Any other Universal SysEx message
+    Other {
+        real_time: bool,
+        device_id: u8,
+        sub_id1: u8,
+        sub_id2: u8,
+        data: Vec<u8>,
+    },
+}
+
+impl UniversalSysEx {
+    /// Master volume as a 0.0-1.0 gain, addressed to all devices
+    pub fn master_volume(gain: f64) -> Self {
+        UniversalSysEx::MasterVolume {
+            device_id: ALL_CALL,
+            volume: (gain.clamp(0.0, 1.0) * 16383.0).round() as u16,
+        }
+    }
+
+    /// Master fine tuning in cents (-100.0 to +100.0), addressed to all
+    /// devices
+    pub fn master_fine_tuning_cents(cents: f64) -> Self {
+        let value = 8192.0 + cents.clamp(-100.0, 100.0) / 100.0 * 8192.0;
+        UniversalSysEx::MasterFineTuning {
+            device_id: ALL_CALL,
+            value: value.round().clamp(0.0, 16383.0) as u16,
+        }
+    }
+
+    /// Device ID this message is addressed to (or sent from)
+    pub fn device_id(&self) -> u8 {
+        match self {
+            UniversalSysEx::GmSystemOn { device_id }
+            | UniversalSysEx::GmSystemOff { device_id }
+            | UniversalSysEx::Gm2SystemOn { device_id }
+            | UniversalSysEx::MasterVolume { device_id, .. }
+            | UniversalSysEx::MasterBalance { device_id, .. }
+            | UniversalSysEx::MasterFineTuning { device_id, .. }
+            | UniversalSysEx::MasterCoarseTuning { device_id, .. }
+            | UniversalSysEx::IdentityRequest { device_id }
+            | UniversalSysEx::IdentityReply { device_id, .. }
+            | UniversalSysEx::TuningDumpRequest { device_id, .. }
+            | UniversalSysEx::TuningDump { device_id, .. }
+            | UniversalSysEx::SingleNoteTuning { device_id, .. }
+            | UniversalSysEx::Other { device_id, .. } => *device_id,
+        }
+    }
+
+    /// Whether this is a real-time (`7F`) message
+    pub fn is_real_time(&self) -> bool {
+        match self {
+            UniversalSysEx::MasterVolume { .. }
+            | UniversalSysEx::MasterBalance { .. }
+            | UniversalSysEx::MasterFineTuning { .. }
+            | UniversalSysEx::MasterCoarseTuning { .. } => true,
+            UniversalSysEx::SingleNoteTuning { real_time, .. }
+            | UniversalSysEx::Other { real_time, .. } => *real_time,
+            _ => false,
+        }
+    }
+
+    /// Encode as a SysEx payload (without F0/F7)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = |sub_id1: u8, sub_id2: u8| {
+            let universal = if self.is_real_time() {
+                REAL_TIME
+            } else {
+                NON_REAL_TIME
+            };
+            vec![universal, self.device_id() & 0x7F, sub_id1, sub_id2]
+        };
+        let lsb_msb = |value: u16| [(value & 0x7F) as u8, ((value >> 7) & 0x7F) as u8];
+
+        match self {
+            UniversalSysEx::GmSystemOn { .. } => header(0x09, 0x01),
+            UniversalSysEx::GmSystemOff { .. } => header(0x09, 0x02),
+            UniversalSysEx::Gm2SystemOn { .. } => header(0x09, 0x03),
+            UniversalSysEx::MasterVolume { volume, .. } => {
+                let mut bytes = header(0x04, 0x01);
+                bytes.extend(lsb_msb(*volume));
+                bytes
+            }
+            UniversalSysEx::MasterBalance { balance, .. } => {
+                let mut bytes = header(0x04, 0x02);
+                bytes.extend(lsb_msb(*balance));
+                bytes
+            }
+            UniversalSysEx::MasterFineTuning { value, .. } => {
+                let mut bytes = header(0x04, 0x03);
+                bytes.extend(lsb_msb(*value));
+                bytes
+            }
+            UniversalSysEx::MasterCoarseTuning { semitones, .. } => {
+                let mut bytes = header(0x04, 0x04);
+                bytes.push(0x00);
+                bytes.push((*semitones as i16 + 0x40).clamp(0, 0x7F) as u8);
+                bytes
+            }
+            UniversalSysEx::IdentityRequest { .. } => header(0x06, 0x01),
+            UniversalSysEx::IdentityReply {
+                manufacturer,
+                family,
+                model,
+                version,
+                ..
+            } => {
+                let mut bytes = header(0x06, 0x02);
+                bytes.extend(manufacturer.to_bytes());
+                bytes.extend(lsb_msb(*family));
+                bytes.extend(lsb_msb(*model));
+                bytes.extend(version.iter().map(|b| b & 0x7F));
+                bytes
+            }
+            UniversalSysEx::TuningDumpRequest { program, .. } => {
+                let mut bytes = header(0x08, 0x00);
+                bytes.push(program & 0x7F);
+                bytes
+            }
+            UniversalSysEx::TuningDump {
+                program,
+                name,
+                tunings,
+                ..
+            } => {
+                let mut bytes = header(0x08, 0x01);
+                bytes.push(program & 0x7F);
+                let mut name_bytes = [b' '; 16];
+                for (slot, c) in name_bytes.iter_mut().zip(name.chars()) {
+                    *slot = if c.is_ascii() && !c.is_ascii_control() {
+                        c as u8
+                    } else {
+                        b'?'
+                    };
+                }
+                bytes.extend(name_bytes);
+                for tuning in tunings.iter() {
+                    bytes.extend(tuning.to_bytes());
+                }
+                bytes.push(checksum(&bytes));
+                bytes
+            }
+            UniversalSysEx::SingleNoteTuning {
+                bank,
+                program,
+                changes,
+                ..
+            } => {
+                let mut bytes = match bank {
+                    Some(bank) => {
+                        let mut bytes = header(0x08, 0x07);
+                        bytes.push(bank & 0x7F);
+                        bytes
+                    }
+                    None => header(0x08, 0x02),
+                };
+                bytes.push(program & 0x7F);
+                bytes.push(changes.len().min(0x7F) as u8);
+                for (key, tuning) in changes.iter().take(0x7F) {
+                    bytes.push(key & 0x7F);
+                    bytes.extend(tuning.to_bytes());
+                }
+                bytes
+            }
+            UniversalSysEx::Other {
+                sub_id1,
+                sub_id2,
+                data,
+                ..
+            } => {
+                let mut bytes = header(*sub_id1, *sub_id2);
+                bytes.extend(data);
+                bytes
+            }
+        }
+    }
+
+    /// Decode a SysEx payload (without F0/F7). Returns `None` if it is not
+    /// a Universal SysEx message, is too short for its type, or (for bulk
+    /// tuning dumps) fails its checksum.
+    pub fn from_bytes(data: &[u8]) -> Option<Self> {
+        let data = data.strip_prefix(&[0xF0]).unwrap_or(data);
+        let data = data.strip_suffix(&[0xF7]).unwrap_or(data);
+        if data.len() < 4 {
+            return None;
+        }
+        let real_time = match data[0] {
+            REAL_TIME => true,
+            NON_REAL_TIME => false,
+            _ => return None,
+        };
+        let device_id = data[1];
+        let (sub_id1, sub_id2) = (data[2], data[3]);
+        let body = &data[4..];
+        let u14 = |bytes: &[u8]| (bytes[0] as u16 & 0x7F) | ((bytes[1] as u16 & 0x7F) << 7);
+
+        let message = match (real_time, sub_id1, sub_id2) {
+            (false, 0x09, 0x01) => UniversalSysEx::GmSystemOn { device_id },
+            (false, 0x09, 0x02) => UniversalSysEx::GmSystemOff { device_id },
+            (false, 0x09, 0x03) => UniversalSysEx::Gm2SystemOn { device_id },
+            (true, 0x04, 0x01) if body.len() >= 2 => UniversalSysEx::MasterVolume {
+                device_id,
+                volume: u14(body),
+            },
+            (true, 0x04, 0x02) if body.len() >= 2 => UniversalSysEx::MasterBalance {
+                device_id,
+                balance: u14(body),
+            },
+            (true, 0x04, 0x03) if body.len() >= 2 => UniversalSysEx::MasterFineTuning {
+                device_id,
+                value: u14(body),
+            },
+            (true, 0x04, 0x04) if body.len() >= 2 => UniversalSysEx::MasterCoarseTuning {
+                device_id,
+                semitones: (body[1] & 0x7F) as i8 - 0x40,
+            },
+            (false, 0x06, 0x01) => UniversalSysEx::IdentityRequest { device_id },
+            (false, 0x06, 0x02) => {
+                let (manufacturer, len) = ManufacturerId::from_bytes(body)?;
+                let rest = body.get(len..len + 8)?;
+                UniversalSysEx::IdentityReply {
+                    device_id,
+                    manufacturer,
+                    family: u14(&rest[0..2]),
+                    model: u14(&rest[2..4]),
+                    version: [rest[4], rest[5], rest[6], rest[7]],
+                }
+            }
+            (false, 0x08, 0x00) if !body.is_empty() => UniversalSysEx::TuningDumpRequest {
+                device_id,
+                program: body[0],
+            },
+            (false, 0x08, 0x01) => {
+                // program, 16-byte name, 128 x 3 tuning bytes, checksum
+                if body.len() < 1 + 16 + 128 * 3 + 1 {
+                    return None;
+                }
+                let checksum_index = 4 + 1 + 16 + 128 * 3;
+                if checksum(&data[..checksum_index]) != data[checksum_index] & 0x7F {
+                    return None;
+                }
+                let name = String::from_utf8_lossy(&body[1..17]).trim_end().to_string();
+                let mut tunings = Box::new([NoteTuning::NO_CHANGE; 128]);
+                for (tuning, bytes) in tunings.iter_mut().zip(body[17..].chunks_exact(3)) {
+                    *tuning = NoteTuning::from_bytes(bytes);
+                }
+                UniversalSysEx::TuningDump {
+                    device_id,
+                    program: body[0],
+                    name,
+                    tunings,
+                }
+            }
+            (true, 0x08, 0x02) | (_, 0x08, 0x07) => {
+                let (bank, rest) = if sub_id2 == 0x07 {
+                    (Some(*body.first()?), &body[1..])
+                } else {
+                    (None, body)
+                };
+                let [program, count, rest @ ..] = rest else {
+                    return None;
+                };
+                let changes: Vec<(u8, NoteTuning)> = rest
+                    .chunks_exact(4)
+                    .take(*count as usize)
+                    .map(|c| (c[0], NoteTuning::from_bytes(&c[1..])))
+                    .collect();
+                if changes.len() != *count as usize {
+                    return None;
+                }
+                UniversalSysEx::SingleNoteTuning {
+                    device_id,
+                    real_time,
+                    bank,
+                    program: *program,
+                    changes,
+                }
+            }
+            _ => UniversalSysEx::Other {
+                real_time,
+                device_id,
+                sub_id1,
+                sub_id2,
+                data: body.to_vec(),
+            },
+        };
+        Some(message)
+    }
+
+    /// Decode from a `MidiMessage::SysEx`
+    pub fn from_message(message: &MidiMessage) -> Option<Self> {
+        match message {
+            MidiMessage::SysEx(data) => Self::from_bytes(data),
+            _ => None,
+        }
+    }
+
+    /// Encode as a `MidiMessage::SysEx`
+    pub fn to_message(&self) -> MidiMessage {
+        MidiMessage::SysEx(self.to_bytes())
+    }
+}
+
+impl From<UniversalSysEx> for MidiMessage {
+    fn from(sysex: UniversalSysEx) -> Self {
+        sysex.to_message()
+    }
+}
+
+/// Framing of a SysEx event in a MIDI file that is not a complete F0…F7
+/// message on its own: the first part of a split message (an F0 packet
+/// without a final F7), or an F7 packet carrying a continuation or an
+/// escaped sequence. The packet's bytes, without a final F7, are the
+/// event's `MidiMessage::SysEx` payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SysExFraming {
+    /// Whether the packet starts with F7 rather than F0
+    pub escape: bool,
+    /// Whether the packet ends with F7
+    pub terminated: bool,
+}
+
+impl SysExFraming {
+    /// Framing of a complete F0…F7 message, which events leave unset
+    pub const COMPLETE: Self = Self {
+        escape: false,
+        terminated: true,
+    };
+
+    /// Status byte the packet starts with in a file
+    pub fn status(&self) -> u8 {
+        if self.escape { 0xF7 } else { 0xF0 }
+    }
+}
+
+/// MTS checksum: XOR of every byte from the universal ID onward, masked to
+/// seven bits.
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, b| acc ^ b) & 0x7F
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gm_and_device_control_bytes() {
+        let gm_on = UniversalSysEx::GmSystemOn {
+            device_id: ALL_CALL,
+        };
+        assert_eq!(gm_on.to_bytes(), vec![0x7E, 0x7F, 0x09, 0x01]);
+        assert_eq!(
+            MidiMessage::from(gm_on.clone()).to_bytes(),
+            vec![0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7]
+        );
+        assert_eq!(UniversalSysEx::from_bytes(&gm_on.to_bytes()), Some(gm_on));
+
+        let volume = UniversalSysEx::master_volume(1.0);
+        assert_eq!(volume.to_bytes(), vec![0x7F, 0x7F, 0x04, 0x01, 0x7F, 0x7F]);
+
+        let coarse = UniversalSysEx::MasterCoarseTuning {
+            device_id: 0x10,
+            semitones: -12,
+        };
+        assert_eq!(coarse.to_bytes(), vec![0x7F, 0x10, 0x04, 0x04, 0x00, 0x34]);
+        assert_eq!(UniversalSysEx::from_bytes(&coarse.to_bytes()), Some(coarse));
+
+        assert_eq!(
+            UniversalSysEx::master_fine_tuning_cents(-100.0),
+            UniversalSysEx::MasterFineTuning {
+                device_id: ALL_CALL,
+                value: 0
+            }
+        );
+        // Framed bytes are accepted too.
+        assert_eq!(
+            UniversalSysEx::from_bytes(&[0xF0, 0x7E, 0x00, 0x09, 0x03, 0xF7]),
+            Some(UniversalSysEx::Gm2SystemOn { device_id: 0 })
+        );
+        // Manufacturer-specific SysEx is not universal.
+        assert_eq!(UniversalSysEx::from_bytes(&[0x41, 0x10, 0x42, 0x12]), None);
+    }
+
+    #[test]
+    fn test_identity_reply_manufacturer_ids() {
+        let reply = UniversalSysEx::IdentityReply {
+            device_id: 0x01,
+            manufacturer: ManufacturerId::Extended(0x20, 0x29),
+            family: 0x0201,
+            model: 0x0003,
+            version: [1, 2, 3, 4],
+        };
+        let bytes = reply.to_bytes();
+        assert_eq!(
+            bytes,
+            vec![
+                0x7E, 0x01, 0x06, 0x02, 0x00, 0x20, 0x29, 0x01, 0x04, 0x03, 0x00, 1, 2, 3, 4
+            ]
+        );
+        assert_eq!(UniversalSysEx::from_bytes(&bytes), Some(reply));
+
+        let short = [0x7E, 0x7F, 0x06, 0x02, 0x43, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert!(matches!(
+            UniversalSysEx::from_bytes(&short),
+            Some(UniversalSysEx::IdentityReply {
+                manufacturer: ManufacturerId::Short(0x43),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_tuning_dump_checksum_and_single_note() {
+        let mut tunings = Box::new([NoteTuning::NO_CHANGE; 128]);
+        for (key, tuning) in tunings.iter_mut().enumerate() {
+            *tuning = NoteTuning::from_semitones(key as f64 + 0.5);
+        }
+        let dump = UniversalSysEx::TuningDump {
+            device_id: ALL_CALL,
+            program: 3,
+            name: "Quarter tones".to_string(),
+            tunings,
+        };
+        let mut bytes = dump.to_bytes();
+        assert_eq!(bytes.len(), 4 + 1 + 16 + 384 + 1);
+        assert_eq!(UniversalSysEx::from_bytes(&bytes), Some(dump));
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0x01;
+        assert_eq!(UniversalSysEx::from_bytes(&bytes), None);
+
+        let change = UniversalSysEx::SingleNoteTuning {
+            device_id: 0,
+            real_time: true,
+            bank: None,
+            program: 0,
+            changes: vec![(69, NoteTuning::from_semitones(69.25))],
+        };
+        let bytes = change.to_bytes();
+        assert_eq!(
+            bytes,
+            vec![0x7F, 0x00, 0x08, 0x02, 0, 1, 69, 69, 0x20, 0x00]
+        );
+        let parsed = UniversalSysEx::from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, change);
+        if let UniversalSysEx::SingleNoteTuning { changes, .. } = parsed {
+            assert_eq!(changes[0].1.to_semitones(), Some(69.25));
+        }
+    }
+}
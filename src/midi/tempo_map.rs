@@ -0,0 +1,550 @@
+//! Tempo and meter maps
+//!
+//! `TempoMap` converts between ticks and seconds over a sequence of tempo
+//! points, each of which either holds its tempo (a plain SMF tempo event) or
+//! ramps linearly or exponentially towards the next point. `MeterMap`
+//! converts between ticks and `bar:beat:tick` positions over a sequence of
+//! time signatures.
+
+use std::fmt;
+
+use super::TimeDivision;
+use crate::core::Fraction;
+use crate::notation::TimeSignature;
+
+/// Default tempo when none is given: 120 BPM
+const DEFAULT_SECONDS_PER_QUARTER: f64 = 0.5;
+
+/// How the tempo moves from one tempo point to the next
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TempoRamp {
+    /// Hold the tempo until the next point
+    #[default]
+    Step,
+    /// Change BPM linearly with ticks until the next point
+    Linear,
+    /// Change BPM by a constant ratio per tick until the next point
+    Exponential,
+}
+
+/// A tempo change in a `TempoMap`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TempoPoint {
+    /// Tick at which this tempo starts
+    pub tick: u64,
+    /// Tempo at `tick`, in seconds per quarter note
+    pub seconds_per_quarter: f64,
+    /// How the tempo moves towards the next point
+    pub ramp: TempoRamp,
+}
+
+impl TempoPoint {
+    /// Tempo at `tick` in beats (quarter notes) per minute
+    pub fn bpm(&self) -> f64 {
+        60.0 / self.seconds_per_quarter
+    }
+
+    /// Tempo at `tick` in microseconds per quarter note, as stored in an
+    /// SMF tempo event
+    pub fn microseconds_per_quarter(&self) -> u32 {
+        (self.seconds_per_quarter * 1_000_000.0).round() as u32
+    }
+}
+
+/// Tick/seconds conversion over tempo changes and ramps
+#[derive(Debug, Clone, PartialEq)]
+pub struct TempoMap {
+    division: TimeDivision,
+    /// Tempo points in tick order; the first is always at tick 0.
+    points: Vec<TempoPoint>,
+    /// Seconds elapsed at each point.
+    seconds: Vec<f64>,
+}
+
+impl TempoMap {
+    /// Create a map holding 120 BPM from tick 0
+    pub fn new(division: TimeDivision) -> Self {
+        let mut map = Self {
+            division,
+            points: vec![TempoPoint {
+                tick: 0,
+                seconds_per_quarter: DEFAULT_SECONDS_PER_QUARTER,
+                ramp: TempoRamp::Step,
+            }],
+            seconds: Vec::new(),
+        };
+        map.update_seconds();
+        map
+    }
+
+    /// Build a step-only map from (tick, microseconds per quarter) tempo
+    /// events.
+    pub(super) fn from_tempo_events(division: TimeDivision, events: &[(u64, u32)]) -> Self {
+        let mut map = Self::new(division);
+        for &(tick, microseconds) in events {
+            map.insert(TempoPoint {
+                tick,
+                seconds_per_quarter: microseconds.max(1) as f64 / 1_000_000.0,
+                ramp: TempoRamp::Step,
+            });
+        }
+        map.update_seconds();
+        map
+    }
+
+    /// The time division ticks are measured in
+    pub fn time_division(&self) -> TimeDivision {
+        self.division
+    }
+
+    /// Tempo points in tick order
+    pub fn points(&self) -> &[TempoPoint] {
+        &self.points
+    }
+
+    /// Set a constant tempo from `tick` until the next point, replacing any
+    /// point already at `tick`
+    pub fn set_tempo(&mut self, tick: u64, bpm: f64) {
+        self.set_tempo_ramp(tick, bpm, TempoRamp::Step);
+    }
+
+    /// Set the tempo at `tick` and ramp from it towards the tempo of the
+    /// next point (e.g. an accelerando from 80 BPM at bar 5 to 120 BPM at
+    /// bar 9 is a `Linear` point at bar 5 followed by a point at bar 9). A
+    /// ramp on the last point behaves like `Step`.
+    pub fn set_tempo_ramp(&mut self, tick: u64, bpm: f64, ramp: TempoRamp) {
+        self.insert(TempoPoint {
+            tick,
+            seconds_per_quarter: 60.0 / bpm.max(f64::MIN_POSITIVE),
+            ramp,
+        });
+        self.update_seconds();
+    }
+
+    /// Remove the tempo point at `tick`, if any (the point at tick 0 is
+    /// reset to 120 BPM instead)
+    pub fn remove_tempo(&mut self, tick: u64) {
+        if tick == 0 {
+            self.points[0].seconds_per_quarter = DEFAULT_SECONDS_PER_QUARTER;
+            self.points[0].ramp = TempoRamp::Step;
+        } else {
+            self.points.retain(|p| p.tick != tick);
+        }
+        self.update_seconds();
+    }
+
+    fn insert(&mut self, point: TempoPoint) {
+        match self.points.binary_search_by_key(&point.tick, |p| p.tick) {
+            Ok(index) => self.points[index] = point,
+            Err(index) => self.points.insert(index, point),
+        }
+    }
+
+    fn ticks_per_quarter(&self) -> f64 {
+        self.division.ticks_per_quarter().max(1) as f64
+    }
+
+    /// Fixed tick rate for SMPTE divisions, where tempo doesn't apply.
+    fn smpte_ticks_per_second(&self) -> Option<f64> {
+        self.division.ticks_per_second().filter(|&tps| tps > 0.0)
+    }
+
+    fn update_seconds(&mut self) {
+        self.seconds.clear();
+        let mut elapsed = 0.0;
+        for i in 0..self.points.len() {
+            if i > 0 {
+                let previous = &self.points[i - 1];
+                elapsed +=
+                    self.segment_seconds(i - 1, (self.points[i].tick - previous.tick) as f64);
+            }
+            self.seconds.push(elapsed);
+        }
+    }
+
+    /// Length and end tempo (BPM) of the ramp starting at point `index`, or
+    /// `None` if the tempo holds.
+    fn ramp(&self, index: usize) -> Option<(f64, f64, TempoRamp)> {
+        let point = &self.points[index];
+        let next = self.points.get(index + 1)?;
+        match point.ramp {
+            TempoRamp::Step => None,
+            _ if next.seconds_per_quarter == point.seconds_per_quarter => None,
+            ramp => Some(((next.tick - point.tick) as f64, next.bpm(), ramp)),
+        }
+    }
+
+    /// Seconds from point `index` to `ticks` ticks after it.
+    fn segment_seconds(&self, index: usize, ticks: f64) -> f64 {
+        let point = &self.points[index];
+        let tpq = self.ticks_per_quarter();
+        let b0 = point.bpm();
+        match self.ramp(index) {
+            None => ticks * point.seconds_per_quarter / tpq,
+            Some((length, b1, TempoRamp::Linear)) => {
+                let bpm = b0 + (b1 - b0) * ticks / length;
+                60.0 / tpq * length / (b1 - b0) * (bpm / b0).ln()
+            }
+            Some((length, b1, _)) => {
+                let log_ratio = (b1 / b0).ln();
+                60.0 / tpq * length / (b0 * log_ratio) * (1.0 - (-log_ratio * ticks / length).exp())
+            }
+        }
+    }
+
+    /// Ticks from point `index` until `seconds` seconds after it.
+    fn segment_ticks(&self, index: usize, seconds: f64) -> f64 {
+        let point = &self.points[index];
+        let tpq = self.ticks_per_quarter();
+        let b0 = point.bpm();
+        match self.ramp(index) {
+            None => seconds * tpq / point.seconds_per_quarter,
+            Some((length, b1, TempoRamp::Linear)) => {
+                let bpm = b0 * (seconds * tpq * (b1 - b0) / (60.0 * length)).exp();
+                (bpm - b0) * length / (b1 - b0)
+            }
+            Some((length, b1, _)) => {
+                let log_ratio = (b1 / b0).ln();
+                let remaining = 1.0 - seconds * tpq * b0 * log_ratio / (60.0 * length);
+                -length * remaining.max(f64::MIN_POSITIVE).ln() / log_ratio
+            }
+        }
+    }
+
+    /// Index of the point in effect at `tick`.
+    fn point_index(&self, tick: u64) -> usize {
+        self.points
+            .partition_point(|p| p.tick <= tick)
+            .saturating_sub(1)
+    }
+
+    /// Tempo in BPM at `tick`, following any ramp
+    pub fn bpm_at(&self, tick: u64) -> f64 {
+        let index = self.point_index(tick);
+        let point = &self.points[index];
+        let ticks = (tick - point.tick) as f64;
+        match self.ramp(index) {
+            None => point.bpm(),
+            Some((length, b1, TempoRamp::Linear)) => {
+                point.bpm() + (b1 - point.bpm()) * ticks.min(length) / length
+            }
+            Some((length, b1, _)) => {
+                point.bpm() * (b1 / point.bpm()).powf(ticks.min(length) / length)
+            }
+        }
+    }
+
+    /// Convert a tick position to seconds
+    pub fn ticks_to_seconds(&self, tick: u64) -> f64 {
+        if let Some(ticks_per_second) = self.smpte_ticks_per_second() {
+            return tick as f64 / ticks_per_second;
+        }
+        let index = self.point_index(tick);
+        self.seconds[index] + self.segment_seconds(index, (tick - self.points[index].tick) as f64)
+    }
+
+    /// Convert seconds to a tick position. For PPQN divisions this is the
+    /// number of whole ticks elapsed; for SMPTE divisions the nearest tick.
+    pub fn seconds_to_ticks(&self, seconds: f64) -> u64 {
+        if let Some(ticks_per_second) = self.smpte_ticks_per_second() {
+            return (seconds * ticks_per_second).round() as u64;
+        }
+        let seconds = seconds.max(0.0);
+        let index = self
+            .seconds
+            .partition_point(|&s| s <= seconds)
+            .saturating_sub(1);
+        let ticks = self.segment_ticks(index, seconds - self.seconds[index]);
+        let tick = self.points[index].tick + ticks.max(0.0) as u64;
+        match self.points.get(index + 1) {
+            Some(next) => tick.min(next.tick),
+            None => tick,
+        }
+    }
+
+    /// Render the map as SMF tempo events (tick, microseconds per quarter).
+    /// Steps become one event each; ramps become an event every `step`
+    /// ticks, each holding the average tempo of its slice so that seconds
+    /// at every slice boundary match the ramp exactly.
+    pub fn to_tempo_events(&self, step: u64) -> Vec<(u64, u32)> {
+        let step = step.max(1);
+        let tpq = self.ticks_per_quarter();
+        let mut events = Vec::new();
+        for (index, point) in self.points.iter().enumerate() {
+            let Some((length, _, _)) = self.ramp(index) else {
+                events.push((point.tick, point.microseconds_per_quarter()));
+                continue;
+            };
+            let length = length as u64;
+            let mut offset = 0;
+            while offset < length {
+                let end = (offset + step).min(length);
+                let seconds = self.segment_seconds(index, end as f64)
+                    - self.segment_seconds(index, offset as f64);
+                let microseconds = seconds / (end - offset) as f64 * tpq * 1_000_000.0;
+                events.push((point.tick + offset, microseconds.round() as u32));
+                offset = end;
+            }
+        }
+        events
+    }
+}
+
+/// A musical position: 1-based bar and beat, plus ticks into the beat.
+/// Beats are denominator units (eighths in 6/8).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BarBeatTick {
+    /// Bar number (the first bar is 1 unless the map says otherwise)
+    pub bar: i32,
+    /// Beat within the bar, starting at 1
+    pub beat: u32,
+    /// Ticks into the beat
+    pub tick: u64,
+}
+
+impl BarBeatTick {
+    /// Create a position
+    pub fn new(bar: i32, beat: u32, tick: u64) -> Self {
+        Self { bar, beat, tick }
+    }
+}
+
+impl fmt::Display for BarBeatTick {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.bar, self.beat, self.tick)
+    }
+}
+
+/// A time signature change in a `MeterMap`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeterPoint {
+    /// Tick at which the time signature takes effect
+    pub tick: u64,
+    /// Bar number starting at `tick`
+    pub bar: i32,
+    /// The time signature
+    pub time_signature: TimeSignature,
+}
+
+/// Tick/`bar:beat:tick` conversion over time signature changes
+///
+/// A time signature always starts a new bar. If one falls in the middle of
+/// a bar, the partial bar before it still counts as a bar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeterMap {
+    ticks_per_quarter: u16,
+    first_bar: i32,
+    points: Vec<MeterPoint>,
+}
+
+impl MeterMap {
+    /// Create a map in 4/4 from tick 0, starting at bar 1
+    pub fn new(ticks_per_quarter: u16) -> Self {
+        Self {
+            ticks_per_quarter: ticks_per_quarter.max(1),
+            first_bar: 1,
+            points: vec![MeterPoint {
+                tick: 0,
+                bar: 1,
+                time_signature: TimeSignature::common_time(),
+            }],
+        }
+    }
+
+    /// Number the first bar `bar` (e.g. 0 for a pickup)
+    pub fn with_first_bar(mut self, bar: i32) -> Self {
+        self.first_bar = bar;
+        self.update_bars();
+        self
+    }
+
+    /// Time signature changes in tick order
+    pub fn points(&self) -> &[MeterPoint] {
+        &self.points
+    }
+
+    /// Set the time signature from `tick` on, replacing any change already
+    /// at `tick`
+    pub fn set_time_signature(&mut self, tick: u64, time_signature: TimeSignature) {
+        let point = MeterPoint {
+            tick,
+            bar: 0,
+            time_signature,
+        };
+        match self.points.binary_search_by_key(&tick, |p| p.tick) {
+            Ok(index) => self.points[index] = point,
+            Err(index) => self.points.insert(index, point),
+        }
+        self.update_bars();
+    }
+
+    fn update_bars(&mut self) {
+        let mut bar = self.first_bar;
+        for i in 0..self.points.len() {
+            if i > 0 {
+                let previous = self.points[i - 1];
+                let elapsed = self.points[i].tick - previous.tick;
+                bar += elapsed.div_ceil(self.ticks_per_bar(&previous.time_signature)) as i32;
+            }
+            self.points[i].bar = bar;
+        }
+    }
+
+    /// Ticks in one bar of `time_signature`
+    pub fn ticks_per_bar(&self, time_signature: &TimeSignature) -> u64 {
+        (self.ticks_per_beat(time_signature) * time_signature.numerator().max(1) as u64).max(1)
+    }
+
+    /// Ticks in one beat (denominator unit) of `time_signature`
+    pub fn ticks_per_beat(&self, time_signature: &TimeSignature) -> u64 {
+        (self.ticks_per_quarter as u64 * 4 / time_signature.denominator().max(1) as u64).max(1)
+    }
+
+    fn point_at(&self, tick: u64) -> &MeterPoint {
+        let index = self.points.partition_point(|p| p.tick <= tick);
+        &self.points[index.saturating_sub(1)]
+    }
+
+    /// Time signature in effect at `tick`
+    pub fn time_signature_at(&self, tick: u64) -> TimeSignature {
+        self.point_at(tick).time_signature
+    }
+
+    /// Metrical strength of `tick` within its bar, from 1.0 on the
+    /// downbeat down to 0.125 off the beat (see
+    /// `TimeSignature::beat_strength`)
+    pub fn beat_strength(&self, tick: u64) -> f64 {
+        let point = self.point_at(tick);
+        let within = (tick - point.tick) % self.ticks_per_bar(&point.time_signature);
+        point
+            .time_signature
+            .beat_strength(Fraction::new(within as i64, self.ticks_per_quarter as i64))
+    }
+
+    /// Convert a tick position to `bar:beat:tick`
+    pub fn ticks_to_bbt(&self, tick: u64) -> BarBeatTick {
+        let point = self.point_at(tick);
+        let bar_ticks = self.ticks_per_bar(&point.time_signature);
+        let beat_ticks = self.ticks_per_beat(&point.time_signature);
+        let elapsed = tick - point.tick;
+        let within = elapsed % bar_ticks;
+        let beats = point.time_signature.numerator().max(1) as u64;
+        let beat = (within / beat_ticks).min(beats - 1);
+        BarBeatTick {
+            bar: point.bar + (elapsed / bar_ticks) as i32,
+            beat: beat as u32 + 1,
+            tick: within - beat * beat_ticks,
+        }
+    }
+
+    /// Tick at which `bar` starts
+    pub fn bar_to_ticks(&self, bar: i32) -> u64 {
+        let index = self
+            .points
+            .partition_point(|p| p.bar <= bar)
+            .saturating_sub(1);
+        let point = &self.points[index];
+        let bars = (bar - point.bar).max(0) as u64;
+        point.tick + bars * self.ticks_per_bar(&point.time_signature)
+    }
+
+    /// Convert a `bar:beat:tick` position to ticks. Beats and ticks past
+    /// the end of the bar simply count on from the bar start.
+    pub fn bbt_to_ticks(&self, position: &BarBeatTick) -> u64 {
+        let start = self.bar_to_ticks(position.bar);
+        let beat_ticks = self.ticks_per_beat(&self.time_signature_at(start));
+        start + position.beat.saturating_sub(1) as u64 * beat_ticks + position.tick
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tempo_ramps_convert_both_ways() {
+        let tpq = 480;
+        for ramp in [TempoRamp::Linear, TempoRamp::Exponential] {
+            let mut map = TempoMap::new(TimeDivision::Ppqn(tpq));
+            map.set_tempo_ramp(0, 60.0, ramp);
+            map.set_tempo(1920, 120.0);
+
+            let end = map.ticks_to_seconds(1920);
+            // Faster than 60 BPM throughout, slower than 120.
+            assert!(end < 4.0 && end > 2.0, "{ramp:?}: {end}");
+            assert!((map.bpm_at(1920) - 120.0).abs() < 1e-9);
+            assert!(map.bpm_at(960) > 60.0 && map.bpm_at(960) < 120.0);
+            for tick in [0, 1, 479, 960, 1919, 1920, 2400] {
+                let seconds = map.ticks_to_seconds(tick);
+                let back = map.seconds_to_ticks(seconds + 1e-9);
+                assert_eq!(back, tick, "{ramp:?} at {tick}");
+            }
+
+            // Rendered slices reproduce the ramp's timing at every slice.
+            let events = map.to_tempo_events(120);
+            assert_eq!(events.len(), 17);
+            let rendered = TempoMap::from_tempo_events(TimeDivision::Ppqn(tpq), &events);
+            for tick in (0..=1920).step_by(120) {
+                assert!(
+                    (rendered.ticks_to_seconds(tick) - map.ticks_to_seconds(tick)).abs() < 1e-5
+                );
+            }
+        }
+
+        // Linear BPM from 60 to 120 over 4 beats: 4 * ln 2 seconds.
+        let mut map = TempoMap::new(TimeDivision::Ppqn(tpq));
+        map.set_tempo_ramp(0, 60.0, TempoRamp::Linear);
+        map.set_tempo(1920, 120.0);
+        assert!((map.ticks_to_seconds(1920) - 4.0 * 2f64.ln()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_file_tempo_map_roundtrip() {
+        use crate::midi::MidiFile;
+
+        let mut file = MidiFile::new();
+        file.add_tracks(2);
+        file.add_tempo(1, 0, 100.0).unwrap();
+        file.add_time_signature(0, 1920, 3, 4).unwrap();
+        assert!((file.tempo_map().bpm_at(10) - 100.0).abs() < 1e-6);
+        assert_eq!(
+            file.meter_map().ticks_to_bbt(1920 + 1440),
+            BarBeatTick::new(3, 1, 0)
+        );
+
+        let mut map = file.tempo_map();
+        map.set_tempo_ramp(960, 100.0, TempoRamp::Exponential);
+        map.set_tempo(2880, 50.0);
+        file.set_tempo_map(0, &map, 240).unwrap();
+        assert!(file.tracks()[1].tempo_events().next().is_none());
+        assert_eq!(file.tracks()[0].tempo_events().count(), 1 + 8 + 1);
+        assert!((file.ticks_to_seconds(2880) - map.ticks_to_seconds(2880)).abs() < 1e-5);
+        assert!(file.set_tempo_map(5, &map, 240).is_err());
+    }
+
+    #[test]
+    fn test_meter_map_positions() {
+        let mut map = MeterMap::new(480);
+        map.set_time_signature(0, TimeSignature::new(4, 4));
+        // 6/8 from bar 3, then 3/4 in the middle of the second 6/8 bar.
+        map.set_time_signature(3840, TimeSignature::new(6, 8));
+        map.set_time_signature(3840 + 1440 + 720, TimeSignature::new(3, 4));
+
+        assert_eq!(map.ticks_to_bbt(0), BarBeatTick::new(1, 1, 0));
+        assert_eq!(map.ticks_to_bbt(1920 + 500), BarBeatTick::new(2, 2, 20));
+        assert_eq!(
+            map.ticks_to_bbt(3840 + 240 * 4 + 10),
+            BarBeatTick::new(3, 5, 10)
+        );
+        assert_eq!(map.points()[2].bar, 5);
+        assert_eq!(map.ticks_to_bbt(6000 + 1440), BarBeatTick::new(6, 1, 0));
+        assert_eq!(map.bar_to_ticks(4), 3840 + 1440);
+        for tick in [0, 17, 2420, 4810, 6000, 9999] {
+            assert_eq!(map.bbt_to_ticks(&map.ticks_to_bbt(tick)), tick);
+        }
+        assert_eq!(map.ticks_to_bbt(2420).to_string(), "2:2:20");
+
+        let pickup = MeterMap::new(480).with_first_bar(0);
+        assert_eq!(pickup.ticks_to_bbt(1920).bar, 1);
+    }
+}
@@ -4,8 +4,15 @@
 
 use std::fmt;
 
+use super::chase::{self, ChannelState};
+use super::cleanup::{self, OverlapReport};
+use super::controller::{self, ControllerEvent, ControllerEventKind, ParameterNumber};
 use super::event::{MidiEvent, NoteSortOrder, compare_events};
+use super::humanize::{self, HumanizeOptions};
 use super::message::{MetaEvent, MidiMessage};
+use super::mpe::{self, MpeConfiguration, MpeNoteCurve};
+use super::quantize::{self, GrooveTemplate, QuantizeOptions};
+use super::sysex::{ALL_CALL, NoteTuning, UniversalSysEx};
 
 /// A MIDI track containing events
 #[derive(Debug, Clone, Default)]
@@ -285,6 +292,81 @@ impl MidiTrack {
         }
     }
 
+    /// Decode RPN/NRPN data entry, data increment/decrement and 14-bit
+    /// controller pairs into logical events, in event order. Each result
+    /// records the indices of the control changes it was built from. The
+    /// track should be sorted first.
+    pub fn decode_controllers(&self) -> Vec<ControllerEvent> {
+        controller::decode(&self.events)
+    }
+
+    /// Effective state of each channel just before `tick`, i.e. after every
+    /// event earlier than `tick`. The track should be sorted first.
+    pub fn channel_states_at(&self, tick: u64) -> [ChannelState; 16] {
+        let before: Vec<MidiEvent> = self
+            .events
+            .iter()
+            .filter(|e| e.tick() < tick)
+            .cloned()
+            .collect();
+        chase::channel_states(&before)
+    }
+
+    /// Events at `tick` that recreate each channel's state at that point
+    /// (see `ChannelState::chase_messages`)
+    pub fn chase_events(&self, tick: u64) -> Vec<MidiEvent> {
+        chase::chase_events(&self.channel_states_at(tick), tick)
+    }
+
+    /// Move note starts (and optionally ends) towards a grid. Note-offs
+    /// move with their note-ons, and links are rebuilt if the track was
+    /// linked.
+    pub fn quantize(&mut self, options: &QuantizeOptions) {
+        quantize::quantize(self, options);
+    }
+
+    /// Measure the average timing and velocity deviation of the notes over
+    /// `slots` repeating grid slots of `grid` ticks
+    pub fn extract_groove(&self, grid: u64, slots: usize) -> GrooveTemplate {
+        quantize::extract_groove(&self.events, grid, slots)
+    }
+
+    /// Move notes `strength` percent of the way towards the timing and
+    /// velocity of a groove template. Notes keep their length.
+    pub fn apply_groove(&mut self, template: &GrooveTemplate, strength: f64) {
+        quantize::apply_groove(self, template, strength);
+    }
+
+    /// Add seeded random deviations to note timing, velocity and length
+    /// (see `HumanizeOptions`). Links are rebuilt if the track was linked.
+    pub fn humanize(&mut self, options: &HumanizeOptions) {
+        humanize::humanize(self, options);
+    }
+
+    /// Extend note-offs that fall while the sustain pedal (CC 64) is down
+    /// to the pedal release, or to the next strike of the same key if that
+    /// comes first. With `remove_pedal`, the sustain controller events are
+    /// removed afterwards. Returns the number of note-offs moved. Links are
+    /// rebuilt if the track was linked.
+    pub fn bake_sustain(&mut self, remove_pedal: bool) -> usize {
+        cleanup::bake_sustain(self, remove_pedal)
+    }
+
+    /// End a note when the same key is struck again on the same channel
+    /// before its note-off, and remove note-ons repeated at the same tick,
+    /// dropping the note-offs left over. Links are rebuilt if the track
+    /// was linked.
+    pub fn repair_overlaps(&mut self) -> OverlapReport {
+        cleanup::repair_overlaps(self)
+    }
+
+    /// Decode the MPE notes of the track into per-note expression curves,
+    /// with times in ticks (see `MpeDecoder`). Zone configuration messages
+    /// in the track update `configuration` as they are reached.
+    pub fn decode_mpe(&self, configuration: MpeConfiguration) -> Vec<MpeNoteCurve> {
+        mpe::decode_track(self, configuration)
+    }
+
     /// Unlink all note events
     pub fn unlink_note_events(&mut self) {
         for event in &mut self.events {
@@ -402,6 +484,56 @@ impl MidiTrack {
         self.add_event(MidiEvent::new(tick, MidiMessage::Meta(meta)));
     }
 
+    /// Add a system exclusive event (payload without F0/F7)
+    pub fn add_sysex(&mut self, tick: u64, data: impl Into<Vec<u8>>) {
+        self.add_event(MidiEvent::new(tick, MidiMessage::SysEx(data.into())));
+    }
+
+    /// Add a typed Universal SysEx event
+    pub fn add_universal_sysex(&mut self, tick: u64, sysex: &UniversalSysEx) {
+        self.add_event(MidiEvent::new(tick, sysex.to_message()));
+    }
+
+    /// Add a General MIDI System On message addressed to all devices
+    pub fn add_gm_system_on(&mut self, tick: u64) {
+        self.add_universal_sysex(
+            tick,
+            &UniversalSysEx::GmSystemOn {
+                device_id: ALL_CALL,
+            },
+        );
+    }
+
+    /// Add a General MIDI 2 System On message addressed to all devices
+    pub fn add_gm2_system_on(&mut self, tick: u64) {
+        self.add_universal_sysex(
+            tick,
+            &UniversalSysEx::Gm2SystemOn {
+                device_id: ALL_CALL,
+            },
+        );
+    }
+
+    /// Add a Master Volume message (0.0-1.0 gain) addressed to all devices
+    pub fn add_master_volume(&mut self, tick: u64, gain: f64) {
+        self.add_universal_sysex(tick, &UniversalSysEx::master_volume(gain));
+    }
+
+    /// Add an MTS Single Note Tuning Change (real-time, no bank) addressed
+    /// to all devices
+    pub fn add_note_tuning(&mut self, tick: u64, program: u8, changes: Vec<(u8, NoteTuning)>) {
+        self.add_universal_sysex(
+            tick,
+            &UniversalSysEx::SingleNoteTuning {
+                device_id: ALL_CALL,
+                real_time: true,
+                bank: None,
+                program,
+                changes,
+            },
+        );
+    }
+
     /// Add a text meta event
     pub fn add_text(&mut self, tick: u64, text: impl Into<String>) {
         self.add_meta_event(tick, MetaEvent::Text(text.into()));
@@ -444,11 +576,75 @@ impl MidiTrack {
     /// then deselect the RPN (CC 101/100 = 127/127) so subsequent data-entry
     /// messages don't accidentally alter it.
     pub fn add_pitch_bend_range(&mut self, tick: u64, channel: u8, semitones: u8, cents: u8) {
-        self.add_controller(tick, channel, 101, 0); // RPN MSB = 0
-        self.add_controller(tick, channel, 100, 0); // RPN LSB = 0 (pitch bend range)
-        self.add_controller(tick, channel, 6, semitones.min(127)); // Data entry MSB
-        self.add_controller(tick, channel, 38, cents.min(127)); // Data entry LSB
-        self.add_controller(tick, channel, 101, 127); // Deselect RPN
+        let value14 = ((semitones.min(127) as u16) << 7) | cents.min(127) as u16;
+        self.add_rpn(tick, channel, controller::RPN_PITCH_BEND_RANGE, value14);
+    }
+
+    /// Write the control changes for a decoded controller event: select the
+    /// parameter (CC 101/100 or 99/98), apply the data entry or increment /
+    /// decrement, then deselect it with a null RPN. A 14-bit controller is
+    /// written as its MSB followed by its LSB.
+    pub fn add_controller_event(&mut self, tick: u64, channel: u8, kind: &ControllerEventKind) {
+        for (cc, value) in controller::encode(kind) {
+            self.add_controller(tick, channel, cc, value);
+        }
+    }
+
+    /// Set a registered parameter to a 14-bit value
+    pub fn add_rpn(&mut self, tick: u64, channel: u8, param: u16, value14: u16) {
+        self.add_controller_event(tick, channel, &ControllerEventKind::Rpn { param, value14 });
+    }
+
+    /// Set a non-registered parameter to a 14-bit value
+    pub fn add_nrpn(&mut self, tick: u64, channel: u8, param: u16, value14: u16) {
+        self.add_controller_event(tick, channel, &ControllerEventKind::Nrpn { param, value14 });
+    }
+
+    /// Send a data increment (CC 96) to a parameter
+    pub fn add_data_increment(
+        &mut self,
+        tick: u64,
+        channel: u8,
+        parameter: ParameterNumber,
+        amount: u8,
+    ) {
+        self.add_controller_event(
+            tick,
+            channel,
+            &ControllerEventKind::DataIncrement { parameter, amount },
+        );
+    }
+
+    /// Send a data decrement (CC 97) to a parameter
+    pub fn add_data_decrement(
+        &mut self,
+        tick: u64,
+        channel: u8,
+        parameter: ParameterNumber,
+        amount: u8,
+    ) {
+        self.add_controller_event(
+            tick,
+            channel,
+            &ControllerEventKind::DataDecrement { parameter, amount },
+        );
+    }
+
+    /// Set a 14-bit controller (0-31) via its MSB and LSB (32-63) pair
+    pub fn add_cc14(&mut self, tick: u64, channel: u8, controller: u8, value14: u16) {
+        self.add_controller_event(
+            tick,
+            channel,
+            &ControllerEventKind::Cc14 {
+                controller,
+                value14,
+            },
+        );
+    }
+
+    /// Deselect any RPN/NRPN on a channel (CC 101/100 = 127/127)
+    pub fn add_null_rpn(&mut self, tick: u64, channel: u8) {
+        self.add_controller(tick, channel, 101, 127);
         self.add_controller(tick, channel, 100, 127);
     }
 
@@ -3,6 +3,7 @@
 use super::MidiFormat;
 use super::file::MidiFile;
 use super::message::{MetaEvent, MidiMessage};
+use super::quantize::QuantizeOptions;
 use super::track::MidiTrack;
 
 use crate::core::{Duration, Fraction, Note, Pitch};
@@ -147,6 +148,8 @@ impl Default for ScoreToMidi {
 pub struct MidiToScore {
     /// Quantization grid (in ticks)
     quantize_ticks: Option<u64>,
+    /// Whether to bake the sustain pedal and repair overlapping notes
+    clean_notes: bool,
     /// Whether to separate voices
     separate_voices: bool,
 }
@@ -156,16 +159,26 @@ impl MidiToScore {
     pub fn new() -> Self {
         Self {
             quantize_ticks: None,
+            clean_notes: false,
             separate_voices: false,
         }
     }
 
-    /// Set quantization
+    /// Quantize note starts and ends to a grid of `ticks` before
+    /// converting
     pub fn with_quantization(mut self, ticks: u64) -> Self {
         self.quantize_ticks = Some(ticks);
         self
     }
 
+    /// Bake the sustain pedal into note lengths and repair same-key
+    /// overlaps and duplicate note-ons before converting (recommended for
+    /// live recordings)
+    pub fn with_note_cleanup(mut self, enabled: bool) -> Self {
+        self.clean_notes = enabled;
+        self
+    }
+
     /// Enable voice separation
     pub fn with_voice_separation(mut self, enabled: bool) -> Self {
         self.separate_voices = enabled;
@@ -217,7 +230,20 @@ impl MidiToScore {
         };
 
         for track in midi.tracks().iter().skip(start_track) {
-            let part = self.convert_track(track, tpq);
+            let grid = self.quantize_ticks.filter(|&grid| grid > 0);
+            let part = if self.clean_notes || grid.is_some() {
+                let mut prepared = track.clone();
+                if self.clean_notes {
+                    prepared.repair_overlaps();
+                    prepared.bake_sustain(true);
+                }
+                if let Some(grid) = grid {
+                    prepared.quantize(&QuantizeOptions::new(grid).with_ends(true));
+                }
+                self.convert_track(&prepared, tpq)
+            } else {
+                self.convert_track(track, tpq)
+            };
             score.add_part(part);
         }
 
@@ -331,6 +357,35 @@ mod tests {
         assert_eq!(midi.track(1).unwrap().name(), Some("Piano"));
     }
 
+    #[test]
+    fn test_midi_to_score_quantization() {
+        let mut midi = MidiFile::with_format(MidiFormat::SingleTrack, 480);
+        midi.add_track();
+        midi.add_note(0, 10, 455, 0, 60, 100).unwrap();
+        midi.link_note_events();
+
+        let score = MidiToScore::new().with_quantization(120).convert(&midi);
+        let measure = &score.parts()[0].measures()[0];
+        let (offset, _) = &measure.elements()[0];
+        assert_eq!(*offset, Fraction::new(0, 1));
+        let note = measure.notes().next().unwrap();
+        assert_eq!(note.quarter_length(), Fraction::new(1, 1));
+    }
+
+    #[test]
+    fn test_midi_to_score_note_cleanup() {
+        let mut midi = MidiFile::with_format(MidiFormat::SingleTrack, 480);
+        midi.add_track();
+        midi.add_sustain_on(0, 0, 0).unwrap();
+        midi.add_note(0, 0, 240, 0, 60, 100).unwrap();
+        midi.add_sustain_off(0, 960, 0).unwrap();
+        midi.link_note_events();
+
+        let score = MidiToScore::new().with_note_cleanup(true).convert(&midi);
+        let note = score.parts()[0].notes().next().unwrap();
+        assert_eq!(note.quarter_length(), Fraction::new(2, 1));
+    }
+
     #[test]
     fn test_fraction_to_ticks() {
         let converter = ScoreToMidi::new().with_ticks_per_quarter(480);
@@ -0,0 +1,1404 @@
+//! Universal MIDI Packets (MIDI 2.0)
+//!
+//! A Universal MIDI Packet (UMP) is one to four 32-bit words whose first
+//! nibble, the message type, sets its size. `Ump` models every message
+//! type: utility messages, system and MIDI 1.0 channel voice messages
+//! (carried as `MidiMessage`), 7- and 8-bit system exclusive, MIDI 2.0
+//! channel voice messages with 16/32-bit resolution and per-note control,
+//! Flex Data and UMP Stream messages. Packets of reserved message types
+//! are kept as raw words, so any well-formed stream decodes and encodes
+//! back to the same words.
+//!
+//! `MidiToUmp` and `UmpToMidi` translate between MIDI 1.0 messages and
+//! MIDI 2.0 channel voice messages following the translation rules of the
+//! UMP specification.
+
+use super::message::MidiMessage;
+
+/// Number of 32-bit words in a packet of message type `message_type`
+pub fn packet_words(message_type: u8) -> usize {
+    match message_type & 0x0F {
+        0x0..=0x2 | 0x6 | 0x7 => 1,
+        0x3 | 0x4 | 0x8..=0xA => 2,
+        0xB | 0xC => 3,
+        _ => 4,
+    }
+}
+
+/// Mask of the low `bits` bits
+fn bit_mask(bits: u32) -> u32 {
+    u32::MAX >> (32 - bits)
+}
+
+/// Scale a `from_bits` value up to `to_bits` with the min-center-max
+/// rule: 0 stays 0, the center stays the center and the maximum becomes
+/// the maximum. Bit counts are clamped to 1..=32 and `value` is masked to
+/// `from_bits`; a `to_bits` below `from_bits` scales down instead.
+pub fn scale_up(value: u32, from_bits: u32, to_bits: u32) -> u32 {
+    let from_bits = from_bits.clamp(1, 32);
+    let to_bits = to_bits.clamp(1, 32);
+    if to_bits < from_bits {
+        return scale_down(value, from_bits, to_bits);
+    }
+    let value = value & bit_mask(from_bits);
+    let scale_bits = to_bits - from_bits;
+    let shifted = value << scale_bits;
+    if value <= 1 << (from_bits - 1) {
+        return shifted;
+    }
+    let repeat_bits = from_bits - 1;
+    let mut repeat = value & ((1 << repeat_bits) - 1);
+    if scale_bits > repeat_bits {
+        repeat <<= scale_bits - repeat_bits;
+    } else {
+        repeat >>= repeat_bits - scale_bits;
+    }
+    let mut scaled = shifted;
+    while repeat != 0 {
+        scaled |= repeat;
+        repeat >>= repeat_bits;
+    }
+    scaled
+}
+
+/// Scale a `from_bits` value down to `to_bits` by dropping low bits. Bit
+/// counts are clamped to 1..=32 and `value` is masked to `from_bits`; a
+/// `to_bits` above `from_bits` scales up instead.
+pub fn scale_down(value: u32, from_bits: u32, to_bits: u32) -> u32 {
+    let from_bits = from_bits.clamp(1, 32);
+    let to_bits = to_bits.clamp(1, 32);
+    if to_bits > from_bits {
+        return scale_up(value, from_bits, to_bits);
+    }
+    (value & bit_mask(from_bits)) >> (from_bits - to_bits)
+}
+
+/// Position of a packet in a message spread over several packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketFormat {
+    /// The whole message in one packet
+    Complete,
+    /// First packet
+    Start,
+    /// Middle packet
+    Continue,
+    /// Last packet
+    End,
+}
+
+impl PacketFormat {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 0x3 {
+            0 => PacketFormat::Complete,
+            1 => PacketFormat::Start,
+            2 => PacketFormat::Continue,
+            _ => PacketFormat::End,
+        }
+    }
+
+    fn bits(self) -> u32 {
+        self as u32
+    }
+
+    /// Format of packet `index` out of `count`
+    fn of(index: usize, count: usize) -> Self {
+        match (index, count) {
+            (_, 1) => PacketFormat::Complete,
+            (0, _) => PacketFormat::Start,
+            (i, n) if i + 1 == n => PacketFormat::End,
+            _ => PacketFormat::Continue,
+        }
+    }
+}
+
+/// Utility messages (message type 0x0)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Utility {
+    /// No operation
+    Noop,
+    /// Jitter-reduction clock: sender time in 1/31250 seconds (the whole
+    /// 20-bit field; the time is the low 16 bits, the rest are reserved)
+    JrClock(u32),
+    /// Jitter-reduction timestamp of the next message, in 1/31250 seconds
+    /// (the whole 20-bit field, as for `JrClock`)
+    JrTimestamp(u32),
+    /// Delta clockstamp ticks per quarter note
+    DeltaClockstampTpq(u16),
+    /// Delta clockstamp: ticks since the previous message (20 bits)
+    DeltaClockstamp(u32),
+}
+
+/// MIDI 2.0 channel voice messages (message type 0x4)
+///
+/// Note numbers, controller indices and banks are 7-bit values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Midi2Message {
+    /// Note off with 16-bit velocity and an optional attribute
+    NoteOff {
+        note: u8,
+        velocity: u16,
+        attribute_type: u8,
+        attribute: u16,
+    },
+    /// Note on with 16-bit velocity and an optional attribute
+    NoteOn {
+        note: u8,
+        velocity: u16,
+        attribute_type: u8,
+        attribute: u16,
+    },
+    /// Polyphonic pressure
+    PolyPressure { note: u8, pressure: u32 },
+    /// Registered per-note controller
+    RegisteredPerNoteController { note: u8, index: u8, value: u32 },
+    /// Assignable per-note controller
+    AssignablePerNoteController { note: u8, index: u8, value: u32 },
+    /// Per-note management: detach per-note controllers and/or reset them
+    PerNoteManagement { note: u8, detach: bool, reset: bool },
+    /// Control change with a 32-bit value
+    ControlChange { index: u8, value: u32 },
+    /// Registered controller (RPN) with a 32-bit value
+    RegisteredController { bank: u8, index: u8, value: u32 },
+    /// Assignable controller (NRPN) with a 32-bit value
+    AssignableController { bank: u8, index: u8, value: u32 },
+    /// Relative change of a registered controller
+    RelativeRegisteredController { bank: u8, index: u8, value: i32 },
+    /// Relative change of an assignable controller
+    RelativeAssignableController { bank: u8, index: u8, value: i32 },
+    /// Program change, with the bank (MSB, LSB) to select first
+    ProgramChange { program: u8, bank: Option<(u8, u8)> },
+    /// Channel pressure
+    ChannelPressure { pressure: u32 },
+    /// Channel pitch bend, centered at 0x8000_0000
+    PitchBend { value: u32 },
+    /// Per-note pitch bend, centered at 0x8000_0000
+    PerNotePitchBend { note: u8, value: u32 },
+}
+
+/// Bank of Flex Data messages for setup and timing
+pub const FLEX_BANK_SETUP: u8 = 0x00;
+/// Bank of Flex Data metadata text messages
+pub const FLEX_BANK_METADATA_TEXT: u8 = 0x01;
+/// Bank of Flex Data performance text messages (lyrics and the like)
+pub const FLEX_BANK_PERFORMANCE_TEXT: u8 = 0x02;
+/// Flex Data status of Set Tempo (bank 0)
+pub const FLEX_SET_TEMPO: u8 = 0x00;
+/// Flex Data status of Set Time Signature (bank 0)
+pub const FLEX_SET_TIME_SIGNATURE: u8 = 0x01;
+
+/// Flex Data message (message type 0xD)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FlexData {
+    /// Group (0-15)
+    pub group: u8,
+    /// Position within a multi-packet message
+    pub format: PacketFormat,
+    /// Whether the message addresses the whole group rather than `channel`
+    pub group_wide: bool,
+    /// Channel (0-15), when not group-wide
+    pub channel: u8,
+    /// Status bank
+    pub bank: u8,
+    /// Status
+    pub status: u8,
+    /// Message data
+    pub data: [u32; 3],
+}
+
+impl FlexData {
+    /// Set Tempo, in units of 10 nanoseconds per quarter note
+    pub fn set_tempo(group: u8, ten_nanoseconds_per_quarter: u32) -> Self {
+        Self {
+            group: group & 0x0F,
+            format: PacketFormat::Complete,
+            group_wide: true,
+            channel: 0,
+            bank: FLEX_BANK_SETUP,
+            status: FLEX_SET_TEMPO,
+            data: [ten_nanoseconds_per_quarter, 0, 0],
+        }
+    }
+
+    /// Set Time Signature: the denominator is given as a power of two, as
+    /// in a Standard MIDI File
+    pub fn set_time_signature(
+        group: u8,
+        numerator: u8,
+        denominator_power: u8,
+        thirty_seconds_per_beat: u8,
+    ) -> Self {
+        let word = u32::from_be_bytes([numerator, denominator_power, thirty_seconds_per_beat, 0]);
+        Self {
+            status: FLEX_SET_TIME_SIGNATURE,
+            data: [word, 0, 0],
+            ..Self::set_tempo(group, 0)
+        }
+    }
+
+    /// A group-wide text message in `bank`/`status`, split into as many
+    /// packets as it needs (12 bytes each)
+    pub fn text(group: u8, bank: u8, status: u8, text: &str) -> Vec<Self> {
+        let bytes = text.as_bytes();
+        let chunks: Vec<&[u8]> = if bytes.is_empty() {
+            vec![&[]]
+        } else {
+            bytes.chunks(12).collect()
+        };
+        let count = chunks.len();
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut padded = [0u8; 12];
+                padded[..chunk.len()].copy_from_slice(chunk);
+                Self {
+                    group: group & 0x0F,
+                    format: PacketFormat::of(index, count),
+                    group_wide: true,
+                    channel: 0,
+                    bank,
+                    status,
+                    data: std::array::from_fn(|word| {
+                        u32::from_be_bytes(std::array::from_fn(|byte| padded[word * 4 + byte]))
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// Tempo in 10 ns units per quarter note, if this is Set Tempo
+    pub fn tempo(&self) -> Option<u32> {
+        (self.bank == FLEX_BANK_SETUP && self.status == FLEX_SET_TEMPO).then_some(self.data[0])
+    }
+
+    /// Numerator, denominator power and 32nd notes per beat, if this is
+    /// Set Time Signature
+    pub fn time_signature(&self) -> Option<(u8, u8, u8)> {
+        let [numerator, denominator_power, thirty_seconds, _] = self.data[0].to_be_bytes();
+        (self.bank == FLEX_BANK_SETUP && self.status == FLEX_SET_TIME_SIGNATURE).then_some((
+            numerator,
+            denominator_power,
+            thirty_seconds,
+        ))
+    }
+
+    /// The text bytes of this packet, without the zero padding
+    pub fn text_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.data.iter().flat_map(|w| w.to_be_bytes()).collect();
+        while bytes.last() == Some(&0) {
+            bytes.pop();
+        }
+        bytes
+    }
+}
+
+/// A Universal MIDI Packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ump {
+    /// Utility message (message type 0x0)
+    Utility(Utility),
+    /// System common or real-time message (message type 0x1)
+    System { group: u8, message: MidiMessage },
+    /// MIDI 1.0 channel voice message (message type 0x2)
+    Midi1ChannelVoice { group: u8, message: MidiMessage },
+    /// Up to 6 bytes of 7-bit system exclusive data (message type 0x3)
+    SysEx7 {
+        group: u8,
+        format: PacketFormat,
+        data: Vec<u8>,
+    },
+    /// MIDI 2.0 channel voice message (message type 0x4)
+    Midi2ChannelVoice {
+        group: u8,
+        channel: u8,
+        message: Midi2Message,
+    },
+    /// Up to 13 bytes of 8-bit system exclusive data (message type 0x5)
+    SysEx8 {
+        group: u8,
+        format: PacketFormat,
+        stream_id: u8,
+        data: Vec<u8>,
+    },
+    /// Flex Data message (message type 0xD)
+    FlexData(FlexData),
+    /// UMP Stream message (message type 0xF)
+    Stream {
+        format: PacketFormat,
+        status: u16,
+        data: [u8; 14],
+    },
+    /// Any other packet (reserved message types, mixed data sets and
+    /// undefined statuses), kept as raw words
+    Other(Vec<u32>),
+}
+
+/// Stream status of Start of Clip
+pub const STREAM_START_OF_CLIP: u16 = 0x20;
+/// Stream status of End of Clip
+pub const STREAM_END_OF_CLIP: u16 = 0x21;
+
+fn header(message_type: u32, group: u8) -> u32 {
+    (message_type << 28) | ((group as u32 & 0x0F) << 24)
+}
+
+impl Ump {
+    /// A UMP Stream message with no data
+    pub fn stream(status: u16) -> Self {
+        Ump::Stream {
+            format: PacketFormat::Complete,
+            status: status & 0x3FF,
+            data: [0; 14],
+        }
+    }
+
+    /// Message type (the first nibble)
+    pub fn message_type(&self) -> u8 {
+        match self {
+            Ump::Utility(_) => 0x0,
+            Ump::System { .. } => 0x1,
+            Ump::Midi1ChannelVoice { .. } => 0x2,
+            Ump::SysEx7 { .. } => 0x3,
+            Ump::Midi2ChannelVoice { .. } => 0x4,
+            Ump::SysEx8 { .. } => 0x5,
+            Ump::FlexData(_) => 0xD,
+            Ump::Stream { .. } => 0xF,
+            Ump::Other(words) => (words.first().copied().unwrap_or(0) >> 28) as u8,
+        }
+    }
+
+    /// Group (0-15), for message types that have one
+    pub fn group(&self) -> Option<u8> {
+        match self {
+            Ump::Utility(_) | Ump::Stream { .. } => None,
+            Ump::System { group, .. }
+            | Ump::Midi1ChannelVoice { group, .. }
+            | Ump::SysEx7 { group, .. }
+            | Ump::Midi2ChannelVoice { group, .. }
+            | Ump::SysEx8 { group, .. } => Some(*group),
+            Ump::FlexData(flex) => Some(flex.group),
+            Ump::Other(words) => Some((words.first().copied().unwrap_or(0) >> 24) as u8 & 0x0F),
+        }
+    }
+
+    /// Wrap a MIDI 1.0 message in MIDI 1.0 protocol packets: channel voice
+    /// and system messages take one packet, SysEx as many 7-bit SysEx
+    /// packets as it needs. Meta events have no UMP form.
+    pub fn from_midi1(group: u8, message: &MidiMessage) -> Vec<Ump> {
+        let group = group & 0x0F;
+        match message {
+            MidiMessage::Meta(_) => Vec::new(),
+            MidiMessage::SysEx(data) => {
+                let chunks: Vec<&[u8]> = if data.is_empty() {
+                    vec![&[]]
+                } else {
+                    data.chunks(6).collect()
+                };
+                let count = chunks.len();
+                chunks
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, chunk)| Ump::SysEx7 {
+                        group,
+                        format: PacketFormat::of(index, count),
+                        data: chunk.to_vec(),
+                    })
+                    .collect()
+            }
+            message if message.channel().is_some() => vec![Ump::Midi1ChannelVoice {
+                group,
+                message: message.clone(),
+            }],
+            message => vec![Ump::System {
+                group,
+                message: message.clone(),
+            }],
+        }
+    }
+
+    /// Encode the packet as 32-bit words
+    pub fn to_words(&self) -> Vec<u32> {
+        match self {
+            Ump::Utility(utility) => {
+                let (status, data) = match *utility {
+                    Utility::Noop => (0, 0),
+                    Utility::JrClock(time) => (1, time & 0xF_FFFF),
+                    Utility::JrTimestamp(time) => (2, time & 0xF_FFFF),
+                    Utility::DeltaClockstampTpq(tpq) => (3, tpq as u32),
+                    Utility::DeltaClockstamp(ticks) => (4, ticks & 0xF_FFFF),
+                };
+                vec![(status << 20) | data]
+            }
+            Ump::System { group, message } | Ump::Midi1ChannelVoice { group, message } => {
+                let message_type = if matches!(self, Ump::System { .. }) {
+                    0x1
+                } else {
+                    0x2
+                };
+                let bytes = message.to_bytes();
+                let byte = |i: usize| bytes.get(i).copied().unwrap_or(0) as u32;
+                vec![header(message_type, *group) | (byte(0) << 16) | (byte(1) << 8) | byte(2)]
+            }
+            Ump::SysEx7 {
+                group,
+                format,
+                data,
+            } => {
+                let count = data.len().min(6);
+                let mut bytes = [0u8; 8];
+                bytes[1] = ((format.bits() << 4) | count as u32) as u8;
+                bytes[2..2 + count].copy_from_slice(&data[..count]);
+                let mut words = words_from_bytes(&bytes);
+                words[0] |= header(0x3, *group);
+                words
+            }
+            Ump::Midi2ChannelVoice {
+                group,
+                channel,
+                message,
+            } => {
+                let (opcode, byte3, byte4, data) = midi2_fields(message);
+                let word = header(0x4, *group)
+                    | (opcode << 20)
+                    | ((*channel as u32 & 0x0F) << 16)
+                    | ((byte3 as u32) << 8)
+                    | byte4 as u32;
+                vec![word, data]
+            }
+            Ump::SysEx8 {
+                group,
+                format,
+                stream_id,
+                data,
+            } => {
+                let count = data.len().min(13);
+                let mut bytes = [0u8; 16];
+                bytes[1] = ((format.bits() << 4) | (count as u32 + 1)) as u8;
+                bytes[2] = *stream_id;
+                bytes[3..3 + count].copy_from_slice(&data[..count]);
+                let mut words = words_from_bytes(&bytes);
+                words[0] |= header(0x5, *group);
+                words
+            }
+            Ump::FlexData(flex) => {
+                let word = header(0xD, flex.group)
+                    | (flex.format.bits() << 22)
+                    | ((flex.group_wide as u32) << 20)
+                    | ((flex.channel as u32 & 0x0F) << 16)
+                    | ((flex.bank as u32) << 8)
+                    | flex.status as u32;
+                vec![word, flex.data[0], flex.data[1], flex.data[2]]
+            }
+            Ump::Stream {
+                format,
+                status,
+                data,
+            } => {
+                let mut bytes = [0u8; 16];
+                bytes[2..].copy_from_slice(data);
+                let mut words = words_from_bytes(&bytes);
+                words[0] |= (0xF << 28) | (format.bits() << 26) | ((*status as u32 & 0x3FF) << 16);
+                words
+            }
+            Ump::Other(words) => words.clone(),
+        }
+    }
+
+    /// Decode the packet at the start of `words`, returning it and the
+    /// number of words it used. `None` if `words` is shorter than the
+    /// packet.
+    pub fn from_words(words: &[u32]) -> Option<(Ump, usize)> {
+        let first = *words.first()?;
+        let message_type = (first >> 28) as u8;
+        let size = packet_words(message_type);
+        let words = words.get(..size)?;
+        let group = ((first >> 24) & 0x0F) as u8;
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes()).collect();
+        let other = || Ump::Other(words.to_vec());
+
+        let ump = match message_type {
+            0x0 => {
+                let data = first & 0xF_FFFF;
+                let utility = match (first >> 20) & 0x0F {
+                    0 => Utility::Noop,
+                    1 => Utility::JrClock(data),
+                    2 => Utility::JrTimestamp(data),
+                    // Reserved bits above the 16-bit resolution keep the
+                    // packet raw.
+                    3 if data <= 0xFFFF => Utility::DeltaClockstampTpq(data as u16),
+                    4 => Utility::DeltaClockstamp(data),
+                    _ => return Some((other(), size)),
+                };
+                Ump::Utility(utility)
+            }
+            0x1 | 0x2 => {
+                let status = bytes[1];
+                let length = match status {
+                    0xF1 | 0xF3 | 0xC0..=0xDF => 2,
+                    0xF2 | 0x80..=0xBF | 0xE0..=0xEF => 3,
+                    _ => 1,
+                };
+                let valid = if message_type == 0x1 {
+                    status >= 0xF1 && status != 0xF7
+                } else {
+                    (0x80..0xF0).contains(&status)
+                };
+                match MidiMessage::from_bytes(&bytes[1..1 + length]) {
+                    Some((message, _)) if valid => {
+                        if message_type == 0x1 {
+                            Ump::System { group, message }
+                        } else {
+                            Ump::Midi1ChannelVoice { group, message }
+                        }
+                    }
+                    _ => other(),
+                }
+            }
+            0x3 => {
+                let status = bytes[1] >> 4;
+                let count = (bytes[1] & 0x0F) as usize;
+                if status > 3 || count > 6 {
+                    other()
+                } else {
+                    Ump::SysEx7 {
+                        group,
+                        format: PacketFormat::from_bits(status as u32),
+                        data: bytes[2..2 + count].to_vec(),
+                    }
+                }
+            }
+            0x4 => {
+                let opcode = (first >> 20) & 0x0F;
+                let channel = ((first >> 16) & 0x0F) as u8;
+                match midi2_message(opcode, bytes[2], bytes[3], words[1]) {
+                    Some(message) => Ump::Midi2ChannelVoice {
+                        group,
+                        channel,
+                        message,
+                    },
+                    None => other(),
+                }
+            }
+            0x5 => {
+                let status = bytes[1] >> 4;
+                let count = (bytes[1] & 0x0F) as usize;
+                if status > 3 || !(1..=14).contains(&count) {
+                    other()
+                } else {
+                    Ump::SysEx8 {
+                        group,
+                        format: PacketFormat::from_bits(status as u32),
+                        stream_id: bytes[2],
+                        data: bytes[3..2 + count].to_vec(),
+                    }
+                }
+            }
+            0xD => {
+                let address = (first >> 20) & 0x3;
+                if address > 1 {
+                    other()
+                } else {
+                    Ump::FlexData(FlexData {
+                        group,
+                        format: PacketFormat::from_bits(first >> 22),
+                        group_wide: address == 1,
+                        channel: ((first >> 16) & 0x0F) as u8,
+                        bank: bytes[2],
+                        status: bytes[3],
+                        data: [words[1], words[2], words[3]],
+                    })
+                }
+            }
+            0xF => Ump::Stream {
+                format: PacketFormat::from_bits(first >> 26),
+                status: ((first >> 16) & 0x3FF) as u16,
+                data: std::array::from_fn(|i| bytes[i + 2]),
+            },
+            _ => other(),
+        };
+        Some((ump, size))
+    }
+}
+
+/// Decode a stream of words into packets. A trailing incomplete packet is
+/// ignored.
+pub fn decode_words(mut words: &[u32]) -> Vec<Ump> {
+    let mut packets = Vec::new();
+    while let Some((ump, size)) = Ump::from_words(words) {
+        packets.push(ump);
+        words = &words[size..];
+    }
+    packets
+}
+
+/// Encode packets as a stream of words
+pub fn encode_words(packets: &[Ump]) -> Vec<u32> {
+    packets.iter().flat_map(Ump::to_words).collect()
+}
+
+fn words_from_bytes(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks(4)
+        .map(|chunk| u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+/// Opcode, the two index bytes and the data word of a MIDI 2.0 channel
+/// voice message.
+fn midi2_fields(message: &Midi2Message) -> (u32, u8, u8, u32) {
+    let seven = |value: u8| value & 0x7F;
+    match *message {
+        Midi2Message::RegisteredPerNoteController { note, index, value } => {
+            (0x0, seven(note), index, value)
+        }
+        Midi2Message::AssignablePerNoteController { note, index, value } => {
+            (0x1, seven(note), index, value)
+        }
+        Midi2Message::RegisteredController { bank, index, value } => {
+            (0x2, seven(bank), seven(index), value)
+        }
+        Midi2Message::AssignableController { bank, index, value } => {
+            (0x3, seven(bank), seven(index), value)
+        }
+        Midi2Message::RelativeRegisteredController { bank, index, value } => {
+            (0x4, seven(bank), seven(index), value as u32)
+        }
+        Midi2Message::RelativeAssignableController { bank, index, value } => {
+            (0x5, seven(bank), seven(index), value as u32)
+        }
+        Midi2Message::PerNotePitchBend { note, value } => (0x6, seven(note), 0, value),
+        Midi2Message::NoteOff {
+            note,
+            velocity,
+            attribute_type,
+            attribute,
+        } => (
+            0x8,
+            seven(note),
+            attribute_type,
+            ((velocity as u32) << 16) | attribute as u32,
+        ),
+        Midi2Message::NoteOn {
+            note,
+            velocity,
+            attribute_type,
+            attribute,
+        } => (
+            0x9,
+            seven(note),
+            attribute_type,
+            ((velocity as u32) << 16) | attribute as u32,
+        ),
+        Midi2Message::PolyPressure { note, pressure } => (0xA, seven(note), 0, pressure),
+        Midi2Message::ControlChange { index, value } => (0xB, seven(index), 0, value),
+        Midi2Message::ProgramChange { program, bank } => {
+            let (msb, lsb) = bank.unwrap_or((0, 0));
+            let data = u32::from_be_bytes([seven(program), 0, seven(msb), seven(lsb)]);
+            (0xC, 0, bank.is_some() as u8, data)
+        }
+        Midi2Message::ChannelPressure { pressure } => (0xD, 0, 0, pressure),
+        Midi2Message::PitchBend { value } => (0xE, 0, 0, value),
+        Midi2Message::PerNoteManagement {
+            note,
+            detach,
+            reset,
+        } => (0xF, seven(note), ((detach as u8) << 1) | reset as u8, 0),
+    }
+}
+
+fn midi2_message(opcode: u32, byte3: u8, byte4: u8, data: u32) -> Option<Midi2Message> {
+    let note = byte3 & 0x7F;
+    let (bank, index) = (byte3 & 0x7F, byte4 & 0x7F);
+    let velocity = (data >> 16) as u16;
+    let attribute = data as u16;
+    Some(match opcode {
+        0x0 => Midi2Message::RegisteredPerNoteController {
+            note,
+            index: byte4,
+            value: data,
+        },
+        0x1 => Midi2Message::AssignablePerNoteController {
+            note,
+            index: byte4,
+            value: data,
+        },
+        0x2 => Midi2Message::RegisteredController {
+            bank,
+            index,
+            value: data,
+        },
+        0x3 => Midi2Message::AssignableController {
+            bank,
+            index,
+            value: data,
+        },
+        0x4 => Midi2Message::RelativeRegisteredController {
+            bank,
+            index,
+            value: data as i32,
+        },
+        0x5 => Midi2Message::RelativeAssignableController {
+            bank,
+            index,
+            value: data as i32,
+        },
+        0x6 => Midi2Message::PerNotePitchBend { note, value: data },
+        0x8 => Midi2Message::NoteOff {
+            note,
+            velocity,
+            attribute_type: byte4,
+            attribute,
+        },
+        0x9 => Midi2Message::NoteOn {
+            note,
+            velocity,
+            attribute_type: byte4,
+            attribute,
+        },
+        0xA => Midi2Message::PolyPressure {
+            note,
+            pressure: data,
+        },
+        0xB => Midi2Message::ControlChange {
+            index: note,
+            value: data,
+        },
+        0xC => {
+            let [program, _, msb, lsb] = data.to_be_bytes();
+            Midi2Message::ProgramChange {
+                program: program & 0x7F,
+                bank: (byte4 & 1 != 0).then_some((msb & 0x7F, lsb & 0x7F)),
+            }
+        }
+        0xD => Midi2Message::ChannelPressure { pressure: data },
+        0xE => Midi2Message::PitchBend { value: data },
+        0xF => Midi2Message::PerNoteManagement {
+            note,
+            detach: byte4 & 2 != 0,
+            reset: byte4 & 1 != 0,
+        },
+        _ => return None,
+    })
+}
+
+const CC_BANK_MSB: u8 = 0;
+const CC_DATA_ENTRY_MSB: u8 = 6;
+const CC_BANK_LSB: u8 = 32;
+const CC_DATA_ENTRY_LSB: u8 = 38;
+const CC_NRPN_LSB: u8 = 98;
+const CC_NRPN_MSB: u8 = 99;
+const CC_RPN_LSB: u8 = 100;
+const CC_RPN_MSB: u8 = 101;
+
+/// Bank and parameter selection of one MIDI 1.0 channel.
+#[derive(Debug, Clone, Copy)]
+struct ChannelSelection {
+    bank: Option<(u8, u8)>,
+    rpn: (u8, u8),
+    nrpn: (u8, u8),
+    /// Whether the last selection was an NRPN
+    nrpn_selected: bool,
+    /// Data entry MSB and LSB of the selected parameter
+    data: (u8, u8),
+}
+
+impl Default for ChannelSelection {
+    fn default() -> Self {
+        Self {
+            bank: None,
+            rpn: (127, 127),
+            nrpn: (127, 127),
+            nrpn_selected: false,
+            data: (0, 0),
+        }
+    }
+}
+
+/// Translates MIDI 1.0 messages into MIDI 2.0 protocol packets
+///
+/// Values are scaled up with `scale_up`. A note-on with velocity 0
+/// becomes a note-off. Bank select is held back and sent with the next
+/// program change. RPN and NRPN data entry becomes a registered or
+/// assignable controller message: the data entry MSB (CC 6) sends it with
+/// an LSB of 0, and a following LSB (CC 38) sends it again with the full
+/// 14-bit value.
+/// System messages and SysEx are wrapped as in `Ump::from_midi1`.
+#[derive(Debug, Clone, Default)]
+pub struct MidiToUmp {
+    group: u8,
+    channels: [ChannelSelection; 16],
+}
+
+impl MidiToUmp {
+    /// Create a translator writing to `group`
+    pub fn new(group: u8) -> Self {
+        Self {
+            group: group & 0x0F,
+            ..Self::default()
+        }
+    }
+
+    /// Translate one message; some messages only update state and yield
+    /// no packet
+    pub fn translate(&mut self, message: &MidiMessage) -> Vec<Ump> {
+        let Some(channel) = message.channel() else {
+            return Ump::from_midi1(self.group, message);
+        };
+        let state = &mut self.channels[channel as usize & 0x0F];
+        let midi2 = match *message {
+            MidiMessage::NoteOn { key, velocity, .. } if velocity > 0 => Midi2Message::NoteOn {
+                note: key,
+                velocity: scale_up(velocity as u32, 7, 16) as u16,
+                attribute_type: 0,
+                attribute: 0,
+            },
+            MidiMessage::NoteOn { key, .. } => Midi2Message::NoteOff {
+                note: key,
+                velocity: scale_up(64, 7, 16) as u16,
+                attribute_type: 0,
+                attribute: 0,
+            },
+            MidiMessage::NoteOff { key, velocity, .. } => Midi2Message::NoteOff {
+                note: key,
+                velocity: scale_up(velocity as u32, 7, 16) as u16,
+                attribute_type: 0,
+                attribute: 0,
+            },
+            MidiMessage::PolyPressure { key, pressure, .. } => Midi2Message::PolyPressure {
+                note: key,
+                pressure: scale_up(pressure as u32, 7, 32),
+            },
+            MidiMessage::ProgramChange { program, .. } => Midi2Message::ProgramChange {
+                program,
+                bank: state.bank,
+            },
+            MidiMessage::ChannelPressure { pressure, .. } => Midi2Message::ChannelPressure {
+                pressure: scale_up(pressure as u32, 7, 32),
+            },
+            MidiMessage::PitchBend { value, .. } => Midi2Message::PitchBend {
+                value: scale_up(value as u32, 14, 32),
+            },
+            MidiMessage::ControlChange {
+                controller, value, ..
+            } => {
+                let selected = if state.nrpn_selected {
+                    state.nrpn
+                } else {
+                    state.rpn
+                };
+                let parameter = selected != (127, 127);
+                match controller {
+                    CC_BANK_MSB => {
+                        state.bank = Some((value, state.bank.map_or(0, |(_, lsb)| lsb)));
+                        return Vec::new();
+                    }
+                    CC_BANK_LSB => {
+                        state.bank = Some((state.bank.map_or(0, |(msb, _)| msb), value));
+                        return Vec::new();
+                    }
+                    CC_RPN_MSB | CC_RPN_LSB => {
+                        if controller == CC_RPN_MSB {
+                            state.rpn.0 = value;
+                        } else {
+                            state.rpn.1 = value;
+                        }
+                        state.nrpn_selected = false;
+                        return Vec::new();
+                    }
+                    CC_NRPN_MSB | CC_NRPN_LSB => {
+                        if controller == CC_NRPN_MSB {
+                            state.nrpn.0 = value;
+                        } else {
+                            state.nrpn.1 = value;
+                        }
+                        state.nrpn_selected = true;
+                        return Vec::new();
+                    }
+                    CC_DATA_ENTRY_MSB | CC_DATA_ENTRY_LSB if parameter => {
+                        state.data = if controller == CC_DATA_ENTRY_MSB {
+                            (value, 0)
+                        } else {
+                            (state.data.0, value)
+                        };
+                        let (bank, index) = selected;
+                        let value14 = ((state.data.0 as u32) << 7) | state.data.1 as u32;
+                        let value = scale_up(value14, 14, 32);
+                        if state.nrpn_selected {
+                            Midi2Message::AssignableController { bank, index, value }
+                        } else {
+                            Midi2Message::RegisteredController { bank, index, value }
+                        }
+                    }
+                    _ => Midi2Message::ControlChange {
+                        index: controller,
+                        value: scale_up(value as u32, 7, 32),
+                    },
+                }
+            }
+            _ => return Vec::new(),
+        };
+        vec![Ump::Midi2ChannelVoice {
+            group: self.group,
+            channel,
+            message: midi2,
+        }]
+    }
+}
+
+/// Translates packets into MIDI 1.0 messages
+///
+/// MIDI 2.0 values are scaled down with `scale_down`; a note-on whose
+/// velocity scales to 0 is sent with velocity 1. Program changes with a
+/// bank are preceded by bank select, and registered and assignable
+/// controllers become RPN/NRPN selection and data entry. Per-note
+/// controllers, per-note pitch bend, per-note management and relative
+/// controllers have no MIDI 1.0 form and are dropped, as are utility,
+/// SysEx8, Flex Data and stream messages. SysEx spread over several
+/// packets is collected and returned with its last packet.
+#[derive(Debug, Clone, Default)]
+pub struct UmpToMidi {
+    sysex: Vec<u8>,
+}
+
+impl UmpToMidi {
+    /// Create a translator
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Translate one packet
+    pub fn translate(&mut self, ump: &Ump) -> Vec<MidiMessage> {
+        match ump {
+            Ump::System { message, .. } | Ump::Midi1ChannelVoice { message, .. } => {
+                vec![message.clone()]
+            }
+            Ump::SysEx7 { format, data, .. } => {
+                if matches!(format, PacketFormat::Complete | PacketFormat::Start) {
+                    self.sysex.clear();
+                }
+                self.sysex.extend_from_slice(data);
+                if matches!(format, PacketFormat::Complete | PacketFormat::End) {
+                    vec![MidiMessage::SysEx(std::mem::take(&mut self.sysex))]
+                } else {
+                    Vec::new()
+                }
+            }
+            Ump::Midi2ChannelVoice {
+                channel, message, ..
+            } => midi2_to_midi1(*channel, message),
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn midi2_to_midi1(channel: u8, message: &Midi2Message) -> Vec<MidiMessage> {
+    let seven = |value: u32, bits: u32| scale_down(value, bits, 7) as u8;
+    let cc = |controller: u8, value: u8| MidiMessage::control_change(channel, controller, value);
+    match *message {
+        Midi2Message::NoteOn { note, velocity, .. } => vec![MidiMessage::note_on(
+            channel,
+            note,
+            seven(velocity as u32, 16).max(1),
+        )],
+        Midi2Message::NoteOff { note, velocity, .. } => {
+            vec![MidiMessage::note_off(
+                channel,
+                note,
+                seven(velocity as u32, 16),
+            )]
+        }
+        Midi2Message::PolyPressure { note, pressure } => vec![MidiMessage::PolyPressure {
+            channel,
+            key: note & 0x7F,
+            pressure: seven(pressure, 32),
+        }],
+        Midi2Message::ControlChange { index, value } => vec![cc(index, seven(value, 32))],
+        Midi2Message::RegisteredController { bank, index, value }
+        | Midi2Message::AssignableController { bank, index, value } => {
+            let (msb_cc, lsb_cc) = if matches!(message, Midi2Message::RegisteredController { .. }) {
+                (CC_RPN_MSB, CC_RPN_LSB)
+            } else {
+                (CC_NRPN_MSB, CC_NRPN_LSB)
+            };
+            let value14 = scale_down(value, 32, 14);
+            vec![
+                cc(msb_cc, bank),
+                cc(lsb_cc, index),
+                cc(CC_DATA_ENTRY_MSB, (value14 >> 7) as u8),
+                cc(CC_DATA_ENTRY_LSB, (value14 & 0x7F) as u8),
+            ]
+        }
+        Midi2Message::ProgramChange { program, bank } => {
+            let mut messages = Vec::new();
+            if let Some((msb, lsb)) = bank {
+                messages.extend([cc(CC_BANK_MSB, msb), cc(CC_BANK_LSB, lsb)]);
+            }
+            messages.push(MidiMessage::program_change(channel, program));
+            messages
+        }
+        Midi2Message::ChannelPressure { pressure } => vec![MidiMessage::ChannelPressure {
+            channel,
+            pressure: seven(pressure, 32),
+        }],
+        Midi2Message::PitchBend { value } => vec![MidiMessage::pitch_bend(
+            channel,
+            scale_down(value, 32, 14) as u16,
+        )],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(ump: Ump) {
+        let words = ump.to_words();
+        assert_eq!(words.len(), packet_words(ump.message_type()), "{ump:?}");
+        assert_eq!(Ump::from_words(&words), Some((ump, words.len())));
+    }
+
+    #[test]
+    fn test_scaling() {
+        assert_eq!(scale_up(0, 7, 32), 0);
+        assert_eq!(scale_up(64, 7, 32), 0x8000_0000);
+        assert_eq!(scale_up(127, 7, 32), 0xFFFF_FFFF);
+        assert_eq!(scale_up(127, 7, 16), 0xFFFF);
+        assert_eq!(scale_up(0x2000, 14, 32), 0x8000_0000);
+        assert_eq!(scale_up(0x3FFF, 14, 32), 0xFFFF_FFFF);
+        for value in 0..128 {
+            assert_eq!(scale_down(scale_up(value, 7, 32), 32, 7), value);
+        }
+
+        // Out-of-range arguments are clamped rather than overflowing.
+        assert_eq!(scale_up(0xFFFF, 16, 7), 127);
+        assert_eq!(scale_down(127, 7, 16), 0xFFFF);
+        assert_eq!(scale_up(1, 0, 8), 0x80);
+        assert_eq!(scale_up(0x1FF, 7, 64), 0xFFFF_FFFF);
+        assert_eq!(scale_down(u32::MAX, 40, 0), 1);
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        let messages = [
+            Midi2Message::NoteOn {
+                note: 60,
+                velocity: 0xC000,
+                attribute_type: 3,
+                attribute: 0x1234,
+            },
+            Midi2Message::NoteOff {
+                note: 60,
+                velocity: 0,
+                attribute_type: 0,
+                attribute: 0,
+            },
+            Midi2Message::PolyPressure {
+                note: 61,
+                pressure: 0x1234_5678,
+            },
+            Midi2Message::RegisteredPerNoteController {
+                note: 62,
+                index: 200,
+                value: 7,
+            },
+            Midi2Message::AssignablePerNoteController {
+                note: 62,
+                index: 74,
+                value: u32::MAX,
+            },
+            Midi2Message::PerNoteManagement {
+                note: 62,
+                detach: true,
+                reset: false,
+            },
+            Midi2Message::ControlChange {
+                index: 74,
+                value: 1,
+            },
+            Midi2Message::RegisteredController {
+                bank: 0,
+                index: 6,
+                value: 0x1000_0000,
+            },
+            Midi2Message::AssignableController {
+                bank: 3,
+                index: 4,
+                value: 5,
+            },
+            Midi2Message::RelativeRegisteredController {
+                bank: 0,
+                index: 1,
+                value: -100,
+            },
+            Midi2Message::RelativeAssignableController {
+                bank: 1,
+                index: 2,
+                value: 100,
+            },
+            Midi2Message::ProgramChange {
+                program: 40,
+                bank: Some((1, 2)),
+            },
+            Midi2Message::ProgramChange {
+                program: 40,
+                bank: None,
+            },
+            Midi2Message::ChannelPressure { pressure: 9 },
+            Midi2Message::PitchBend { value: 0x8000_0000 },
+            Midi2Message::PerNotePitchBend {
+                note: 64,
+                value: 0x9000_0000,
+            },
+        ];
+        for message in messages {
+            round_trip(Ump::Midi2ChannelVoice {
+                group: 5,
+                channel: 9,
+                message,
+            });
+        }
+        for utility in [
+            Utility::Noop,
+            Utility::JrClock(1234),
+            Utility::JrClock(0xF_FFFF),
+            Utility::JrTimestamp(42),
+            Utility::DeltaClockstampTpq(960),
+            Utility::DeltaClockstamp(0xF_FFFF),
+        ] {
+            round_trip(Ump::Utility(utility));
+        }
+        // Reserved bits above a 16-bit tick resolution survive as well.
+        let words = [0x0031_03C0];
+        let (ump, _) = Ump::from_words(&words).unwrap();
+        assert_eq!(ump.to_words(), words);
+        round_trip(Ump::System {
+            group: 1,
+            message: MidiMessage::SongPosition(0x1234),
+        });
+        round_trip(Ump::System {
+            group: 1,
+            message: MidiMessage::TimingClock,
+        });
+        round_trip(Ump::Midi1ChannelVoice {
+            group: 2,
+            message: MidiMessage::pitch_bend(3, 0x1234),
+        });
+        round_trip(Ump::SysEx7 {
+            group: 0,
+            format: PacketFormat::Start,
+            data: vec![0x7E, 0x7F, 0x09, 0x01, 0x00, 0x10],
+        });
+        round_trip(Ump::SysEx8 {
+            group: 0,
+            format: PacketFormat::Complete,
+            stream_id: 7,
+            data: (0..13).map(|b| b * 19).collect(),
+        });
+        for flex in FlexData::text(3, FLEX_BANK_METADATA_TEXT, 1, "A project name")
+            .into_iter()
+            .chain([
+                FlexData::set_tempo(0, 50_000_000),
+                FlexData::set_time_signature(0, 6, 3, 8),
+            ])
+        {
+            round_trip(Ump::FlexData(flex));
+        }
+        round_trip(Ump::stream(STREAM_START_OF_CLIP));
+        round_trip(Ump::Other(vec![0x6000_0001]));
+        round_trip(Ump::Other(vec![0xB000_0000, 1, 2]));
+
+        let stream = [0x0030_03C0, 0x4090_3C00, 0xFFFF_0000, 0x2080_3C40];
+        let packets = decode_words(&stream);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(encode_words(&packets), stream);
+        assert_eq!(Ump::from_words(&[0x4090_3C00]), None);
+    }
+
+    #[test]
+    fn test_flex_data_helpers() {
+        let packets = FlexData::text(0, FLEX_BANK_PERFORMANCE_TEXT, 1, "Hello, MIDI 2.0 world");
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].format, PacketFormat::Start);
+        assert_eq!(packets[1].format, PacketFormat::End);
+        let text: Vec<u8> = packets.iter().flat_map(FlexData::text_bytes).collect();
+        assert_eq!(text, b"Hello, MIDI 2.0 world");
+        assert_eq!(FlexData::set_tempo(0, 50_000_000).tempo(), Some(50_000_000));
+        assert_eq!(
+            FlexData::set_time_signature(0, 7, 3, 8).time_signature(),
+            Some((7, 3, 8))
+        );
+        assert_eq!(packets[0].tempo(), None);
+    }
+
+    #[test]
+    fn test_midi1_to_midi2_translation() {
+        let mut translator = MidiToUmp::new(1);
+        let midi2 = |message: &MidiMessage, translator: &mut MidiToUmp| -> Vec<Midi2Message> {
+            translator
+                .translate(message)
+                .into_iter()
+                .map(|ump| match ump {
+                    Ump::Midi2ChannelVoice {
+                        group: 1, message, ..
+                    } => message,
+                    other => panic!("unexpected {other:?}"),
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            midi2(&MidiMessage::note_on(0, 60, 127), &mut translator),
+            vec![Midi2Message::NoteOn {
+                note: 60,
+                velocity: 0xFFFF,
+                attribute_type: 0,
+                attribute: 0
+            }]
+        );
+        assert!(matches!(
+            midi2(&MidiMessage::note_on(0, 60, 0), &mut translator)[0],
+            Midi2Message::NoteOff {
+                velocity: 0x8000,
+                ..
+            }
+        ));
+        assert_eq!(
+            midi2(&MidiMessage::pitch_bend(0, 0x2000), &mut translator),
+            vec![Midi2Message::PitchBend { value: 0x8000_0000 }]
+        );
+
+        assert!(midi2(&MidiMessage::control_change(0, 0, 1), &mut translator).is_empty());
+        assert!(midi2(&MidiMessage::control_change(0, 32, 5), &mut translator).is_empty());
+        assert_eq!(
+            midi2(&MidiMessage::program_change(0, 40), &mut translator),
+            vec![Midi2Message::ProgramChange {
+                program: 40,
+                bank: Some((1, 5))
+            }]
+        );
+
+        // Pitch bend range RPN: 12 semitones, sent with the MSB alone,
+        // then refined to 12 semitones and 50 cents by the LSB.
+        for (cc, value) in [(101, 0), (100, 0)] {
+            assert!(midi2(&MidiMessage::control_change(0, cc, value), &mut translator).is_empty());
+        }
+        assert_eq!(
+            midi2(&MidiMessage::control_change(0, 6, 12), &mut translator),
+            vec![Midi2Message::RegisteredController {
+                bank: 0,
+                index: 0,
+                value: scale_up(12 << 7, 14, 32)
+            }]
+        );
+        assert_eq!(
+            midi2(&MidiMessage::control_change(0, 38, 50), &mut translator),
+            vec![Midi2Message::RegisteredController {
+                bank: 0,
+                index: 0,
+                value: scale_up((12 << 7) | 50, 14, 32)
+            }]
+        );
+        assert_eq!(
+            midi2(&MidiMessage::control_change(0, 7, 100), &mut translator),
+            vec![Midi2Message::ControlChange {
+                index: 7,
+                value: scale_up(100, 7, 32)
+            }]
+        );
+
+        let sysex = MidiMessage::SysEx((0..10).collect());
+        let packets = translator.translate(&sysex);
+        assert_eq!(packets.len(), 2);
+        let mut back = UmpToMidi::new();
+        assert!(back.translate(&packets[0]).is_empty());
+        assert_eq!(back.translate(&packets[1]), vec![sysex]);
+        assert_eq!(
+            translator.translate(&MidiMessage::Start),
+            vec![Ump::System {
+                group: 1,
+                message: MidiMessage::Start
+            }]
+        );
+    }
+
+    #[test]
+    fn test_midi2_to_midi1_translation() {
+        let mut to_midi1 = UmpToMidi::new();
+        let mut to_midi2 = MidiToUmp::new(0);
+        let messages = [
+            MidiMessage::note_on(2, 60, 1),
+            MidiMessage::note_off(2, 60, 64),
+            MidiMessage::control_change(2, 74, 127),
+            MidiMessage::pitch_bend(2, 0x3FFF),
+            MidiMessage::ChannelPressure {
+                channel: 2,
+                pressure: 33,
+            },
+            MidiMessage::PolyPressure {
+                channel: 2,
+                key: 60,
+                pressure: 90,
+            },
+        ];
+        for message in messages {
+            let packets = to_midi2.translate(&message);
+            assert_eq!(to_midi1.translate(&packets[0]), vec![message]);
+        }
+
+        let bank_program = Ump::Midi2ChannelVoice {
+            group: 0,
+            channel: 3,
+            message: Midi2Message::ProgramChange {
+                program: 5,
+                bank: Some((1, 2)),
+            },
+        };
+        assert_eq!(
+            to_midi1.translate(&bank_program),
+            vec![
+                MidiMessage::control_change(3, 0, 1),
+                MidiMessage::control_change(3, 32, 2),
+                MidiMessage::program_change(3, 5),
+            ]
+        );
+        let nrpn = Ump::Midi2ChannelVoice {
+            group: 0,
+            channel: 3,
+            message: Midi2Message::AssignableController {
+                bank: 1,
+                index: 2,
+                value: scale_up(0x1234, 14, 32),
+            },
+        };
+        assert_eq!(
+            to_midi1.translate(&nrpn),
+            vec![
+                MidiMessage::control_change(3, 99, 1),
+                MidiMessage::control_change(3, 98, 2),
+                MidiMessage::control_change(3, 6, 0x24),
+                MidiMessage::control_change(3, 38, 0x34),
+            ]
+        );
+        let quiet = Ump::Midi2ChannelVoice {
+            group: 0,
+            channel: 0,
+            message: Midi2Message::NoteOn {
+                note: 60,
+                velocity: 1,
+                attribute_type: 0,
+                attribute: 0,
+            },
+        };
+        assert_eq!(
+            to_midi1.translate(&quiet),
+            vec![MidiMessage::note_on(0, 60, 1)]
+        );
+        let per_note = Ump::Midi2ChannelVoice {
+            group: 0,
+            channel: 0,
+            message: Midi2Message::PerNotePitchBend { note: 60, value: 0 },
+        };
+        assert!(to_midi1.translate(&per_note).is_empty());
+    }
+}
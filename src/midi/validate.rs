@@ -0,0 +1,645 @@
+//! Structural validation of MIDI files
+//!
+//! `MidiFile::validate` checks an in-memory file against the Standard MIDI
+//! File rules and a few sanity rules, reporting each problem with a
+//! severity and location. `MidiFile::autofix` repairs the problems that
+//! have an unambiguous fix.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use super::event::{MidiEvent, compare_events};
+use super::file::{MidiFile, TickState, TrackState};
+use super::lenient::Severity;
+use super::message::{MetaEvent, MidiMessage};
+use super::track::MidiTrack;
+use super::{MidiFormat, NoteSortOrder};
+
+/// A problem found by `MidiFile::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationKind {
+    /// Tracks use delta or mixed tick timing; everything else assumes
+    /// absolute ticks.
+    NotAbsoluteTicks(TickState),
+    /// Tracks are joined (see `MidiFile::join_tracks`) but there is not
+    /// exactly one track.
+    JoinedTrackCount { tracks: usize },
+    /// A format 0 file has more than one track.
+    MultipleTracksInFormat0 { tracks: usize },
+    /// Events are not in tick order.
+    UnsortedEvents,
+    /// The track has no End-of-Track meta event.
+    MissingEndOfTrack,
+    /// Events (or further End-of-Track events) follow the End-of-Track.
+    EventsAfterEndOfTrack { count: usize },
+    /// A note-on that no note-off ends.
+    UnmatchedNoteOn { channel: u8, key: u8 },
+    /// A note-off with no sounding note to end.
+    UnmatchedNoteOff { channel: u8, key: u8 },
+    /// A note-on ended by a note-off on the same tick.
+    ZeroLengthNote { channel: u8, key: u8 },
+    /// A tempo event outside the first track of a format 1 file.
+    TempoOutsideFirstTrack,
+    /// A meta event that belongs in the first track of a format 1 file
+    /// (time signature, SMPTE offset) found in another track.
+    MisplacedMeta { type_: u8 },
+    /// A meta event that must come before any timed event (sequence number,
+    /// SMPTE offset) at a later tick.
+    LateMeta { type_: u8 },
+    /// A channel message in the conductor (first) track of a format 1 file.
+    ChannelEventInConductorTrack,
+    /// A channel number above 15.
+    InvalidChannel(u8),
+    /// A data byte above 127.
+    DataByteOutOfRange { value: u16 },
+}
+
+impl ValidationKind {
+    /// Severity of this kind of problem
+    pub fn severity(&self) -> Severity {
+        match self {
+            ValidationKind::UnsortedEvents
+            | ValidationKind::MissingEndOfTrack
+            | ValidationKind::UnmatchedNoteOff { .. }
+            | ValidationKind::ZeroLengthNote { .. }
+            | ValidationKind::TempoOutsideFirstTrack
+            | ValidationKind::MisplacedMeta { .. }
+            | ValidationKind::LateMeta { .. }
+            | ValidationKind::ChannelEventInConductorTrack => Severity::Warning,
+            ValidationKind::NotAbsoluteTicks(_)
+            | ValidationKind::JoinedTrackCount { .. }
+            | ValidationKind::MultipleTracksInFormat0 { .. }
+            | ValidationKind::EventsAfterEndOfTrack { .. }
+            | ValidationKind::UnmatchedNoteOn { .. }
+            | ValidationKind::InvalidChannel(_)
+            | ValidationKind::DataByteOutOfRange { .. } => Severity::Error,
+        }
+    }
+}
+
+impl fmt::Display for ValidationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationKind::NotAbsoluteTicks(state) => {
+                write!(f, "tracks use {:?} tick timing, not absolute", state)
+            }
+            ValidationKind::JoinedTrackCount { tracks } => {
+                write!(f, "tracks are joined but there are {} tracks", tracks)
+            }
+            ValidationKind::MultipleTracksInFormat0 { tracks } => {
+                write!(f, "format 0 file has {} tracks", tracks)
+            }
+            ValidationKind::UnsortedEvents => write!(f, "events are not in tick order"),
+            ValidationKind::MissingEndOfTrack => write!(f, "missing End-of-Track"),
+            ValidationKind::EventsAfterEndOfTrack { count } => {
+                write!(f, "{} events after End-of-Track", count)
+            }
+            ValidationKind::UnmatchedNoteOn { channel, key } => {
+                write!(
+                    f,
+                    "note-on without note-off (channel {}, key {})",
+                    channel, key
+                )
+            }
+            ValidationKind::UnmatchedNoteOff { channel, key } => {
+                write!(
+                    f,
+                    "note-off without note-on (channel {}, key {})",
+                    channel, key
+                )
+            }
+            ValidationKind::ZeroLengthNote { channel, key } => {
+                write!(f, "zero-length note (channel {}, key {})", channel, key)
+            }
+            ValidationKind::TempoOutsideFirstTrack => {
+                write!(f, "tempo event outside the first track")
+            }
+            ValidationKind::MisplacedMeta { type_ } => {
+                write!(f, "meta event {:#04x} outside the first track", type_)
+            }
+            ValidationKind::LateMeta { type_ } => {
+                write!(f, "meta event {:#04x} after the start of the track", type_)
+            }
+            ValidationKind::ChannelEventInConductorTrack => {
+                write!(f, "channel message in the conductor track")
+            }
+            ValidationKind::InvalidChannel(channel) => write!(f, "invalid channel {}", channel),
+            ValidationKind::DataByteOutOfRange { value } => {
+                write!(f, "data byte {} above 127", value)
+            }
+        }
+    }
+}
+
+/// A problem reported by `MidiFile::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// Index of the track, if the problem is in one track
+    pub track: Option<usize>,
+    /// Index of the event within the track, if the problem is one event
+    pub event: Option<usize>,
+    /// Tick of that event
+    pub tick: Option<u64>,
+    /// How serious the problem is
+    pub severity: Severity,
+    /// What is wrong
+    pub kind: ValidationKind,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match (self.track, self.event, self.tick) {
+            (Some(track), Some(event), Some(tick)) => write!(
+                f,
+                "{} in track {} at event {} (tick {}): {}",
+                severity, track, event, tick, self.kind
+            ),
+            (Some(track), _, _) => write!(f, "{} in track {}: {}", severity, track, self.kind),
+            _ => write!(f, "{}: {}", severity, self.kind),
+        }
+    }
+}
+
+/// Collects issues for one track.
+struct Issues<'a> {
+    issues: &'a mut Vec<ValidationIssue>,
+    track: usize,
+    events: &'a [MidiEvent],
+}
+
+impl Issues<'_> {
+    fn track(&mut self, kind: ValidationKind) {
+        self.issues.push(ValidationIssue {
+            track: Some(self.track),
+            event: None,
+            tick: None,
+            severity: kind.severity(),
+            kind,
+        });
+    }
+
+    fn event(&mut self, index: usize, kind: ValidationKind) {
+        self.issues.push(ValidationIssue {
+            track: Some(self.track),
+            event: Some(index),
+            tick: Some(self.events[index].tick()),
+            severity: kind.severity(),
+            kind,
+        });
+    }
+}
+
+fn file_issue(kind: ValidationKind) -> ValidationIssue {
+    ValidationIssue {
+        track: None,
+        event: None,
+        tick: None,
+        severity: kind.severity(),
+        kind,
+    }
+}
+
+fn is_end_of_track(event: &MidiEvent) -> bool {
+    matches!(event.message(), MidiMessage::Meta(MetaEvent::EndOfTrack))
+}
+
+/// Channel number of a channel message, unmasked.
+fn raw_channel(message: &MidiMessage) -> Option<u8> {
+    match *message {
+        MidiMessage::NoteOff { channel, .. }
+        | MidiMessage::NoteOn { channel, .. }
+        | MidiMessage::PolyPressure { channel, .. }
+        | MidiMessage::ControlChange { channel, .. }
+        | MidiMessage::ProgramChange { channel, .. }
+        | MidiMessage::ChannelPressure { channel, .. }
+        | MidiMessage::PitchBend { channel, .. } => Some(channel),
+        _ => None,
+    }
+}
+
+/// The largest data byte of a channel or SysEx message, if above 127.
+fn oversized_data(message: &MidiMessage) -> Option<u16> {
+    let largest = match *message {
+        MidiMessage::NoteOff { key, velocity, .. } | MidiMessage::NoteOn { key, velocity, .. } => {
+            key.max(velocity) as u16
+        }
+        MidiMessage::PolyPressure { key, pressure, .. } => key.max(pressure) as u16,
+        MidiMessage::ControlChange {
+            controller, value, ..
+        } => controller.max(value) as u16,
+        MidiMessage::ProgramChange { program, .. } => program as u16,
+        MidiMessage::ChannelPressure { pressure, .. } => pressure as u16,
+        MidiMessage::PitchBend { value, .. } => value >> 7,
+        MidiMessage::SysEx(ref data) => data.iter().copied().max().unwrap_or(0) as u16,
+        _ => 0,
+    };
+    (largest > 0x7F).then_some(largest)
+}
+
+/// Event indices in the order the track will be written.
+fn write_order(events: &[MidiEvent]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..events.len()).collect();
+    order.sort_by(|&a, &b| compare_events(&events[a], &events[b], NoteSortOrder::default()));
+    order
+}
+
+fn validate_track(
+    file: &MidiFile,
+    index: usize,
+    track: &MidiTrack,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    let events = track.events();
+    let mut issues = Issues {
+        issues,
+        track: index,
+        events,
+    };
+
+    if events
+        .windows(2)
+        .any(|pair| pair[1].tick() < pair[0].tick())
+    {
+        issues.track(ValidationKind::UnsortedEvents);
+    }
+
+    let order = write_order(events);
+    match order.iter().position(|&i| is_end_of_track(&events[i])) {
+        None => issues.track(ValidationKind::MissingEndOfTrack),
+        Some(position) if position + 1 < order.len() => issues.event(
+            order[position],
+            ValidationKind::EventsAfterEndOfTrack {
+                count: order.len() - position - 1,
+            },
+        ),
+        Some(_) => {}
+    }
+
+    // Pair notes the way `link_note_events` does.
+    let mut sounding: Vec<VecDeque<usize>> = vec![VecDeque::new(); 16 * 128];
+    for &i in &order {
+        let event = &events[i];
+        let (Some(channel), Some(key)) = (event.channel(), event.key()) else {
+            continue;
+        };
+        let slot = (channel as usize & 0x0F) * 128 + (key as usize & 0x7F);
+        if event.is_note_on() {
+            sounding[slot].push_back(i);
+        } else if event.is_note_off() {
+            match sounding[slot].pop_front() {
+                Some(on) if events[on].tick() == event.tick() => {
+                    issues.event(on, ValidationKind::ZeroLengthNote { channel, key })
+                }
+                Some(_) => {}
+                None => issues.event(i, ValidationKind::UnmatchedNoteOff { channel, key }),
+            }
+        }
+    }
+    let mut unmatched: Vec<usize> = sounding.into_iter().flatten().collect();
+    unmatched.sort_unstable();
+    for on in unmatched {
+        let channel = events[on].channel().unwrap_or(0);
+        let key = events[on].key().unwrap_or(0);
+        issues.event(on, ValidationKind::UnmatchedNoteOn { channel, key });
+    }
+
+    let conductor = file.format() == MidiFormat::MultiTrack && file.num_tracks() > 1;
+    let first_timed_tick = events
+        .iter()
+        .filter(|e| !e.is_meta())
+        .map(MidiEvent::tick)
+        .min();
+    for (i, event) in events.iter().enumerate() {
+        let message = event.message();
+        if let Some(channel) = raw_channel(message) {
+            if channel > 15 {
+                issues.event(i, ValidationKind::InvalidChannel(channel));
+            }
+            if conductor && index == 0 {
+                issues.event(i, ValidationKind::ChannelEventInConductorTrack);
+            }
+        }
+        if let Some(value) = oversized_data(message) {
+            issues.event(i, ValidationKind::DataByteOutOfRange { value });
+        }
+        let MidiMessage::Meta(meta) = message else {
+            continue;
+        };
+        match meta {
+            MetaEvent::Tempo(_) if conductor && index > 0 => {
+                issues.event(i, ValidationKind::TempoOutsideFirstTrack)
+            }
+            MetaEvent::TimeSignature { .. } | MetaEvent::SmpteOffset { .. }
+                if conductor && index > 0 =>
+            {
+                issues.event(
+                    i,
+                    ValidationKind::MisplacedMeta {
+                        type_: meta.type_byte(),
+                    },
+                )
+            }
+            _ => {}
+        }
+        if matches!(
+            meta,
+            MetaEvent::SequenceNumber(_) | MetaEvent::SmpteOffset { .. }
+        ) && (event.tick() > 0 || first_timed_tick.is_some_and(|t| t < event.tick()))
+        {
+            issues.event(
+                i,
+                ValidationKind::LateMeta {
+                    type_: meta.type_byte(),
+                },
+            );
+        }
+    }
+}
+
+/// The message with every data byte masked to 7 bits and the channel to 4.
+fn masked(message: &MidiMessage) -> MidiMessage {
+    match message {
+        MidiMessage::SysEx(data) => MidiMessage::SysEx(data.iter().map(|b| b & 0x7F).collect()),
+        MidiMessage::PitchBend { channel, value } => MidiMessage::PitchBend {
+            channel: channel & 0x0F,
+            value: (*value).min(0x3FFF),
+        },
+        message if raw_channel(message).is_some() => {
+            let mut bytes = message.to_bytes();
+            bytes[0] = (bytes[0] & 0xF0) | (raw_channel(message).unwrap_or(0) & 0x0F);
+            for byte in &mut bytes[1..] {
+                *byte &= 0x7F;
+            }
+            MidiMessage::from_bytes(&bytes).map_or_else(|| message.clone(), |(m, _)| m)
+        }
+        message => message.clone(),
+    }
+}
+
+impl MidiFile {
+    /// Check the file for spec violations and likely mistakes. An empty
+    /// list means nothing was found. Event locations refer to the current
+    /// order of each track's events.
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        let mut issues = Vec::new();
+        let tick_state = self.get_tick_state();
+        if tick_state != TickState::Absolute {
+            issues.push(file_issue(ValidationKind::NotAbsoluteTicks(tick_state)));
+        }
+        if self.track_state() == TrackState::Joined && self.num_tracks() != 1 {
+            issues.push(file_issue(ValidationKind::JoinedTrackCount {
+                tracks: self.num_tracks(),
+            }));
+        }
+        if self.format() == MidiFormat::SingleTrack && self.num_tracks() > 1 {
+            issues.push(file_issue(ValidationKind::MultipleTracksInFormat0 {
+                tracks: self.num_tracks(),
+            }));
+        }
+        for (index, track) in self.tracks().iter().enumerate() {
+            validate_track(self, index, track, &mut issues);
+        }
+        issues
+    }
+
+    /// Repair what can be repaired without guessing, and return the issues
+    /// that were fixed (as reported by `validate` beforehand): tick timing
+    /// is made absolute, joined tracks are split again, events are sorted,
+    /// End-of-Track is moved to the end (or added), tempo, time signature
+    /// and SMPTE offset events move to the first track of a format 1 file,
+    /// unmatched note-offs are removed, unmatched note-ons are ended at the
+    /// end of their track, and out-of-range data bytes are masked to 7
+    /// bits. Call `validate` again for what is left.
+    pub fn autofix(&mut self) -> Vec<ValidationIssue> {
+        let before = self.validate();
+        if before.is_empty() {
+            return before;
+        }
+
+        self.make_absolute_ticks();
+        if self.track_state() == TrackState::Joined {
+            self.split_tracks();
+        }
+        let conductor = self.format() == MidiFormat::MultiTrack && self.num_tracks() > 1;
+
+        // Move the tempo map to the first track.
+        let mut moved = Vec::new();
+        if conductor {
+            for track in self.tracks_mut().iter_mut().skip(1) {
+                track.events_mut().retain(|event| {
+                    let timing = matches!(
+                        event.message(),
+                        MidiMessage::Meta(
+                            MetaEvent::Tempo(_)
+                                | MetaEvent::TimeSignature { .. }
+                                | MetaEvent::SmpteOffset { .. }
+                        )
+                    );
+                    if timing {
+                        moved.push(event.clone());
+                    }
+                    !timing
+                });
+            }
+        }
+
+        for (index, track) in self.tracks_mut().iter_mut().enumerate() {
+            let was_linked = track.events().iter().any(MidiEvent::is_linked);
+            if index == 0 {
+                for mut event in moved.drain(..) {
+                    event.set_seq(0);
+                    track.add_event(event);
+                }
+            }
+            fix_track(track);
+            if was_linked {
+                track.link_note_events();
+            }
+        }
+
+        let after = self.validate();
+        before
+            .into_iter()
+            .filter(|issue| !after.contains(issue))
+            .collect()
+    }
+}
+
+/// Per-track part of `autofix`.
+fn fix_track(track: &mut MidiTrack) {
+    let events = std::mem::take(track.events_mut());
+    let mut events: Vec<MidiEvent> = events
+        .into_iter()
+        .filter(|event| !is_end_of_track(event))
+        .map(|mut event| {
+            let message = masked(event.message());
+            event.set_message(message);
+            event.unlink_event();
+            event
+        })
+        .collect();
+    let order = write_order(&events);
+
+    // Drop unmatched note-offs and end unmatched note-ons.
+    let mut sounding: Vec<VecDeque<usize>> = vec![VecDeque::new(); 16 * 128];
+    let mut dropped = vec![false; events.len()];
+    for &i in &order {
+        let event = &events[i];
+        let (Some(channel), Some(key)) = (event.channel(), event.key()) else {
+            continue;
+        };
+        let slot = channel as usize * 128 + key as usize;
+        if event.is_note_on() {
+            sounding[slot].push_back(i);
+        } else if event.is_note_off() && sounding[slot].pop_front().is_none() {
+            dropped[i] = true;
+        }
+    }
+    let end = events.iter().map(MidiEvent::tick).max().unwrap_or(0);
+    let mut endings = Vec::new();
+    for on in sounding.into_iter().flatten() {
+        let (channel, key) = (
+            events[on].channel().unwrap_or(0),
+            events[on].key().unwrap_or(0),
+        );
+        let mut off = MidiEvent::note_off(end, channel, key, 0);
+        off.set_seq(u32::MAX);
+        endings.push(off);
+    }
+    let mut index = 0;
+    events.retain(|_| {
+        index += 1;
+        !dropped[index - 1]
+    });
+    events.extend(endings);
+
+    *track.events_mut() = events;
+    track.sort();
+    track.add_end_of_track();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(issues: &[ValidationIssue]) -> Vec<&ValidationKind> {
+        issues.iter().map(|issue| &issue.kind).collect()
+    }
+
+    #[test]
+    fn test_validate_clean_file() {
+        let mut file = MidiFile::new();
+        file.add_tracks(2);
+        file.add_tempo(0, 0, 120.0).unwrap();
+        file.add_note(1, 0, 480, 0, 60, 100).unwrap();
+        file.finalize();
+        assert_eq!(file.validate(), vec![]);
+        assert_eq!(file.autofix(), vec![]);
+    }
+
+    #[test]
+    fn test_validate_reports_and_autofix_repairs() {
+        let mut file = MidiFile::new();
+        file.add_tracks(2);
+        file.add_note(0, 0, 480, 0, 60, 100).unwrap();
+        file.add_tempo(1, 0, 100.0).unwrap();
+        let track = &mut file.tracks_mut()[1];
+        track.add_event(MidiEvent::note_on(10, 1, 62, 90));
+        track.add_event(MidiEvent::note_off(20, 1, 64, 0));
+        track.add_note(30, 0, 1, 65, 90);
+        track.add_event(MidiEvent::new(
+            40,
+            MidiMessage::ControlChange {
+                channel: 1,
+                controller: 7,
+                value: 200,
+            },
+        ));
+        track.add_end_of_track();
+        track.add_event(MidiEvent::note_on(50, 1, 66, 90));
+        track.add_event(MidiEvent::note_off(60, 1, 66, 0));
+
+        let issues = file.validate();
+        let found = kinds(&issues);
+        for expected in [
+            ValidationKind::MissingEndOfTrack,
+            ValidationKind::ChannelEventInConductorTrack,
+            ValidationKind::TempoOutsideFirstTrack,
+            ValidationKind::UnmatchedNoteOn {
+                channel: 1,
+                key: 62,
+            },
+            ValidationKind::UnmatchedNoteOff {
+                channel: 1,
+                key: 64,
+            },
+            ValidationKind::ZeroLengthNote {
+                channel: 1,
+                key: 65,
+            },
+            ValidationKind::DataByteOutOfRange { value: 200 },
+            ValidationKind::EventsAfterEndOfTrack { count: 2 },
+        ] {
+            assert!(found.contains(&&expected), "missing {expected:?}");
+        }
+        let off = issues
+            .iter()
+            .find(|i| matches!(i.kind, ValidationKind::UnmatchedNoteOff { .. }))
+            .unwrap();
+        assert_eq!(
+            (off.track, off.event, off.tick),
+            (Some(1), Some(2), Some(20))
+        );
+        assert_eq!(off.severity, Severity::Warning);
+        assert_eq!(
+            off.to_string(),
+            "warning in track 1 at event 2 (tick 20): note-off without note-on (channel 1, key 64)"
+        );
+
+        let fixed = file.autofix();
+        assert!(!fixed.is_empty());
+        let remaining = file.validate();
+        assert_eq!(
+            kinds(&remaining),
+            vec![
+                &ValidationKind::ChannelEventInConductorTrack,
+                &ValidationKind::ChannelEventInConductorTrack,
+                &ValidationKind::ZeroLengthNote {
+                    channel: 1,
+                    key: 65
+                },
+            ]
+        );
+        assert!(file.tracks()[0].tempo_events().next().is_some());
+        let last = file.tracks()[1].events().last().unwrap();
+        assert!(is_end_of_track(last));
+        assert_eq!(last.tick(), 60);
+    }
+
+    #[test]
+    fn test_validate_file_level_state() {
+        let mut file = MidiFile::with_format(MidiFormat::SingleTrack, 96);
+        file.add_tracks(2);
+        file.finalize();
+        file.tracks_mut()[0].make_delta_times();
+        let issues = file.validate();
+        assert_eq!(
+            issues[0].kind,
+            ValidationKind::NotAbsoluteTicks(TickState::Mixed)
+        );
+        assert_eq!(issues[0].track, None);
+        assert_eq!(
+            issues[1].kind,
+            ValidationKind::MultipleTracksInFormat0 { tracks: 2 }
+        );
+        file.autofix();
+        assert_eq!(
+            kinds(&file.validate()),
+            vec![&ValidationKind::MultipleTracksInFormat0 { tracks: 2 }]
+        );
+    }
+}
@@ -1,109 +1,692 @@
 //! ALSA implementation for Linux
+//!
+//! Uses the ALSA sequencer API, following upstream RtMidi's `MidiInAlsa`/
+//! `MidiOutAlsa`: every `AlsaMidiInput`/`AlsaMidiOutput` opens its own
+//! sequencer client, creates a single application port, and either
+//! subscribes that port to/from an existing port (`open_port`) or leaves it
+//! open for other clients to connect to (`open_virtual_port`).
 
-use super::RtMidiError;
-use super::port::MidiPort;
+use std::ffi::CString;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
 
-/// Get available MIDI input ports
+use alsa::PollDescriptors;
+use alsa::seq::{
+    Addr, ClientIter, Event, EventType, MidiEvent, PortCap, PortInfo, PortIter, PortSubscribe,
+    PortType, Seq,
+};
+
+use super::input_data::InputData;
+use super::port::{Api, MidiPort};
+use super::{MidiCallback, RtMidiError, RtMidiErrorCallback};
+
+/// How long the input thread blocks in `poll` before re-checking whether it
+/// has been asked to stop.
+const INPUT_POLL_TIMEOUT_MS: i32 = 50;
+
+/// Size of the buffer used to decode a single sequencer event back into raw
+/// MIDI bytes. Sysex arrives as variable-length events and bypasses it.
+const DECODE_BUFFER_SIZE: usize = 32;
+
+/// Get available MIDI input ports (ports we can read from)
 pub fn get_input_ports() -> Vec<MidiPort> {
-    // ALSA implementation would enumerate sequencer clients
-    vec![]
+    list_ports(PortCap::READ | PortCap::SUBS_READ)
 }
 
-/// Get available MIDI output ports
+/// Get available MIDI output ports (ports we can write to)
 pub fn get_output_ports() -> Vec<MidiPort> {
-    vec![]
+    list_ports(PortCap::WRITE | PortCap::SUBS_WRITE)
+}
+
+fn list_ports(caps: PortCap) -> Vec<MidiPort> {
+    let Ok(seq) = Seq::open(None, None, true) else {
+        return vec![];
+    };
+    enumerate_ports(&seq, caps)
+        .into_iter()
+        .enumerate()
+        .map(|(i, (_, name))| MidiPort::new(i, name, Api::Alsa))
+        .collect()
+}
+
+/// Enumerate every MIDI port with the given capabilities, in the same order
+/// `get_input_ports`/`get_output_ports` report them. Skips the system client
+/// (timer/announce) and ports that are neither generic MIDI, synth, nor
+/// application ports, matching upstream RtMidi's `portInfo`.
+fn enumerate_ports(seq: &Seq, caps: PortCap) -> Vec<(Addr, String)> {
+    let mut ports = Vec::new();
+    for client in ClientIter::new(seq) {
+        let client_id = client.get_client();
+        if client_id == 0 {
+            continue;
+        }
+        let client_name = client.get_name().unwrap_or("").to_string();
+
+        for port in PortIter::new(seq, client_id) {
+            let port_type = port.get_type();
+            if !port_type
+                .intersects(PortType::MIDI_GENERIC | PortType::SYNTH | PortType::APPLICATION)
+            {
+                continue;
+            }
+            if !port.get_capability().contains(caps) {
+                continue;
+            }
+            let addr = port.addr();
+            let name = format!(
+                "{}:{} {}:{}",
+                client_name,
+                port.get_name().unwrap_or(""),
+                addr.client,
+                addr.port
+            );
+            ports.push((addr, name));
+        }
+    }
+    ports
+}
+
+fn driver_error(context: &str, err: alsa::Error) -> RtMidiError {
+    RtMidiError::DriverError(format!("{}: {}", context, err))
+}
+
+fn c_string(name: &str) -> Result<CString, RtMidiError> {
+    CString::new(name).map_err(|_| RtMidiError::DriverError(format!("invalid name: {:?}", name)))
+}
+
+/// Open a sequencer client with the given name.
+fn open_client(client_name: &str) -> Result<Seq, RtMidiError> {
+    let seq = Seq::open(None, None, true)
+        .map_err(|e| driver_error("Failed to open ALSA sequencer", e))?;
+    seq.set_client_name(&c_string(client_name)?)
+        .map_err(|e| driver_error("Failed to set client name", e))?;
+    Ok(seq)
+}
+
+/// Create an application port on `seq` with the given capabilities,
+/// optionally timestamped in real time against `queue`.
+fn create_port(
+    seq: &Seq,
+    port_name: &str,
+    caps: PortCap,
+    queue: Option<i32>,
+) -> Result<i32, RtMidiError> {
+    let mut info = PortInfo::empty().map_err(|e| driver_error("Failed to create port", e))?;
+    info.set_name(&c_string(port_name)?);
+    info.set_capability(caps);
+    info.set_type(PortType::MIDI_GENERIC | PortType::APPLICATION);
+    info.set_midi_channels(16);
+    if let Some(queue) = queue {
+        info.set_timestamping(true);
+        info.set_timestamp_real(true);
+        info.set_timestamp_queue(queue);
+    }
+    seq.create_port(&info)
+        .map_err(|e| driver_error("Failed to create port", e))?;
+    Ok(info.get_port())
+}
+
+/// Rename a port previously created with `create_port`.
+fn rename_port(seq: &Seq, port: i32, name: &str) -> Result<(), RtMidiError> {
+    let client = seq
+        .client_id()
+        .map_err(|e| driver_error("Failed to rename port", e))?;
+    let mut info = seq
+        .get_any_port_info(Addr { client, port })
+        .map_err(|e| driver_error("Failed to rename port", e))?;
+    info.set_name(&c_string(name)?);
+    seq.set_port_info(port, &mut info)
+        .map_err(|e| driver_error("Failed to rename port", e))
+}
+
+fn subscribe(seq: &Seq, sender: Addr, dest: Addr, queue: Option<i32>) -> Result<(), RtMidiError> {
+    let sub = PortSubscribe::empty().map_err(|e| driver_error("Failed to subscribe", e))?;
+    sub.set_sender(sender);
+    sub.set_dest(dest);
+    if let Some(queue) = queue {
+        sub.set_queue(queue);
+        sub.set_time_update(true);
+        sub.set_time_real(true);
+    }
+    seq.subscribe_port(&sub)
+        .map_err(|e| driver_error("Failed to subscribe", e))
 }
 
 /// ALSA MIDI input handler
 pub struct AlsaMidiInput {
-    // ALSA sequencer handle would go here
+    seq: Arc<Mutex<Seq>>,
+    /// Our own application port (the destination of the subscription).
+    port: Option<i32>,
+    /// The port we subscribed to, if this is not a virtual port.
+    connected: Option<Addr>,
+    /// Queue used to timestamp incoming events in real time.
+    queue: i32,
+    callback_data: Arc<Mutex<InputData>>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
 }
 
 impl AlsaMidiInput {
     /// Create a new ALSA MIDI input
     pub fn new(client_name: &str) -> Result<Self, RtMidiError> {
-        let _ = client_name;
-        // Would create ALSA sequencer client here
-        Ok(Self {})
+        let seq = open_client(client_name)?;
+        let queue = seq
+            .alloc_queue()
+            .map_err(|e| driver_error("Failed to allocate queue", e))?;
+
+        Ok(Self {
+            seq: Arc::new(Mutex::new(seq)),
+            port: None,
+            connected: None,
+            queue,
+            callback_data: Arc::new(Mutex::new(InputData::new())),
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+        })
     }
 
     /// Open a MIDI input port
     pub fn open_port(&mut self, port_index: usize, port_name: &str) -> Result<(), RtMidiError> {
-        let _ = (port_index, port_name);
-        Ok(())
+        {
+            let seq = self.seq.lock().unwrap();
+            let (source, _) = enumerate_ports(&seq, PortCap::READ | PortCap::SUBS_READ)
+                .into_iter()
+                .nth(port_index)
+                .ok_or(RtMidiError::InvalidPort(port_index))?;
+
+            let port = create_port(
+                &seq,
+                port_name,
+                PortCap::WRITE | PortCap::SUBS_WRITE,
+                Some(self.queue),
+            )?;
+            let client = seq
+                .client_id()
+                .map_err(|e| driver_error("Failed to query client id", e))?;
+            if let Err(e) = subscribe(&seq, source, Addr { client, port }, Some(self.queue)) {
+                let _ = seq.delete_port(port);
+                return Err(e);
+            }
+
+            self.port = Some(port);
+            self.connected = Some(source);
+        }
+
+        self.start_input_thread()
     }
 
-    /// Create a virtual MIDI input port
+    /// Create a virtual MIDI input port. Other clients can connect to it
+    /// and send MIDI; incoming data goes through the same filter/callback/
+    /// queue path as a real port.
     pub fn open_virtual_port(&mut self, port_name: &str) -> Result<(), RtMidiError> {
-        let _ = port_name;
+        {
+            let seq = self.seq.lock().unwrap();
+            let port = create_port(
+                &seq,
+                port_name,
+                PortCap::WRITE | PortCap::SUBS_WRITE,
+                Some(self.queue),
+            )?;
+            self.port = Some(port);
+        }
+
+        self.start_input_thread()
+    }
+
+    /// Start the timestamp queue and the thread that drains incoming
+    /// sequencer events.
+    fn start_input_thread(&mut self) -> Result<(), RtMidiError> {
+        {
+            let seq = self.seq.lock().unwrap();
+            seq.control_queue(self.queue, EventType::Start, 0, None)
+                .map_err(|e| driver_error("Failed to start queue", e))?;
+            seq.drain_output()
+                .map_err(|e| driver_error("Failed to start queue", e))?;
+        }
+
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.reset_time();
+        }
+
+        self.stop.store(false, Ordering::SeqCst);
+        let seq = Arc::clone(&self.seq);
+        let data = Arc::clone(&self.callback_data);
+        let stop = Arc::clone(&self.stop);
+        let thread = std::thread::Builder::new()
+            .name("mkmidilibrary-alsa-input".to_string())
+            .spawn(move || input_thread(seq, data, stop))
+            .map_err(|e| RtMidiError::ThreadError(e.to_string()))?;
+        self.thread = Some(thread);
         Ok(())
     }
 
     /// Close the currently open port
-    pub fn close_port(&mut self) {}
+    pub fn close_port(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        let seq = self.seq.lock().unwrap();
+        if let Some(port) = self.port.take() {
+            if let (Some(source), Ok(client)) = (self.connected.take(), seq.client_id()) {
+                let _ = seq.unsubscribe_port(source, Addr { client, port });
+            }
+            let _ = seq.delete_port(port);
+        }
+        let _ = seq.control_queue(self.queue, EventType::Stop, 0, None);
+        let _ = seq.drain_output();
+    }
+
+    /// Rename the underlying sequencer client
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), RtMidiError> {
+        let seq = self.seq.lock().unwrap();
+        seq.set_client_name(&c_string(name)?)
+            .map_err(|e| driver_error("Failed to rename client", e))
+    }
+
+    /// Rename the currently open port
+    pub fn set_port_name(&mut self, name: &str) -> Result<(), RtMidiError> {
+        let port = self.port.ok_or(RtMidiError::PortNotOpen)?;
+        let seq = self.seq.lock().unwrap();
+        rename_port(&seq, port, name)
+    }
 
     /// Set a callback for incoming messages
-    pub fn set_callback<F>(&mut self, _callback: F)
+    pub fn set_callback<F>(&mut self, callback: F)
     where
         F: FnMut(f64, &[u8]) + Send + 'static,
     {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.callback = Some(Box::new(callback));
+        }
     }
 
     /// Cancel the callback
-    pub fn cancel_callback(&mut self) {}
+    pub fn cancel_callback(&mut self) {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.callback = None;
+        }
+    }
 
-    /// Get a message from the queue
+    /// Remove and return the message and error callbacks
+    pub(crate) fn take_callbacks(&mut self) -> (Option<MidiCallback>, Option<RtMidiErrorCallback>) {
+        self.callback_data
+            .lock()
+            .map(|mut data| (data.callback.take(), data.error_callback.take()))
+            .unwrap_or((None, None))
+    }
+
+    /// Get a message from the queue (when not using callback)
     pub fn get_message(&mut self) -> Option<(f64, Vec<u8>)> {
-        None
+        self.callback_data
+            .lock()
+            .ok()
+            .and_then(|mut data| data.queue.pop_front())
     }
 
     /// Set message type filtering
-    pub fn ignore_types(&mut self, _sysex: bool, _timing: bool, _active_sensing: bool) {}
+    pub fn ignore_types(&mut self, sysex: bool, timing: bool, active_sensing: bool) {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.ignore_sysex = sysex;
+            data.ignore_timing = timing;
+            data.ignore_active_sensing = active_sensing;
+        }
+    }
 
     /// Set the maximum number of queued messages before incoming messages
     /// are dropped.
-    pub fn set_queue_size_limit(&mut self, _limit: usize) {}
+    pub fn set_queue_size_limit(&mut self, limit: usize) {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.queue_size_limit = limit;
+        }
+    }
 
-    /// Register a callback for non-fatal warnings.
-    pub fn set_error_callback<F>(&mut self, _callback: F)
+    /// Register a callback for non-fatal warnings (see
+    /// `RtMidiError::Warning`/`DebugWarning`), such as a dropped message when
+    /// the polling queue is full or an input overrun in the sequencer.
+    pub fn set_error_callback<F>(&mut self, callback: F)
     where
         F: FnMut(&RtMidiError) + Send + 'static,
     {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.error_callback = Some(Box::new(callback));
+        }
     }
 
     /// Remove any registered error callback.
-    pub fn cancel_error_callback(&mut self) {}
+    pub fn cancel_error_callback(&mut self) {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.error_callback = None;
+        }
+    }
+}
+
+impl Drop for AlsaMidiInput {
+    fn drop(&mut self) {
+        self.close_port();
+        if let Ok(seq) = self.seq.lock() {
+            let _ = seq.free_queue(self.queue);
+        }
+    }
+}
+
+/// Receive loop: waits for sequencer input without holding the `Seq` lock
+/// (so the owning thread can still create ports or change filters), then
+/// drains every pending event and hands complete messages to `InputData`.
+fn input_thread(seq: Arc<Mutex<Seq>>, data: Arc<Mutex<InputData>>, stop: Arc<AtomicBool>) {
+    let mut fds = {
+        let seq = seq.lock().unwrap();
+        match (&*seq, Some(alsa::Direction::Capture)).get() {
+            Ok(fds) => fds,
+            Err(e) => {
+                if let Ok(mut data) = data.lock() {
+                    data.warn(RtMidiError::DriverError(format!(
+                        "Failed to get poll descriptors: {}",
+                        e
+                    )));
+                }
+                return;
+            }
+        }
+    };
+
+    let decoder = match MidiEvent::new(DECODE_BUFFER_SIZE as u32) {
+        Ok(decoder) => decoder,
+        Err(e) => {
+            if let Ok(mut data) = data.lock() {
+                data.warn(driver_error("Failed to create event decoder", e));
+            }
+            return;
+        }
+    };
+    decoder.enable_running_status(false);
+
+    let mut buffer = [0u8; DECODE_BUFFER_SIZE];
+    // Large sysex messages arrive split across several sequencer events;
+    // they are accumulated here until the terminating 0xF7 shows up.
+    let mut sysex: Vec<u8> = Vec::new();
+
+    while !stop.load(Ordering::SeqCst) {
+        match alsa::poll::poll(&mut fds, INPUT_POLL_TIMEOUT_MS) {
+            Ok(0) => continue,
+            Ok(_) => {}
+            Err(e) => {
+                if e.errno() == libc::EINTR {
+                    continue;
+                }
+                if let Ok(mut data) = data.lock() {
+                    data.warn(driver_error("poll failed", e));
+                }
+                break;
+            }
+        }
+
+        let seq = seq.lock().unwrap();
+        let mut input = seq.input();
+        loop {
+            match input.event_input_pending(true) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {}
+            }
+
+            let mut event = match input.event_input() {
+                Ok(event) => event,
+                Err(e) => {
+                    let kind = std::io::Error::from_raw_os_error(e.errno()).kind();
+                    if kind == ErrorKind::WouldBlock {
+                        break;
+                    }
+                    if let Ok(mut data) = data.lock() {
+                        if kind == ErrorKind::StorageFull {
+                            // ENOSPC: the sequencer's input pool overran
+                            // and events were lost.
+                            data.warn(RtMidiError::Warning(
+                                "ALSA input overrun, events were dropped".to_string(),
+                            ));
+                        } else {
+                            data.warn(RtMidiError::DebugWarning(format!(
+                                "Failed to read ALSA event: {}",
+                                e
+                            )));
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            let time = event.get_time().map(|t| t.as_secs_f64()).unwrap_or(0.0);
+            let message = match event.get_type() {
+                // Subscription bookkeeping, not MIDI data.
+                EventType::PortSubscribed
+                | EventType::PortUnsubscribed
+                | EventType::PortStart
+                | EventType::PortExit
+                | EventType::PortChange
+                | EventType::ClientStart
+                | EventType::ClientExit
+                | EventType::ClientChange => continue,
+                EventType::Sysex => {
+                    let Some(chunk) = event.get_ext() else {
+                        continue;
+                    };
+                    if chunk.first() == Some(&0xF0) {
+                        sysex.clear();
+                    }
+                    sysex.extend_from_slice(chunk);
+                    if sysex.last() != Some(&0xF7) {
+                        continue;
+                    }
+                    std::mem::take(&mut sysex)
+                }
+                _ => match decoder.decode(&mut buffer, &mut event) {
+                    Ok(len) if len > 0 => buffer[..len].to_vec(),
+                    Ok(_) => continue,
+                    Err(e) => {
+                        if let Ok(mut data) = data.lock() {
+                            data.warn(RtMidiError::DebugWarning(format!(
+                                "Failed to decode ALSA event: {}",
+                                e
+                            )));
+                        }
+                        continue;
+                    }
+                },
+            };
+
+            if let Ok(mut data) = data.lock() {
+                data.deliver(time, &message);
+            }
+        }
+    }
 }
 
 /// ALSA MIDI output handler
 pub struct AlsaMidiOutput {
-    // ALSA sequencer handle would go here
+    seq: Seq,
+    /// Our own application port (the sender of the subscription).
+    port: Option<i32>,
+    /// The port we subscribed to, if this is not a virtual port.
+    connected: Option<Addr>,
 }
 
 impl AlsaMidiOutput {
     /// Create a new ALSA MIDI output
     pub fn new(client_name: &str) -> Result<Self, RtMidiError> {
-        let _ = client_name;
-        Ok(Self {})
+        Ok(Self {
+            seq: open_client(client_name)?,
+            port: None,
+            connected: None,
+        })
     }
 
     /// Open a MIDI output port
     pub fn open_port(&mut self, port_index: usize, port_name: &str) -> Result<(), RtMidiError> {
-        let _ = (port_index, port_name);
+        let (dest, _) = enumerate_ports(&self.seq, PortCap::WRITE | PortCap::SUBS_WRITE)
+            .into_iter()
+            .nth(port_index)
+            .ok_or(RtMidiError::InvalidPort(port_index))?;
+
+        let port = create_port(
+            &self.seq,
+            port_name,
+            PortCap::READ | PortCap::SUBS_READ,
+            None,
+        )?;
+        let client = self
+            .seq
+            .client_id()
+            .map_err(|e| driver_error("Failed to query client id", e))?;
+        if let Err(e) = subscribe(&self.seq, Addr { client, port }, dest, None) {
+            let _ = self.seq.delete_port(port);
+            return Err(e);
+        }
+
+        self.port = Some(port);
+        self.connected = Some(dest);
         Ok(())
     }
 
-    /// Create a virtual MIDI output port
+    /// Create a virtual MIDI output port. Other clients can subscribe to it
+    /// to receive what we send.
     pub fn open_virtual_port(&mut self, port_name: &str) -> Result<(), RtMidiError> {
-        let _ = port_name;
+        let port = create_port(
+            &self.seq,
+            port_name,
+            PortCap::READ | PortCap::SUBS_READ,
+            None,
+        )?;
+        self.port = Some(port);
         Ok(())
     }
 
     /// Close the currently open port
-    pub fn close_port(&mut self) {}
+    pub fn close_port(&mut self) {
+        if let Some(port) = self.port.take() {
+            if let (Some(dest), Ok(client)) = (self.connected.take(), self.seq.client_id()) {
+                let _ = self.seq.unsubscribe_port(Addr { client, port }, dest);
+            }
+            let _ = self.seq.delete_port(port);
+        }
+    }
 
-    /// Send a MIDI message
-    pub fn send_message(&mut self, _message: &[u8]) -> Result<(), RtMidiError> {
+    /// Rename the underlying sequencer client
+    pub fn set_client_name(&mut self, name: &str) -> Result<(), RtMidiError> {
+        self.seq
+            .set_client_name(&c_string(name)?)
+            .map_err(|e| driver_error("Failed to rename client", e))
+    }
+
+    /// Rename the currently open port
+    pub fn set_port_name(&mut self, name: &str) -> Result<(), RtMidiError> {
+        let port = self.port.ok_or(RtMidiError::PortNotOpen)?;
+        rename_port(&self.seq, port, name)
+    }
+
+    /// Send a MIDI message to every subscriber of our port
+    pub fn send_message(&mut self, message: &[u8]) -> Result<(), RtMidiError> {
+        let port = self.port.ok_or(RtMidiError::PortNotOpen)?;
+
+        // The encoder needs room for the whole message (sysex included). It
+        // is created per call rather than stored, which keeps this type
+        // `Send` (`snd_midi_event_t` is not thread-safe to share).
+        let mut encoder = MidiEvent::new(message.len().max(DECODE_BUFFER_SIZE) as u32)
+            .map_err(|e| driver_error("Failed to create event encoder", e))?;
+        encoder.enable_running_status(false);
+
+        let mut pos = 0;
+        while pos < message.len() {
+            let (consumed, event) = encoder
+                .encode(&message[pos..])
+                .map_err(|e| driver_error("Failed to encode message", e))?;
+            if consumed == 0 {
+                return Err(RtMidiError::InvalidMessage);
+            }
+            pos += consumed;
+
+            if let Some(event) = event {
+                let mut event: Event<'static> = event.into_owned();
+                event.set_source(port);
+                event.set_subs();
+                event.set_direct();
+                self.seq
+                    .event_output_direct(&mut event)
+                    .map_err(|e| driver_error("Failed to send message", e))?;
+            }
+        }
+
+        self.seq
+            .drain_output()
+            .map_err(|e| driver_error("Failed to send message", e))?;
         Ok(())
     }
 }
+
+impl Drop for AlsaMidiOutput {
+    fn drop(&mut self) {
+        self.close_port();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_get_ports() {
+        // Just verifies enumeration doesn't panic, with or without a
+        // sequencer.
+        let inputs = get_input_ports();
+        let outputs = get_output_ports();
+        for port in inputs.iter().chain(outputs.iter()) {
+            assert_eq!(port.api(), Api::Alsa);
+        }
+    }
+
+    #[test]
+    fn test_alsa_virtual_port_loopback() {
+        // Needs a sequencer device, which containers often lack.
+        if !std::path::Path::new("/dev/snd/seq").exists() {
+            return;
+        }
+        let mut input = AlsaMidiInput::new("mkmidilibrary-test-input").unwrap();
+        let port_name = "mkmidilibrary-test-virtual-port";
+        input.open_virtual_port(port_name).unwrap();
+        input.ignore_types(false, true, true);
+
+        let ports = get_output_ports();
+        let index = ports
+            .iter()
+            .position(|p| p.name().contains(port_name))
+            .expect("virtual port is listed");
+
+        let mut output = AlsaMidiOutput::new("mkmidilibrary-test-output").unwrap();
+        output.open_port(index, "conn").unwrap();
+        output.send_message(&[0x90, 60, 100]).unwrap();
+        output.send_message(&[0xF8]).unwrap(); // filtered as timing
+        output
+            .send_message(&[0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7])
+            .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        let mut received = Vec::new();
+        while received.len() < 2 && Instant::now() < deadline {
+            if let Some((_, msg)) = input.get_message() {
+                received.push(msg);
+            } else {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        assert_eq!(
+            received,
+            vec![
+                vec![0x90, 60, 100],
+                vec![0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7]
+            ]
+        );
+    }
+}
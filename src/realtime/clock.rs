@@ -0,0 +1,599 @@
+//! MIDI clock and transport sync
+//!
+//! `MidiClockMaster` drives external gear: it sends Timing Clock at 24
+//! pulses per quarter note, following a fixed tempo or a `TempoMap`, plus
+//! Start, Stop, Continue and Song Position Pointer on transport changes.
+//! `MidiClockFollower` does the reverse, tracking the transport state, song
+//! position and a smoothed tempo from an incoming clock stream.
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::RtMidiError;
+use super::input::MidiInput;
+use super::output::MidiOutput;
+use super::player::raise_thread_priority;
+use crate::midi::{MidiMessage, TempoMap, TimeDivision};
+
+/// Timing Clock pulses per quarter note
+pub const CLOCKS_PER_QUARTER: u64 = 24;
+/// Timing Clock pulses per MIDI beat (a sixteenth note), the unit of Song
+/// Position Pointer
+pub const CLOCKS_PER_MIDI_BEAT: u64 = 6;
+/// Largest Song Position Pointer value (14 bits)
+const MAX_SONG_POSITION: u64 = 0x3FFF;
+/// Longest pulse interval still counted as a running clock (2.5 BPM)
+const MAX_CLOCK_INTERVAL: f64 = 1.0;
+/// Tempo of a master created with an unusable BPM
+const DEFAULT_BPM: f64 = 120.0;
+/// Wait for a pulse whose time cannot be represented (e.g. after a tempo
+/// of zero in a tempo map); it is recomputed whenever the transport changes
+const FAR_FUTURE: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Whether `bpm` is a usable tempo
+fn is_valid_bpm(bpm: f64) -> bool {
+    bpm.is_finite() && bpm > 0.0
+}
+
+/// A constant-tempo map
+fn constant_tempo(bpm: f64) -> TempoMap {
+    let mut map = TempoMap::new(TimeDivision::Ppqn(960));
+    map.set_tempo(0, bpm);
+    map
+}
+
+/// Time of clock pulse `clock` on `map`, in seconds
+fn clock_seconds(map: &TempoMap, clock: u64) -> f64 {
+    let ticks_per_quarter = map.time_division().ticks_per_quarter().max(1) as u64;
+    let tick = clock * ticks_per_quarter / CLOCKS_PER_QUARTER;
+    let remainder = clock * ticks_per_quarter % CLOCKS_PER_QUARTER;
+    let seconds = map.ticks_to_seconds(tick);
+    if remainder == 0 {
+        return seconds;
+    }
+    let fraction = remainder as f64 / CLOCKS_PER_QUARTER as f64;
+    seconds + (map.ticks_to_seconds(tick + 1) - seconds) * fraction
+}
+
+/// Clock state shared by `MidiClockMaster` and its thread.
+struct MasterTransport {
+    output: Option<MidiOutput>,
+    tempo_map: TempoMap,
+    running: bool,
+    /// Index of the next clock pulse, counted from song position 0
+    position: u64,
+    /// A clock pulse and the instant it is (or was) due; later pulses are
+    /// timed from it
+    anchor: (u64, Instant),
+    error: Option<RtMidiError>,
+    shutdown: bool,
+}
+
+impl MasterTransport {
+    fn send(&mut self, message: &MidiMessage) {
+        let Some(output) = self.output.as_mut() else {
+            return;
+        };
+        if let Err(error) = output.send_midi_message(message)
+            && self.error.is_none()
+        {
+            self.error = Some(error);
+        }
+    }
+
+    /// When clock pulse `clock` is due
+    fn due(&self, clock: u64) -> Instant {
+        let (anchor_clock, anchor_time) = self.anchor;
+        let offset =
+            clock_seconds(&self.tempo_map, clock) - clock_seconds(&self.tempo_map, anchor_clock);
+        // A NaN, infinite or huge offset fails the conversion.
+        let offset = if offset <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::try_from_secs_f64(offset).unwrap_or(FAR_FUTURE)
+        };
+        anchor_time
+            .checked_add(offset)
+            .unwrap_or(anchor_time + FAR_FUTURE)
+    }
+
+    fn send_song_position(&mut self) {
+        let beats = (self.position / CLOCKS_PER_MIDI_BEAT).min(MAX_SONG_POSITION);
+        self.send(&MidiMessage::SongPosition(beats as u16));
+    }
+}
+
+struct MasterShared {
+    transport: Mutex<MasterTransport>,
+    changed: Condvar,
+}
+
+impl MasterShared {
+    fn lock(&self) -> MutexGuard<'_, MasterTransport> {
+        self.transport.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Sends MIDI clock and transport messages through a `MidiOutput`
+///
+/// Clock pulses are sent from a dedicated thread only while running.
+/// Dropping the master (or `into_output`) sends Stop if it was running.
+pub struct MidiClockMaster {
+    shared: Arc<MasterShared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MidiClockMaster {
+    /// Create a stopped master at a constant `bpm` (120 BPM if `bpm` is
+    /// not finite and positive)
+    pub fn new(output: MidiOutput, bpm: f64) -> Result<Self, RtMidiError> {
+        let bpm = if is_valid_bpm(bpm) { bpm } else { DEFAULT_BPM };
+        Self::with_tempo_map(output, constant_tempo(bpm))
+    }
+
+    /// Create a stopped master following `tempo_map` (which should use a
+    /// PPQN division)
+    pub fn with_tempo_map(output: MidiOutput, tempo_map: TempoMap) -> Result<Self, RtMidiError> {
+        let shared = Arc::new(MasterShared {
+            transport: Mutex::new(MasterTransport {
+                output: Some(output),
+                tempo_map,
+                running: false,
+                position: 0,
+                anchor: (0, Instant::now()),
+                error: None,
+                shutdown: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let thread = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("mkmidilibrary-clock".to_string())
+                .spawn(move || {
+                    raise_thread_priority();
+                    clock_thread(&shared);
+                })
+                .map_err(|e| RtMidiError::ThreadError(e.to_string()))?
+        };
+        Ok(Self {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    fn update<T>(&self, change: impl FnOnce(&mut MasterTransport) -> T) -> T {
+        let mut transport = self.shared.lock();
+        let result = change(&mut transport);
+        self.shared.changed.notify_all();
+        result
+    }
+
+    /// Send Start and run from song position 0
+    pub fn start(&self) {
+        self.update(|transport| {
+            transport.send(&MidiMessage::Start);
+            transport.position = 0;
+            transport.anchor = (0, Instant::now());
+            transport.running = true;
+        });
+    }
+
+    /// Send Stop and stop the clock, keeping the position
+    pub fn stop(&self) {
+        self.update(|transport| {
+            if transport.running {
+                transport.running = false;
+                transport.send(&MidiMessage::Stop);
+            }
+        });
+    }
+
+    /// Send Continue and run from the current position
+    pub fn resume(&self) {
+        self.update(|transport| {
+            if !transport.running {
+                transport.send(&MidiMessage::Continue);
+                transport.anchor = (transport.position, Instant::now());
+                transport.running = true;
+            }
+        });
+    }
+
+    /// Move to `midi_beats` sixteenth notes from the start and send it as
+    /// Song Position Pointer. While running, the clock stops for the jump
+    /// and continues from the new position.
+    pub fn set_song_position(&self, midi_beats: u16) {
+        self.update(|transport| {
+            let running = transport.running;
+            if running {
+                transport.send(&MidiMessage::Stop);
+            }
+            transport.position = (midi_beats as u64).min(MAX_SONG_POSITION) * CLOCKS_PER_MIDI_BEAT;
+            transport.send_song_position();
+            if running {
+                transport.send(&MidiMessage::Continue);
+                transport.anchor = (transport.position, Instant::now());
+            }
+        });
+    }
+
+    /// Move to the MIDI beat at or before `tick` of the tempo map (see
+    /// `set_song_position`)
+    pub fn seek_ticks(&self, tick: u64) {
+        let ticks_per_quarter = self
+            .shared
+            .lock()
+            .tempo_map
+            .time_division()
+            .ticks_per_quarter()
+            .max(1) as u64;
+        let beats = tick * (CLOCKS_PER_QUARTER / CLOCKS_PER_MIDI_BEAT) / ticks_per_quarter;
+        self.set_song_position(beats.min(MAX_SONG_POSITION) as u16);
+    }
+
+    /// Switch to a constant `bpm`; the next pulse is timed from the last.
+    /// A `bpm` that is not finite and positive is ignored.
+    pub fn set_bpm(&self, bpm: f64) {
+        if is_valid_bpm(bpm) {
+            self.set_tempo_map(constant_tempo(bpm));
+        }
+    }
+
+    /// Switch to `tempo_map`; the next pulse is timed from the last
+    pub fn set_tempo_map(&self, tempo_map: TempoMap) {
+        self.update(|transport| {
+            let previous = transport.position.saturating_sub(1);
+            let last_due = transport.due(previous);
+            transport.tempo_map = tempo_map;
+            transport.anchor = if transport.running && transport.position > 0 {
+                (previous, last_due)
+            } else {
+                (transport.position, Instant::now())
+            };
+        });
+    }
+
+    /// Tempo in BPM at the current position
+    pub fn bpm(&self) -> f64 {
+        let transport = self.shared.lock();
+        let ticks_per_quarter = transport.tempo_map.time_division().ticks_per_quarter() as u64;
+        let tick = transport.position * ticks_per_quarter / CLOCKS_PER_QUARTER;
+        transport.tempo_map.bpm_at(tick)
+    }
+
+    /// Whether the clock is running
+    pub fn is_running(&self) -> bool {
+        self.shared.lock().running
+    }
+
+    /// Clock pulses sent since song position 0
+    pub fn position_clocks(&self) -> u64 {
+        self.shared.lock().position
+    }
+
+    /// Take the first error the output reported since the last call
+    pub fn take_error(&self) -> Option<RtMidiError> {
+        self.shared.lock().error.take()
+    }
+
+    /// Stop the clock and its thread, and hand back the output
+    pub fn into_output(mut self) -> MidiOutput {
+        self.shutdown();
+        self.shared
+            .lock()
+            .output
+            .take()
+            .expect("the output is only taken here")
+    }
+
+    fn shutdown(&mut self) {
+        self.stop();
+        self.update(|transport| transport.shutdown = true);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MidiClockMaster {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.shutdown();
+        }
+    }
+}
+
+fn clock_thread(shared: &MasterShared) {
+    let mut transport = shared.lock();
+    loop {
+        if transport.shutdown {
+            return;
+        }
+        if !transport.running {
+            transport = shared
+                .changed
+                .wait(transport)
+                .unwrap_or_else(|e| e.into_inner());
+            continue;
+        }
+        let due = transport.due(transport.position);
+        let now = Instant::now();
+        if now >= due {
+            transport.send(&MidiMessage::TimingClock);
+            transport.position += 1;
+            continue;
+        }
+        transport = shared
+            .changed
+            .wait_timeout(transport, due - now)
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+}
+
+/// Transport state seen by a `MidiClockFollower`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ClockState {
+    /// Stopped (initially, and after Stop)
+    #[default]
+    Stopped,
+    /// Running (after Start or Continue)
+    Running,
+}
+
+/// Follower state.
+struct Follower {
+    smoothing: f64,
+    state: ClockState,
+    /// Clock pulses since song position 0
+    position: u64,
+    /// Time of the last message, in seconds since the first
+    time: Option<f64>,
+    /// Time of the last clock pulse
+    last_clock: Option<f64>,
+    /// Smoothed pulse interval in seconds
+    interval: Option<f64>,
+}
+
+impl Follower {
+    fn receive(&mut self, timestamp: f64, data: &[u8]) {
+        let time = self.time.map_or(0.0, |time| time + timestamp.max(0.0));
+        self.time = Some(time);
+        match data.first() {
+            Some(0xF8) => {
+                if let Some(last) = self.last_clock {
+                    let interval = time - last;
+                    self.interval = match self.interval {
+                        // A gap this long means the clock paused; start over.
+                        _ if interval > MAX_CLOCK_INTERVAL => None,
+                        Some(average) => Some(average + (interval - average) * self.smoothing),
+                        None => Some(interval),
+                    };
+                }
+                self.last_clock = Some(time);
+                if self.state == ClockState::Running {
+                    self.position += 1;
+                }
+            }
+            Some(0xFA) => {
+                self.state = ClockState::Running;
+                self.position = 0;
+            }
+            Some(0xFB) => self.state = ClockState::Running,
+            Some(0xFC) => self.state = ClockState::Stopped,
+            Some(0xF2) => {
+                if let Some(MidiMessage::SongPosition(beats)) =
+                    MidiMessage::from_bytes(data).map(|(message, _)| message)
+                {
+                    self.position = beats as u64 * CLOCKS_PER_MIDI_BEAT;
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Follows MIDI clock and transport messages from a `MidiInput`
+///
+/// The tempo is estimated from the spacing of clock pulses, smoothed with
+/// an exponential moving average. Like `MidiRecorder`, the follower is a
+/// cheap handle that `attach` hands to the input's callback.
+#[derive(Clone)]
+pub struct MidiClockFollower {
+    follower: Arc<Mutex<Follower>>,
+}
+
+impl Default for MidiClockFollower {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiClockFollower {
+    /// Create a follower with a smoothing factor of 0.1
+    pub fn new() -> Self {
+        Self::with_smoothing(0.1)
+    }
+
+    /// Create a follower whose tempo estimate moves this fraction of the
+    /// way towards each new pulse interval (1 follows every pulse, smaller
+    /// values smooth out more jitter)
+    pub fn with_smoothing(smoothing: f64) -> Self {
+        Self {
+            follower: Arc::new(Mutex::new(Follower {
+                smoothing: smoothing.clamp(f64::EPSILON, 1.0),
+                state: ClockState::Stopped,
+                position: 0,
+                time: None,
+                last_clock: None,
+                interval: None,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Follower> {
+        self.follower.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Route `input`'s messages to this follower (replacing any callback
+    /// it had), and stop the input from ignoring timing messages
+    pub fn attach(&self, input: &mut MidiInput) {
+        let config = input.config().clone();
+        input.ignore_types(config.ignore_sysex, false, config.ignore_active_sensing);
+        let follower = self.clone();
+        input.set_callback(move |timestamp, data| follower.receive(timestamp, data));
+    }
+
+    /// Handle a message with a timestamp relative to the previous one, as
+    /// delivered by a `MidiInput` callback or `TimestampedMessage`
+    pub fn receive(&self, timestamp: f64, data: &[u8]) {
+        self.lock().receive(timestamp, data);
+    }
+
+    /// Transport state
+    pub fn state(&self) -> ClockState {
+        self.lock().state
+    }
+
+    /// Estimated tempo, once two clock pulses in a row have arrived
+    pub fn bpm(&self) -> Option<f64> {
+        self.lock()
+            .interval
+            .map(|interval| 60.0 / (interval * CLOCKS_PER_QUARTER as f64))
+    }
+
+    /// Clock pulses since song position 0
+    pub fn position_clocks(&self) -> u64 {
+        self.lock().position
+    }
+
+    /// Position in quarter notes since song position 0
+    pub fn position_quarters(&self) -> f64 {
+        self.position_clocks() as f64 / CLOCKS_PER_QUARTER as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realtime::Api;
+
+    #[test]
+    fn test_clock_seconds() {
+        let map = constant_tempo(125.0); // 0.02 s per pulse
+        assert!((clock_seconds(&map, 24) - 0.48).abs() < 1e-9);
+        assert!((clock_seconds(&map, 1) - 0.02).abs() < 1e-9);
+
+        let mut map = TempoMap::new(TimeDivision::Ppqn(100)); // 100/24 ticks per pulse
+        map.set_tempo(0, 60.0);
+        map.set_tempo(100, 120.0);
+        assert!((clock_seconds(&map, 12) - 0.5).abs() < 1e-9);
+        assert!((clock_seconds(&map, 36) - 1.25).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_unusable_tempo() {
+        let output = MidiOutput::with_api(Api::Dummy, "Test").unwrap();
+        let master = MidiClockMaster::new(output, f64::NAN).unwrap();
+        assert!((master.bpm() - 120.0).abs() < 1e-9);
+        for bpm in [0.0, -60.0, f64::INFINITY] {
+            master.set_bpm(bpm);
+            assert!((master.bpm() - 120.0).abs() < 1e-9);
+        }
+
+        // Pulses after a tempo of zero never come due, rather than being
+        // sent at once or panicking the clock thread.
+        let mut tempo_map = TempoMap::new(TimeDivision::Ppqn(960));
+        tempo_map.set_tempo(0, 0.0);
+        let now = Instant::now();
+        let transport = MasterTransport {
+            output: None,
+            tempo_map,
+            running: true,
+            position: 0,
+            anchor: (0, now),
+            error: None,
+            shutdown: false,
+        };
+        assert!(transport.due(1) >= now + FAR_FUTURE);
+        assert!(transport.due(24) >= now + FAR_FUTURE);
+    }
+
+    #[test]
+    fn test_follower() {
+        let follower = MidiClockFollower::with_smoothing(0.5);
+        assert_eq!(follower.bpm(), None);
+        let pulse = 60.0 / 120.0 / 24.0;
+        follower.receive(0.0, &[0xF8]);
+        follower.receive(pulse, &[0xF8]);
+        assert!((follower.bpm().unwrap() - 120.0).abs() < 1e-6);
+        assert_eq!(follower.position_clocks(), 0);
+
+        follower.receive(0.001, &[0xFA]);
+        assert_eq!(follower.state(), ClockState::Running);
+        for _ in 0..48 {
+            follower.receive(pulse, &[0xF8]);
+        }
+        assert_eq!(follower.position_clocks(), 48);
+        assert!((follower.position_quarters() - 2.0).abs() < 1e-9);
+        // Switch to 240 BPM: the estimate converges.
+        for _ in 0..24 {
+            follower.receive(pulse / 2.0, &[0xF8]);
+        }
+        assert!((follower.bpm().unwrap() - 240.0).abs() < 0.01);
+
+        follower.receive(0.0, &[0xFC]);
+        follower.receive(0.0, &MidiMessage::SongPosition(8).to_bytes());
+        assert_eq!(follower.state(), ClockState::Stopped);
+        assert_eq!(follower.position_clocks(), 48);
+        follower.receive(pulse, &[0xF8]);
+        assert_eq!(follower.position_clocks(), 48);
+        follower.receive(0.0, &[0xFB]);
+        follower.receive(pulse, &[0xF8]);
+        assert_eq!(follower.position_clocks(), 49);
+
+        // A long pause resets the estimate.
+        follower.receive(2.0, &[0xF8]);
+        assert_eq!(follower.bpm(), None);
+    }
+
+    #[test]
+    fn test_master_drives_follower() {
+        let port = "mkmidilibrary-test-clock";
+        let mut output = MidiOutput::with_api(Api::Dummy, "Test").unwrap();
+        output.open_virtual_port(port).unwrap();
+        let mut input = MidiInput::with_api(Api::Dummy, "Test").unwrap();
+        let index = input.ports().iter().position(|p| p.name() == port).unwrap();
+        input.open_port(index, "in").unwrap();
+        let follower = MidiClockFollower::with_smoothing(0.2);
+        follower.attach(&mut input);
+
+        // 10 ms per pulse. The loopback delivers each message as it is
+        // sent, so the follower counts exactly the pulses sent, however
+        // late the clock thread runs; the tempo estimate itself is tested
+        // with exact timestamps in `test_follower`.
+        let master = MidiClockMaster::new(output, 250.0).unwrap();
+        master.start();
+        assert_eq!(follower.state(), ClockState::Running);
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while master.position_clocks() < 5 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        master.stop();
+        let sent = master.position_clocks();
+        assert!(sent >= 5, "{sent} pulses");
+        assert_eq!(follower.state(), ClockState::Stopped);
+        assert_eq!(follower.position_clocks(), sent);
+
+        master.set_song_position(16);
+        assert_eq!(master.position_clocks(), 96);
+        assert_eq!(follower.position_clocks(), 96);
+        master.seek_ticks(480); // an eighth note at 960 PPQN
+        assert_eq!(follower.position_clocks(), 12);
+        assert!(master.take_error().is_none());
+        let output = master.into_output();
+        assert!(output.is_port_open());
+    }
+}
@@ -0,0 +1,429 @@
+//! In-process loopback implementation (`Api::Dummy`)
+//!
+//! Ports live in a process-wide registry of named buses. A virtual port
+//! opened by a `MidiOutput` shows up in every Dummy `MidiInput`'s port list
+//! (and vice versa), and every byte sent on a bus is delivered to all inputs
+//! attached to it, going through the same filter/callback/queue path as the
+//! real backends. Delivery happens synchronously on the sending thread, so
+//! a callback must not send back into the bus it is listening on.
+
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::Instant;
+
+use super::RtMidiError;
+use super::input_data::InputData;
+use super::port::{Api, MidiPort};
+
+/// A named loopback bus. Endpoints refer to it by `id`, so renaming it is
+/// seen by all of them.
+struct Bus {
+    id: u64,
+    name: String,
+    /// Number of open virtual output ports (makes the bus an input port).
+    sources: usize,
+    /// Number of open virtual input ports (makes the bus an output port).
+    destinations: usize,
+    /// Inputs currently receiving from this bus, keyed by endpoint id.
+    inputs: Vec<(u64, Arc<Mutex<InputData>>)>,
+}
+
+struct Registry {
+    buses: Vec<Bus>,
+    next_id: u64,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    buses: Vec::new(),
+    next_id: 1,
+});
+
+/// Common time base for message timestamps.
+static EPOCH: OnceLock<Instant> = OnceLock::new();
+
+fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn now_seconds() -> f64 {
+    EPOCH.get_or_init(Instant::now).elapsed().as_secs_f64()
+}
+
+impl Registry {
+    fn next_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// The bus named `name`, created if there is none
+    fn bus_named(&mut self, name: &str) -> &mut Bus {
+        let index = match self.buses.iter().position(|b| b.name == name) {
+            Some(index) => index,
+            None => {
+                let id = self.next_id();
+                self.buses.push(Bus {
+                    id,
+                    name: name.to_string(),
+                    sources: 0,
+                    destinations: 0,
+                    inputs: Vec::new(),
+                });
+                self.buses.len() - 1
+            }
+        };
+        &mut self.buses[index]
+    }
+
+    fn bus_mut(&mut self, id: u64) -> Option<&mut Bus> {
+        self.buses.iter_mut().find(|b| b.id == id)
+    }
+
+    /// Rename bus `id`, unless another bus already has the name.
+    fn rename(&mut self, id: u64, name: &str) -> Result<(), RtMidiError> {
+        if self.buses.iter().any(|b| b.name == name && b.id != id) {
+            return Err(RtMidiError::DriverError(format!(
+                "a Dummy port named {:?} already exists",
+                name
+            )));
+        }
+        if let Some(bus) = self.bus_mut(id) {
+            bus.name = name.to_string();
+        }
+        Ok(())
+    }
+
+    /// Drop buses nobody refers to any more.
+    fn prune(&mut self) {
+        self.buses
+            .retain(|b| b.sources > 0 || b.destinations > 0 || !b.inputs.is_empty());
+    }
+
+    /// Buses with a virtual output port, in input port order
+    fn sources(&self) -> impl Iterator<Item = &Bus> {
+        self.buses.iter().filter(|b| b.sources > 0)
+    }
+
+    /// Buses with a virtual input port, in output port order
+    fn destinations(&self) -> impl Iterator<Item = &Bus> {
+        self.buses.iter().filter(|b| b.destinations > 0)
+    }
+}
+
+/// Get available Dummy input ports (virtual output ports opened in this
+/// process)
+pub fn get_input_ports() -> Vec<MidiPort> {
+    registry()
+        .sources()
+        .enumerate()
+        .map(|(i, bus)| MidiPort::new(i, bus.name.clone(), Api::Dummy))
+        .collect()
+}
+
+/// Get available Dummy output ports (virtual input ports opened in this
+/// process)
+pub fn get_output_ports() -> Vec<MidiPort> {
+    registry()
+        .destinations()
+        .enumerate()
+        .map(|(i, bus)| MidiPort::new(i, bus.name.clone(), Api::Dummy))
+        .collect()
+}
+
+/// Dummy (loopback) MIDI input handler
+pub struct DummyMidiInput {
+    id: u64,
+    /// Id of the bus this input is attached to, if a port is open.
+    bus: Option<u64>,
+    /// Whether `bus` was created by `open_virtual_port`.
+    is_virtual: bool,
+    callback_data: Arc<Mutex<InputData>>,
+}
+
+impl DummyMidiInput {
+    /// Create a new Dummy MIDI input
+    pub fn new() -> Self {
+        Self {
+            id: registry().next_id(),
+            bus: None,
+            is_virtual: false,
+            callback_data: Arc::new(Mutex::new(InputData::new())),
+        }
+    }
+
+    fn attach(&mut self, bus: &mut Bus) {
+        bus.inputs.push((self.id, Arc::clone(&self.callback_data)));
+        self.bus = Some(bus.id);
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.reset_time();
+        }
+    }
+
+    /// Open a Dummy input port, i.e. start receiving from a virtual output
+    pub fn open_port(&mut self, port_index: usize, _port_name: &str) -> Result<(), RtMidiError> {
+        let mut registry = registry();
+        let id = registry
+            .sources()
+            .nth(port_index)
+            .map(|b| b.id)
+            .ok_or(RtMidiError::InvalidPort(port_index))?;
+        if let Some(bus) = registry.bus_mut(id) {
+            self.attach(bus);
+        }
+        self.is_virtual = false;
+        Ok(())
+    }
+
+    /// Create a virtual input port that Dummy outputs can open by index
+    pub fn open_virtual_port(&mut self, port_name: &str) -> Result<(), RtMidiError> {
+        let mut registry = registry();
+        let bus = registry.bus_named(port_name);
+        bus.destinations += 1;
+        self.attach(bus);
+        self.is_virtual = true;
+        Ok(())
+    }
+
+    /// Close the currently open port
+    pub fn close_port(&mut self) {
+        let Some(id) = self.bus.take() else {
+            return;
+        };
+        let mut registry = registry();
+        if let Some(bus) = registry.bus_mut(id) {
+            bus.inputs.retain(|(input, _)| *input != self.id);
+            if self.is_virtual {
+                bus.destinations = bus.destinations.saturating_sub(1);
+            }
+        }
+        self.is_virtual = false;
+        registry.prune();
+    }
+
+    /// Rename the currently open port. Only a virtual port has a name of
+    /// its own; renaming it renames the bus for every endpoint on it.
+    pub fn set_port_name(&mut self, name: &str) -> Result<(), RtMidiError> {
+        let id = self.bus.ok_or(RtMidiError::PortNotOpen)?;
+        if self.is_virtual {
+            registry().rename(id, name)?;
+        }
+        Ok(())
+    }
+
+    /// Set a callback for incoming messages
+    pub fn set_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(f64, &[u8]) + Send + 'static,
+    {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.callback = Some(Box::new(callback));
+        }
+    }
+
+    /// Cancel the callback
+    pub fn cancel_callback(&mut self) {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.callback = None;
+        }
+    }
+
+    /// Get a message from the queue (when not using callback)
+    pub fn get_message(&mut self) -> Option<(f64, Vec<u8>)> {
+        self.callback_data
+            .lock()
+            .ok()
+            .and_then(|mut data| data.queue.pop_front())
+    }
+
+    /// Set message type filtering
+    pub fn ignore_types(&mut self, sysex: bool, timing: bool, active_sensing: bool) {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.ignore_sysex = sysex;
+            data.ignore_timing = timing;
+            data.ignore_active_sensing = active_sensing;
+        }
+    }
+
+    /// Set the maximum number of queued messages before incoming messages
+    /// are dropped.
+    pub fn set_queue_size_limit(&mut self, limit: usize) {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.queue_size_limit = limit;
+        }
+    }
+
+    /// Register a callback for non-fatal warnings, such as a dropped
+    /// message when the polling queue is full.
+    pub fn set_error_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&RtMidiError) + Send + 'static,
+    {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.error_callback = Some(Box::new(callback));
+        }
+    }
+
+    /// Remove any registered error callback.
+    pub fn cancel_error_callback(&mut self) {
+        if let Ok(mut data) = self.callback_data.lock() {
+            data.error_callback = None;
+        }
+    }
+}
+
+impl Default for DummyMidiInput {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for DummyMidiInput {
+    fn drop(&mut self) {
+        self.close_port();
+    }
+}
+
+/// Dummy (loopback) MIDI output handler
+#[derive(Default)]
+pub struct DummyMidiOutput {
+    /// Id of the bus this output sends to, if a port is open.
+    bus: Option<u64>,
+    /// Whether `bus` was created by `open_virtual_port`.
+    is_virtual: bool,
+}
+
+impl DummyMidiOutput {
+    /// Create a new Dummy MIDI output
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a Dummy output port, i.e. start sending to a virtual input
+    pub fn open_port(&mut self, port_index: usize, _port_name: &str) -> Result<(), RtMidiError> {
+        let id = registry()
+            .destinations()
+            .nth(port_index)
+            .map(|b| b.id)
+            .ok_or(RtMidiError::InvalidPort(port_index))?;
+        self.bus = Some(id);
+        self.is_virtual = false;
+        Ok(())
+    }
+
+    /// Create a virtual output port that Dummy inputs can open by index
+    pub fn open_virtual_port(&mut self, port_name: &str) -> Result<(), RtMidiError> {
+        let mut registry = registry();
+        let bus = registry.bus_named(port_name);
+        bus.sources += 1;
+        self.bus = Some(bus.id);
+        self.is_virtual = true;
+        Ok(())
+    }
+
+    /// Close the currently open port
+    pub fn close_port(&mut self) {
+        let Some(id) = self.bus.take() else {
+            return;
+        };
+        if self.is_virtual {
+            let mut registry = registry();
+            if let Some(bus) = registry.bus_mut(id) {
+                bus.sources = bus.sources.saturating_sub(1);
+            }
+            registry.prune();
+        }
+        self.is_virtual = false;
+    }
+
+    /// Rename the currently open port (see `DummyMidiInput::set_port_name`).
+    pub fn set_port_name(&mut self, name: &str) -> Result<(), RtMidiError> {
+        let id = self.bus.ok_or(RtMidiError::PortNotOpen)?;
+        if self.is_virtual {
+            registry().rename(id, name)?;
+        }
+        Ok(())
+    }
+
+    /// Send a MIDI message to every input attached to the bus
+    pub fn send_message(&mut self, message: &[u8]) -> Result<(), RtMidiError> {
+        let id = self.bus.ok_or(RtMidiError::PortNotOpen)?;
+
+        // Snapshot the receivers so callbacks run without the registry lock
+        // held (a callback may legitimately open or close other ports).
+        let inputs: Vec<Arc<Mutex<InputData>>> = registry()
+            .bus_mut(id)
+            .map(|b| b.inputs.iter().map(|(_, data)| Arc::clone(data)).collect())
+            .unwrap_or_default();
+
+        let time = now_seconds();
+        for data in inputs {
+            if let Ok(mut data) = data.lock() {
+                data.deliver(time, message);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DummyMidiOutput {
+    fn drop(&mut self) {
+        self.close_port();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_output_listed_and_delivers() {
+        let mut output = DummyMidiOutput::new();
+        output
+            .open_virtual_port("dummy-impl-test-virtual-output")
+            .unwrap();
+
+        let ports = get_input_ports();
+        let index = ports
+            .iter()
+            .position(|p| p.name() == "dummy-impl-test-virtual-output")
+            .unwrap();
+
+        let mut input = DummyMidiInput::new();
+        input.open_port(index, "in").unwrap();
+        output.send_message(&[0x90, 60, 100]).unwrap();
+        assert_eq!(input.get_message(), Some((0.0, vec![0x90, 60, 100])));
+
+        output.close_port();
+        input.close_port();
+        assert!(
+            !get_input_ports()
+                .iter()
+                .any(|p| p.name() == "dummy-impl-test-virtual-output")
+        );
+    }
+
+    #[test]
+    fn test_output_follows_renamed_virtual_input() {
+        let mut input = DummyMidiInput::new();
+        input
+            .open_virtual_port("dummy-impl-test-rename-before")
+            .unwrap();
+        let index = get_output_ports()
+            .iter()
+            .position(|p| p.name() == "dummy-impl-test-rename-before")
+            .unwrap();
+        let mut output = DummyMidiOutput::new();
+        output.open_port(index, "out").unwrap();
+
+        input.set_port_name("dummy-impl-test-rename-after").unwrap();
+        output.send_message(&[0x90, 60, 100]).unwrap();
+        assert_eq!(input.get_message(), Some((0.0, vec![0x90, 60, 100])));
+
+        input.close_port();
+        output.close_port();
+        assert!(
+            !get_output_ports()
+                .iter()
+                .any(|p| p.name().starts_with("dummy-impl-test-rename"))
+        );
+    }
+}
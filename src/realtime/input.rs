@@ -1,5 +1,6 @@
 //! Real-time MIDI input
 
+use super::dummy_impl::DummyMidiInput;
 use super::port::{Api, MidiPort};
 use super::{MidiCallback, MidiInputConfig, RtMidiError, RtMidiErrorCallback};
 
@@ -36,6 +37,8 @@ pub struct MidiInput {
     /// Non-fatal warning callback set before a port was opened, applied the
     /// same way as `pending_callback`.
     pending_error_callback: Option<RtMidiErrorCallback>,
+    /// In-process loopback backend (`Api::Dummy`)
+    dummy: Option<DummyMidiInput>,
     /// Platform-specific data
     #[cfg(target_os = "macos")]
     platform: Option<PlatformInput>,
@@ -72,6 +75,7 @@ impl MidiInput {
             port_name: None,
             pending_callback: None,
             pending_error_callback: None,
+            dummy: None,
             platform: None,
         })
     }
@@ -110,6 +114,9 @@ impl MidiInput {
     /// next time a port is opened.
     pub fn set_client_name(&mut self, name: &str) -> Result<(), RtMidiError> {
         self.client_name = name.to_string();
+        if self.api == Api::Dummy {
+            return Ok(());
+        }
         self.platform_set_client_name(name)
     }
 
@@ -119,10 +126,13 @@ impl MidiInput {
             return Err(RtMidiError::PortNotOpen);
         }
         self.port_name = Some(name.to_string());
+        if let Some(ref mut d) = self.dummy {
+            return d.set_port_name(name);
+        }
         self.platform_set_port_name(name)
     }
 
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     fn platform_set_client_name(&mut self, name: &str) -> Result<(), RtMidiError> {
         match self.platform {
             Some(ref mut p) => p.set_client_name(name),
@@ -130,7 +140,7 @@ impl MidiInput {
         }
     }
 
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     fn platform_set_port_name(&mut self, name: &str) -> Result<(), RtMidiError> {
         match self.platform {
             Some(ref mut p) => p.set_port_name(name),
@@ -138,14 +148,14 @@ impl MidiInput {
         }
     }
 
-    #[cfg(not(target_os = "macos"))]
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
     fn platform_set_client_name(&mut self, _name: &str) -> Result<(), RtMidiError> {
         Err(RtMidiError::DriverError(
             "set_client_name is not implemented for this platform".to_string(),
         ))
     }
 
-    #[cfg(not(target_os = "macos"))]
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
     fn platform_set_port_name(&mut self, _name: &str) -> Result<(), RtMidiError> {
         Err(RtMidiError::DriverError(
             "set_port_name is not implemented for this platform".to_string(),
@@ -228,7 +238,9 @@ impl MidiInput {
         F: FnMut(f64, &[u8]) + Send + 'static,
     {
         let boxed: MidiCallback = Box::new(callback);
-        if self.has_platform() {
+        if let Some(ref mut d) = self.dummy {
+            d.set_callback(boxed);
+        } else if self.has_platform() {
             self.platform_set_callback(boxed);
         } else {
             self.pending_callback = Some(boxed);
@@ -238,6 +250,9 @@ impl MidiInput {
     /// Cancel the callback and return to queue-based input
     pub fn cancel_callback(&mut self) {
         self.pending_callback = None;
+        if let Some(ref mut d) = self.dummy {
+            d.cancel_callback();
+        }
         if self.has_platform() {
             self.platform_cancel_callback();
         }
@@ -247,8 +262,11 @@ impl MidiInput {
     /// callback is currently registered (messages are delivered to the
     /// callback instead) or if no port is open.
     pub fn get_message(&mut self) -> Option<TimestampedMessage> {
-        self.platform_get_message()
-            .map(|(timestamp, data)| TimestampedMessage { timestamp, data })
+        let message = match self.dummy {
+            Some(ref mut d) => d.get_message(),
+            None => self.platform_get_message(),
+        };
+        message.map(|(timestamp, data)| TimestampedMessage { timestamp, data })
     }
 
     /// Set which message types to ignore. Applies immediately to an already-open
@@ -257,6 +275,9 @@ impl MidiInput {
         self.config.ignore_sysex = sysex;
         self.config.ignore_timing = timing;
         self.config.ignore_active_sensing = active_sensing;
+        if let Some(ref mut d) = self.dummy {
+            d.ignore_types(sysex, timing, active_sensing);
+        }
         if self.has_platform() {
             self.platform_ignore_types(sysex, timing, active_sensing);
         }
@@ -270,7 +291,9 @@ impl MidiInput {
         F: FnMut(&RtMidiError) + Send + 'static,
     {
         let boxed: RtMidiErrorCallback = Box::new(callback);
-        if self.has_platform() {
+        if let Some(ref mut d) = self.dummy {
+            d.set_error_callback(boxed);
+        } else if self.has_platform() {
             self.platform_set_error_callback(boxed);
         } else {
             self.pending_error_callback = Some(boxed);
@@ -280,6 +303,9 @@ impl MidiInput {
     /// Remove any registered error callback.
     pub fn cancel_error_callback(&mut self) {
         self.pending_error_callback = None;
+        if let Some(ref mut d) = self.dummy {
+            d.cancel_error_callback();
+        }
         if self.has_platform() {
             self.platform_cancel_error_callback();
         }
@@ -432,10 +458,8 @@ impl MidiInput {
     fn platform_ignore_types(&mut self, _sysex: bool, _timing: bool, _active_sensing: bool) {}
 
     fn get_ports_impl(&self) -> Vec<MidiPort> {
-        // Default implementation returns empty
-        // Platform-specific code would override this
         match self.api {
-            Api::Dummy => vec![MidiPort::new(0, "Dummy Input", Api::Dummy)],
+            Api::Dummy => super::dummy_impl::get_input_ports(),
             #[cfg(target_os = "macos")]
             Api::CoreMidi => self.get_ports_coremidi(),
             #[cfg(target_os = "linux")]
@@ -448,7 +472,7 @@ impl MidiInput {
 
     fn open_port_impl(&mut self, _port: usize, _port_name: &str) -> Result<(), RtMidiError> {
         match self.api {
-            Api::Dummy => Ok(()),
+            Api::Dummy => self.open_port_dummy(_port, _port_name),
             #[cfg(target_os = "macos")]
             Api::CoreMidi => self.open_port_coremidi(_port, _port_name),
             #[cfg(target_os = "linux")]
@@ -461,7 +485,7 @@ impl MidiInput {
 
     fn open_virtual_port_impl(&mut self, _port_name: &str) -> Result<(), RtMidiError> {
         match self.api {
-            Api::Dummy => Ok(()),
+            Api::Dummy => self.open_virtual_port_dummy(_port_name),
             #[cfg(target_os = "macos")]
             Api::CoreMidi => self.open_virtual_port_coremidi(_port_name),
             #[cfg(target_os = "linux")]
@@ -472,7 +496,7 @@ impl MidiInput {
 
     fn close_port_impl(&mut self) {
         match self.api {
-            Api::Dummy => {}
+            Api::Dummy => self.close_port_dummy(),
             #[cfg(target_os = "macos")]
             Api::CoreMidi => self.close_port_coremidi(),
             #[cfg(target_os = "linux")]
@@ -483,6 +507,48 @@ impl MidiInput {
         }
     }
 
+    // Dummy (loopback) implementations, wired the same way as the
+    // platform backends below but available on every OS.
+    fn open_port_dummy(&mut self, port: usize, name: &str) -> Result<(), RtMidiError> {
+        let mut dummy = DummyMidiInput::new();
+        dummy.open_port(port, name)?;
+        self.apply_pending_state_dummy(&mut dummy);
+        self.dummy = Some(dummy);
+        Ok(())
+    }
+
+    fn open_virtual_port_dummy(&mut self, name: &str) -> Result<(), RtMidiError> {
+        let mut dummy = DummyMidiInput::new();
+        dummy.open_virtual_port(name)?;
+        self.apply_pending_state_dummy(&mut dummy);
+        self.dummy = Some(dummy);
+        Ok(())
+    }
+
+    /// Apply the currently configured filter settings and any callback set
+    /// before the port was opened to a freshly constructed Dummy backend.
+    fn apply_pending_state_dummy(&mut self, dummy: &mut DummyMidiInput) {
+        dummy.ignore_types(
+            self.config.ignore_sysex,
+            self.config.ignore_timing,
+            self.config.ignore_active_sensing,
+        );
+        dummy.set_queue_size_limit(self.config.queue_size);
+        if let Some(callback) = self.pending_callback.take() {
+            dummy.set_callback(callback);
+        }
+        if let Some(callback) = self.pending_error_callback.take() {
+            dummy.set_error_callback(callback);
+        }
+    }
+
+    fn close_port_dummy(&mut self) {
+        if let Some(ref mut d) = self.dummy {
+            d.close_port();
+        }
+        self.dummy = None;
+    }
+
     // CoreMIDI implementations
     #[cfg(target_os = "macos")]
     fn get_ports_coremidi(&self) -> Vec<MidiPort> {
@@ -533,13 +599,9 @@ impl MidiInput {
         self.platform = None;
     }
 
-    // ALSA implementations. `AlsaMidiInput` itself is currently a stub
-    // (see its own doc comment) — real sequencer I/O needs a Linux
-    // environment to write and verify against actual hardware/drivers —
-    // but the wiring here (constructing it, applying pending config/
-    // callbacks, storing it in `self.platform`) matches the CoreMIDI
-    // pattern exactly, so filling in that stub later doesn't need any
-    // dispatch changes.
+    // ALSA implementations. The wiring here (constructing the backend,
+    // applying pending config/callbacks, storing it in `self.platform`)
+    // matches the CoreMIDI pattern exactly.
     #[cfg(target_os = "linux")]
     fn get_ports_alsa(&self) -> Vec<MidiPort> {
         super::alsa_impl::get_input_ports()
@@ -548,8 +610,13 @@ impl MidiInput {
     #[cfg(target_os = "linux")]
     fn open_port_alsa(&mut self, port: usize, name: &str) -> Result<(), RtMidiError> {
         let mut platform = super::alsa_impl::AlsaMidiInput::new(&self.client_name)?;
-        platform.open_port(port, name)?;
+        // Configure before opening, so the input thread never sees a
+        // message without the callback and filters.
         self.apply_pending_state_alsa(&mut platform);
+        if let Err(error) = platform.open_port(port, name) {
+            self.restore_pending_state_alsa(&mut platform);
+            return Err(error);
+        }
         self.platform = Some(platform);
         Ok(())
     }
@@ -557,8 +624,11 @@ impl MidiInput {
     #[cfg(target_os = "linux")]
     fn open_virtual_port_alsa(&mut self, name: &str) -> Result<(), RtMidiError> {
         let mut platform = super::alsa_impl::AlsaMidiInput::new(&self.client_name)?;
-        platform.open_virtual_port(name)?;
         self.apply_pending_state_alsa(&mut platform);
+        if let Err(error) = platform.open_virtual_port(name) {
+            self.restore_pending_state_alsa(&mut platform);
+            return Err(error);
+        }
         self.platform = Some(platform);
         Ok(())
     }
@@ -581,6 +651,14 @@ impl MidiInput {
         }
     }
 
+    /// Take back the callbacks handed to a backend that failed to open.
+    #[cfg(target_os = "linux")]
+    fn restore_pending_state_alsa(&mut self, platform: &mut super::alsa_impl::AlsaMidiInput) {
+        let (callback, error_callback) = platform.take_callbacks();
+        self.pending_callback = self.pending_callback.take().or(callback);
+        self.pending_error_callback = self.pending_error_callback.take().or(error_callback);
+    }
+
     #[cfg(target_os = "linux")]
     fn close_port_alsa(&mut self) {
         if let Some(ref mut p) = self.platform {
@@ -589,8 +667,8 @@ impl MidiInput {
         self.platform = None;
     }
 
-    // Windows MM implementations — same relationship to `WinMmMidiInput`
-    // as the ALSA ones above have to `AlsaMidiInput`.
+    // Windows MM implementations — same wiring as the ALSA ones above.
+    // `WinMmMidiInput` itself is still a stub.
     #[cfg(target_os = "windows")]
     fn get_ports_winmm(&self) -> Vec<MidiPort> {
         super::winmm_impl::get_input_ports()
@@ -708,6 +786,99 @@ mod tests {
         assert_eq!(input.config().queue_size, 42);
     }
 
+    /// Open a Dummy output on a fresh virtual port and a Dummy input on it.
+    fn dummy_loopback(port_name: &str, input: &mut MidiInput) -> super::super::output::MidiOutput {
+        let mut output =
+            super::super::output::MidiOutput::with_api(Api::Dummy, "loopback-out").unwrap();
+        output.open_virtual_port(port_name).unwrap();
+        let index = input
+            .ports()
+            .iter()
+            .position(|p| p.name() == port_name)
+            .unwrap();
+        input.open_port(index, "loopback-in").unwrap();
+        output
+    }
+
+    #[test]
+    fn test_dummy_loopback_queue_and_timestamps() {
+        let mut input = MidiInput::with_api(Api::Dummy, "Test").unwrap();
+        let mut output = dummy_loopback("mkmidilibrary-test-dummy-queue", &mut input);
+
+        output.send_note_on(0, 60, 100).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        output.send_note_off(0, 60, 0).unwrap();
+
+        let first = input.get_message().unwrap();
+        assert_eq!(first.data, vec![0x90, 60, 100]);
+        assert_eq!(first.timestamp, 0.0);
+        let second = input.get_message().unwrap();
+        assert_eq!(second.data, vec![0x80, 60, 0]);
+        assert!(second.timestamp >= 0.015, "delta was {}", second.timestamp);
+        assert!(input.get_message().is_none());
+    }
+
+    #[test]
+    fn test_dummy_loopback_callback_and_ignore_types() {
+        use std::sync::{Arc, Mutex};
+
+        let received: Arc<Mutex<Vec<Vec<u8>>>> = Arc::new(Mutex::new(Vec::new()));
+        let received_clone = Arc::clone(&received);
+        let mut input = MidiInput::with_api(Api::Dummy, "Test").unwrap();
+        input.set_callback(move |_, data| received_clone.lock().unwrap().push(data.to_vec()));
+        let mut output = dummy_loopback("mkmidilibrary-test-dummy-callback", &mut input);
+
+        // Sysex and timing clock are ignored by default.
+        output
+            .send_message(&[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7])
+            .unwrap();
+        output.send_message(&[0xF8]).unwrap();
+        output.send_control_change(1, 7, 90).unwrap();
+
+        input.ignore_types(false, true, true);
+        output
+            .send_message(&[0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7])
+            .unwrap();
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            vec![vec![0xB1, 7, 90], vec![0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7]]
+        );
+        assert!(input.get_message().is_none());
+    }
+
+    #[test]
+    fn test_dummy_virtual_input_and_queue_overflow() {
+        use std::sync::{Arc, Mutex};
+
+        let mut input = MidiInput::with_queue_size(Api::Dummy, "Test", 2).unwrap();
+        let warnings = Arc::new(Mutex::new(0));
+        let warnings_clone = Arc::clone(&warnings);
+        input.set_error_callback(move |_| *warnings_clone.lock().unwrap() += 1);
+        let port_name = "mkmidilibrary-test-dummy-virtual-input";
+        input.open_virtual_port(port_name).unwrap();
+
+        let mut output =
+            super::super::output::MidiOutput::with_api(Api::Dummy, "loopback-out").unwrap();
+        let index = output
+            .ports()
+            .iter()
+            .position(|p| p.name() == port_name)
+            .unwrap();
+        output.open_port(index, "conn").unwrap();
+        for key in 0..4 {
+            output.send_note_on(0, key, 100).unwrap();
+        }
+
+        assert_eq!(*warnings.lock().unwrap(), 2);
+        assert_eq!(input.get_message().unwrap().data, vec![0x90, 0, 100]);
+        assert_eq!(input.get_message().unwrap().data, vec![0x90, 1, 100]);
+        assert!(input.get_message().is_none());
+
+        input.close_port();
+        assert!(!output.ports().iter().any(|p| p.name() == port_name));
+    }
+
     #[test]
     fn test_ignore_sysex_defaults_true() {
         // Matches upstream RtMidi's default (ignoreFlags(7) ignores sysex,
@@ -0,0 +1,133 @@
+//! Shared state for platform input backends
+//!
+//! Backends that run their own receive thread (ALSA) hand each incoming
+//! message to an `InputData` behind a mutex, which applies the configured
+//! type filters and then delivers it either to the user callback or to the
+//! polling queue, mirroring upstream RtMidi's `RtMidiInData`.
+
+use std::collections::VecDeque;
+
+use super::{MidiCallback, RtMidiError, RtMidiErrorCallback};
+
+/// Callback, queue and filter state shared between a backend's receive
+/// thread and the `MidiInput` that owns it.
+pub(crate) struct InputData {
+    /// Registered message callback (takes precedence over the queue).
+    pub callback: Option<MidiCallback>,
+    /// Non-fatal warning/debug-warning reporting channel (see
+    /// `RtMidiError::Warning`/`DebugWarning`).
+    pub error_callback: Option<RtMidiErrorCallback>,
+    /// Polling queue used when no callback is registered.
+    pub queue: VecDeque<(f64, Vec<u8>)>,
+    /// Maximum number of messages the polling queue holds. Once full,
+    /// incoming messages are dropped (not the oldest queued message),
+    /// matching upstream RtMidi's fixed-size `MidiQueue::push`.
+    pub queue_size_limit: usize,
+    pub ignore_sysex: bool,
+    pub ignore_timing: bool,
+    pub ignore_active_sensing: bool,
+    /// Backend timestamp (in seconds) of the previously delivered message,
+    /// used to report each message's timestamp as a delta from the one
+    /// before it.
+    last_time: Option<f64>,
+}
+
+impl InputData {
+    pub fn new() -> Self {
+        Self {
+            callback: None,
+            error_callback: None,
+            queue: VecDeque::new(),
+            queue_size_limit: 100,
+            ignore_sysex: true,
+            ignore_timing: true,
+            ignore_active_sensing: true,
+            last_time: None,
+        }
+    }
+
+    /// Forget the previous message time so the next delivered message
+    /// reports a timestamp of `0.0` (called when a port is (re-)opened).
+    pub fn reset_time(&mut self) {
+        self.last_time = None;
+    }
+
+    /// Whether `message` is filtered out by the current ignore flags.
+    pub fn is_ignored(&self, message: &[u8]) -> bool {
+        match message.first() {
+            Some(0xF0) => self.ignore_sysex,
+            Some(0xF8) => self.ignore_timing,
+            Some(0xFE) => self.ignore_active_sensing,
+            Some(_) => false,
+            None => true,
+        }
+    }
+
+    /// Filter and deliver one complete message received at `time` seconds
+    /// on the backend's clock.
+    pub fn deliver(&mut self, time: f64, message: &[u8]) {
+        if self.is_ignored(message) {
+            return;
+        }
+
+        let delta = match self.last_time {
+            Some(prev) => (time - prev).max(0.0),
+            None => 0.0,
+        };
+        self.last_time = Some(time);
+
+        if let Some(ref mut cb) = self.callback {
+            cb(delta, message);
+        } else if self.queue.len() < self.queue_size_limit {
+            self.queue.push_back((delta, message.to_vec()));
+        } else {
+            self.warn(RtMidiError::Warning(
+                "input queue full, dropping message".to_string(),
+            ));
+        }
+    }
+
+    /// Report a non-fatal condition through the error callback, if any.
+    pub fn warn(&mut self, error: RtMidiError) {
+        if let Some(ref mut err_cb) = self.error_callback {
+            err_cb(&error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_deliver_reports_deltas_and_caps_queue() {
+        let mut data = InputData::new();
+        data.queue_size_limit = 2;
+        let warnings = Arc::new(Mutex::new(0));
+        let warnings_clone = Arc::clone(&warnings);
+        data.error_callback = Some(Box::new(move |_| *warnings_clone.lock().unwrap() += 1));
+
+        data.deliver(1.0, &[0x90, 60, 100]);
+        data.deliver(1.25, &[0x80, 60, 0]);
+        data.deliver(2.0, &[0x90, 62, 100]);
+
+        assert_eq!(data.queue.len(), 2);
+        assert_eq!(data.queue[0].0, 0.0);
+        assert!((data.queue[1].0 - 0.25).abs() < 1e-9);
+        assert_eq!(*warnings.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_ignore_flags() {
+        let mut data = InputData::new();
+        data.deliver(0.0, &[0xF8]);
+        data.deliver(0.0, &[0xFE]);
+        data.deliver(0.0, &[0xF0, 0x7E, 0xF7]);
+        assert!(data.queue.is_empty());
+
+        data.ignore_timing = false;
+        data.deliver(0.0, &[0xF8]);
+        assert_eq!(data.queue.len(), 1);
+    }
+}
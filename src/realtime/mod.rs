@@ -7,10 +7,17 @@
 //! - macOS: CoreMIDI
 //! - Linux: ALSA
 //! - Windows: Windows Multimedia API
+//! - All platforms: `Api::Dummy`, an in-process loopback between virtual
+//!   ports (useful for testing without hardware)
 
+mod clock;
+mod dummy_impl;
 mod input;
+mod input_data;
 mod output;
+mod player;
 mod port;
+mod recorder;
 
 #[cfg(target_os = "macos")]
 mod coremidi_impl;
@@ -21,9 +28,14 @@ mod alsa_impl;
 #[cfg(target_os = "windows")]
 mod winmm_impl;
 
+pub use clock::{
+    CLOCKS_PER_MIDI_BEAT, CLOCKS_PER_QUARTER, ClockState, MidiClockFollower, MidiClockMaster,
+};
 pub use input::MidiInput;
 pub use output::MidiOutput;
+pub use player::{MidiPlayer, PlayerState};
 pub use port::{Api, MidiPort};
+pub use recorder::{MidiRecorder, RecordMode, RecorderOptions};
 
 use thiserror::Error;
 
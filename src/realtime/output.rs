@@ -1,7 +1,9 @@
 //! Real-time MIDI output
 
+use super::dummy_impl::DummyMidiOutput;
 use super::port::{Api, MidiPort};
 use super::{RtMidiError, RtMidiErrorCallback};
+use crate::midi::MidiMessage;
 
 #[cfg(target_os = "macos")]
 use super::coremidi_impl::CoreMidiOutput;
@@ -21,6 +23,8 @@ pub struct MidiOutput {
     /// non-fatal conditions (e.g. a device disconnect); `send_message`'s own
     /// failures are reported through its `Result` instead.
     error_callback: Option<RtMidiErrorCallback>,
+    /// In-process loopback backend (`Api::Dummy`)
+    dummy: Option<DummyMidiOutput>,
     /// Platform-specific data
     #[cfg(target_os = "macos")]
     platform: Option<PlatformOutput>,
@@ -55,6 +59,7 @@ impl MidiOutput {
             port_open: false,
             port_name: None,
             error_callback: None,
+            dummy: None,
             platform: None,
         })
     }
@@ -74,6 +79,9 @@ impl MidiOutput {
     /// next time a port is opened.
     pub fn set_client_name(&mut self, name: &str) -> Result<(), RtMidiError> {
         self.client_name = name.to_string();
+        if self.api == Api::Dummy {
+            return Ok(());
+        }
         self.platform_set_client_name(name)
     }
 
@@ -83,6 +91,9 @@ impl MidiOutput {
             return Err(RtMidiError::PortNotOpen);
         }
         self.port_name = Some(name.to_string());
+        if let Some(ref mut d) = self.dummy {
+            return d.set_port_name(name);
+        }
         self.platform_set_port_name(name)
     }
 
@@ -102,7 +113,7 @@ impl MidiOutput {
         self.error_callback = None;
     }
 
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     fn platform_set_client_name(&mut self, name: &str) -> Result<(), RtMidiError> {
         match self.platform {
             Some(ref mut p) => p.set_client_name(name),
@@ -110,7 +121,7 @@ impl MidiOutput {
         }
     }
 
-    #[cfg(target_os = "macos")]
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
     fn platform_set_port_name(&mut self, name: &str) -> Result<(), RtMidiError> {
         match self.platform {
             Some(ref mut p) => p.set_port_name(name),
@@ -118,14 +129,14 @@ impl MidiOutput {
         }
     }
 
-    #[cfg(not(target_os = "macos"))]
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
     fn platform_set_client_name(&mut self, _name: &str) -> Result<(), RtMidiError> {
         Err(RtMidiError::DriverError(
             "set_client_name is not implemented for this platform".to_string(),
         ))
     }
 
-    #[cfg(not(target_os = "macos"))]
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
     fn platform_set_port_name(&mut self, _name: &str) -> Result<(), RtMidiError> {
         Err(RtMidiError::DriverError(
             "set_port_name is not implemented for this platform".to_string(),
@@ -203,6 +214,15 @@ impl MidiOutput {
         self.send_message_impl(message)
     }
 
+    /// Send a `MidiMessage`. Meta events only exist in files and are
+    /// rejected.
+    pub fn send_midi_message(&mut self, message: &MidiMessage) -> Result<(), RtMidiError> {
+        if message.is_meta() {
+            return Err(RtMidiError::InvalidMessage);
+        }
+        self.send_message(&message.to_bytes())
+    }
+
     /// Send a note on message
     pub fn send_note_on(&mut self, channel: u8, key: u8, velocity: u8) -> Result<(), RtMidiError> {
         self.send_message(&[0x90 | (channel & 0x0F), key & 0x7F, velocity & 0x7F])
@@ -252,7 +272,7 @@ impl MidiOutput {
 
     fn get_ports_impl(&self) -> Vec<MidiPort> {
         match self.api {
-            Api::Dummy => vec![MidiPort::new(0, "Dummy Output", Api::Dummy)],
+            Api::Dummy => super::dummy_impl::get_output_ports(),
             #[cfg(target_os = "macos")]
             Api::CoreMidi => self.get_ports_coremidi(),
             #[cfg(target_os = "linux")]
@@ -265,7 +285,7 @@ impl MidiOutput {
 
     fn open_port_impl(&mut self, _port: usize, _port_name: &str) -> Result<(), RtMidiError> {
         match self.api {
-            Api::Dummy => Ok(()),
+            Api::Dummy => self.open_port_dummy(_port, _port_name),
             #[cfg(target_os = "macos")]
             Api::CoreMidi => self.open_port_coremidi(_port, _port_name),
             #[cfg(target_os = "linux")]
@@ -278,7 +298,7 @@ impl MidiOutput {
 
     fn open_virtual_port_impl(&mut self, _port_name: &str) -> Result<(), RtMidiError> {
         match self.api {
-            Api::Dummy => Ok(()),
+            Api::Dummy => self.open_virtual_port_dummy(_port_name),
             #[cfg(target_os = "macos")]
             Api::CoreMidi => self.open_virtual_port_coremidi(_port_name),
             #[cfg(target_os = "linux")]
@@ -289,7 +309,7 @@ impl MidiOutput {
 
     fn close_port_impl(&mut self) {
         match self.api {
-            Api::Dummy => {}
+            Api::Dummy => self.close_port_dummy(),
             #[cfg(target_os = "macos")]
             Api::CoreMidi => self.close_port_coremidi(),
             #[cfg(target_os = "linux")]
@@ -302,7 +322,7 @@ impl MidiOutput {
 
     fn send_message_impl(&mut self, _message: &[u8]) -> Result<(), RtMidiError> {
         match self.api {
-            Api::Dummy => Ok(()),
+            Api::Dummy => self.send_message_dummy(_message),
             #[cfg(target_os = "macos")]
             Api::CoreMidi => self.send_message_coremidi(_message),
             #[cfg(target_os = "linux")]
@@ -313,6 +333,37 @@ impl MidiOutput {
         }
     }
 
+    // Dummy (loopback) implementations, wired the same way as the
+    // platform backends below but available on every OS.
+    fn open_port_dummy(&mut self, port: usize, name: &str) -> Result<(), RtMidiError> {
+        let mut dummy = DummyMidiOutput::new();
+        dummy.open_port(port, name)?;
+        self.dummy = Some(dummy);
+        Ok(())
+    }
+
+    fn open_virtual_port_dummy(&mut self, name: &str) -> Result<(), RtMidiError> {
+        let mut dummy = DummyMidiOutput::new();
+        dummy.open_virtual_port(name)?;
+        self.dummy = Some(dummy);
+        Ok(())
+    }
+
+    fn close_port_dummy(&mut self) {
+        if let Some(ref mut d) = self.dummy {
+            d.close_port();
+        }
+        self.dummy = None;
+    }
+
+    fn send_message_dummy(&mut self, message: &[u8]) -> Result<(), RtMidiError> {
+        if let Some(ref mut d) = self.dummy {
+            d.send_message(message)
+        } else {
+            Err(RtMidiError::PortNotOpen)
+        }
+    }
+
     // CoreMIDI implementations
     #[cfg(target_os = "macos")]
     fn get_ports_coremidi(&self) -> Vec<MidiPort> {
@@ -352,13 +403,9 @@ impl MidiOutput {
         }
     }
 
-    // ALSA implementations. `AlsaMidiOutput` itself is currently a stub
-    // (see its own doc comment) — real sequencer I/O needs a Linux
-    // environment to write and verify against actual hardware/drivers —
-    // but the wiring here (constructing it, storing it in `self.platform`,
-    // and reading it back on close/send) matches the CoreMIDI pattern
-    // exactly, so filling in that stub later doesn't need any dispatch
-    // changes.
+    // ALSA implementations. The wiring here (constructing the backend,
+    // storing it in `self.platform`, and reading it back on close/send)
+    // matches the CoreMIDI pattern exactly.
     #[cfg(target_os = "linux")]
     fn get_ports_alsa(&self) -> Vec<MidiPort> {
         super::alsa_impl::get_output_ports()
@@ -397,8 +444,8 @@ impl MidiOutput {
         }
     }
 
-    // Windows MM implementations — same relationship to `WinMmMidiOutput`
-    // as the ALSA ones above have to `AlsaMidiOutput`.
+    // Windows MM implementations — same wiring as the ALSA ones above.
+    // `WinMmMidiOutput` itself is still a stub.
     #[cfg(target_os = "windows")]
     fn get_ports_winmm(&self) -> Vec<MidiPort> {
         super::winmm_impl::get_output_ports()
@@ -459,4 +506,29 @@ mod tests {
         let result = output.send_message(&[0x90, 60, 100]);
         assert!(matches!(result, Err(RtMidiError::PortNotOpen)));
     }
+
+    #[test]
+    fn test_send_midi_message() {
+        let mut output = MidiOutput::with_api(Api::Dummy, "Test").unwrap();
+        output
+            .open_virtual_port("mkmidilibrary-test-send-midi-message")
+            .unwrap();
+        let mut input = super::super::MidiInput::with_api(Api::Dummy, "Test").unwrap();
+        let index = input
+            .ports()
+            .iter()
+            .position(|p| p.name() == "mkmidilibrary-test-send-midi-message")
+            .unwrap();
+        input.open_port(index, "in").unwrap();
+
+        output
+            .send_midi_message(&MidiMessage::pitch_bend(1, 0x2800))
+            .unwrap();
+        assert_eq!(input.get_message().unwrap().data, vec![0xE1, 0x00, 0x50]);
+        let meta = MidiMessage::Meta(crate::midi::MetaEvent::EndOfTrack);
+        assert!(matches!(
+            output.send_midi_message(&meta),
+            Err(RtMidiError::InvalidMessage)
+        ));
+    }
 }
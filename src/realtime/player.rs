@@ -0,0 +1,785 @@
+//! Scheduled `MidiFile` playback over a `MidiOutput`
+//!
+//! `MidiPlayer` owns a `MidiOutput` and a dedicated playback thread that
+//! streams a file's events at their tempo-mapped times. The transport
+//! (play, pause, stop, seek, loop), tempo scaling and mute/solo are
+//! controlled from any thread. Whenever playback is interrupted, sounding
+//! notes are released and held pedals lifted, and on every (re)start the
+//! channel state at the play position (programs, controllers, pitch bend)
+//! is chased so playback from the middle of a file sounds right.
+
+use std::ops::Range;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::RtMidiError;
+use super::output::MidiOutput;
+use crate::midi::{
+    MidiEvent, MidiFile, MidiMessage, MidiTrack, NoteSortOrder, TempoMap, compare_events,
+};
+
+const SUSTAIN: u8 = 64;
+const SOSTENUTO: u8 = 66;
+
+/// Transport state of a `MidiPlayer`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlayerState {
+    /// Not playing; `play` starts from the beginning once the end has been
+    /// reached
+    #[default]
+    Stopped,
+    /// Streaming events
+    Playing,
+    /// Not playing; `play` resumes from the current position
+    Paused,
+}
+
+/// The file's channel and system events in playback order, with the
+/// timing needed to schedule them.
+struct Schedule {
+    /// Events in time order; `MidiEvent::track` is the source track
+    events: Vec<MidiEvent>,
+    /// Start time of each event in seconds
+    seconds: Vec<f64>,
+    tempo_map: TempoMap,
+    duration: f64,
+    /// All events as one track, for chasing channel state
+    chase: MidiTrack,
+    tracks: usize,
+}
+
+impl Schedule {
+    fn new(file: &MidiFile) -> Self {
+        let mut file = file.clone();
+        file.split_tracks();
+        file.make_absolute_ticks();
+
+        let mut events: Vec<MidiEvent> = file
+            .tracks()
+            .iter()
+            .enumerate()
+            .flat_map(|(index, track)| {
+                track
+                    .events()
+                    .iter()
+                    .filter(|e| !e.is_meta())
+                    .map(move |e| MidiEvent::with_track(e.tick(), e.message().clone(), index))
+            })
+            .collect();
+        events.sort_by(|a, b| compare_events(a, b, NoteSortOrder::NoteOffsBeforeOns));
+
+        let tempo_map = file.tempo_map();
+        let mut chase = MidiTrack::new();
+        for event in &events {
+            chase.add_event(event.clone());
+        }
+        Self {
+            seconds: events
+                .iter()
+                .map(|e| tempo_map.ticks_to_seconds(e.tick()))
+                .collect(),
+            events,
+            tempo_map,
+            duration: file.total_seconds(),
+            chase,
+            tracks: file.num_tracks(),
+        }
+    }
+
+    /// Index of the first event at or after `tick`
+    fn index_at(&self, tick: u64) -> usize {
+        self.events.partition_point(|e| e.tick() < tick)
+    }
+}
+
+/// Playback state shared by the controlling handle and the thread.
+struct Transport {
+    output: Option<MidiOutput>,
+    state: PlayerState,
+    /// Play position in file seconds at `anchor`
+    position: f64,
+    /// Play position in ticks, when it sits exactly on a tick (after a
+    /// seek); used for chasing and to pick the next event
+    position_tick: Option<u64>,
+    anchor: Instant,
+    /// Index of the next event to send
+    next: usize,
+    /// Position up to which events have been sent, in file seconds
+    processed: f64,
+    /// Whether channel state must be chased before sending events
+    needs_chase: bool,
+    tempo_scale: f64,
+    loop_range: Option<Range<u64>>,
+    track_mute: Vec<bool>,
+    track_solo: Vec<bool>,
+    channel_mute: [bool; 16],
+    channel_solo: [bool; 16],
+    /// Notes sent and not yet released, as (track, channel, key)
+    sounding: Vec<(usize, u8, u8)>,
+    /// Pedals held down, as (channel, controller)
+    pedals: Vec<(u8, u8)>,
+    error: Option<RtMidiError>,
+    shutdown: bool,
+}
+
+impl Transport {
+    /// Current play position in file seconds
+    fn now(&self) -> f64 {
+        match self.state {
+            PlayerState::Playing => {
+                self.position + self.anchor.elapsed().as_secs_f64() * self.tempo_scale
+            }
+            _ => self.position,
+        }
+    }
+
+    fn is_audible(&self, track: usize, channel: Option<u8>) -> bool {
+        let track_solo = self.track_solo.iter().any(|&s| s);
+        let track_on = !self.track_mute.get(track).copied().unwrap_or(false)
+            && (!track_solo || self.track_solo.get(track).copied().unwrap_or(false));
+        let channel_on = channel.is_none_or(|channel| {
+            let channel = (channel & 0x0F) as usize;
+            let channel_solo = self.channel_solo.iter().any(|&s| s);
+            !self.channel_mute[channel] && (!channel_solo || self.channel_solo[channel])
+        });
+        track_on && channel_on
+    }
+
+    /// Send a message, keeping the first error for `take_error`.
+    fn send(&mut self, message: &MidiMessage) {
+        let Some(output) = self.output.as_mut() else {
+            return;
+        };
+        if let MidiMessage::ControlChange {
+            channel,
+            controller: controller @ (SUSTAIN | SOSTENUTO),
+            value,
+        } = *message
+        {
+            self.pedals.retain(|&p| p != (channel, controller));
+            if value >= 64 {
+                self.pedals.push((channel, controller));
+            }
+        }
+        if let Err(error) = output.send_midi_message(message)
+            && self.error.is_none()
+        {
+            self.error = Some(error);
+        }
+    }
+
+    fn dispatch(&mut self, event: &MidiEvent) {
+        let track = event.track();
+        match *event.message() {
+            MidiMessage::NoteOn {
+                channel,
+                key,
+                velocity,
+            } if velocity > 0 => {
+                if self.is_audible(track, Some(channel)) {
+                    self.sounding.push((track, channel, key));
+                    self.send(event.message());
+                }
+            }
+            MidiMessage::NoteOn { channel, key, .. }
+            | MidiMessage::NoteOff { channel, key, .. } => {
+                // A note that was sent is always released, even if its
+                // track has been muted since.
+                let sounding = self
+                    .sounding
+                    .iter()
+                    .position(|&n| n == (track, channel, key));
+                if let Some(index) = sounding {
+                    self.sounding.swap_remove(index);
+                    self.send(event.message());
+                } else if self.is_audible(track, Some(channel)) {
+                    self.send(event.message());
+                }
+            }
+            _ => {
+                if self.is_audible(track, event.channel()) {
+                    self.send(event.message());
+                }
+            }
+        }
+    }
+
+    /// Send note-offs for the sounding notes that `release` selects.
+    fn release_notes(&mut self, release: impl Fn(&Self, usize, u8) -> bool) {
+        let (released, kept): (Vec<_>, Vec<_>) = self
+            .sounding
+            .iter()
+            .copied()
+            .partition(|&(track, channel, _)| release(self, track, channel));
+        self.sounding = kept;
+        for (_, channel, key) in released {
+            self.send(&MidiMessage::note_off(channel, key, 0));
+        }
+    }
+
+    /// Release every sounding note and lift every held pedal.
+    fn release_all(&mut self) {
+        self.release_notes(|_, _, _| true);
+        for (channel, controller) in std::mem::take(&mut self.pedals) {
+            self.send(&MidiMessage::control_change(channel, controller, 0));
+        }
+    }
+
+    /// Release the notes of tracks and channels that are no longer audible.
+    fn release_silenced(&mut self) {
+        self.release_notes(|transport, track, channel| !transport.is_audible(track, Some(channel)));
+    }
+
+    /// Move the play position to `tick`.
+    fn jump(&mut self, schedule: &Schedule, tick: u64) {
+        self.release_all();
+        self.position = schedule.tempo_map.ticks_to_seconds(tick);
+        self.position_tick = Some(tick);
+        self.processed = self.position;
+        self.anchor = Instant::now();
+        self.next = schedule.index_at(tick);
+        self.needs_chase = true;
+    }
+
+    /// Send the channel state at the play position.
+    fn chase(&mut self, schedule: &Schedule) {
+        self.needs_chase = false;
+        let tick = self
+            .position_tick
+            .unwrap_or_else(|| schedule.tempo_map.seconds_to_ticks(self.position));
+        for event in schedule.chase.chase_events(tick) {
+            self.send(event.message());
+        }
+    }
+
+    /// Loop range in seconds, if the play position is inside it
+    fn pending_loop(&self, schedule: &Schedule) -> Option<(u64, f64)> {
+        let range = self.loop_range.as_ref()?;
+        let end = schedule.tempo_map.ticks_to_seconds(range.end);
+        (self.processed < end).then_some((range.start, end))
+    }
+
+    /// Stop playback at the current position.
+    fn halt(&mut self, state: PlayerState) {
+        self.position = self.now();
+        self.state = state;
+        self.release_all();
+    }
+}
+
+/// State shared between `MidiPlayer` and its thread.
+struct Shared {
+    transport: Mutex<Transport>,
+    /// Wakes the thread on transport changes, and `wait` on stops
+    changed: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Transport> {
+        self.transport.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Plays a `MidiFile` to a `MidiOutput` on a dedicated thread
+///
+/// Event times come from the file's tempo map. Meta events are not sent.
+/// Dropping the player (or `into_output`) stops playback, releasing any
+/// sounding notes.
+pub struct MidiPlayer {
+    shared: Arc<Shared>,
+    schedule: Arc<Schedule>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MidiPlayer {
+    /// Create a stopped player for `file`, sending to `output` (which
+    /// should have a port open)
+    pub fn new(file: &MidiFile, output: MidiOutput) -> Result<Self, RtMidiError> {
+        let schedule = Arc::new(Schedule::new(file));
+        let shared = Arc::new(Shared {
+            transport: Mutex::new(Transport {
+                output: Some(output),
+                state: PlayerState::Stopped,
+                position: 0.0,
+                position_tick: Some(0),
+                anchor: Instant::now(),
+                next: 0,
+                processed: 0.0,
+                needs_chase: true,
+                tempo_scale: 1.0,
+                loop_range: None,
+                track_mute: vec![false; schedule.tracks],
+                track_solo: vec![false; schedule.tracks],
+                channel_mute: [false; 16],
+                channel_solo: [false; 16],
+                sounding: Vec::new(),
+                pedals: Vec::new(),
+                error: None,
+                shutdown: false,
+            }),
+            changed: Condvar::new(),
+        });
+
+        let thread = {
+            let shared = Arc::clone(&shared);
+            let schedule = Arc::clone(&schedule);
+            std::thread::Builder::new()
+                .name("mkmidilibrary-player".to_string())
+                .spawn(move || {
+                    raise_thread_priority();
+                    playback_thread(&shared, &schedule);
+                })
+                .map_err(|e| RtMidiError::ThreadError(e.to_string()))?
+        };
+        Ok(Self {
+            shared,
+            schedule,
+            thread: Some(thread),
+        })
+    }
+
+    fn update<T>(&self, change: impl FnOnce(&mut Transport, &Schedule) -> T) -> T {
+        let mut transport = self.shared.lock();
+        let result = change(&mut transport, &self.schedule);
+        self.shared.changed.notify_all();
+        result
+    }
+
+    /// Start or resume playback. After the end of the file has been
+    /// reached, playback starts again from the beginning.
+    pub fn play(&self) {
+        self.update(|transport, schedule| {
+            if transport.state == PlayerState::Playing {
+                return;
+            }
+            if transport.position >= schedule.duration && transport.next >= schedule.events.len() {
+                transport.jump(schedule, 0);
+            }
+            transport.anchor = Instant::now();
+            transport.state = PlayerState::Playing;
+        });
+    }
+
+    /// Pause playback, keeping the position
+    pub fn pause(&self) {
+        self.update(|transport, _| {
+            if transport.state == PlayerState::Playing {
+                transport.halt(PlayerState::Paused);
+                transport.needs_chase = true;
+            }
+        });
+    }
+
+    /// Stop playback and return to the beginning
+    pub fn stop(&self) {
+        self.update(|transport, schedule| {
+            transport.halt(PlayerState::Stopped);
+            transport.jump(schedule, 0);
+        });
+    }
+
+    /// Current transport state
+    pub fn state(&self) -> PlayerState {
+        self.shared.lock().state
+    }
+
+    /// Block until playback stops or pauses. Never returns while a loop
+    /// range is set and playing.
+    pub fn wait(&self) {
+        let mut transport = self.shared.lock();
+        while transport.state == PlayerState::Playing {
+            transport = self
+                .shared
+                .changed
+                .wait(transport)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    /// Length of the file in seconds (at a tempo scale of 1)
+    pub fn duration_seconds(&self) -> f64 {
+        self.schedule.duration
+    }
+
+    /// Play position in file seconds (at a tempo scale of 1)
+    pub fn position_seconds(&self) -> f64 {
+        self.shared.lock().now()
+    }
+
+    /// Play position in ticks
+    pub fn position_ticks(&self) -> u64 {
+        let transport = self.shared.lock();
+        match (transport.state, transport.position_tick) {
+            (PlayerState::Playing, _) | (_, None) => {
+                self.schedule.tempo_map.seconds_to_ticks(transport.now())
+            }
+            (_, Some(tick)) => tick,
+        }
+    }
+
+    /// Move the play position to `seconds` into the file. Sounding notes
+    /// are released and the channel state at the new position is sent.
+    pub fn seek_seconds(&self, seconds: f64) {
+        let tick = self.schedule.tempo_map.seconds_to_ticks(seconds.max(0.0));
+        self.seek_ticks(tick);
+    }
+
+    /// Move the play position to `tick` (see `seek_seconds`)
+    pub fn seek_ticks(&self, tick: u64) {
+        self.update(|transport, schedule| transport.jump(schedule, tick));
+    }
+
+    /// Loop playback over `range` (in ticks) once the play position
+    /// enters it, or stop looping with `None`. Empty ranges are ignored.
+    pub fn set_loop(&self, range: Option<Range<u64>>) {
+        self.update(|transport, _| {
+            transport.loop_range = range.filter(|r| r.start < r.end);
+        });
+    }
+
+    /// Current loop range in ticks
+    pub fn loop_range(&self) -> Option<Range<u64>> {
+        self.shared.lock().loop_range.clone()
+    }
+
+    /// Play faster (> 1) or slower (< 1) than the file's tempo
+    pub fn set_tempo_scale(&self, scale: f64) {
+        if !(scale.is_finite() && scale > 0.0) {
+            return;
+        }
+        self.update(|transport, _| {
+            transport.position = transport.now();
+            transport.anchor = Instant::now();
+            transport.tempo_scale = scale;
+        });
+    }
+
+    /// Current tempo scale
+    pub fn tempo_scale(&self) -> f64 {
+        self.shared.lock().tempo_scale
+    }
+
+    /// Mute or unmute a track. Muting releases the track's sounding notes.
+    pub fn set_track_mute(&self, track: usize, mute: bool) {
+        self.update(|transport, _| {
+            if let Some(flag) = transport.track_mute.get_mut(track) {
+                *flag = mute;
+            }
+            transport.release_silenced();
+        });
+    }
+
+    /// Solo or unsolo a track. While any track is soloed, only soloed
+    /// tracks play.
+    pub fn set_track_solo(&self, track: usize, solo: bool) {
+        self.update(|transport, _| {
+            if let Some(flag) = transport.track_solo.get_mut(track) {
+                *flag = solo;
+            }
+            transport.release_silenced();
+        });
+    }
+
+    /// Mute or unmute a channel (0-15)
+    pub fn set_channel_mute(&self, channel: u8, mute: bool) {
+        self.update(|transport, _| {
+            transport.channel_mute[(channel & 0x0F) as usize] = mute;
+            transport.release_silenced();
+        });
+    }
+
+    /// Solo or unsolo a channel (0-15). While any channel is soloed, only
+    /// soloed channels play.
+    pub fn set_channel_solo(&self, channel: u8, solo: bool) {
+        self.update(|transport, _| {
+            transport.channel_solo[(channel & 0x0F) as usize] = solo;
+            transport.release_silenced();
+        });
+    }
+
+    /// Take the first error the output reported since the last call
+    pub fn take_error(&self) -> Option<RtMidiError> {
+        self.shared.lock().error.take()
+    }
+
+    /// Stop playback and the thread, and hand back the output
+    pub fn into_output(mut self) -> MidiOutput {
+        self.shutdown();
+        self.shared
+            .lock()
+            .output
+            .take()
+            .expect("the output is only taken here")
+    }
+
+    fn shutdown(&mut self) {
+        self.update(|transport, _| {
+            transport.halt(PlayerState::Stopped);
+            transport.shutdown = true;
+        });
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MidiPlayer {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            self.shutdown();
+        }
+    }
+}
+
+fn playback_thread(shared: &Shared, schedule: &Schedule) {
+    let mut transport = shared.lock();
+    loop {
+        if transport.shutdown {
+            return;
+        }
+        if transport.state != PlayerState::Playing {
+            transport = shared
+                .changed
+                .wait(transport)
+                .unwrap_or_else(|e| e.into_inner());
+            continue;
+        }
+        if transport.needs_chase {
+            transport.chase(schedule);
+        }
+
+        let now = transport.now();
+        let pending_loop = transport.pending_loop(schedule);
+        if let Some((start, end)) = pending_loop
+            && now >= end
+        {
+            while transport.next < schedule.events.len() && schedule.seconds[transport.next] < end {
+                let index = transport.next;
+                transport.next += 1;
+                transport.dispatch(&schedule.events[index]);
+            }
+            transport.jump(schedule, start);
+            continue;
+        }
+        while transport.next < schedule.events.len() && schedule.seconds[transport.next] <= now {
+            let index = transport.next;
+            transport.next += 1;
+            transport.dispatch(&schedule.events[index]);
+        }
+        transport.processed = now;
+        transport.position_tick = None;
+
+        let finished = transport.next >= schedule.events.len() && now >= schedule.duration;
+        if finished && pending_loop.is_none() {
+            transport.halt(PlayerState::Stopped);
+            transport.position = schedule.duration;
+            shared.changed.notify_all();
+            continue;
+        }
+
+        let mut target = schedule
+            .seconds
+            .get(transport.next)
+            .copied()
+            .unwrap_or(schedule.duration);
+        if let Some((_, end)) = pending_loop {
+            target = target.min(end);
+        }
+        let wait = ((target - now) / transport.tempo_scale).max(0.0);
+        transport = shared
+            .changed
+            .wait_timeout(transport, Duration::from_secs_f64(wait))
+            .unwrap_or_else(|e| e.into_inner())
+            .0;
+    }
+}
+
+/// Ask the OS to run the calling (playback or clock) thread ahead of normal
+/// threads. Without the privileges for that, it runs at normal priority.
+#[cfg(unix)]
+pub(super) fn raise_thread_priority() {
+    // SAFETY: plain libc calls on the current thread with a valid
+    // `sched_param`.
+    unsafe {
+        let param = libc::sched_param {
+            sched_priority: libc::sched_get_priority_min(libc::SCHED_FIFO),
+        };
+        libc::pthread_setschedparam(libc::pthread_self(), libc::SCHED_FIFO, &param);
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub(super) fn raise_thread_priority() {
+    use winapi::um::processthreadsapi::{GetCurrentThread, SetThreadPriority};
+    use winapi::um::winbase::THREAD_PRIORITY_TIME_CRITICAL;
+    // SAFETY: `GetCurrentThread` returns a pseudo-handle that is always
+    // valid for the calling thread.
+    unsafe {
+        SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_TIME_CRITICAL as i32);
+    }
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+pub(super) fn raise_thread_priority() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realtime::{Api, MidiInput};
+
+    /// A player on a Dummy virtual port named `port` and an input
+    /// listening to it.
+    fn player_with_input(file: &MidiFile, port: &str) -> (MidiPlayer, MidiInput) {
+        let mut output = MidiOutput::with_api(Api::Dummy, "Test").unwrap();
+        output.open_virtual_port(port).unwrap();
+        let mut input = MidiInput::with_api(Api::Dummy, "Test").unwrap();
+        let index = input.ports().iter().position(|p| p.name() == port).unwrap();
+        input.open_port(index, "in").unwrap();
+        (MidiPlayer::new(file, output).unwrap(), input)
+    }
+
+    fn received(input: &mut MidiInput) -> Vec<Vec<u8>> {
+        std::iter::from_fn(|| input.get_message())
+            .map(|m| m.data)
+            .collect()
+    }
+
+    /// Add messages from `input` to `messages` until `done` holds for them,
+    /// giving up after ten seconds.
+    fn receive_until(
+        input: &mut MidiInput,
+        messages: &mut Vec<Vec<u8>>,
+        done: impl Fn(&[Vec<u8>]) -> bool,
+    ) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            messages.extend(received(input));
+            if done(messages) || Instant::now() >= deadline {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn two_track_file() -> MidiFile {
+        let mut file = MidiFile::new();
+        file.add_tracks(3);
+        file.add_tempo(0, 0, 600.0).unwrap(); // 480 ticks = 0.1 s
+        file.add_patch_change(1, 0, 0, 5).unwrap();
+        file.add_note(1, 0, 120, 0, 60, 100).unwrap();
+        file.add_note(2, 240, 120, 1, 64, 90).unwrap();
+        file.finalize();
+        file
+    }
+
+    #[test]
+    fn test_player_plays_in_order() {
+        let file = two_track_file();
+        let (player, mut input) = player_with_input(&file, "mkmidilibrary-test-player-order");
+        assert!((player.duration_seconds() - 0.075).abs() < 1e-9);
+
+        let start = Instant::now();
+        player.set_tempo_scale(2.0);
+        player.play();
+        player.wait();
+        let elapsed = start.elapsed().as_secs_f64();
+        assert_eq!(player.state(), PlayerState::Stopped);
+        assert!(elapsed >= 0.03, "played too fast: {elapsed}");
+        assert_eq!(
+            received(&mut input),
+            vec![
+                vec![0xC0, 5],
+                vec![0x90, 60, 100],
+                vec![0x80, 60, 0],
+                vec![0x91, 64, 90],
+                vec![0x81, 64, 0],
+            ]
+        );
+        assert!(player.take_error().is_none());
+    }
+
+    #[test]
+    fn test_player_mute_solo_and_seek() {
+        let file = two_track_file();
+        let (player, mut input) = player_with_input(&file, "mkmidilibrary-test-player-solo");
+        player.set_track_solo(2, true);
+        player.play();
+        player.wait();
+        assert_eq!(
+            received(&mut input),
+            vec![vec![0x91, 64, 90], vec![0x81, 64, 0]]
+        );
+
+        // Seeking past the program change chases it.
+        player.set_track_solo(2, false);
+        player.set_channel_mute(1, true);
+        player.seek_ticks(60);
+        assert_eq!(player.position_ticks(), 60);
+        assert!((player.position_seconds() - 0.0125).abs() < 1e-9);
+        player.play();
+        player.wait();
+        assert_eq!(received(&mut input), vec![vec![0xC0, 5], vec![0x80, 60, 0]]);
+    }
+
+    #[test]
+    fn test_player_loops() {
+        let file = two_track_file();
+        let (player, mut input) = player_with_input(&file, "mkmidilibrary-test-player-loop");
+        player.set_loop(Some(0..240));
+        player.play();
+        let is_note_on = |m: &Vec<u8>| m[..] == [0x90, 60, 100];
+        let mut messages = Vec::new();
+        receive_until(&mut input, &mut messages, |messages| {
+            messages.iter().filter(|m| is_note_on(m)).count() >= 2
+        });
+        assert_eq!(player.state(), PlayerState::Playing);
+        player.pause();
+        assert!(player.position_ticks() < 240);
+        messages.extend(received(&mut input));
+        let note_ons = messages.iter().filter(|m| is_note_on(m)).count();
+        assert!(note_ons >= 2, "{messages:?}");
+        assert!(!messages.contains(&vec![0x91, 64, 90]));
+        let note_offs = messages.iter().filter(|m| m[0] == 0x80).count();
+        assert_eq!(note_offs, note_ons);
+    }
+
+    #[test]
+    fn test_player_releases_notes_on_stop() {
+        let mut file = MidiFile::new();
+        file.add_tracks(2);
+        file.add_sustain_on(1, 0, 3).unwrap();
+        file.add_note(1, 0, 48_000, 3, 60, 100).unwrap();
+        file.finalize();
+        let (player, mut input) = player_with_input(&file, "mkmidilibrary-test-player-stop");
+        let note_on = vec![0x93, 60, 100];
+        player.play();
+        let mut messages = Vec::new();
+        receive_until(&mut input, &mut messages, |messages| {
+            messages.contains(&note_on)
+        });
+        player.stop();
+        assert_eq!(player.state(), PlayerState::Stopped);
+        assert_eq!(player.position_ticks(), 0);
+        messages.extend(received(&mut input));
+        assert_eq!(
+            messages,
+            vec![
+                vec![0xB3, 64, 127],
+                vec![0x93, 60, 100],
+                vec![0x83, 60, 0],
+                vec![0xB3, 64, 0],
+            ]
+        );
+
+        player.play();
+        let mut messages = Vec::new();
+        receive_until(&mut input, &mut messages, |messages| {
+            messages.contains(&note_on)
+        });
+        let output = player.into_output();
+        assert!(output.is_port_open());
+        messages.extend(received(&mut input));
+        assert_eq!(messages.last(), Some(&vec![0xB3, 64, 0]));
+    }
+}
@@ -0,0 +1,629 @@
+//! Recording from a `MidiInput` into a `MidiTrack`
+//!
+//! `MidiRecorder` attaches to a `MidiInput` callback and turns incoming
+//! messages into events at absolute ticks on a fixed-tempo timeline. The
+//! first message after `start` is placed by the wall clock; later ones
+//! add up the input's relative timestamps, so the backend's timing is
+//! kept. Recording can be limited to a punch range, preceded by a
+//! count-in, and merged into an existing file as an overdub or a replace.
+
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use super::input::MidiInput;
+use crate::midi::{
+    MetaEvent, MidiError, MidiEvent, MidiFile, MidiMessage, MidiTrack, TimeDivision,
+};
+
+/// How a take combines with the events already in a track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RecordMode {
+    /// Add the take on top of the existing events
+    #[default]
+    Overdub,
+    /// Clear the recorded range of the track first: notes starting inside
+    /// it and other channel events in it are removed, and notes sounding
+    /// into it are cut off at its start
+    Replace,
+}
+
+/// Settings for `MidiRecorder`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecorderOptions {
+    ticks_per_quarter: u16,
+    bpm: f64,
+    start_tick: u64,
+    pre_roll: u64,
+    punch: Option<Range<u64>>,
+    mode: RecordMode,
+}
+
+impl Default for RecorderOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RecorderOptions {
+    /// Record at 480 PPQN and 120 BPM from tick 0, without count-in or
+    /// punch range, overdubbing
+    pub fn new() -> Self {
+        Self {
+            ticks_per_quarter: 480,
+            bpm: 120.0,
+            start_tick: 0,
+            pre_roll: 0,
+            punch: None,
+            mode: RecordMode::Overdub,
+        }
+    }
+
+    /// Resolution of the recorded track, at least 1 and at most
+    /// `TimeDivision::MAX_TICKS_PER_QUARTER` (the range a MIDI file header
+    /// can store, so ticks match the file `MidiRecorder::to_file` returns)
+    pub fn with_ticks_per_quarter(mut self, ticks_per_quarter: u16) -> Self {
+        self.ticks_per_quarter = ticks_per_quarter.clamp(1, TimeDivision::MAX_TICKS_PER_QUARTER);
+        self
+    }
+
+    /// Tempo of the recording timeline
+    pub fn with_bpm(mut self, bpm: f64) -> Self {
+        if bpm.is_finite() && bpm > 0.0 {
+            self.bpm = bpm;
+        }
+        self
+    }
+
+    /// Tick at which recording starts (after the count-in)
+    pub fn with_start_tick(mut self, tick: u64) -> Self {
+        self.start_tick = tick;
+        self
+    }
+
+    /// Count-in before `start_tick`, in ticks. Messages during the count-in
+    /// are not recorded.
+    pub fn with_pre_roll(mut self, ticks: u64) -> Self {
+        self.pre_roll = ticks;
+        self
+    }
+
+    /// Only record inside `range` (in ticks). Notes still held at the
+    /// punch-out are ended there. A reversed range is swapped.
+    pub fn with_punch(mut self, range: Range<u64>) -> Self {
+        self.punch = Some(range.start.min(range.end)..range.start.max(range.end));
+        self
+    }
+
+    /// How `MidiRecorder::merge_into` combines the take with existing events
+    pub fn with_mode(mut self, mode: RecordMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Ticks per quarter note
+    pub fn ticks_per_quarter(&self) -> u16 {
+        self.ticks_per_quarter
+    }
+
+    /// Tempo in BPM
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    /// Recording start tick
+    pub fn start_tick(&self) -> u64 {
+        self.start_tick
+    }
+
+    /// Count-in length in ticks
+    pub fn pre_roll(&self) -> u64 {
+        self.pre_roll
+    }
+
+    /// Punch range in ticks
+    pub fn punch(&self) -> Option<&Range<u64>> {
+        self.punch.as_ref()
+    }
+
+    /// Record mode
+    pub fn mode(&self) -> RecordMode {
+        self.mode
+    }
+
+    /// Timeline tick `seconds` after `start`, counting from the start of
+    /// the count-in (negative before tick 0)
+    fn tick_at(&self, seconds: f64) -> i64 {
+        let ticks = seconds * self.bpm / 60.0 * self.ticks_per_quarter as f64;
+        (self.start_tick as i64 - self.pre_roll as i64) + ticks.round() as i64
+    }
+
+    /// The part of the timeline that gets recorded; empty when the punch
+    /// range ends before the start tick
+    fn window(&self) -> Range<u64> {
+        match &self.punch {
+            Some(punch) => {
+                let start = punch.start.max(self.start_tick);
+                start..punch.end.max(start)
+            }
+            None => self.start_tick..u64::MAX,
+        }
+    }
+}
+
+/// The take being recorded.
+struct Take {
+    options: RecorderOptions,
+    started: Option<Instant>,
+    /// Time of the last message, in seconds after `started`
+    last: Option<f64>,
+    events: Vec<MidiEvent>,
+    /// Recorded notes whose note-off has not arrived, as (channel, key)
+    held: Vec<(u8, u8)>,
+    /// Tick range covered by the last take
+    recorded: Option<Range<u64>>,
+}
+
+impl Take {
+    /// End held notes at `tick`.
+    fn release_held(&mut self, tick: u64) {
+        for (channel, key) in std::mem::take(&mut self.held) {
+            self.events.push(MidiEvent::note_off(tick, channel, key, 0));
+        }
+    }
+
+    fn record(&mut self, seconds: f64, data: &[u8]) {
+        let Some((message, _)) = MidiMessage::from_bytes(data) else {
+            return;
+        };
+        if !message.is_channel_message() && !matches!(message, MidiMessage::SysEx(_)) {
+            return;
+        }
+        let Ok(tick) = u64::try_from(self.options.tick_at(seconds)) else {
+            return;
+        };
+        let window = self.options.window();
+        if tick >= window.end && !self.held.is_empty() {
+            self.release_held(window.end);
+        }
+        match message {
+            MidiMessage::NoteOn {
+                channel,
+                key,
+                velocity,
+            } if velocity > 0 => {
+                if window.contains(&tick) {
+                    self.held.push((channel, key));
+                    self.events.push(MidiEvent::new(tick, message));
+                }
+            }
+            MidiMessage::NoteOn { channel, key, .. }
+            | MidiMessage::NoteOff { channel, key, .. } => {
+                // Only the note-offs of recorded notes are kept.
+                if let Some(index) = self.held.iter().position(|&n| n == (channel, key)) {
+                    self.held.remove(index);
+                    self.events.push(MidiEvent::new(tick, message));
+                }
+            }
+            _ => {
+                if window.contains(&tick) {
+                    self.events.push(MidiEvent::new(tick, message));
+                }
+            }
+        }
+    }
+
+    fn now(&self) -> Option<f64> {
+        self.started.map(|started| started.elapsed().as_secs_f64())
+    }
+}
+
+/// Records messages from a `MidiInput` into a track
+///
+/// The recorder is a cheap handle: `attach` gives a clone of it to the
+/// input's callback, and the take can be read at any time with `track`.
+#[derive(Clone)]
+pub struct MidiRecorder {
+    take: Arc<Mutex<Take>>,
+}
+
+impl MidiRecorder {
+    /// Create a stopped recorder
+    pub fn new(options: RecorderOptions) -> Self {
+        Self {
+            take: Arc::new(Mutex::new(Take {
+                options,
+                started: None,
+                last: None,
+                events: Vec::new(),
+                held: Vec::new(),
+                recorded: None,
+            })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Take> {
+        self.take.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Recording settings
+    pub fn options(&self) -> RecorderOptions {
+        self.lock().options.clone()
+    }
+
+    /// Change the settings. Ignored while recording, so a take is always
+    /// recorded on one timeline.
+    pub fn set_options(&self, options: RecorderOptions) {
+        let mut take = self.lock();
+        if take.started.is_none() {
+            take.options = options;
+        }
+    }
+
+    /// Route `input`'s messages to this recorder (replacing any callback
+    /// it had). Messages arriving while stopped are ignored.
+    pub fn attach(&self, input: &mut MidiInput) {
+        let recorder = self.clone();
+        input.set_callback(move |timestamp, data| recorder.receive(timestamp, data));
+    }
+
+    /// Handle a message with a timestamp relative to the previous one, as
+    /// delivered by a `MidiInput` callback or `TimestampedMessage`
+    pub fn receive(&self, timestamp: f64, data: &[u8]) {
+        let mut take = self.lock();
+        let Some(now) = take.now() else {
+            return;
+        };
+        let seconds = match take.last {
+            Some(last) => last + timestamp.max(0.0),
+            None => now,
+        };
+        take.last = Some(seconds);
+        take.record(seconds, data);
+    }
+
+    /// Record a message at `seconds` after `start` (counting the count-in)
+    pub fn record_at(&self, seconds: f64, data: &[u8]) {
+        let mut take = self.lock();
+        if take.started.is_some() {
+            take.last = Some(seconds);
+            take.record(seconds, data);
+        }
+    }
+
+    /// Start a new take, discarding the previous one. The count-in, if
+    /// any, starts now.
+    pub fn start(&self) {
+        let mut take = self.lock();
+        take.started = Some(Instant::now());
+        take.last = None;
+        take.events.clear();
+        take.held.clear();
+        take.recorded = None;
+    }
+
+    /// Stop recording now (see `stop_at`)
+    pub fn stop(&self) {
+        let seconds = self.lock().now();
+        if let Some(seconds) = seconds {
+            self.stop_at(seconds);
+        }
+    }
+
+    /// Stop recording at `seconds` after `start`. Notes still held are
+    /// ended there, or at the punch-out if that comes first.
+    pub fn stop_at(&self, seconds: f64) {
+        let mut take = self.lock();
+        if take.started.take().is_none() {
+            return;
+        }
+        let window = take.options.window();
+        let tick = u64::try_from(take.options.tick_at(seconds))
+            .unwrap_or(0)
+            .max(window.start)
+            .min(window.end);
+        take.release_held(tick);
+        take.recorded = (!window.is_empty()).then_some(window.start..tick);
+    }
+
+    /// Whether a take is being recorded (including the count-in)
+    pub fn is_recording(&self) -> bool {
+        self.lock().started.is_some()
+    }
+
+    /// Whether the count-in is still running
+    pub fn in_pre_roll(&self) -> bool {
+        let take = self.lock();
+        take.now()
+            .is_some_and(|seconds| take.options.tick_at(seconds) < take.options.start_tick as i64)
+    }
+
+    /// Current timeline position in ticks while recording (0 during a
+    /// count-in that starts before tick 0)
+    pub fn position_ticks(&self) -> Option<u64> {
+        let take = self.lock();
+        take.now()
+            .map(|seconds| take.options.tick_at(seconds).max(0) as u64)
+    }
+
+    /// The take so far as a sorted track with linked notes and End of
+    /// Track. Notes still held are left open.
+    pub fn track(&self) -> MidiTrack {
+        let take = self.lock();
+        let mut track = MidiTrack::new();
+        for event in &take.events {
+            track.add_event(event.clone());
+        }
+        track.sort();
+        track.add_end_of_track();
+        track.link_note_events();
+        track
+    }
+
+    /// The take as a format 1 file: a conductor track with the recording
+    /// tempo, then the take
+    pub fn to_file(&self) -> MidiFile {
+        let options = self.options();
+        let mut file = MidiFile::with_format(
+            crate::midi::MidiFormat::MultiTrack,
+            options.ticks_per_quarter,
+        );
+        file.add_track().add_event(MidiEvent::new(
+            0,
+            MidiMessage::Meta(MetaEvent::tempo_from_bpm(options.bpm)),
+        ));
+        file.add_track_from(self.track());
+        file.finalize();
+        file.link_note_events();
+        file
+    }
+
+    /// Merge the take into `track_index` of `file`, rescaling ticks to the
+    /// file's resolution. In `RecordMode::Replace` the recorded range (the
+    /// punch range, or from the start tick to where recording stopped) is
+    /// cleared first.
+    pub fn merge_into(&self, file: &mut MidiFile, track_index: usize) -> Result<(), MidiError> {
+        let (options, recorded) = {
+            let take = self.lock();
+            (take.options.clone(), take.recorded.clone())
+        };
+        let take = self.track();
+        file.make_absolute_ticks();
+        let scale =
+            |tick: u64| tick * file.ticks_per_quarter() as u64 / options.ticks_per_quarter as u64;
+        let mut events: Vec<MidiEvent> = take
+            .events()
+            .iter()
+            .filter(|e| !e.is_meta())
+            .map(|e| MidiEvent::new(scale(e.tick()), e.message().clone()))
+            .collect();
+        let range = recorded.map(|r| scale(r.start)..scale(r.end));
+
+        let track = file
+            .track_mut(track_index)
+            .ok_or(MidiError::TrackOutOfBounds(track_index))?;
+        let mut merged = match (options.mode, range) {
+            (RecordMode::Replace, Some(range)) => clear_range(track, range),
+            _ => track.clone(),
+        };
+        merged
+            .events_mut()
+            .retain(|e| !matches!(e.message(), MidiMessage::Meta(MetaEvent::EndOfTrack)));
+        for event in events.drain(..) {
+            merged.add_event(event);
+        }
+        merged.sort();
+        merged.add_end_of_track();
+        merged.link_note_events();
+        *track = merged;
+        Ok(())
+    }
+}
+
+/// `track` without the channel events in `range`: notes starting inside it
+/// are removed with their note-offs, notes sounding into it end at its
+/// start, and meta events are kept.
+fn clear_range(track: &MidiTrack, range: Range<u64>) -> MidiTrack {
+    let mut cleared = MidiTrack::new();
+    // Notes started before the range, and notes removed, as (channel, key)
+    let mut sounding: Vec<(u8, u8)> = Vec::new();
+    let mut removed: Vec<(u8, u8)> = Vec::new();
+    for event in track.events() {
+        let tick = event.tick();
+        let mut event = event.clone();
+        event.unlink_event();
+        match (event.channel(), event.key()) {
+            (Some(channel), Some(key)) if event.is_note_on() => {
+                if range.contains(&tick) {
+                    removed.push((channel, key));
+                    continue;
+                }
+                if tick < range.start {
+                    sounding.push((channel, key));
+                }
+            }
+            (Some(channel), Some(key)) if event.is_note_off() => {
+                if let Some(index) = removed.iter().position(|&n| n == (channel, key)) {
+                    removed.remove(index);
+                    continue;
+                }
+                if let Some(index) = sounding.iter().position(|&n| n == (channel, key)) {
+                    sounding.remove(index);
+                    if tick > range.start {
+                        event.set_tick(range.start);
+                    }
+                } else if range.contains(&tick) {
+                    continue;
+                }
+            }
+            (Some(_), _) if range.contains(&tick) => continue,
+            _ => {}
+        }
+        cleared.add_event(event);
+    }
+    cleared.sort();
+    cleared
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::realtime::{Api, MidiOutput};
+
+    fn notes(track: &MidiTrack) -> Vec<(u64, u64, u8)> {
+        track
+            .events()
+            .iter()
+            .filter(|e| e.is_note_on())
+            .map(|e| {
+                (
+                    e.tick(),
+                    e.tick_duration(track.events()).unwrap(),
+                    e.key().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_recorder_pre_roll_and_punch() {
+        // 120 BPM at 480 PPQN: 960 ticks per second.
+        let options = RecorderOptions::new()
+            .with_start_tick(1920)
+            .with_pre_roll(960)
+            .with_punch(2400..2880);
+        let recorder = MidiRecorder::new(options);
+        recorder.record_at(0.1, &[0x90, 50, 100]);
+        assert!(!recorder.is_recording());
+
+        recorder.start();
+        recorder.record_at(0.5, &[0x90, 48, 100]); // count-in: dropped
+        recorder.record_at(0.6, &[0x80, 48, 0]);
+        recorder.record_at(1.25, &[0x90, 60, 100]); // tick 2160: before punch-in
+        recorder.record_at(1.5, &[0x90, 62, 100]); // tick 2400
+        recorder.record_at(1.6, &[0xB0, 1, 30]);
+        recorder.record_at(1.75, &[0x80, 60, 0]);
+        recorder.record_at(1.75, &[0x90, 64, 0]); // not recorded
+        recorder.record_at(1.8, &[0x90, 64, 90]);
+        recorder.record_at(2.25, &[0xB0, 1, 40]); // after punch-out
+        recorder.stop_at(2.5);
+        assert!(!recorder.is_recording());
+
+        let track = recorder.track();
+        assert_eq!(notes(&track), vec![(2400, 480, 62), (2688, 192, 64)]);
+        let controllers = track
+            .events()
+            .iter()
+            .filter(|e| matches!(e.message(), MidiMessage::ControlChange { .. }))
+            .count();
+        assert_eq!(controllers, 1);
+
+        let file = recorder.to_file();
+        assert_eq!(file.num_tracks(), 2);
+        assert!((file.tempo_map().bpm_at(0) - 120.0).abs() < 1e-6);
+        assert_eq!(notes(&file.tracks()[1]).len(), 2);
+    }
+
+    #[test]
+    fn test_recorder_empty_punch_window() {
+        let options = RecorderOptions::new().with_punch(Range {
+            start: 960,
+            end: 480,
+        });
+        assert_eq!(options.punch(), Some(&(480..960)));
+
+        // The punch range ends before recording starts: nothing is recorded.
+        let options = RecorderOptions::new()
+            .with_start_tick(1920)
+            .with_punch(0..960)
+            .with_mode(RecordMode::Replace);
+        let recorder = MidiRecorder::new(options);
+        recorder.start();
+        recorder.record_at(2.5, &[0x90, 60, 100]);
+        recorder.stop_at(3.0);
+        assert!(recorder.track().events().iter().all(|e| e.is_meta()));
+
+        let mut file = MidiFile::with_format(crate::midi::MidiFormat::MultiTrack, 480);
+        file.add_tracks(2);
+        file.add_note(1, 0, 480, 0, 40, 80).unwrap();
+        file.finalize();
+        recorder.merge_into(&mut file, 1).unwrap();
+        assert_eq!(notes(&file.tracks()[1]), vec![(0, 480, 40)]);
+    }
+
+    #[test]
+    fn test_recorder_replace_and_overdub() {
+        let mut file = MidiFile::with_format(crate::midi::MidiFormat::MultiTrack, 960);
+        file.add_tracks(2);
+        file.add_note(1, 0, 1920, 0, 40, 80).unwrap(); // sounds into the range
+        file.add_note(1, 1920, 480, 0, 41, 80).unwrap(); // inside the range
+        file.add_note(1, 3840, 480, 0, 42, 80).unwrap(); // after it
+        file.finalize();
+
+        let take = |mode| {
+            let recorder =
+                MidiRecorder::new(RecorderOptions::new().with_mode(mode).with_punch(480..1440));
+            recorder.start();
+            recorder.record_at(0.75, &[0x90, 60, 100]); // tick 720
+            recorder.record_at(1.0, &[0x80, 60, 0]);
+            recorder.stop_at(2.0);
+            recorder
+        };
+
+        let mut replaced = file.clone();
+        take(RecordMode::Replace)
+            .merge_into(&mut replaced, 1)
+            .unwrap();
+        assert_eq!(
+            notes(&replaced.tracks()[1]),
+            vec![(0, 960, 40), (1440, 480, 60), (3840, 480, 42)]
+        );
+
+        let mut overdubbed = file.clone();
+        take(RecordMode::Overdub)
+            .merge_into(&mut overdubbed, 1)
+            .unwrap();
+        assert_eq!(notes(&overdubbed.tracks()[1]).len(), 4);
+        assert_eq!(
+            overdubbed.tracks()[1].events().last().unwrap().message(),
+            &MidiMessage::Meta(MetaEvent::EndOfTrack)
+        );
+        assert!(matches!(
+            take(RecordMode::Overdub).merge_into(&mut overdubbed, 5),
+            Err(MidiError::TrackOutOfBounds(5))
+        ));
+    }
+
+    #[test]
+    fn test_recorder_from_input() {
+        let port = "mkmidilibrary-test-recorder";
+        let mut output = MidiOutput::with_api(Api::Dummy, "Test").unwrap();
+        output.open_virtual_port(port).unwrap();
+        let mut input = MidiInput::with_api(Api::Dummy, "Test").unwrap();
+        let index = input.ports().iter().position(|p| p.name() == port).unwrap();
+        input.open_port(index, "in").unwrap();
+
+        let recorder = MidiRecorder::new(RecorderOptions::new());
+        recorder.attach(&mut input);
+        output.send_note_on(0, 70, 100).unwrap(); // before start: ignored
+        recorder.start();
+        output.send_note_on(0, 60, 100).unwrap();
+        output.send_note_off(0, 60, 0).unwrap();
+        recorder.stop();
+        output.send_note_on(0, 72, 100).unwrap(); // after stop: ignored
+        let keys: Vec<u8> = notes(&recorder.track()).iter().map(|n| n.2).collect();
+        assert_eq!(keys, vec![60]);
+
+        // Timestamps from the input are relative to the previous message.
+        // 120 BPM at 480 PPQN: 960 ticks per second.
+        recorder.start();
+        recorder.record_at(0.5, &[0x90, 60, 100]);
+        recorder.receive(0.25, &[0x80, 60, 0]);
+        recorder.receive(0.0, &[0x90, 62, 100]);
+        recorder.stop_at(1.0);
+        assert_eq!(
+            notes(&recorder.track()),
+            vec![(480, 240, 60), (720, 240, 62)]
+        );
+    }
+}
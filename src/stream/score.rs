@@ -5,6 +5,7 @@
 use std::fmt;
 
 use crate::core::{Chord, Duration, Fraction, Interval, Pitch, Rest};
+use crate::midi::{MeterMap, TempoMap, TimeDivision};
 use crate::notation::{KeySignature, Tempo, TimeSignature};
 
 use super::base::MusicElement;
@@ -221,6 +222,56 @@ impl Score {
             .unwrap_or(crate::core::Fraction::new(0, 1))
     }
 
+    /// Tempo map at the given MIDI resolution: the score's tempo (or 120
+    /// BPM) from the start
+    pub fn tempo_map(&self, ticks_per_quarter: u16) -> TempoMap {
+        let mut map = TempoMap::new(TimeDivision::Ppqn(ticks_per_quarter));
+        if let Some(tempo) = &self.tempo {
+            map.set_tempo(0, tempo.bpm());
+        }
+        map
+    }
+
+    /// Meter map at the given MIDI resolution, laid out from the first
+    /// part's measures. Bars are numbered from the first measure's number,
+    /// so a pickup measure is bar 0, and a measure shorter than its time
+    /// signature (e.g. a pickup) ends early.
+    pub fn meter_map(&self, ticks_per_quarter: u16) -> MeterMap {
+        let default = self
+            .time_signature
+            .unwrap_or_else(TimeSignature::common_time);
+        let Some(part) = self.parts.first().filter(|p| !p.is_empty()) else {
+            let mut map = MeterMap::new(ticks_per_quarter);
+            map.set_time_signature(0, default);
+            return map;
+        };
+
+        let to_ticks = |offset: Fraction| {
+            let ticks = offset * Fraction::from(ticks_per_quarter as i64);
+            (*ticks.numer() / *ticks.denom()) as u64
+        };
+        let mut map =
+            MeterMap::new(ticks_per_quarter).with_first_bar(part.measures()[0].number() as i32);
+        let mut offset = Fraction::new(0, 1);
+        let mut previous: Option<(TimeSignature, Fraction)> = None;
+        for index in 0..part.num_measures() {
+            let ts = part.time_signature_at(index).copied().unwrap_or(default);
+            let starts_bar = match previous {
+                None => true,
+                Some((prev_ts, prev_length)) => {
+                    prev_ts != ts || prev_length != prev_ts.bar_duration()
+                }
+            };
+            if starts_bar {
+                map.set_time_signature(to_ticks(offset), ts);
+            }
+            let length = part.measure_duration(index);
+            previous = Some((ts, length));
+            offset += length;
+        }
+        map
+    }
+
     /// Iterate over all notes in the score
     pub fn notes(&self) -> impl Iterator<Item = &crate::core::Note> {
         self.parts.iter().flat_map(|p| p.notes())
@@ -430,6 +481,36 @@ mod tests {
         assert_eq!(score.composer(), Some("Ludwig van Beethoven"));
     }
 
+    #[test]
+    fn test_score_tempo_and_meter_maps() {
+        let mut score = Score::new();
+        score.set_tempo(Tempo::new(90.0));
+        let mut part = Part::with_name("Piano");
+        let mut pickup = Measure::pickup();
+        pickup.set_time_signature(TimeSignature::new(3, 4));
+        pickup.set_duration(Fraction::new(1, 1));
+        part.add_measure(pickup);
+        part.add_measure(Measure::new(1));
+        part.add_measure(Measure::new(2));
+        let mut m3 = Measure::new(3);
+        m3.set_time_signature(TimeSignature::new(6, 8));
+        part.add_measure(m3);
+        score.add_part(part);
+
+        assert!((score.tempo_map(480).bpm_at(0) - 90.0).abs() < 1e-9);
+
+        let meter = score.meter_map(480);
+        let bar = |tick| meter.ticks_to_bbt(tick).bar;
+        assert_eq!(bar(0), 0);
+        assert_eq!(meter.ticks_to_bbt(480).to_string(), "1:1:0");
+        assert_eq!(meter.bar_to_ticks(3), 480 + 2 * 1440);
+        assert_eq!(
+            meter.time_signature_at(meter.bar_to_ticks(3)),
+            TimeSignature::new(6, 8)
+        );
+        assert_eq!(bar(480 + 2 * 1440 + 1440), 4);
+    }
+
     #[test]
     fn test_score_create_part() {
         let mut score = Score::new();